@@ -0,0 +1,40 @@
+// How long call/cc takes to capture a continuation, with more and more calls waiting on the stack.
+// Continuations share their frames, so the time per capture should stay the same as the stack grows.
+//
+//     cargo bench --bench continuations
+
+extern crate rusty_scheme;
+
+use rusty_scheme::interpreter::interpreter;
+
+use std::time::{Duration, Instant};
+
+const CAPTURES: usize = 10000;
+
+// Loops at the bottom of a stack of depth calls to +, calling f each time round
+fn program(depth: usize, f: &str) -> String {
+    format!("(define (spin i) (if (= i 0) 0 (begin ({}) (spin (- i 1)))))
+             (define (deep n) (if (= n 0) (spin {}) (+ 1 (deep (- n 1)))))
+             (deep {})", f, CAPTURES, depth)
+}
+
+// The best of a few runs, to keep out the noise
+fn time(src: &str) -> Duration {
+    (0..5).map(|_| {
+        let interpreter = interpreter::new("cps");
+        let start = Instant::now();
+        interpreter.execute(src).unwrap();
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn main() {
+    println!("{:>8}  {:>14}", "depth", "ns per capture");
+    for &depth in &[10, 100, 1000, 10000] {
+        // the same loop calling a procedure that doesn't capture anything is taken off
+        let plain = time(&program(depth, "(lambda () #f)"));
+        let capturing = time(&program(depth, "(lambda () (call/cc (lambda (k) k)))"));
+        let per_capture = capturing.saturating_sub(plain).as_nanos() / CAPTURES as u128;
+        println!("{:>8}  {:>14}", depth, per_capture);
+    }
+}
@@ -0,0 +1,36 @@
+;; A coroutine generator runs its procedure until it yields a value, and picks up
+;; where it left off the next time the generator is called.
+(define (tree-leaves tree)
+  (make-coroutine-generator
+   (lambda (yield)
+     (let walk ((t tree))
+       (cond ((null? t) #f)
+             ((pair? t) (walk (car t)) (walk (cdr t)))
+             (else (yield t)))))))
+
+(displayln (generator->list (tree-leaves '((1 2) (3 (4 5)) 6))))
+
+;; Two trees have the same fringe if their leaves come out in the same order,
+;; which generators can check without flattening either of them.
+(define (same-fringe? a b)
+  (let ((ga (tree-leaves a)) (gb (tree-leaves b)))
+    (let loop ()
+      (let ((x (ga)) (y (gb)))
+        (cond ((and (eof-object? x) (eof-object? y)) #t)
+              ((equal? x y) (loop))
+              (else #f))))))
+
+(displayln (same-fringe? '(1 (2 3)) '((1 2) 3)))
+(displayln (same-fringe? '(1 (2 3)) '((1 3) 2)))
+
+;; Generators can be endless, since only the items that are asked for get made
+(define squares (gmap (lambda (n) (* n n)) (make-range-generator 1)))
+(displayln (generator->list (gtake (gfilter even? squares) 5)))
+
+(do-generator (c (string->generator "abc"))
+  (displayln c))
+
+;; An accumulator collects what it's given until it gets the eof object
+(define total (sum-accumulator))
+(generator-for-each total (make-iota-generator 10))
+(displayln (total (eof-object)))
@@ -0,0 +1,55 @@
+;; Green threads take turns running. Yield lets the next thread that's ready have a turn.
+(define (greeter name)
+  (lambda ()
+    (displayln (string-append "Hello from " name))
+    (yield)
+    (displayln (string-append "Hello again from " name))
+    (yield)
+    (displayln (string-append "Hello once more from " name))))
+
+(displayln "Starting...")
+(define t1 (spawn (greeter "thread #1")))
+(define t2 (spawn (greeter "thread #2")))
+(thread-join! t1)
+(thread-join! t2)
+(displayln "Done")
+
+;; Channels pass values between threads. Getting from an empty channel waits for a value to be put in
+;; it, and putting in a full one waits for there to be room.
+(define numbers (make-channel 2))
+(define relayed (make-channel))
+
+(define producer
+  (spawn (lambda ()
+           (do ((i 1 (+ i 1))) ((> i 5))
+             (channel-put! numbers i))
+           (channel-put! numbers 'done))))
+
+(define relay
+  (spawn (lambda ()
+           (let loop ()
+             (let ((n (channel-get! numbers)))
+               (channel-put! relayed n)
+               (unless (eq? n 'done)
+                 (loop)))))))
+
+;; The main thread squares the numbers as the relay passes them on
+(let loop ()
+  (let ((n (channel-get! relayed)))
+    (unless (eq? n 'done)
+      (displayln (* n n))
+      (loop))))
+
+;; A thread that never waits is still switched out after a slice of steps, so it can't keep the others
+;; from running
+(define stop #f)
+(define spinner
+  (spawn (lambda ()
+           (let loop ((spins 0))
+             (if stop spins (loop (+ spins 1)))))))
+(define stopper
+  (spawn (lambda ()
+           (sleep 0.01)
+           (set! stop #t))))
+(thread-join! stopper)
+(displayln (if (> (thread-join! spinner) 0) "The spinner was stopped" "The spinner never ran"))
@@ -0,0 +1,101 @@
+use std::fmt;
+
+// Character names accepted after #\, as listed in R7RS
+const NAMES: [(&str, char); 9] = [
+    ("alarm", '\x07'),
+    ("backspace", '\x08'),
+    ("delete", '\x7f'),
+    ("escape", '\x1b'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
+// Parses the text following #\, which is either a single character, a character name, or a hex scalar value
+pub fn parse(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    let first = match (chars.next(), chars.next()) {
+        (Some(c), None) => return Some(c),
+        (Some(c), Some(_)) => c,
+        (None, _) => return None
+    };
+    if let Some(&(_, c)) = NAMES.iter().find(|&&(name, _)| name == s) {
+        return Some(c);
+    }
+    if first == 'x' || first == 'X' {
+        return u32::from_str_radix(&s[1..], 16).ok().and_then(::std::char::from_u32);
+    }
+    None
+}
+
+// Case conversions only apply when the result is a single character, as required for chars
+pub fn upcase(c: char) -> char {
+    single(c.to_uppercase()).unwrap_or(c)
+}
+
+pub fn downcase(c: char) -> char {
+    single(c.to_lowercase()).unwrap_or(c)
+}
+
+pub fn foldcase(c: char) -> char {
+    downcase(upcase(c))
+}
+
+fn single<I: Iterator<Item = char>>(mut chars: I) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None
+    }
+}
+
+// Formats a character using the #\ syntax the reader accepts, for printing with write semantics
+pub struct Written(pub char);
+
+impl fmt::Display for Written {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = self.0;
+        if let Some(&(name, _)) = NAMES.iter().find(|&&(_, named)| named == c) {
+            write!(f, "#\\{}", name)
+        } else if c.is_control() {
+            write!(f, "#\\x{:x}", c as u32)
+        } else {
+            write!(f, "#\\{}", c)
+        }
+    }
+}
+
+#[test]
+fn test_character_parsing() {
+    assert_eq!(parse("a"), Some('a'));
+    assert_eq!(parse("("), Some('('));
+    assert_eq!(parse("λ"), Some('λ'));
+    assert_eq!(parse("x"), Some('x'));
+    assert_eq!(parse("space"), Some(' '));
+    assert_eq!(parse("newline"), Some('\n'));
+    assert_eq!(parse("x3bb"), Some('λ'));
+    assert_eq!(parse("X41"), Some('A'));
+    assert_eq!(parse("xd800"), None);
+    assert_eq!(parse("spaces"), None);
+    assert_eq!(parse(""), None);
+}
+
+#[test]
+fn test_character_printing() {
+    assert_eq!(Written('a').to_string(), "#\\a");
+    assert_eq!(Written(' ').to_string(), "#\\space");
+    assert_eq!(Written('\n').to_string(), "#\\newline");
+    assert_eq!(Written('\x01').to_string(), "#\\x1");
+    assert_eq!(Written('λ').to_string(), "#\\λ");
+}
+
+#[test]
+fn test_character_case() {
+    assert_eq!(upcase('a'), 'A');
+    assert_eq!(upcase('λ'), 'Λ');
+    assert_eq!(upcase('ß'), 'ß');
+    assert_eq!(downcase('Σ'), 'σ');
+    assert_eq!(foldcase('Σ'), 'σ');
+    assert_eq!(upcase('1'), '1');
+}
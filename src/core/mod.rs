@@ -1 +1,4 @@
-pub mod repl;
\ No newline at end of file
+pub mod repl;
+pub mod number;
+pub mod character;
+pub mod string;
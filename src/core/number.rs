@@ -0,0 +1,701 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+
+use std::cmp::Ordering;
+use std::fmt;
+
+// The numeric tower shared by the reader and both interpreters.
+// Exact values are always kept in their simplest representation: a rational with a denominator
+// of 1 becomes an integer, and an integer that fits in 64 bits is stored as an i64.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Number {
+    Integer(i64),
+    BigInteger(BigInt),
+    // boxed to keep numbers, and the values and nodes that hold them, small
+    Rational(Box<BigRational>),
+    Real(f64),
+}
+
+pub struct NumberError {
+    message: String,
+}
+
+impl fmt::Display for NumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl fmt::Debug for NumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+macro_rules! number_error {
+    ($($arg:tt)*) => (
+        return Err(NumberError { message: format!($($arg)*)})
+    )
+}
+
+// The largest exact result of expt, in bits, before it is reported as an error
+const MAX_EXPT_BITS: u64 = 1 << 24;
+
+// Both operands of a binary operation, converted to the lowest level of the tower that can hold them
+enum Operands {
+    Integers(i64, i64),
+    BigIntegers(BigInt, BigInt),
+    Rationals(BigRational, BigRational),
+    Reals(f64, f64),
+}
+
+impl Number {
+    pub fn parse(s: &str) -> Option<Number> {
+        let mut radix = 10;
+        let mut exactness = None;
+        let mut rest = s;
+        while rest.starts_with('#') {
+            match rest[1..].chars().next().map(|c| c.to_ascii_lowercase()) {
+                Some('x') => radix = 16,
+                Some('b') => radix = 2,
+                Some('o') => radix = 8,
+                Some('d') => radix = 10,
+                Some(c) if c == 'e' || c == 'i' => {
+                    if exactness.is_some() { return None }
+                    exactness = Some(c)
+                },
+                _ => return None
+            }
+            rest = &rest[2..];
+        }
+
+        parse_real(rest, radix, exactness == Some('e')).and_then(|value| match exactness {
+            Some('i') => Some(value.to_inexact()),
+            Some('e') => value.to_exact().ok(),
+            _ => Some(value)
+        })
+    }
+
+    fn from_bigint(i: BigInt) -> Number {
+        match i.to_i64() {
+            Some(n) => Number::Integer(n),
+            None => Number::BigInteger(i)
+        }
+    }
+
+    fn from_rational(r: BigRational) -> Number {
+        if r.is_integer() {
+            Number::from_bigint(r.to_integer())
+        } else {
+            Number::Rational(Box::new(r))
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(*self, Number::Real(_))
+    }
+
+    pub fn is_exact_integer(&self) -> bool {
+        matches!(*self, Number::Integer(_) | Number::BigInteger(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match *self {
+            Number::Integer(_) | Number::BigInteger(_) => true,
+            Number::Rational(_) => false,
+            Number::Real(x) => x.is_finite() && x.fract() == 0.0
+        }
+    }
+
+    pub fn is_rational(&self) -> bool {
+        match *self {
+            Number::Real(x) => x.is_finite(),
+            _ => true
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        match *self {
+            Number::Real(x) => x.is_nan(),
+            _ => false
+        }
+    }
+
+    // eqv? on numbers: exactness has to match, and inexact numbers compare by their bits, so that
+    // 0.0 and -0.0 differ but a NaN is the same as itself
+    pub fn is_eqv(&self, other: &Number) -> bool {
+        match (self, other) {
+            (&Number::Real(a), &Number::Real(b)) => a.to_bits() == b.to_bits(),
+            _ => self == other
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            Number::Integer(n) => n as f64,
+            Number::BigInteger(ref n) => n.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(ref r) => r.to_f64().unwrap_or(f64::NAN),
+            Number::Real(x) => x
+        }
+    }
+
+    // Only valid for exact numbers
+    fn to_bigint(&self) -> BigInt {
+        match *self {
+            Number::Integer(n) => BigInt::from(n),
+            Number::BigInteger(ref n) => n.clone(),
+            Number::Rational(ref r) => r.to_integer(),
+            Number::Real(_) => panic!("to_bigint called on an inexact number")
+        }
+    }
+
+    // Only valid for exact numbers
+    fn to_rational(&self) -> BigRational {
+        match *self {
+            Number::Integer(n) => BigRational::from_integer(BigInt::from(n)),
+            Number::BigInteger(ref n) => BigRational::from_integer(n.clone()),
+            Number::Rational(ref r) => (**r).clone(),
+            Number::Real(_) => panic!("to_rational called on an inexact number")
+        }
+    }
+
+    pub fn to_exact(&self) -> Result<Number, NumberError> {
+        match *self {
+            Number::Real(x) => {
+                match BigRational::from_float(x) {
+                    Some(r) => Ok(Number::from_rational(r)),
+                    None => number_error!("No exact representation for {}", self)
+                }
+            },
+            _ => Ok(self.clone())
+        }
+    }
+
+    pub fn to_inexact(&self) -> Number {
+        Number::Real(self.to_f64())
+    }
+
+    fn coerce(&self, other: &Number) -> Operands {
+        match (self, other) {
+            (&Number::Integer(a), &Number::Integer(b)) => Operands::Integers(a, b),
+            (&Number::Real(_), _) | (_, &Number::Real(_)) => Operands::Reals(self.to_f64(), other.to_f64()),
+            (&Number::Rational(_), _) | (_, &Number::Rational(_)) => Operands::Rationals(self.to_rational(), other.to_rational()),
+            _ => Operands::BigIntegers(self.to_bigint(), other.to_bigint())
+        }
+    }
+
+    pub fn add(&self, other: &Number) -> Number {
+        match self.coerce(other) {
+            Operands::Integers(a, b) => match a.checked_add(b) {
+                Some(n) => Number::Integer(n),
+                None => Number::from_bigint(BigInt::from(a) + BigInt::from(b))
+            },
+            Operands::BigIntegers(a, b) => Number::from_bigint(a + b),
+            Operands::Rationals(a, b) => Number::from_rational(a + b),
+            Operands::Reals(a, b) => Number::Real(a + b)
+        }
+    }
+
+    pub fn sub(&self, other: &Number) -> Number {
+        match self.coerce(other) {
+            Operands::Integers(a, b) => match a.checked_sub(b) {
+                Some(n) => Number::Integer(n),
+                None => Number::from_bigint(BigInt::from(a) - BigInt::from(b))
+            },
+            Operands::BigIntegers(a, b) => Number::from_bigint(a - b),
+            Operands::Rationals(a, b) => Number::from_rational(a - b),
+            Operands::Reals(a, b) => Number::Real(a - b)
+        }
+    }
+
+    pub fn mul(&self, other: &Number) -> Number {
+        match self.coerce(other) {
+            Operands::Integers(a, b) => match a.checked_mul(b) {
+                Some(n) => Number::Integer(n),
+                None => Number::from_bigint(BigInt::from(a) * BigInt::from(b))
+            },
+            Operands::BigIntegers(a, b) => Number::from_bigint(a * b),
+            Operands::Rationals(a, b) => Number::from_rational(a * b),
+            Operands::Reals(a, b) => Number::Real(a * b)
+        }
+    }
+
+    pub fn div(&self, other: &Number) -> Result<Number, NumberError> {
+        match self.coerce(other) {
+            Operands::Reals(a, b) => Ok(Number::Real(a / b)),
+            _ => {
+                if other.is_zero() {
+                    number_error!("Division by zero: (/ {} {})", self, other)
+                }
+                Ok(Number::from_rational(self.to_rational() / other.to_rational()))
+            }
+        }
+    }
+
+    pub fn negate(&self) -> Number {
+        match *self {
+            Number::Integer(n) => match n.checked_neg() {
+                Some(n) => Number::Integer(n),
+                None => Number::from_bigint(-BigInt::from(n))
+            },
+            Number::BigInteger(ref n) => Number::from_bigint(-n),
+            Number::Rational(ref r) => Number::from_rational(-&**r),
+            Number::Real(x) => Number::Real(-x)
+        }
+    }
+
+    pub fn compare(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (&Number::Real(a), &Number::Real(b)) => a.partial_cmp(&b),
+            (&Number::Real(_), _) => other.compare(self).map(|o| o.reverse()),
+            (_, &Number::Real(b)) => {
+                // Compare mixed exact/inexact numbers exactly, so that comparisons stay transitive
+                if b.is_nan() {
+                    None
+                } else if b.is_infinite() {
+                    Some(if b > 0.0 { Ordering::Less } else { Ordering::Greater })
+                } else {
+                    let exact = BigRational::from_float(b).unwrap();
+                    Some(self.to_rational().cmp(&exact))
+                }
+            },
+            (&Number::Integer(a), &Number::Integer(b)) => Some(a.cmp(&b)),
+            _ => Some(self.to_rational().cmp(&other.to_rational()))
+        }
+    }
+
+    pub fn num_eq(&self, other: &Number) -> bool {
+        self.compare(other) == Some(Ordering::Equal)
+    }
+
+    pub fn max(&self, other: &Number) -> Number {
+        let res = if self.compare(other) == Some(Ordering::Less) || other.is_nan() { other } else { self };
+        if self.is_exact() && other.is_exact() { res.clone() } else { res.to_inexact() }
+    }
+
+    pub fn min(&self, other: &Number) -> Number {
+        let res = if self.compare(other) == Some(Ordering::Greater) || other.is_nan() { other } else { self };
+        if self.is_exact() && other.is_exact() { res.clone() } else { res.to_inexact() }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Number::Integer(n) => n == 0,
+            Number::BigInteger(ref n) => n.is_zero(),
+            Number::Rational(ref r) => r.is_zero(),
+            Number::Real(x) => x == 0.0
+        }
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.compare(&Number::Integer(0)) == Some(Ordering::Greater)
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.compare(&Number::Integer(0)) == Some(Ordering::Less)
+    }
+
+    pub fn is_even(&self) -> Result<bool, NumberError> {
+        match *self {
+            Number::Integer(n) => Ok(n % 2 == 0),
+            Number::BigInteger(ref n) => Ok(n.is_even()),
+            Number::Real(x) if self.is_integer() => Ok(x % 2.0 == 0.0),
+            _ => number_error!("Expected an integer: {}", self)
+        }
+    }
+
+    pub fn abs(&self) -> Number {
+        if self.is_negative() { self.negate() } else { self.clone() }
+    }
+
+    // quotient, remainder and modulo share their argument checking and exactness handling
+    fn integer_division(&self, other: &Number, name: &str) -> Result<Number, NumberError> {
+        if !self.is_integer() || !other.is_integer() {
+            number_error!("Must supply integers to {}: {} {}", name, self, other)
+        }
+        if other.is_zero() {
+            number_error!("Division by zero: ({} {} {})", name, self, other)
+        }
+        match self.coerce(other) {
+            Operands::Reals(a, b) => {
+                let res = match name {
+                    "quotient" => (a / b).trunc(),
+                    "remainder" => a % b,
+                    _ => {
+                        let r = a % b;
+                        if r != 0.0 && (r < 0.0) != (b < 0.0) { r + b } else { r }
+                    }
+                };
+                Ok(Number::Real(res))
+            },
+            Operands::Integers(a, b) if b != -1 => {
+                let res = match name {
+                    "quotient" => a / b,
+                    "remainder" => a % b,
+                    _ => a.mod_floor(&b)
+                };
+                Ok(Number::Integer(res))
+            },
+            _ => {
+                let (a, b) = (self.to_bigint(), other.to_bigint());
+                let res = match name {
+                    "quotient" => a / b,
+                    "remainder" => a % b,
+                    _ => a.mod_floor(&b)
+                };
+                Ok(Number::from_bigint(res))
+            }
+        }
+    }
+
+    pub fn quotient(&self, other: &Number) -> Result<Number, NumberError> {
+        self.integer_division(other, "quotient")
+    }
+
+    pub fn remainder(&self, other: &Number) -> Result<Number, NumberError> {
+        self.integer_division(other, "remainder")
+    }
+
+    pub fn modulo(&self, other: &Number) -> Result<Number, NumberError> {
+        self.integer_division(other, "modulo")
+    }
+
+    // The quotient and remainder, as truncate/ gives them
+    pub fn truncate_division(&self, other: &Number) -> Result<(Number, Number), NumberError> {
+        Ok((try!(self.quotient(other)), try!(self.remainder(other))))
+    }
+
+    // The quotient rounded down and the remainder with the sign of the divisor, as floor/ gives them
+    pub fn floor_division(&self, other: &Number) -> Result<(Number, Number), NumberError> {
+        let modulo = try!(self.modulo(other));
+        let quotient = try!(self.sub(&modulo).div(other));
+        Ok((quotient, modulo))
+    }
+
+    pub fn gcd(&self, other: &Number) -> Result<Number, NumberError> {
+        if !self.is_integer() || !other.is_integer() {
+            number_error!("Must supply integers to gcd: {} {}", self, other)
+        }
+        let res = Number::from_bigint(try!(self.to_exact()).to_bigint().gcd(&try!(other.to_exact()).to_bigint()));
+        if self.is_exact() && other.is_exact() { Ok(res) } else { Ok(res.to_inexact()) }
+    }
+
+    pub fn lcm(&self, other: &Number) -> Result<Number, NumberError> {
+        if !self.is_integer() || !other.is_integer() {
+            number_error!("Must supply integers to lcm: {} {}", self, other)
+        }
+        let res = Number::from_bigint(try!(self.to_exact()).to_bigint().lcm(&try!(other.to_exact()).to_bigint()));
+        if self.is_exact() && other.is_exact() { Ok(res) } else { Ok(res.to_inexact()) }
+    }
+
+    pub fn numerator(&self) -> Result<Number, NumberError> {
+        match *self {
+            Number::Rational(ref r) => Ok(Number::from_bigint(r.numer().clone())),
+            Number::Real(_) => Ok(try!(try!(self.to_exact()).numerator()).to_inexact()),
+            _ => Ok(self.clone())
+        }
+    }
+
+    pub fn denominator(&self) -> Result<Number, NumberError> {
+        match *self {
+            Number::Rational(ref r) => Ok(Number::from_bigint(r.denom().clone())),
+            Number::Real(_) => Ok(try!(try!(self.to_exact()).denominator()).to_inexact()),
+            _ => Ok(Number::Integer(1))
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::from_rational(r.floor()),
+            Number::Real(x) => Number::Real(x.floor()),
+            _ => self.clone()
+        }
+    }
+
+    pub fn ceiling(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::from_rational(r.ceil()),
+            Number::Real(x) => Number::Real(x.ceil()),
+            _ => self.clone()
+        }
+    }
+
+    pub fn truncate(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::from_rational(r.trunc()),
+            Number::Real(x) => Number::Real(x.trunc()),
+            _ => self.clone()
+        }
+    }
+
+    // Rounds to the nearest integer, with ties going to the even integer
+    pub fn round(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => {
+                let floor = r.floor();
+                let half = BigRational::new(BigInt::one(), BigInt::from(2));
+                let res = match (&**r - &floor).cmp(&half) {
+                    Ordering::Less => floor,
+                    Ordering::Greater => floor + BigRational::one(),
+                    Ordering::Equal => if floor.to_integer().is_even() { floor } else { floor + BigRational::one() }
+                };
+                Number::from_rational(res)
+            },
+            Number::Real(x) => {
+                let rounded = x.round();
+                if (x - x.trunc()).abs() == 0.5 {
+                    Number::Real(2.0 * (x / 2.0).round())
+                } else {
+                    Number::Real(rounded)
+                }
+            },
+            _ => self.clone()
+        }
+    }
+
+    pub fn sqrt(&self) -> Number {
+        if self.is_exact() && !self.is_negative() {
+            let r = self.to_rational();
+            let (n, d) = (r.numer().sqrt(), r.denom().sqrt());
+            if &(&n * &n) == r.numer() && &(&d * &d) == r.denom() {
+                return Number::from_rational(BigRational::new(n, d));
+            }
+        }
+        Number::Real(self.to_f64().sqrt())
+    }
+
+    // The largest integer whose square is at most this one, and what's left over
+    pub fn exact_integer_sqrt(&self) -> Result<(Number, Number), NumberError> {
+        if !self.is_exact_integer() || self.is_negative() {
+            number_error!("Must supply a non-negative exact integer to exact-integer-sqrt: {}", self)
+        }
+        let n = self.to_bigint();
+        let root = n.sqrt();
+        let rest = &n - &root * &root;
+        Ok((Number::from_bigint(root), Number::from_bigint(rest)))
+    }
+
+    pub fn expt(&self, exponent: &Number) -> Result<Number, NumberError> {
+        if self.is_exact() && exponent.is_exact_integer() {
+            if exponent.is_negative() && self.is_zero() {
+                number_error!("Division by zero: (expt {} {})", self, exponent)
+            }
+            if self.is_zero() || self.num_eq(&Number::Integer(1)) {
+                return Ok(if exponent.is_zero() { Number::Integer(1) } else { self.clone() });
+            }
+            if self.num_eq(&Number::Integer(-1)) {
+                return Ok(if try!(exponent.is_even()) { Number::Integer(1) } else { self.clone() });
+            }
+            // Refuse exponents whose result could not reasonably be held in memory
+            let base = self.to_rational();
+            let bits = ::std::cmp::max(base.numer().bits(), base.denom().bits());
+            let e = match *exponent {
+                Number::Integer(e) if e.unsigned_abs() <= MAX_EXPT_BITS / bits => e as i32,
+                _ => number_error!("Exponent too large: (expt {} {})", self, exponent)
+            };
+            return Ok(Number::from_rational(base.pow(e)));
+        }
+        match *exponent {
+            Number::Integer(e) if e <= i64::from(i32::MAX) && e >= i64::from(i32::MIN) => {
+                Ok(Number::Real(self.to_f64().powi(e as i32)))
+            },
+            _ => Ok(Number::Real(self.to_f64().powf(exponent.to_f64())))
+        }
+    }
+
+    pub fn to_string_radix(&self, radix: u32) -> Result<String, NumberError> {
+        match *self {
+            _ if radix == 10 => Ok(self.to_string()),
+            Number::Integer(n) => Ok(BigInt::from(n).to_str_radix(radix)),
+            Number::BigInteger(ref n) => Ok(n.to_str_radix(radix)),
+            Number::Rational(ref r) => Ok(format!("{}/{}", r.numer().to_str_radix(radix), r.denom().to_str_radix(radix))),
+            Number::Real(_) => number_error!("Inexact numbers can only be written in radix 10: {}", self)
+        }
+    }
+
+    pub fn map_real(&self, f: fn(f64) -> f64) -> Number {
+        Number::Real(f(self.to_f64()))
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Integer(n)        => write!(f, "{}", n),
+            Number::BigInteger(ref n) => write!(f, "{}", n),
+            Number::Rational(ref r)   => write!(f, "{}/{}", r.numer(), r.denom()),
+            Number::Real(x) => {
+                if x.is_nan() {
+                    write!(f, "+nan.0")
+                } else if x.is_infinite() {
+                    write!(f, "{}inf.0", if x > 0.0 { "+" } else { "-" })
+                } else {
+                    // Debug formatting always includes a decimal point or an exponent
+                    write!(f, "{:?}", x)
+                }
+            }
+        }
+    }
+}
+
+fn parse_real(s: &str, radix: u32, exact: bool) -> Option<Number> {
+    let (negative, body) = match s.chars().next() {
+        Some('+') => (false, &s[1..]),
+        Some('-') => (true, &s[1..]),
+        _ => (false, s)
+    };
+    if body.len() != s.len() {
+        match body {
+            "inf.0" => return Some(Number::Real(if negative { f64::NEG_INFINITY } else { f64::INFINITY })),
+            "nan.0" => return Some(Number::Real(f64::NAN)),
+            _ => ()
+        }
+    }
+
+    let magnitude = if let Some(pos) = body.find('/') {
+        let (n, d) = match (parse_uinteger(&body[..pos], radix), parse_uinteger(&body[pos + 1..], radix)) {
+            (Some(n), Some(d)) => (n, d),
+            _ => return None
+        };
+        if d.is_zero() {
+            return None;
+        }
+        Some(Number::from_rational(BigRational::new(n, d)))
+    } else if let Some(n) = parse_uinteger(body, radix) {
+        Some(Number::from_bigint(n))
+    } else if radix == 10 {
+        parse_decimal(body, exact)
+    } else {
+        None
+    };
+    magnitude.map(|magnitude| if negative { magnitude.negate() } else { magnitude })
+}
+
+fn parse_uinteger(s: &str, radix: u32) -> Option<BigInt> {
+    if s.is_empty() || !s.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    BigInt::parse_bytes(s.as_bytes(), radix)
+}
+
+// Parses decimal notation: digits with an optional fractional part and an optional exponent
+fn parse_decimal(s: &str, exact: bool) -> Option<Number> {
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(pos) => {
+            match s[pos + 1..].parse::<i32>() {
+                Ok(e) => (&s[..pos], e),
+                Err(_) => return None
+            }
+        },
+        None => (s, 0)
+    };
+    let (int_part, frac_part) = match mantissa.find('.') {
+        Some(pos) => (&mantissa[..pos], &mantissa[pos + 1..]),
+        None => (mantissa, "")
+    };
+    if int_part.len() + frac_part.len() == 0 || !int_part.chars().chain(frac_part.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    if exact {
+        let digits = BigInt::parse_bytes(format!("{}{}", int_part, frac_part).as_bytes(), 10).unwrap();
+        let scale = i64::from(exponent) - frac_part.len() as i64;
+        if scale.abs() > i64::from(u16::MAX) {
+            return None;
+        }
+        let power = BigRational::from_integer(BigInt::from(10)).pow(scale as i32);
+        Some(Number::from_rational(BigRational::from_integer(digits) * power))
+    } else {
+        s.parse::<f64>().ok().map(Number::Real)
+    }
+}
+
+#[test]
+fn test_number_parsing() {
+    assert_eq!(Number::parse("42"), Some(Number::Integer(42)));
+    assert_eq!(Number::parse("-42"), Some(Number::Integer(-42)));
+    assert_eq!(Number::parse("1.5"), Some(Number::Real(1.5)));
+    assert_eq!(Number::parse(".5"), Some(Number::Real(0.5)));
+    assert_eq!(Number::parse("1e10"), Some(Number::Real(1e10)));
+    assert_eq!(Number::parse("#x1F"), Some(Number::Integer(31)));
+    assert_eq!(Number::parse("#b-101"), Some(Number::Integer(-5)));
+    assert_eq!(Number::parse("#o17"), Some(Number::Integer(15)));
+    assert_eq!(Number::parse("#i3/4"), Some(Number::Real(0.75)));
+    assert_eq!(Number::parse("#e1.5").unwrap().to_string(), "3/2");
+    assert_eq!(Number::parse("#x#e10"), Some(Number::Integer(16)));
+    assert_eq!(Number::parse("6/4").unwrap().to_string(), "3/2");
+    assert_eq!(Number::parse("-inf.0"), Some(Number::Real(f64::NEG_INFINITY)));
+    assert_eq!(Number::parse("12345678901234567890").unwrap().to_string(), "12345678901234567890");
+    assert_eq!(Number::parse("1/0"), None);
+    assert_eq!(Number::parse("1.2.3"), None);
+    assert_eq!(Number::parse("#xg"), None);
+    assert_eq!(Number::parse("#e#i1"), None);
+}
+
+#[test]
+fn test_number_printing() {
+    assert_eq!(Number::Integer(-7).to_string(), "-7");
+    assert_eq!(Number::Real(1.0).to_string(), "1.0");
+    assert_eq!(Number::Real(-0.25).to_string(), "-0.25");
+    assert_eq!(Number::Real(f64::INFINITY).to_string(), "+inf.0");
+    assert_eq!(Number::Real(f64::NAN).to_string(), "+nan.0");
+    assert_eq!(Number::Integer(1).div(&Number::Integer(-3)).unwrap().to_string(), "-1/3");
+    assert_eq!(Number::Integer(-255).to_string_radix(16).unwrap(), "-ff");
+    assert_eq!(Number::parse("-5/3").unwrap().to_string_radix(2).unwrap(), "-101/11");
+    assert_eq!(Number::Real(0.5).to_string_radix(10).unwrap(), "0.5");
+    assert!(Number::Real(0.5).to_string_radix(2).is_err());
+}
+
+#[test]
+fn test_number_contagion() {
+    assert_eq!(Number::Integer(1).add(&Number::Real(0.5)), Number::Real(1.5));
+    assert_eq!(Number::Integer(4).div(&Number::Integer(2)).unwrap(), Number::Integer(2));
+    assert_eq!(Number::parse("1/2").unwrap().add(&Number::parse("1/2").unwrap()), Number::Integer(1));
+    assert_eq!(Number::Integer(i64::MAX).add(&Number::Integer(1)).to_string(), "9223372036854775808");
+    assert_eq!(Number::Integer(1).compare(&Number::Real(1.0)), Some(Ordering::Equal));
+    assert_eq!(Number::Integer(2).round(), Number::Integer(2));
+    assert_eq!(Number::Real(2.5).round(), Number::Real(2.0));
+    assert_eq!(Number::parse("7/2").unwrap().round(), Number::Integer(4));
+}
+
+#[test]
+fn test_number_eqv() {
+    assert!(Number::Integer(2).is_eqv(&Number::Integer(2)));
+    assert!(!Number::Integer(2).is_eqv(&Number::Real(2.0)));
+    assert!(Number::parse("1/2").unwrap().is_eqv(&Number::parse("2/4").unwrap()));
+    assert!(!Number::Real(0.0).is_eqv(&Number::Real(-0.0)));
+    assert!(Number::Real(f64::NAN).is_eqv(&Number::Real(f64::NAN)));
+}
+
+#[test]
+fn test_number_boundaries() {
+    let min = Number::Integer(i64::MIN);
+    assert_eq!(min.negate().to_string(), "9223372036854775808");
+    assert_eq!(min.abs().to_string(), "9223372036854775808");
+    assert_eq!(min.sub(&Number::Integer(1)).to_string(), "-9223372036854775809");
+    assert_eq!(min.mul(&Number::Integer(-1)).to_string(), "9223372036854775808");
+    assert_eq!(min.quotient(&Number::Integer(-1)).unwrap().to_string(), "9223372036854775808");
+    assert_eq!(min.remainder(&Number::Integer(-1)).unwrap(), Number::Integer(0));
+    assert_eq!(min.modulo(&Number::Integer(-1)).unwrap(), Number::Integer(0));
+    assert_eq!(min.negate().add(&min), Number::Integer(0));
+    assert_eq!(Number::Integer(1).div(&Number::Integer(0)).unwrap_err().to_string(), "Division by zero: (/ 1 0)");
+    assert_eq!(Number::Integer(1).modulo(&Number::Integer(0)).unwrap_err().to_string(), "Division by zero: (modulo 1 0)");
+    assert_eq!(Number::Integer(0).expt(&Number::Integer(-1)).unwrap_err().to_string(), "Division by zero: (expt 0 -1)");
+    assert_eq!(Number::Integer(2).expt(&Number::Integer(i64::MAX)).unwrap_err().to_string(), "Exponent too large: (expt 2 9223372036854775807)");
+    assert_eq!(Number::Integer(-1).expt(&Number::Integer(i64::MIN)).unwrap(), Number::Integer(1));
+    assert_eq!(Number::Integer(2).expt(&Number::Integer(-2)).unwrap().to_string(), "1/4");
+    assert_eq!(Number::Real(2.0).expt(&Number::Integer(i64::MIN)).unwrap(), Number::Real(0.0));
+    assert_eq!(Number::Real(f64::NAN).to_exact().unwrap_err().to_string(), "No exact representation for +nan.0");
+}
+
+#[test]
+fn test_number_division_pairs() {
+    assert_eq!(Number::Integer(17).exact_integer_sqrt().unwrap(), (Number::Integer(4), Number::Integer(1)));
+    assert_eq!(Number::parse("100000000000000000000").unwrap().exact_integer_sqrt().unwrap(), (Number::Integer(10000000000), Number::Integer(0)));
+    assert!(Number::Integer(-4).exact_integer_sqrt().is_err());
+    assert!(Number::Real(4.0).exact_integer_sqrt().is_err());
+    assert_eq!(Number::Integer(-7).floor_division(&Number::Integer(2)).unwrap(), (Number::Integer(-4), Number::Integer(1)));
+    assert_eq!(Number::Integer(-7).truncate_division(&Number::Integer(2)).unwrap(), (Number::Integer(-3), Number::Integer(-1)));
+    assert_eq!(Number::Real(-7.0).floor_division(&Number::Integer(2)).unwrap(), (Number::Real(-4.0), Number::Real(1.0)));
+}
@@ -3,7 +3,7 @@ use std::ffi::CString;
 use std::ffi::CStr;
 
 #[link(name = "readline")]
-extern {
+extern "C" {
     fn readline(prompt: *const libc::c_char) -> *const libc::c_char;
     fn add_history(entry: *const libc::c_char);
 }
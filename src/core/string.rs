@@ -0,0 +1,88 @@
+use std::fmt;
+
+// Maps the character following a backslash in a string literal to the character it stands for.
+// Hex escapes (\x41;) and line continuations are handled by the lexer.
+pub fn unescape(c: char) -> Option<char> {
+    match c {
+        'a' => Some('\x07'),
+        'b' => Some('\x08'),
+        't' => Some('\t'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        '"' => Some('"'),
+        '\\' => Some('\\'),
+        '|' => Some('|'),
+        _ => None
+    }
+}
+
+// Formats a string as a literal the reader accepts, for printing with write semantics
+pub struct Written<'a>(pub &'a str);
+
+impl<'a> fmt::Display for Written<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "\""));
+        for c in self.0.chars() {
+            try!(match c {
+                '"' => write!(f, "\\\""),
+                '\\' => write!(f, "\\\\"),
+                '\x07' => write!(f, "\\a"),
+                '\x08' => write!(f, "\\b"),
+                '\t' => write!(f, "\\t"),
+                '\n' => write!(f, "\\n"),
+                '\r' => write!(f, "\\r"),
+                _ if c.is_control() => write!(f, "\\x{:x};", c as u32),
+                _ => write!(f, "{}", c)
+            });
+        }
+        write!(f, "\"")
+    }
+}
+
+// Strings are indexed by character rather than by byte; returns the slice between two character
+// indices, or None if the range is out of bounds
+pub fn char_range(s: &str, start: usize, end: usize) -> Option<&str> {
+    if start > end {
+        return None;
+    }
+    let mut offsets = s.char_indices().map(|(i, _)| i).chain(Some(s.len()));
+    offsets.nth(start).and_then(|from| {
+        let to = if end == start { Some(from) } else { offsets.nth(end - start - 1) };
+        to.map(|to| &s[from..to])
+    })
+}
+
+// Splits on the separator, or on runs of whitespace when there is none
+pub fn split(s: &str, separator: Option<&str>) -> Vec<String> {
+    match separator {
+        Some(sep) if !sep.is_empty() => s.split(sep).map(|p| p.to_string()).collect(),
+        Some(_) => s.chars().map(|c| c.to_string()).collect(),
+        None => s.split_whitespace().map(|p| p.to_string()).collect()
+    }
+}
+
+#[test]
+fn test_string_printing() {
+    assert_eq!(Written("hello").to_string(), "\"hello\"");
+    assert_eq!(Written("a\"b\\c").to_string(), "\"a\\\"b\\\\c\"");
+    assert_eq!(Written("line\n\ttab").to_string(), "\"line\\n\\ttab\"");
+    assert_eq!(Written("\x01λ").to_string(), "\"\\x1;λ\"");
+}
+
+#[test]
+fn test_string_char_range() {
+    assert_eq!(char_range("hello", 1, 3), Some("el"));
+    assert_eq!(char_range("λx.x", 0, 1), Some("λ"));
+    assert_eq!(char_range("λx.x", 1, 4), Some("x.x"));
+    assert_eq!(char_range("abc", 3, 3), Some(""));
+    assert_eq!(char_range("abc", 2, 4), None);
+    assert_eq!(char_range("abc", 4, 4), None);
+    assert_eq!(char_range("abc", 2, 1), None);
+}
+
+#[test]
+fn test_string_split() {
+    assert_eq!(split("  a b\tc ", None), vec!["a", "b", "c"]);
+    assert_eq!(split("a,b,,c", Some(",")), vec!["a", "b", "", "c"]);
+    assert_eq!(split("abc", Some("")), vec!["a", "b", "c"]);
+}
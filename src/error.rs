@@ -0,0 +1,121 @@
+use crate::reader::parser::Node;
+use crate::reader::span::Span;
+
+use std::error;
+use std::fmt;
+
+// Everything that can go wrong reading or running Scheme code. The Display form is the message the
+// REPL prints, but host code can match on the variant and the kind instead of reading it. The details
+// are boxed to keep Results small.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Error {
+    Syntax(Box<Details>),
+    Parse(Box<Details>),
+    Runtime(Box<Details>),
+}
+
+// What an error was about. Values are converted to the data they print as, so they don't depend on
+// which interpreter raised them; ones that aren't data, like procedures, become their printed form.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Details {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub value: Option<Node>,
+    pub irritants: Vec<Node>,
+    pub location: Option<Span>,
+    pub backtrace: Vec<Frame>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ErrorKind {
+    // The input ended in the middle of a token or a form
+    Incomplete,
+    // Anything else the reader couldn't make sense of
+    Malformed,
+    UnboundVariable,
+    WrongType,
+    // A procedure got the wrong number of arguments; the value is the list of them
+    Arity,
+    // The program gave up by itself, with error or an uncaught raise. The value is the message error
+    // was called with, followed by the irritants, or the object that was raised.
+    Raised,
+    Other,
+}
+
+// A line of a backtrace: where a call was made, and the procedure that made it, unless that was the top level
+#[derive(PartialEq, Clone, Debug)]
+pub struct Frame {
+    pub procedure: Option<String>,
+    pub location: Option<Span>,
+}
+
+impl Details {
+    pub fn new(kind: ErrorKind, message: String, location: Option<Span>) -> Details {
+        Details { kind, message, value: None, irritants: vec![], location, backtrace: vec![] }
+    }
+}
+
+impl Error {
+    pub fn details(&self) -> &Details {
+        match *self {
+            Error::Syntax(ref d) | Error::Parse(ref d) | Error::Runtime(ref d) => d
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.details().kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.details().message
+    }
+
+    pub fn value(&self) -> Option<&Node> {
+        self.details().value.as_ref()
+    }
+
+    pub fn irritants(&self) -> &[Node] {
+        &self.details().irritants
+    }
+
+    pub fn location(&self) -> Option<&Span> {
+        self.details().location.as_ref()
+    }
+
+    // The calls that were waiting for a result when the error happened, innermost first. Only the
+    // CPS interpreter keeps track of them.
+    pub fn backtrace(&self) -> &[Frame] {
+        &self.details().backtrace
+    }
+}
+
+// Reader errors give the line and column in words, and runtime errors lead with where they happened
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let details = self.details();
+        match (self, &details.location) {
+            (Error::Runtime(_), Some(span)) => write!(f, "{}: RuntimeError: {}", span, details.message),
+            (Error::Runtime(_), None) => write!(f, "RuntimeError: {}", details.message),
+            (_, location) => {
+                let name = if let Error::Syntax(_) = *self { "SyntaxError" } else { "ParseError" };
+                try!(write!(f, "{}: {}", name, details.message));
+                match *location {
+                    Some(ref span) => write!(f, " (line: {}, column: {})", span.line, span.column),
+                    None => Ok(())
+                }
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let procedure = self.procedure.as_deref().unwrap_or("<top level>");
+        match self.location {
+            Some(ref span) => write!(f, "at {} ({})", procedure, span),
+            None => write!(f, "at {}", procedure)
+        }
+    }
+}
@@ -1,9 +1,20 @@
 use crate::reader::parser::*;
+use crate::reader::span::Span;
+use crate::error::{Error, Details, ErrorKind};
+use crate::core::character;
+use crate::core::number::{Number, NumberError};
+use crate::core::string;
+use crate::interpreter::syntax_rules::MacroError;
+use crate::interpreter::expander::{self, Expander, Evaluator, Transformer, Renaming};
 
 use std::fmt;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::iter;
 
 pub fn new() -> Interpreter {
     Interpreter::new()
@@ -11,37 +22,93 @@ pub fn new() -> Interpreter {
 
 #[derive(Clone)]
 pub struct Interpreter {
-    root: Rc<RefCell<Environment>>
+    toplevel: Rc<RefCell<Environment>>
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
-        Interpreter { root: Environment::new_root() }
+        Interpreter { toplevel: Environment::new_toplevel() }
     }
 
+    // Each top-level form is expanded and then run, so it can use the macros defined before it
     pub fn run(&self, nodes: &[Node]) -> Result<Value, RuntimeError> {
-        let values = Value::from_nodes(nodes);
-        evaluate_values(&values, self.root.clone())
+        let mut res = Value::from_vec(vec![]);
+        for node in nodes {
+            let expanded = try!(Environment::expander(self.toplevel.clone()).expand(node));
+            res = try!(evaluate_tail(&Value::from_node(&expanded), self.toplevel.clone()));
+        }
+        Ok(res)
+    }
+
+    // Like run, but errors say where in the source they happened. The spans of the lists in the code
+    // are kept in the root environment, and an error is located at the innermost one it came out of.
+    pub fn run_spanned(&self, forms: &[(Node, SourceMap)]) -> Result<Value, RuntimeError> {
+        let mut res = Value::from_vec(vec![]);
+        let root = Environment::get_root(self.toplevel.clone());
+        for (node, map) in forms {
+            let expanded = try!(Environment::expander(self.toplevel.clone()).expand(node).map_err(|e| RuntimeError::from(e).at(&map.span)));
+            let value = Value::from_node_spanned(&expanded, map, &mut root.borrow_mut().spans);
+            res = try!(evaluate_tail(&value, self.toplevel.clone()).map_err(|e| e.at(&map.span)));
+            root.borrow_mut().spans.prune();
+        }
+        Ok(res)
+    }
+
+    // Expands the macros in a program without running it, defining the macros it defines
+    pub fn expand(&self, nodes: &[Node]) -> Result<Vec<Node>, RuntimeError> {
+        let expander = Environment::expander(self.toplevel.clone());
+        let mut expanded = vec![];
+        for node in nodes {
+            expanded.push(try!(expander.expand(node)));
+        }
+        Ok(expanded)
     }
 }
 
 #[derive(PartialEq, Clone)]
 pub enum Value {
     Symbol(String),
-    Integer(i64),
+    Number(Number),
     Boolean(bool),
-    String(String),
-    List(Vec<Value>),
+    Char(char),
+    // Strings and lists are shared rather than copied, which gives them identity for eq? and eqv?
+    String(Rc<String>),
+    List(Rc<Vec<Value>>),
+    DottedList(Rc<Vec<Value>>, Box<Value>),
+    Vector(Rc<RefCell<Vec<Value>>>),
     Procedure(Function),
-    Macro(Vec<String>, Vec<Value>),
+    ErrorObject(Rc<RuntimeError>),
+    // What values gives for other than one value
+    Values(Vec<Value>),
 }
 
 // null == empty list
-macro_rules! null { () => (Value::List(vec![])) }
+macro_rules! null { () => (Value::from_vec(vec![])) }
 
 pub enum Function {
     Native(ValueOperation),
-    Scheme(Vec<String>, Vec<Value>, Rc<RefCell<Environment>>),
+    Scheme(Rc<Params>, Rc<Vec<Value>>, Rc<RefCell<Environment>>),
+    CaseLambda(Rc<Vec<(Params, Vec<Value>)>>, Rc<RefCell<Environment>>),
+    // the rename and compare procedures an er-macro-transformer is called with
+    Rename(Rc<Renaming>),
+    Compare(Rc<Renaming>),
+    Parameter(Rc<Parameter>),
+}
+
+// What make-parameter makes: calling it gives its value, which parameterize changes for the extent
+// of its body, after passing the new value through the converter
+pub struct Parameter {
+    value: RefCell<Value>,
+    converter: Option<Function>,
+}
+
+// The parameters of a lambda: the required ones, then #!optional ones along with the expressions for
+// their defaults, then a rest parameter that collects any remaining arguments into a list
+#[derive(Clone)]
+pub struct Params {
+    required: Vec<String>,
+    optional: Vec<(String, Value)>,
+    rest: Option<String>,
 }
 
 // type signature for all native functions
@@ -52,13 +119,87 @@ impl Value {
         nodes.iter().map(Value::from_node).collect()
     }
 
+    fn from_vec(vec: Vec<Value>) -> Value {
+        Value::List(Rc::new(vec))
+    }
+
+    // Builds a list ending in the given tail instead of null. Dotted lists are kept in normal form:
+    // they always have at least one element, and their tail is never a list.
+    fn from_vec_dotted(mut vec: Vec<Value>, tail: Value) -> Value {
+        match tail {
+            Value::List(rest) => {
+                vec.extend(rest.iter().cloned());
+                Value::from_vec(vec)
+            },
+            Value::DottedList(rest, tail) => {
+                vec.extend(rest.iter().cloned());
+                Value::DottedList(Rc::new(vec), tail)
+            },
+            _ if vec.is_empty() => tail,
+            _ => Value::DottedList(Rc::new(vec), Box::new(tail))
+        }
+    }
+
+    fn from_vector(vec: Vec<Value>) -> Value {
+        Value::Vector(Rc::new(RefCell::new(vec)))
+    }
+
+    fn from_string(s: String) -> Value {
+        Value::String(Rc::new(s))
+    }
+
+    // The inverse of from_node, for handing code to the macro expander
+    fn to_node(&self) -> Result<Node, RuntimeError> {
+        match *self {
+            Value::Symbol(ref val) => Ok(Node::Identifier(val.clone())),
+            Value::Number(ref val) => Ok(Node::Number(val.clone())),
+            Value::Boolean(val) => Ok(Node::Boolean(val)),
+            Value::Char(val) => Ok(Node::Char(val)),
+            Value::String(ref val) => Ok(Node::String((**val).clone())),
+            Value::List(ref list) => Ok(Node::List(try!(Value::to_nodes(list)))),
+            Value::DottedList(ref list, ref tail) => Ok(Node::DottedList(try!(Value::to_nodes(list)), Box::new(try!(tail.to_node())))),
+            Value::Vector(ref vec) => Ok(Node::Vector(try!(Value::to_nodes(&vec.borrow())))),
+            _ => Err(RuntimeError::new(format!("Can't use {:?} as syntax", self)))
+        }
+    }
+
+    // For values in errors, which may not be data
+    fn to_datum(&self) -> Node {
+        self.to_node().unwrap_or_else(|_| Node::Identifier(format!("{:?}", self)))
+    }
+
+    fn to_nodes(values: &[Value]) -> Result<Vec<Node>, RuntimeError> {
+        values.iter().map(|v| v.to_node()).collect()
+    }
+
+    // Like from_node, but records the span of each list in spans, and of each identifier in one
+    fn from_node_spanned(node: &Node, map: &SourceMap, spans: &mut SpanTable) -> Value {
+        match *node {
+            Node::List(ref nodes) => {
+                let vec = nodes.iter().enumerate().map(|(i, n)| Value::from_node_spanned(n, &map.part(i, nodes.len()), spans)).collect();
+                let list = Rc::new(vec);
+                spans.insert(&list, &map.span);
+                for (i, node) in nodes.iter().enumerate() {
+                    if let Node::Identifier(_) = *node {
+                        spans.insert_identifier(&list, i, &map.part(i, nodes.len()).span);
+                    }
+                }
+                Value::List(list)
+            },
+            _ => Value::from_node(node)
+        }
+    }
+
     fn from_node(node: &Node) -> Value {
         match *node {
             Node::Identifier(ref val) => Value::Symbol(val.clone()),
-            Node::Integer(val) => Value::Integer(val),
+            Node::Number(ref val) => Value::Number(val.clone()),
             Node::Boolean(val) => Value::Boolean(val),
-            Node::String(ref val) => Value::String(val.clone()),
-            Node::List(ref nodes) => Value::List(Value::from_nodes(&nodes))
+            Node::Char(val) => Value::Char(val),
+            Node::String(ref val) => Value::from_string(val.clone()),
+            Node::List(ref nodes) => Value::from_vec(Value::from_nodes(nodes)),
+            Node::DottedList(ref nodes, ref tail) => Value::from_vec_dotted(Value::from_nodes(nodes), Value::from_node(tail)),
+            Node::Vector(ref nodes) => Value::from_vector(Value::from_nodes(nodes))
         }
     }
 }
@@ -67,15 +208,17 @@ impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Value::Symbol(ref val) => write!(f, "{}", val),
-            Value::Integer(val)    => write!(f, "{}", val),
+            Value::Number(ref val) => write!(f, "{}", val),
             Value::Boolean(val)    => write!(f, "#{}", if val { "t" } else { "f" }),
+            Value::Char(val)       => write!(f, "{}", val),
             Value::String(ref val) => write!(f, "{}", val),
-            Value::List(ref list)  => {
-                let strs: Vec<String> = list.iter().map(|v| format!("{}", v)).collect();
-                write!(f, "({})", &strs.join(" "))
-            },
+            Value::List(_) | Value::DottedList(..) | Value::Vector(_) => Printer::new(false, self).value(f, self),
             Value::Procedure(_)   => write!(f, "#<procedure>"),
-            Value::Macro(_,_)     => write!(f, "#<macro>"),
+            Value::ErrorObject(ref e) => write!(f, "#<error-object {}>", e.message),
+            Value::Values(ref vals) => {
+                let strs: Vec<String> = vals.iter().map(|v| format!("{}", v)).collect();
+                write!(f, "{}", &strs.join("\n"))
+            },
         }
     }
 }
@@ -83,19 +226,137 @@ impl fmt::Display for Value {
 impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Value::String(ref val) => write!(f, "\"{}\"", val),
-            Value::List(ref list)  => {
-                let strs: Vec<String> = list.iter().map(|v| format!("{:?}", v)).collect();
-                write!(f, "({})", &strs.join(" "))
+            Value::Char(val)       => write!(f, "{}", character::Written(val)),
+            Value::String(ref val) => write!(f, "{}", string::Written(val)),
+            Value::List(_) | Value::DottedList(..) | Value::Vector(_) => Printer::new(true, self).value(f, self),
+            Value::Values(ref vals) => {
+                let strs: Vec<String> = vals.iter().map(|v| format!("{:?}", v)).collect();
+                write!(f, "{}", &strs.join("\n"))
             },
             _                      => write!(f, "{}", self)
         }
     }
 }
 
+// Writes lists and vectors for Display (write: false) and Debug (write: true). vector-set! can put a
+// vector inside itself, so vectors that contain themselves get datum labels, as in #0=#(#0# 2)
+struct Printer {
+    write: bool,
+    labels: HashMap<usize, Option<usize>>,
+    next_label: usize,
+}
+
+impl Printer {
+    fn new(write: bool, val: &Value) -> Printer {
+        let mut labels = HashMap::new();
+        find_cycles(val, &mut HashSet::new(), &mut HashSet::new(), &mut labels);
+        Printer { write, labels, next_label: 0 }
+    }
+
+    fn value(&mut self, f: &mut fmt::Formatter, val: &Value) -> fmt::Result {
+        match *val {
+            Value::List(ref list) => {
+                try!(write!(f, "("));
+                try!(self.values(f, list));
+                write!(f, ")")
+            },
+            Value::DottedList(ref list, ref tail) => {
+                try!(write!(f, "("));
+                try!(self.values(f, list));
+                try!(write!(f, " . "));
+                try!(self.value(f, tail));
+                write!(f, ")")
+            },
+            Value::Vector(ref vec) => {
+                if try!(self.label(f, Rc::as_ptr(vec) as usize)) {
+                    return Ok(());
+                }
+                try!(write!(f, "#("));
+                try!(self.values(f, &vec.borrow()));
+                write!(f, ")")
+            },
+            _ if self.write => write!(f, "{:?}", val),
+            _ => write!(f, "{}", val)
+        }
+    }
+
+    fn values(&mut self, f: &mut fmt::Formatter, vals: &[Value]) -> fmt::Result {
+        for (i, v) in vals.iter().enumerate() {
+            if i > 0 {
+                try!(write!(f, " "));
+            }
+            try!(self.value(f, v));
+        }
+        Ok(())
+    }
+
+    // Writes #n= the first time a labelled vector is seen, or #n# after that. Returns true in the
+    // latter case, as the vector itself shouldn't be written again.
+    fn label(&mut self, f: &mut fmt::Formatter, addr: usize) -> Result<bool, fmt::Error> {
+        match self.labels.get_mut(&addr) {
+            Some(&mut Some(n)) => {
+                try!(write!(f, "#{}#", n));
+                Ok(true)
+            },
+            Some(label) => {
+                *label = Some(self.next_label);
+                try!(write!(f, "#{}=", self.next_label));
+                self.next_label += 1;
+                Ok(false)
+            },
+            None => Ok(false)
+        }
+    }
+}
+
+// Finds the vectors that can be reached from themselves. Vectors on the current path are in `active`,
+// and fully explored ones in `done`.
+fn find_cycles(val: &Value, active: &mut HashSet<usize>, done: &mut HashSet<usize>, labels: &mut HashMap<usize, Option<usize>>) {
+    match *val {
+        Value::List(ref list) => {
+            for v in list.iter() {
+                find_cycles(v, active, done, labels);
+            }
+        },
+        Value::DottedList(ref list, ref tail) => {
+            for v in list.iter() {
+                find_cycles(v, active, done, labels);
+            }
+            find_cycles(tail, active, done, labels);
+        },
+        Value::Vector(ref vec) => {
+            let addr = Rc::as_ptr(vec) as usize;
+            if active.contains(&addr) {
+                labels.insert(addr, None);
+                return;
+            }
+            if done.contains(&addr) {
+                return;
+            }
+            active.insert(addr);
+            for v in vec.borrow().iter() {
+                find_cycles(v, active, done, labels);
+            }
+            active.remove(&addr);
+            done.insert(addr);
+        },
+        _ => ()
+    }
+}
+
+// Procedures are compared by identity. Every evaluation of a lambda allocates a new body, which is
+// shared by all the copies of the procedure.
 impl PartialEq for Function {
     fn eq(&self, other: &Function) -> bool {
-        self == other
+        match (self, other) {
+            (Function::Native(a), Function::Native(b)) => *a as usize == *b as usize,
+            (Function::Scheme(_, a, _), Function::Scheme(_, b, _)) => Rc::ptr_eq(a, b),
+            (Function::CaseLambda(a, _), Function::CaseLambda(b, _)) => Rc::ptr_eq(a, b),
+            (Function::Rename(a), Function::Rename(b)) => Rc::ptr_eq(a, b),
+            (Function::Compare(a), Function::Compare(b)) => Rc::ptr_eq(a, b),
+            (Function::Parameter(a), Function::Parameter(b)) => Rc::ptr_eq(a, b),
+            _ => false
+        }
     }
 }
 
@@ -103,51 +364,377 @@ impl Clone for Function {
     fn clone(&self) -> Function {
         match *self {
             Function::Native(ref func) => Function::Native(*func),
-            Function::Scheme(ref a, ref b, ref env) => Function::Scheme(a.clone(), b.clone(), env.clone())
+            Function::Scheme(ref a, ref b, ref env) => Function::Scheme(a.clone(), b.clone(), env.clone()),
+            Function::CaseLambda(ref a, ref env) => Function::CaseLambda(a.clone(), env.clone()),
+            Function::Rename(ref renaming) => Function::Rename(renaming.clone()),
+            Function::Compare(ref renaming) => Function::Compare(renaming.clone()),
+            Function::Parameter(ref parameter) => Function::Parameter(parameter.clone())
         }
     }
 }
 
-pub struct RuntimeError {
+// Errors are passed back through every call on their way out, so they're kept small
+#[derive(PartialEq, Clone)]
+pub struct RuntimeError(Box<ErrorData>);
+
+#[derive(PartialEq, Clone)]
+pub struct ErrorData {
+    kind: ErrorKind,
     message: String,
+    value: Option<Box<Value>>,
+    irritants: Vec<Value>,
+    location: Option<Rc<Span>>,
+    // What raise was called with, which handlers get instead of the error
+    object: Option<Box<Value>>,
+    // Only handlers installed below this depth can catch the error. Errors from a handler called by
+    // raise-continuable can only be caught by the ones outside it, and anything else by all of them.
+    handler_depth: usize,
+    // What the guard that caught a raise-continuable gives, on the way out to it
+    guarded: Option<Box<Value>>,
+}
+
+impl Deref for RuntimeError {
+    type Target = ErrorData;
+
+    fn deref(&self) -> &ErrorData {
+        &self.0
+    }
+}
+
+impl DerefMut for RuntimeError {
+    fn deref_mut(&mut self) -> &mut ErrorData {
+        &mut self.0
+    }
+}
+
+impl RuntimeError {
+    fn new(message: String) -> RuntimeError {
+        RuntimeError(Box::new(ErrorData { kind: ErrorKind::Other, message, value: None, irritants: vec![], location: None, object: None, handler_depth: usize::MAX, guarded: None }))
+    }
+
+    // The error as an error object, which any handler can catch when it's raised again
+    fn to_object(&self) -> RuntimeError {
+        let mut e = self.clone();
+        e.handler_depth = usize::MAX;
+        e
+    }
+
+    fn of(mut self, kind: ErrorKind, value: Option<Value>) -> RuntimeError {
+        self.kind = kind;
+        self.value = value.map(Box::new);
+        self
+    }
+
+    fn at(mut self, span: &Span) -> RuntimeError {
+        if self.location.is_none() {
+            self.location = Some(Rc::new(span.clone()));
+        }
+        self
+    }
+
+    // Marks an error that came out of the handler at depth
+    fn outside(mut self, depth: usize) -> RuntimeError {
+        self.handler_depth = self.handler_depth.min(depth);
+        self
+    }
+
+    // What a handler is called with: the object that was raised, or else the error itself
+    fn condition(&self) -> Value {
+        match self.object {
+            Some(ref obj) => (**obj).clone(),
+            None => Value::ErrorObject(Rc::new(self.to_object()))
+        }
+    }
+
+    // What error-object-message gives: the message error was called with, or a built-in error's own
+    fn message_value(&self) -> Value {
+        match (self.kind, &self.value) {
+            (ErrorKind::Raised, Some(msg)) => (**msg).clone(),
+            _ => Value::from_string(self.message.clone())
+        }
+    }
+
+    // What error-object-irritants gives: the irritants error was called with, or what a built-in error was about
+    fn irritant_values(&self) -> Value {
+        match (self.kind, &self.value) {
+            (ErrorKind::Raised, _) => Value::from_vec(self.irritants.clone()),
+            (_, Some(val)) => Value::from_vec(vec![(**val).clone()]),
+            _ => Value::from_vec(vec![])
+        }
+    }
 }
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "RuntimeError: {}", self.message)
+        match self.location {
+            Some(ref span) => write!(f, "{}: RuntimeError: {}", span, self.message),
+            None => write!(f, "RuntimeError: {}", self.message)
+        }
     }
 }
 impl fmt::Debug for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "RuntimeError: {}", self.message)
+        write!(f, "{}", self)
+    }
+}
+
+impl From<NumberError> for RuntimeError {
+    fn from(e: NumberError) -> RuntimeError {
+        RuntimeError::new(e.to_string())
+    }
+}
+
+impl From<MacroError> for RuntimeError {
+    fn from(e: MacroError) -> RuntimeError {
+        RuntimeError::new(e.message)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Error {
+        let e = *e.0;
+        Error::Runtime(Box::new(Details {
+            kind: e.kind,
+            message: e.message,
+            value: e.value.map(|v| v.to_datum()),
+            irritants: e.irritants.iter().map(Value::to_datum).collect(),
+            location: e.location.map(|span| (*span).clone()),
+            backtrace: vec![]
+        }))
+    }
+}
+
+impl From<RuntimeError> for MacroError {
+    fn from(e: RuntimeError) -> MacroError {
+        MacroError { message: e.0.message }
     }
 }
 
+// Errors are Other unless they say what they were about, as in runtime_error!(WrongType(val); "..."),
+// or at least what kind of error they are, when the value is gone
 macro_rules! runtime_error {
+    ($kind:ident($value:expr); $($arg:tt)*) => (
+        return Err(RuntimeError::new(format!($($arg)*)).of(ErrorKind::$kind, Some($value)))
+    );
+    ($kind:ident; $($arg:tt)*) => (
+        return Err(RuntimeError::new(format!($($arg)*)).of(ErrorKind::$kind, None))
+    );
     ($($arg:tt)*) => (
-        return Err(RuntimeError { message: format!($($arg)*)})
+        return Err(RuntimeError::new(format!($($arg)*)))
     )
 }
 
+impl Params {
+    // Accepts (a b), (a b . rest) and a lone symbol, which takes every argument as a list. An optional
+    // parameter is either a name, which defaults to #f, or (name default), e.g. (a #!optional (b 1) #!rest c)
+    fn parse(formals: &Value) -> Result<Params, RuntimeError> {
+        let (items, tail): (&[Value], Option<&Value>) = match *formals {
+            Value::Symbol(_) => (&[], Some(formals)),
+            Value::List(ref items) => (items, None),
+            Value::DottedList(ref items, ref tail) => (items, Some(tail)),
+            _ => runtime_error!("Unexpected value for parameters: {:?}", formals)
+        };
+
+        #[derive(PartialEq)]
+        enum Section { Required, Optional, Rest }
+        let mut section = Section::Required;
+        let mut params = Params { required: vec![], optional: vec![], rest: None };
+        for item in items.iter() {
+            match *item {
+                Value::Symbol(ref s) if s.starts_with("#!") => {
+                    section = match (s.as_ref(), section) {
+                        ("#!optional", Section::Required) => Section::Optional,
+                        ("#!rest", Section::Required) | ("#!rest", Section::Optional) => Section::Rest,
+                        _ => runtime_error!("Unexpected {} in parameter list", s)
+                    };
+                },
+                Value::Symbol(ref s) => {
+                    match section {
+                        Section::Required => params.required.push(s.clone()),
+                        Section::Optional => params.optional.push((s.clone(), Value::Boolean(false))),
+                        Section::Rest if params.rest.is_none() => params.rest = Some(s.clone()),
+                        Section::Rest => runtime_error!("Only one parameter can follow #!rest: {}", s)
+                    }
+                },
+                Value::List(ref l) if section == Section::Optional && l.len() == 2 => {
+                    match l[0] {
+                        Value::Symbol(ref name) => params.optional.push((name.clone(), l[1].clone())),
+                        _ => runtime_error!("Unexpected value in parameter list: {:?}", item)
+                    }
+                },
+                _ => runtime_error!("Unexpected value in parameter list: {:?}", item)
+            }
+        }
+        if section == Section::Rest && params.rest.is_none() {
+            runtime_error!("Missing parameter after #!rest");
+        }
+        match tail {
+            Some(Value::Symbol(s)) if params.rest.is_none() => params.rest = Some(s.clone()),
+            Some(other) => runtime_error!("Unexpected value for rest parameter: {:?}", other),
+            None => ()
+        }
+        Ok(params)
+    }
+
+    fn accepts(&self, n: usize) -> bool {
+        n >= self.required.len() && (self.rest.is_some() || n <= self.required.len() + self.optional.len())
+    }
+
+    fn check_arity(&self, args: &[Value]) -> Result<(), RuntimeError> {
+        if self.accepts(args.len()) {
+            return Ok(());
+        }
+        let min = self.required.len();
+        if self.rest.is_some() {
+            runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least {} arguments to function: {:?}", min, args)
+        } else if self.optional.is_empty() {
+            runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly {} arguments to function: {:?}", min, args)
+        } else {
+            runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply between {} and {} arguments to function: {:?}", min, min + self.optional.len(), args)
+        }
+    }
+
+    // Defines the arguments in a new environment for the procedure
+    fn bind(&self, values: Vec<Value>, func_env: Rc<RefCell<Environment>>) -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+        let proc_env = Environment::new_child(func_env);
+        try!(self.define(values, &proc_env));
+        Ok(proc_env)
+    }
+
+    // Defaults for missing optional arguments are evaluated in the environment the arguments are
+    // defined in, so they can refer to the earlier parameters
+    fn define(&self, mut values: Vec<Value>, proc_env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+        let fixed = self.required.len() + self.optional.len();
+        let rest = if values.len() > fixed { values.split_off(fixed) } else { vec![] };
+        let mut values = values.into_iter();
+        for name in self.required.iter() {
+            try!(proc_env.borrow_mut().define(name.clone(), values.next().unwrap()));
+        }
+        for (name, default) in self.optional.iter() {
+            let val = match values.next() {
+                Some(val) => val,
+                None => try!(evaluate_value(default, proc_env.clone()))
+            };
+            try!(proc_env.borrow_mut().define(name.clone(), val));
+        }
+        if let Some(ref name) = self.rest {
+            try!(proc_env.borrow_mut().define(name.clone(), Value::from_vec(rest)));
+        }
+        Ok(())
+    }
+}
+
+// Where the lists in the code were read from, by the address of their storage, and the identifiers in
+// them, by their address in the list. Each entry holds a weak reference to the list, and only counts
+// while the list is there. The reference also keeps the address of the list from being reused.
+#[derive(Default)]
+struct SpanTable {
+    spans: HashMap<usize, (Weak<Vec<Value>>, Rc<Span>)>,
+    // how many entries were left the last time the dead ones were removed
+    live: usize,
+}
+
+impl SpanTable {
+    fn insert(&mut self, list: &Rc<Vec<Value>>, span: &Span) {
+        self.spans.insert(Rc::as_ptr(list) as usize, (Rc::downgrade(list), Rc::new(span.clone())));
+    }
+
+    fn insert_identifier(&mut self, list: &Rc<Vec<Value>>, i: usize, span: &Span) {
+        self.spans.insert(&list[i] as *const Value as usize, (Rc::downgrade(list), Rc::new(span.clone())));
+    }
+
+    fn get(&self, list: &Vec<Value>) -> Option<Rc<Span>> {
+        self.at(list as *const Vec<Value> as usize)
+    }
+
+    fn identifier(&self, value: &Value) -> Option<Rc<Span>> {
+        self.at(value as *const Value as usize)
+    }
+
+    fn at(&self, address: usize) -> Option<Rc<Span>> {
+        match self.spans.get(&address) {
+            Some((list, span)) if list.strong_count() > 0 => Some(span.clone()),
+            _ => None
+        }
+    }
+
+    // A copy of values, such as the body of a procedure, where its identifiers are where they were
+    // read from. Procedures are made while the program runs, so the entries of the ones that are gone
+    // are removed here too.
+    fn copy(&mut self, values: &[Value]) -> Rc<Vec<Value>> {
+        let copy = Rc::new(values.to_vec());
+        for (i, value) in values.iter().enumerate() {
+            if let Some(span) = self.identifier(value) {
+                self.spans.insert(&copy[i] as *const Value as usize, (Rc::downgrade(&copy), span));
+            }
+        }
+        self.prune();
+        copy
+    }
+
+    // Removes the entries of the lists that are gone, once there are twice as many as were left before
+    fn prune(&mut self) {
+        if self.spans.len() > 2 * self.live + 64 {
+            self.spans.retain(|_, &mut (ref list, _)| list.strong_count() > 0);
+            self.live = self.spans.len();
+        }
+    }
+}
+
+// A guard keeps its variable and clauses, so that raise-continuable can try them where it was called,
+// and how many winds there were when it was installed, so it can leave the ones inside it first
+#[derive(Clone)]
+enum Handler {
+    Procedure(Function),
+    Guard(String, Rc<Vec<Value>>, Rc<RefCell<Environment>>, usize),
+}
+
+// What dynamic-wind and parameterize do on the way into their bodies and out of them
+type Winder = Rc<dyn Fn() -> Result<Value, RuntimeError>>;
+
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
     values: HashMap<String, Value>,
+    // only the root has the expander, which holds the macros defined at the top level
+    expander: Option<Rc<Expander>>,
+    // and the handlers installed by with-exception-handler and guard, innermost last
+    handlers: Vec<Handler>,
+    // and the before and after thunks of the bodies being run, innermost last
+    winds: Vec<(Winder, Winder)>,
+    // the root also has the spans of the code run by run_spanned
+    spans: SpanTable,
 }
 
 /**
  * 
  */
 impl Environment {
-    fn new_root() -> Rc<RefCell<Environment>> {
-        let mut env = Environment { parent: None, values: HashMap::new() };
+    // The built-ins are in the root, and programs run in a child of it, so their top-level
+    // definitions can shadow them
+    fn new_toplevel() -> Rc<RefCell<Environment>> {
+        let mut env = Environment { parent: None, values: HashMap::new(), expander: None, handlers: vec![], winds: vec![], spans: SpanTable::default() };
         let predefined_functions = &[
             ("define", Function::Native(native_define)),
-            ("define-syntax-rule", Function::Native(native_define_syntax_rule)),
             ("begin", Function::Native(native_begin)),
             ("let", Function::Native(native_let)),
+            ("let*", Function::Native(native_let_star)),
+            ("letrec", Function::Native(native_letrec)),
+            ("letrec*", Function::Native(native_letrec)),
+            ("cond", Function::Native(native_cond)),
+            ("case", Function::Native(native_case)),
+            ("when", Function::Native(native_when)),
+            ("unless", Function::Native(native_unless)),
+            ("do", Function::Native(native_do)),
+            ("guard", Function::Native(native_guard)),
+            ("parameterize", Function::Native(native_parameterize)),
+            ("let-values", Function::Native(native_let_values)),
+            ("let*-values", Function::Native(native_let_star_values)),
+            ("receive", Function::Native(native_receive)),
+            ("define-values", Function::Native(native_define_values)),
             ("set!", Function::Native(native_set)),
             ("lambda", Function::Native(native_lambda)),
             ("λ", Function::Native(native_lambda)),
+            ("case-lambda", Function::Native(native_case_lambda)),
             ("if", Function::Native(native_if)),
             ("+", Function::Native(native_plus)),
             ("-", Function::Native(native_minus)),
@@ -156,10 +743,122 @@ impl Environment {
             ("<", Function::Native(native_lessthan)),
             (">", Function::Native(native_greaterthan)),
             ("=", Function::Native(native_equal)),
+            ("<=", Function::Native(native_lessthan_or_equal)),
+            (">=", Function::Native(native_greaterthan_or_equal)),
+            ("max", Function::Native(native_max)),
+            ("min", Function::Native(native_min)),
+            ("quotient", Function::Native(native_quotient)),
+            ("remainder", Function::Native(native_remainder)),
+            ("modulo", Function::Native(native_modulo)),
+            ("gcd", Function::Native(native_gcd)),
+            ("lcm", Function::Native(native_lcm)),
+            ("abs", Function::Native(native_abs)),
+            ("floor", Function::Native(native_floor)),
+            ("ceiling", Function::Native(native_ceiling)),
+            ("round", Function::Native(native_round)),
+            ("truncate", Function::Native(native_truncate)),
+            ("numerator", Function::Native(native_numerator)),
+            ("denominator", Function::Native(native_denominator)),
+            ("exact", Function::Native(native_exact)),
+            ("inexact", Function::Native(native_inexact)),
+            ("exact->inexact", Function::Native(native_inexact)),
+            ("inexact->exact", Function::Native(native_exact)),
+            ("sqrt", Function::Native(native_sqrt)),
+            ("expt", Function::Native(native_expt)),
+            ("exp", Function::Native(native_exp)),
+            ("log", Function::Native(native_log)),
+            ("sin", Function::Native(native_sin)),
+            ("cos", Function::Native(native_cos)),
+            ("tan", Function::Native(native_tan)),
+            ("asin", Function::Native(native_asin)),
+            ("acos", Function::Native(native_acos)),
+            ("atan", Function::Native(native_atan)),
+            ("number?", Function::Native(native_is_number)),
+            ("complex?", Function::Native(native_is_number)),
+            ("real?", Function::Native(native_is_number)),
+            ("rational?", Function::Native(native_is_rational)),
+            ("integer?", Function::Native(native_is_integer)),
+            ("exact?", Function::Native(native_is_exact)),
+            ("inexact?", Function::Native(native_is_inexact)),
+            ("exact-integer?", Function::Native(native_is_exact_integer)),
+            ("nan?", Function::Native(native_is_nan)),
+            ("zero?", Function::Native(native_is_zero)),
+            ("positive?", Function::Native(native_is_positive)),
+            ("negative?", Function::Native(native_is_negative)),
+            ("odd?", Function::Native(native_is_odd)),
+            ("even?", Function::Native(native_is_even)),
+            ("char?", Function::Native(native_is_char)),
+            ("char->integer", Function::Native(native_char_to_integer)),
+            ("integer->char", Function::Native(native_integer_to_char)),
+            ("char-upcase", Function::Native(native_char_upcase)),
+            ("char-downcase", Function::Native(native_char_downcase)),
+            ("char-foldcase", Function::Native(native_char_foldcase)),
+            ("char-alphabetic?", Function::Native(native_is_char_alphabetic)),
+            ("char-numeric?", Function::Native(native_is_char_numeric)),
+            ("char-whitespace?", Function::Native(native_is_char_whitespace)),
+            ("char-upper-case?", Function::Native(native_is_char_upper_case)),
+            ("char-lower-case?", Function::Native(native_is_char_lower_case)),
+            ("char=?", Function::Native(native_char_equal)),
+            ("char<?", Function::Native(native_char_lessthan)),
+            ("char>?", Function::Native(native_char_greaterthan)),
+            ("char<=?", Function::Native(native_char_lessthan_or_equal)),
+            ("char>=?", Function::Native(native_char_greaterthan_or_equal)),
+            ("char-ci=?", Function::Native(native_char_ci_equal)),
+            ("char-ci<?", Function::Native(native_char_ci_lessthan)),
+            ("char-ci>?", Function::Native(native_char_ci_greaterthan)),
+            ("char-ci<=?", Function::Native(native_char_ci_lessthan_or_equal)),
+            ("char-ci>=?", Function::Native(native_char_ci_greaterthan_or_equal)),
+            ("string?", Function::Native(native_is_string)),
+            ("string", Function::Native(native_string)),
+            ("make-string", Function::Native(native_make_string)),
+            ("string-length", Function::Native(native_string_length)),
+            ("string-ref", Function::Native(native_string_ref)),
+            ("substring", Function::Native(native_substring)),
+            ("string-copy", Function::Native(native_string_copy)),
+            ("string-append", Function::Native(native_string_append)),
+            ("string->list", Function::Native(native_string_to_list)),
+            ("list->string", Function::Native(native_list_to_string)),
+            ("string->symbol", Function::Native(native_string_to_symbol)),
+            ("symbol->string", Function::Native(native_symbol_to_string)),
+            ("number->string", Function::Native(native_number_to_string)),
+            ("string->number", Function::Native(native_string_to_number)),
+            ("string=?", Function::Native(native_string_equal)),
+            ("string<?", Function::Native(native_string_lessthan)),
+            ("string>?", Function::Native(native_string_greaterthan)),
+            ("string<=?", Function::Native(native_string_lessthan_or_equal)),
+            ("string>=?", Function::Native(native_string_greaterthan_or_equal)),
+            ("string-upcase", Function::Native(native_string_upcase)),
+            ("string-downcase", Function::Native(native_string_downcase)),
+            ("string-split", Function::Native(native_string_split)),
+            ("string-index", Function::Native(native_string_index)),
+            ("vector?", Function::Native(native_is_vector)),
+            ("make-vector", Function::Native(native_make_vector)),
+            ("vector", Function::Native(native_vector)),
+            ("vector-length", Function::Native(native_vector_length)),
+            ("vector-ref", Function::Native(native_vector_ref)),
+            ("vector-set!", Function::Native(native_vector_set)),
+            ("vector->list", Function::Native(native_vector_to_list)),
+            ("vector-copy", Function::Native(native_vector_copy)),
+            ("vector-fill!", Function::Native(native_vector_fill)),
+            ("list->vector", Function::Native(native_list_to_vector)),
+            ("vector-append", Function::Native(native_vector_append)),
+            ("vector-map", Function::Native(native_vector_map)),
+            ("vector-for-each", Function::Native(native_vector_for_each)),
             ("and", Function::Native(native_and)),
             ("or", Function::Native(native_or)),
             ("null?", Function::Native(native_null)),
             ("list", Function::Native(native_list)),
+            ("pair?", Function::Native(native_is_pair)),
+            ("list?", Function::Native(native_is_list)),
+            ("eq?", Function::Native(native_eqv)),
+            ("eqv?", Function::Native(native_eqv)),
+            ("equal?", Function::Native(native_equal_values)),
+            ("memq", Function::Native(native_memv)),
+            ("memv", Function::Native(native_memv)),
+            ("member", Function::Native(native_member)),
+            ("assq", Function::Native(native_assv)),
+            ("assv", Function::Native(native_assv)),
+            ("assoc", Function::Native(native_assoc)),
             ("car", Function::Native(native_car)),
             ("cdr", Function::Native(native_cdr)),
             ("cons", Function::Native(native_cons)),
@@ -167,23 +866,45 @@ impl Environment {
             ("quote", Function::Native(native_quote)),
             ("quasiquote", Function::Native(native_quasiquote)),
             ("error", Function::Native(native_error)),
+            ("raise", Function::Native(native_raise)),
+            ("raise-continuable", Function::Native(native_raise_continuable)),
+            ("with-exception-handler", Function::Native(native_with_exception_handler)),
+            ("values", Function::Native(native_values)),
+            ("call-with-values", Function::Native(native_call_with_values)),
+            ("exact-integer-sqrt", Function::Native(native_exact_integer_sqrt)),
+            ("floor/", Function::Native(native_floor_division)),
+            ("truncate/", Function::Native(native_truncate_division)),
+            ("dynamic-wind", Function::Native(native_dynamic_wind)),
+            ("make-parameter", Function::Native(native_make_parameter)),
+            ("error-object?", Function::Native(native_is_error_object)),
+            ("error-object-message", Function::Native(native_error_object_message)),
+            ("error-object-irritants", Function::Native(native_error_object_irritants)),
             ("apply", Function::Native(native_apply)),
             ("eval", Function::Native(native_eval)),
+            ("macroexpand", Function::Native(native_macroexpand)),
+            ("macroexpand-1", Function::Native(native_macroexpand_1)),
             ("write", Function::Native(native_write)),
             ("display", Function::Native(native_display)),
             ("displayln", Function::Native(native_displayln)),
             ("print", Function::Native(native_print)),
             ("newline", Function::Native(native_newline)),
+            ("gensym", Function::Native(native_gensym)),
+            ("generate-temporaries", Function::Native(native_generate_temporaries)),
             ];
         for item in predefined_functions.iter() {
             let (name, ref func) = *item;
             env.define(name.to_string(), Value::Procedure(func.clone())).unwrap();
         }
-        Rc::new(RefCell::new(env))
+        let globals: Vec<String> = env.values.keys().cloned().collect();
+        let env = Rc::new(RefCell::new(env));
+        let toplevel = Environment::new_child(env.clone());
+        let evaluator = MacroEvaluator { toplevel: Rc::downgrade(&toplevel) };
+        env.borrow_mut().expander = Some(Rc::new(Expander::new(&globals, Box::new(evaluator))));
+        toplevel
     }
 
     fn new_child(parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
-        let env = Environment { parent: Some(parent), values: HashMap::new() };
+        let env = Environment { parent: Some(parent), values: HashMap::new(), expander: None, handlers: vec![], winds: vec![], spans: SpanTable::default() };
         Rc::new(RefCell::new(env))
     }
 
@@ -209,7 +930,7 @@ impl Environment {
             // recurse up the environment tree until a value is found or the end is reached
             match self.parent {
                 Some(ref parent) => parent.borrow_mut().set(key, value),
-                None => runtime_error!("Can't set! an undefined variable: {:?}", key)
+                None => runtime_error!(UnboundVariable(Value::Symbol(key)); "Can't set! an undefined variable: {:?}", key)
             }
         }
     }
@@ -227,6 +948,11 @@ impl Environment {
         }
     }
 
+    // The body of a procedure, from the code that makes it
+    fn body(values: &[Value], env: &Rc<RefCell<Environment>>) -> Rc<Vec<Value>> {
+        Environment::get_root(env.clone()).borrow_mut().spans.copy(values)
+    }
+
     fn get_root(env_ref: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
         let env = env_ref.borrow();
         match env.parent {
@@ -234,153 +960,318 @@ impl Environment {
             None => env_ref.clone()
         }
     }
+
+    // where a program's top-level definitions go, which is the child of the root that env is in
+    fn get_toplevel(env_ref: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let env = env_ref.borrow();
+        match env.parent {
+            Some(ref parent) if parent.borrow().parent.is_some() => Environment::get_toplevel(parent.clone()),
+            _ => env_ref.clone()
+        }
+    }
+
+    // the expander is kept in the root, with the macros defined at the top level
+    fn expander(env_ref: Rc<RefCell<Environment>>) -> Rc<Expander> {
+        let root = Environment::get_root(env_ref);
+        let expander = root.borrow().expander.clone();
+        expander.expect("The root environment must have an expander")
+    }
+}
+
+// runs the transformers of procedural macros at the top level, while the expander is expanding.
+// the root environment keeps the expander, so this only has a weak reference back to the top level
+struct MacroEvaluator {
+    toplevel: Weak<RefCell<Environment>>,
+}
+
+struct MacroProcedure {
+    procedure: Function,
+    toplevel: Weak<RefCell<Environment>>,
+}
+
+impl Evaluator for MacroEvaluator {
+    fn evaluate(&self, expr: &Node) -> Result<Rc<dyn Transformer>, MacroError> {
+        let toplevel = self.toplevel.upgrade().expect("The expander must not outlive its environment");
+        match try!(evaluate_value(&Value::from_node(expr), toplevel)) {
+            Value::Procedure(procedure) => Ok(Rc::new(MacroProcedure { procedure, toplevel: self.toplevel.clone() })),
+            v => Err(MacroError { message: format!("A macro transformer must be a procedure: {:?}", v) })
+        }
+    }
+}
+
+impl Transformer for MacroProcedure {
+    fn call(&self, args: &[Node], renaming: Option<Rc<Renaming>>) -> Result<Node, MacroError> {
+        let toplevel = self.toplevel.upgrade().expect("The expander must not outlive its environment");
+        let mut values = Value::from_nodes(args);
+        if let Some(renaming) = renaming {
+            values.push(Value::Procedure(Function::Rename(renaming.clone())));
+            values.push(Value::Procedure(Function::Compare(renaming)));
+        }
+        let res = try!(apply_function_to_values(&self.procedure, values, toplevel));
+        Ok(try!(res.to_node()))
+    }
 }
 
 fn evaluate_values(values: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     let mut res = null!();
     for v in values.iter() {
-        res = try!(evaluate_value(v, env.clone()));
+        res = try!(evaluate_tail(v, env.clone()));
     }
     Ok(res)
 }
 
+// Evaluates value where a single value is expected. Getting some other number of them is an error
+// in the expression that gave them.
 fn evaluate_value(value: &Value, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    match value {
-        &Value::Symbol(ref v) => {
-            match env.borrow().get(v) {
-                Some(val) => Ok(val),
-                None => runtime_error!("Identifier not found: {:?}", value)
-            }
+    match try!(evaluate_tail(value, env.clone())) {
+        Value::Values(vals) => Err(multiple_values(vals, value, &env)),
+        val => Ok(val)
+    }
+}
+
+// Likewise kept out of evaluate_value
+fn multiple_values(vals: Vec<Value>, value: &Value, env: &Rc<RefCell<Environment>>) -> RuntimeError {
+    let e = one_value(Value::Values(vals)).unwrap_err();
+    match *value {
+        Value::List(ref values) => locate(e, values, env),
+        _ => e
+    }
+}
+
+fn one_value(val: Value) -> Result<Value, RuntimeError> {
+    match val {
+        Value::Values(vals) => {
+            let count = vals.len();
+            let vals = Value::from_vec(vals);
+            runtime_error!(Arity(vals.clone()); "Expected one value, but got {}: {:?}", count, vals)
         },
-        &Value::Integer(v) => Ok(Value::Integer(v)),
-        &Value::Boolean(v) => Ok(Value::Boolean(v)),
-        &Value::String(ref v) => Ok(Value::String(v.clone())),
-        &Value::List(ref vec) => {
+        val => Ok(val)
+    }
+}
+
+// Evaluates value where it can give any number of values: in a tail position, which passes them on,
+// or where they're thrown away
+fn evaluate_tail(value: &Value, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    match *value {
+        Value::Symbol(ref v) => lookup(v, value, &env),
+        Value::Number(ref v) => Ok(Value::Number(v.clone())),
+        Value::Boolean(v) => Ok(Value::Boolean(v)),
+        Value::Char(v) => Ok(Value::Char(v)),
+        Value::String(ref v) => Ok(Value::String(v.clone())),
+        Value::List(ref vec) => {
             if vec.len() > 0 {
                 evaluate_expression(vec, env.clone())
             } else {
                 Ok(null!())
             }
         },
-        &Value::Procedure(ref v) => Ok(Value::Procedure(v.clone())),
-        &Value::Macro(ref a, ref b) => Ok(Value::Macro(a.clone(), b.clone())),
+        Value::DottedList(_, _) => improper(value),
+        Value::Vector(ref v) => Ok(Value::Vector(v.clone())),
+        Value::Procedure(ref v) => Ok(Value::Procedure(v.clone())),
+        Value::ErrorObject(ref e) => Ok(Value::ErrorObject(e.clone())),
+        Value::Values(ref vals) => Ok(Value::Values(vals.clone())),
+    }
+}
+
+// The errors are made outside evaluate_tail, which every nested expression goes through, to keep its
+// stack frame small
+fn lookup(name: &String, value: &Value, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    match env.borrow().get(name) {
+        Some(val) => Ok(val),
+        None => {
+            // an identifier read from the source is where the error is
+            let mut e = RuntimeError::new(format!("Identifier not found: {:?}", value)).of(ErrorKind::UnboundVariable, Some(value.clone()));
+            e.location = Environment::get_root(env.clone()).borrow().spans.identifier(value);
+            Err(e)
+        }
     }
 }
 
-fn quote_value(value: &Value, quasi: bool, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn improper(value: &Value) -> Result<Value, RuntimeError> {
+    runtime_error!("Can't evaluate an improper list: {:?}", value)
+}
+
+// Quasiquotes a value, evaluating what is unquoted at depth 1. Each nested quasiquote goes a level
+// deeper and each unquote a level back out, so inner unquotes are kept for the inner quasiquote
+fn quasiquote_value(value: &Value, depth: usize, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     match value {
-        &Value::Symbol(ref v) => Ok(Value::Symbol(v.clone())),
-        &Value::Integer(v) => Ok(Value::Integer(v)),
-        &Value::Boolean(v) => Ok(Value::Boolean(v)),
-        &Value::String(ref v) => Ok(Value::String(v.clone())),
-        &Value::List(ref vec) => {
-            // check if we are unquoting inside a quasiquote
-            if quasi && vec.len() > 0 && vec[0] == Value::Symbol("unquote".to_string()) {
-                if vec.len() != 2 {
-                    runtime_error!("Must supply exactly one argument to unquote: {:?}", vec);
+        Value::List(vec) => {
+            if let Some((name, operand)) = quasiquote_form(vec) {
+                return match name {
+                    "unquote" if depth == 1 => evaluate_value(operand, env),
+                    "unquote-splicing" if depth == 1 => runtime_error!("unquote-splicing must be inside a list or vector: {:?}", value),
+                    _ => {
+                        let depth = if name == "quasiquote" { depth + 1 } else { depth - 1 };
+                        let inner = try!(quasiquote_value(operand, depth, env));
+                        Ok(Value::from_vec(vec![Value::Symbol(name.to_string()), inner]))
+                    }
                 }
-                evaluate_value(&vec[1], env.clone())
-            } else {
-                let res: Result<Vec<Value>, RuntimeError> = vec.iter().map(|v| quote_value(v, quasi, env.clone())).collect();
-                let new_vec = try!(res);
-                Ok(Value::List(new_vec))
             }
+            // the parser reads (a . ,b) as (a unquote b), so an unquote second from the end is the tail
+            let split = vec.len().saturating_sub(2);
+            if split > 0 && quasiquote_form(&vec[split..]).is_some() {
+                let items = try!(quasiquote_items(&vec[..split], depth, env.clone()));
+                let tail = try!(quasiquote_value(&Value::from_vec(vec[split..].to_vec()), depth, env));
+                return Ok(Value::from_vec_dotted(items, tail))
+            }
+            Ok(Value::from_vec(try!(quasiquote_items(vec, depth, env))))
         },
-        &Value::Procedure(ref v) => Ok(Value::Procedure(v.clone())),
-        &Value::Macro(ref a, ref b) => Ok(Value::Macro(a.clone(), b.clone())),
+        Value::DottedList(vec, tail) => {
+            let items = try!(quasiquote_items(vec, depth, env.clone()));
+            let tail = try!(quasiquote_value(tail, depth, env));
+            Ok(Value::from_vec_dotted(items, tail))
+        },
+        Value::Vector(vec) => {
+            let items = try!(quasiquote_items(&vec.borrow(), depth, env));
+            Ok(Value::from_vector(items))
+        },
+        _ => Ok(value.clone())
+    }
+}
+
+// Quasiquotes the elements of a list or vector, splicing in the lists that unquote-splicing gives
+fn quasiquote_items(values: &[Value], depth: usize, env: Rc<RefCell<Environment>>) -> Result<Vec<Value>, RuntimeError> {
+    let mut items = vec![];
+    for value in values.iter() {
+        match value {
+            Value::List(vec) if depth == 1 && quasiquote_form(vec).map(|(name, _)| name) == Some("unquote-splicing") => {
+                match try!(evaluate_value(&vec[1], env.clone())) {
+                    Value::List(spliced) => items.extend(spliced.iter().cloned()),
+                    v => runtime_error!("unquote-splicing must produce a list: {:?}", v)
+                }
+            },
+            _ => items.push(try!(quasiquote_value(value, depth, env.clone())))
+        }
+    }
+    Ok(items)
+}
+
+// Matches (quasiquote x), (unquote x) and (unquote-splicing x)
+fn quasiquote_form(vec: &[Value]) -> Option<(&'static str, &Value)> {
+    if vec.len() != 2 {
+        return None
+    }
+    match vec[0] {
+        Value::Symbol(ref s) if s == "quasiquote" => Some(("quasiquote", &vec[1])),
+        Value::Symbol(ref s) if s == "unquote" => Some(("unquote", &vec[1])),
+        Value::Symbol(ref s) if s == "unquote-splicing" => Some(("unquote-splicing", &vec[1])),
+        _ => None
     }
 }
 
+// An error is located at the innermost expression it came out of that has a span
 fn evaluate_expression(values: &Vec<Value>, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    match evaluate_call(values, env.clone()) {
+        Err(e) => Err(locate(e, values, &env)),
+        res => res
+    }
+}
+
+fn locate(mut e: RuntimeError, values: &Vec<Value>, env: &Rc<RefCell<Environment>>) -> RuntimeError {
+    if e.location.is_none() {
+        e.location = Environment::get_root(env.clone()).borrow().spans.get(values);
+    }
+    e
+}
+
+fn evaluate_call(values: &Vec<Value>, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if values.len() == 0 {
         runtime_error!("Can't evaluate an empty expression: {:?}", values);
     }
     let first = try!(evaluate_value(&values[0], env.clone()));
     match first {
         Value::Procedure(f) => apply_function(&f, &values[1..], env.clone()),
-        Value::Macro(a, b) => expand_macro(a, b, &values[1..], env.clone()),
-        _ => runtime_error!("First element in an expression must be a procedure: {:?}", first)
+        _ => runtime_error!(WrongType(first); "First element in an expression must be a procedure: {:?}", first)
     }
 }
 
 fn apply_function(func: &Function, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    match func {
-        &Function::Native(native_fn) => {
+    match *func {
+        Function::Native(native_fn) => {
             native_fn(args, env)
         },
-        &Function::Scheme(ref arg_names, ref body, ref func_env) => {
-            if arg_names.len() != args.len() {
-                runtime_error!("Must supply exactly {} arguments to function: {:?}", arg_names.len(), args);
+        Function::Scheme(ref params, ref body, ref func_env) => {
+            apply_scheme_function(params, body, func_env, args, env)
+        },
+        Function::CaseLambda(ref clauses, ref func_env) => {
+            // use the first clause that accepts this many arguments
+            match clauses.iter().find(|&(params, _)| params.accepts(args.len())) {
+                Some((params, body)) => apply_scheme_function(params, body, func_env, args, env),
+                None => runtime_error!("No case-lambda clause accepts {} arguments: {:?}", args.len(), args)
             }
-
-            // create a new, child environment for the procedure and define the arguments as local variables
-            let proc_env = Environment::new_child(func_env.clone());
-            for (name, arg) in arg_names.iter().zip(args.iter()) {
-                let val = try!(evaluate_value(arg, env.clone()));
-                try!(proc_env.borrow_mut().define(name.clone(), val));
+        },
+        Function::Rename(ref renaming) => {
+            if args.len() != 1 {
+                runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to rename: {:?}", args);
             }
-
-            // evaluate procedure body with new environment with procedure environment as parent
-            let inner_env = Environment::new_child(proc_env);
-            evaluate_values(&body, inner_env)
+            match try!(evaluate_value(&args[0], env)) {
+                Value::Symbol(name) => Ok(Value::Symbol(renaming.rename(&name))),
+                v => runtime_error!("Expected a symbol to rename: {:?}", v)
+            }
+        },
+        Function::Compare(ref renaming) => {
+            if args.len() != 2 {
+                runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to compare: {:?}", args);
+            }
+            match (try!(evaluate_value(&args[0], env.clone())), try!(evaluate_value(&args[1], env))) {
+                (Value::Symbol(a), Value::Symbol(b)) => Ok(Value::Boolean(renaming.compare(&a, &b))),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        Function::Parameter(ref parameter) => {
+            if !args.is_empty() {
+                runtime_error!(Arity(Value::from_vec(args.to_vec())); "A parameter takes no arguments: {:?}", args);
+            }
+            Ok(parameter.value.borrow().clone())
         }
     }
 }
 
-fn expand_macro(arg_names: Vec<String>, body: Vec<Value>, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    let mut substitutions = HashMap::new();
-    for (name, arg) in arg_names.iter().zip(args.iter()) {
-        substitutions.insert(name.clone(), arg.clone());
+fn apply_scheme_function(params: &Params, body: &[Value], func_env: &Rc<RefCell<Environment>>, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    try!(params.check_arity(args));
+
+    // create a new, child environment for the procedure and define the arguments as local variables
+    let mut vals = Vec::with_capacity(args.len());
+    for arg in args {
+        vals.push(try!(evaluate_value(arg, env.clone())));
     }
-    let expanded = try!(expand_macro_substitute_values(&body, substitutions));
-    evaluate_values(&expanded, env)
-}
+    let proc_env = try!(params.bind(vals, func_env.clone()));
 
-fn expand_macro_substitute_values(values: &[Value], substitutions: HashMap<String,Value>) -> Result<Vec<Value>, RuntimeError> {
-    values.iter().map(|n| expand_macro_substitute_value(n, substitutions.clone())).collect()
+    // evaluate procedure body with new environment with procedure environment as parent
+    let inner_env = Environment::new_child(proc_env);
+    evaluate_values(body, inner_env)
 }
 
-fn expand_macro_substitute_value(value: &Value, substitutions: HashMap<String,Value>) -> Result<Value, RuntimeError> {
-    let res = match value {
-        &Value::Symbol(ref s) => {
-            if substitutions.contains_key(s) {
-                substitutions.get(s).unwrap().clone()
-            } else {
-                Value::Symbol(s.clone())
-            }
-        },
-        &Value::List(ref l) => {
-            Value::List(try!(expand_macro_substitute_values(&l, substitutions)))
-        },
-        other => other.clone()
-    };
-    Ok(res)
+// Natives that call back into Scheme already have evaluated arguments, so quote them to pass them through unchanged
+fn apply_function_to_values(func: &Function, values: Vec<Value>, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let args: Vec<Value> = values.into_iter().map(|v| Value::from_vec(vec![Value::Symbol("quote".to_string()), v])).collect();
+    apply_function(func, &args, env)
 }
 
 fn native_define(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to define: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to define: {:?}", args);
     }
     let (name, val) = match args[0] {
         Value::Symbol(ref name) => {
             let val = try!(evaluate_value(&args[1], env.clone()));
             (name, val)
         },
-        Value::List(ref list) => {
+        Value::List(ref list) | Value::DottedList(ref list, _) => {
             // if a list is the second argument, it's shortcut for defining a procedure
             // (define (<name> <args>) <body>) == (define <name> (lambda (<args>) <body>)
             if list.len() < 1 {
-                runtime_error!("Must supply at least one argument in list part of define: {:?}", list);
+                runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument in list part of define: {:?}", list);
             }
             match list[0] {
                 Value::Symbol(ref name) => {
-                    let res: Result<Vec<String>, RuntimeError> = (&list[1..]).iter().map(|i| match *i {
-                        Value::Symbol(ref s) => Ok(s.clone()),
-                        _ => runtime_error!("Unexpected argument in define arguments: {:?}", i)
-                    }).collect();
-                    let arg_names = try!(res);
-                    let body = (&args[1..]).to_vec();
-                    let val = Value::Procedure(Function::Scheme(arg_names, body, env.clone()));
+                    let formals = match args[0] {
+                        Value::DottedList(_, ref tail) => Value::from_vec_dotted(list[1..].to_vec(), (**tail).clone()),
+                        _ => Value::from_vec(list[1..].to_vec())
+                    };
+                    let params = try!(Params::parse(&formals));
+                    let body = Environment::body(&args[1..], &env);
+                    let val = Value::Procedure(Function::Scheme(Rc::new(params), body, env.clone()));
                     (name, val)
                 },
                 _ => runtime_error!("Must supply a symbol in list part of define: {:?}", list)
@@ -393,47 +1284,19 @@ fn native_define(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value,
     Ok(null!())
 }
 
-fn native_define_syntax_rule(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to define-syntax-rule: {:?}", args);
-    }
-    let (name, val) = match args[0] {
-        Value::List(ref list) => {
-            // (define-syntax-rule (<name> <args>) <template>)
-            if list.len() < 1 {
-                runtime_error!("Must supply at least one argument in list part of define-syntax-rule: {:?}", list);
-            }
-            match list[0] {
-                Value::Symbol(ref name) => {
-                    let res: Result<Vec<String>, RuntimeError> = (&list[1..]).iter().map(|i| match *i {
-                        Value::Symbol(ref s) => Ok(s.clone()),
-                        _ => runtime_error!("Unexpected argument in define-syntax-rule arguments: {:?}", i)
-                    }).collect();
-                    let arg_names = try!(res);
-                    let body = (&args[1..]).to_vec();
-                    let val = Value::Macro(arg_names, body);
-                    (name, val)
-                },
-                _ => runtime_error!("Must supply a symbol in list part of define: {:?}", list)
-            }
-        },
-        _ => runtime_error!("Unexpected value for pattern in define-syntax-rule: {:?}", args)
-    };
-
-    try!(env.borrow_mut().define(name.clone(), val));
-    Ok(null!())
-}
-
-fn native_begin(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() < 1 {
-        runtime_error!("Must supply at least one argument to begin: {:?}", args);
+fn native_begin(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument to begin: {:?}", args);
     }
     evaluate_values(args, env)
 }
 
 fn native_let(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to let: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to let: {:?}", args);
+    }
+    if let Value::Symbol(ref name) = args[0] {
+        return evaluate_named_let(name, &args[1..], env);
     }
 
     // create a new, child environment for the let expression and define the arguments as local variables
@@ -466,9 +1329,202 @@ fn native_let(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, Ru
     evaluate_values(body, inner_env)
 }
 
+// (let <name> ((<var> <init>) ...) <body>) binds <name> to a procedure with the body, and calls it with the inits
+fn evaluate_named_let(name: &str, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least three arguments to a named let: {:?}", args);
+    }
+    let bindings = try!(evaluate_bindings("let", &args[0]));
+    let vars = Value::from_vec(bindings.iter().map(|(var, _)| Value::Symbol(var.clone())).collect());
+    let res: Result<Vec<Value>, RuntimeError> = bindings.iter().map(|&(_, init)| evaluate_value(init, env.clone())).collect();
+    let values = try!(res);
+
+    let loop_env = Environment::new_child(env);
+    let func = Function::Scheme(Rc::new(try!(Params::parse(&vars))), Environment::body(&args[1..], &loop_env), loop_env.clone());
+    try!(loop_env.borrow_mut().define(name.to_string(), Value::Procedure(func.clone())));
+    apply_function_to_values(&func, values, loop_env)
+}
+
+// Splits a list of (<name> <expr>) bindings into names and unevaluated expressions
+fn evaluate_bindings<'a>(form: &str, bindings: &'a Value) -> Result<Vec<(String, &'a Value)>, RuntimeError> {
+    match *bindings {
+        Value::List(ref list) => list.iter().map(|binding| match *binding {
+            Value::List(ref entry) if entry.len() == 2 => match entry[0] {
+                Value::Symbol(ref name) => Ok((name.clone(), &entry[1])),
+                _ => runtime_error!("Unexpected value for name in {}: {:?}", form, entry[0])
+            },
+            _ => runtime_error!("{} expression values must have exactly 2 params: {:?}", form, binding)
+        }).collect(),
+        _ => runtime_error!("Unexpected value for expressions in {}: {:?}", form, bindings)
+    }
+}
+
+fn native_let_star(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to let*: {:?}", args);
+    }
+
+    // each binding gets its own environment, so it can see the ones before it
+    let mut let_env = env;
+    for (name, init) in try!(evaluate_bindings("let*", &args[0])) {
+        let val = try!(evaluate_value(init, let_env.clone()));
+        let_env = Environment::new_child(let_env);
+        try!(let_env.borrow_mut().define(name, val));
+    }
+    let inner_env = Environment::new_child(let_env);
+    evaluate_values(&args[1..], inner_env)
+}
+
+// letrec and letrec*: the inits are evaluated in order, in the environment they're being defined in,
+// so procedures can refer to each other
+fn native_letrec(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to letrec: {:?}", args);
+    }
+    let let_env = Environment::new_child(env);
+    for (name, init) in try!(evaluate_bindings("letrec", &args[0])) {
+        let val = try!(evaluate_value(init, let_env.clone()));
+        try!(let_env.borrow_mut().define(name, val));
+    }
+    let inner_env = Environment::new_child(let_env);
+    evaluate_values(&args[1..], inner_env)
+}
+
+fn native_cond(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(try!(evaluate_cond(args, env)).unwrap_or(null!()))
+}
+
+// Evaluates the first clause whose test is true, if there is one
+fn evaluate_cond(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Option<Value>, RuntimeError> {
+    for (i, clause) in args.iter().enumerate() {
+        let clause = match *clause {
+            Value::List(ref clause) if clause.len() > 0 => clause,
+            _ => runtime_error!("Unexpected clause in cond: {:?}", clause)
+        };
+        let test = match clause[0] {
+            Value::Symbol(ref s) if s == "else" => {
+                if i != args.len() - 1 {
+                    runtime_error!("else must be the last clause in cond");
+                }
+                Value::Boolean(true)
+            },
+            ref test => try!(evaluate_value(test, env.clone()))
+        };
+        if test == Value::Boolean(false) {
+            continue;
+        }
+        return evaluate_clause_body(test, &clause[1..], env).map(Some);
+    }
+    Ok(None)
+}
+
+// The body of a cond or case clause, which is either expressions, nothing (for cond), or => and a
+// procedure to call with the value that selected the clause
+fn evaluate_clause_body(selector: Value, body: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    match body.first() {
+        None => Ok(selector),
+        Some(Value::Symbol(s)) if s == "=>" => {
+            if body.len() != 2 {
+                runtime_error!("Must supply exactly one procedure after =>: {:?}", body);
+            }
+            match try!(evaluate_value(&body[1], env.clone())) {
+                Value::Procedure(f) => apply_function_to_values(&f, vec![selector], env),
+                v => runtime_error!(WrongType(v); "Expected a procedure after =>: {:?}", v)
+            }
+        },
+        Some(_) => evaluate_values(body, env)
+    }
+}
+
+fn native_case(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument to case: {:?}", args);
+    }
+    let key = try!(evaluate_value(&args[0], env.clone()));
+    for clause in args[1..].iter() {
+        let clause = match *clause {
+            Value::List(ref clause) if clause.len() > 1 => clause,
+            _ => runtime_error!("Unexpected clause in case: {:?}", clause)
+        };
+        let matched = match clause[0] {
+            Value::Symbol(ref s) if s == "else" => true,
+            Value::List(ref data) => data.iter().any(|datum| values_eqv(&key, datum)),
+            _ => runtime_error!("Unexpected data in case clause: {:?}", clause[0])
+        };
+        if matched {
+            return evaluate_clause_body(key, &clause[1..], env);
+        }
+    }
+    Ok(null!())
+}
+
+fn native_when(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_when_unless(args, env, "when", true)
+}
+
+fn native_unless(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_when_unless(args, env, "unless", false)
+}
+
+fn evaluate_when_unless(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, run_if: bool) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to {}: {:?}", name, args);
+    }
+    let condition = try!(evaluate_value(&args[0], env.clone()));
+    if (condition != Value::Boolean(false)) == run_if {
+        evaluate_values(&args[1..], env)
+    } else {
+        Ok(null!())
+    }
+}
+
+fn native_do(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    // (do ((<var> <init> <step>) ...) (<test> <expr> ...) <command> ...)
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to do: {:?}", args);
+    }
+    let specs = match args[0] {
+        Value::List(ref specs) => try!(specs.iter().map(|spec| match *spec {
+            Value::List(ref spec) if spec.len() == 2 || spec.len() == 3 => match spec[0] {
+                Value::Symbol(ref var) => Ok((var.clone(), &spec[1], spec.get(2))),
+                _ => runtime_error!("Unexpected value for variable in do: {:?}", spec[0])
+            },
+            _ => runtime_error!("Unexpected variable spec in do: {:?}", spec)
+        }).collect::<Result<Vec<_>, RuntimeError>>()),
+        _ => runtime_error!("Unexpected value for variables in do: {:?}", args[0])
+    };
+    let exit = match args[1] {
+        Value::List(ref exit) if exit.len() > 0 => exit,
+        _ => runtime_error!("Must supply a test in do: {:?}", args[1])
+    };
+    let commands = &args[2..];
+
+    // every iteration gets a fresh environment, so closures made in the body keep their own variables
+    let mut loop_env = Environment::new_child(env.clone());
+    for &(ref var, init, _) in specs.iter() {
+        let val = try!(evaluate_value(init, env.clone()));
+        try!(loop_env.borrow_mut().define(var.clone(), val));
+    }
+    loop {
+        if try!(evaluate_value(&exit[0], loop_env.clone())) != Value::Boolean(false) {
+            return evaluate_values(&exit[1..], loop_env);
+        }
+        try!(evaluate_values(commands, loop_env.clone()));
+        let next_env = Environment::new_child(env.clone());
+        for &(ref var, _, step) in specs.iter() {
+            let val = match step {
+                Some(step) => try!(evaluate_value(step, loop_env.clone())),
+                None => try!(evaluate_value(&Value::Symbol(var.clone()), loop_env.clone()))
+            };
+            try!(next_env.borrow_mut().define(var.clone(), val));
+        }
+        loop_env = next_env;
+    }
+}
+
 fn native_set(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to set!: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to set!: {:?}", args);
     }
     let name = match args[0] {
         Value::Symbol(ref x) => x,
@@ -481,146 +1537,899 @@ fn native_set(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, Ru
 
 fn native_lambda(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to lambda: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to lambda: {:?}", args);
+    }
+    let params = try!(Params::parse(&args[0]));
+    let body = Environment::body(&args[1..], &env);
+    Ok(Value::Procedure(Function::Scheme(Rc::new(params), body, env.clone())))
+}
+
+fn native_case_lambda(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    // (case-lambda (<params> <body>) ...)
+    let res: Result<Vec<(Params, Vec<Value>)>, RuntimeError> = args.iter().map(|clause| match *clause {
+        Value::List(ref l) if l.len() >= 2 => Ok((try!(Params::parse(&l[0])), l[1..].to_vec())),
+        _ => runtime_error!("Unexpected clause in case-lambda: {:?}", clause)
+    }).collect();
+    Ok(Value::Procedure(Function::CaseLambda(Rc::new(try!(res)), env.clone())))
+}
+
+fn native_if(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 3 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly three arguments to if: {:?}", args);
+    }
+    let condition = try!(evaluate_value(&args[0], env.clone()));
+    match condition {
+        Value::Boolean(false) => evaluate_tail(&args[2], env.clone()),
+        _ => evaluate_tail(&args[1], env.clone())
+    }
+}
+
+fn evaluate_numbers(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Vec<Number>, RuntimeError> {
+    let mut nums = Vec::with_capacity(args.len());
+    for n in args {
+        match try!(evaluate_value(n, env.clone())) {
+            Value::Number(x) => nums.push(x),
+            v => runtime_error!(WrongType(v); "Expected a number value: {:?}", v)
+        }
+    }
+    Ok(nums)
+}
+
+fn evaluate_unary_number(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, op: fn(&Number) -> Result<Number, NumberError>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    Ok(Value::Number(try!(op(&nums[0]))))
+}
+
+fn native_exact_integer_sqrt(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to exact-integer-sqrt: {:?}", args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    let (root, rest) = try!(nums[0].exact_integer_sqrt());
+    Ok(Value::Values(vec![Value::Number(root), Value::Number(rest)]))
+}
+
+fn native_floor_division(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_division(args, env, "floor/")
+}
+
+fn native_truncate_division(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_division(args, env, "truncate/")
+}
+
+// The quotient and remainder come back as two values
+fn evaluate_division(args: &[Value], env: Rc<RefCell<Environment>>, name: &str) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to {}: {:?}", name, args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    let (quotient, remainder) = if name == "floor/" {
+        try!(nums[0].floor_division(&nums[1]))
+    } else {
+        try!(nums[0].truncate_division(&nums[1]))
+    };
+    Ok(Value::Values(vec![Value::Number(quotient), Value::Number(remainder)]))
+}
+
+fn evaluate_binary_number(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, op: fn(&Number, &Number) -> Result<Number, NumberError>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to {}: {:?}", name, args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    Ok(Value::Number(try!(op(&nums[0], &nums[1]))))
+}
+
+fn evaluate_number_predicate(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, pred: fn(&Number) -> Result<bool, NumberError>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    Ok(Value::Boolean(try!(pred(&nums[0]))))
+}
+
+// Type predicates are false for non-numbers, rather than an error
+fn evaluate_number_type_predicate(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, pred: fn(&Number) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Number(n) => Ok(Value::Boolean(pred(&n))),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn compare_numbers(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, accept: fn(Ordering) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to {}: {:?}", name, args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    let res = nums.windows(2).all(|pair| match pair[0].compare(&pair[1]) {
+        Some(o) => accept(o),
+        None => false
+    });
+    Ok(Value::Boolean(res))
+}
+
+fn native_plus(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let nums = try!(evaluate_numbers(args, env));
+    Ok(Value::Number(nums.iter().fold(Number::Integer(0), |s, n| s.add(n))))
+}
+
+fn native_minus(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument to -: {:?}", args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    if nums.len() == 1 {
+        return Ok(Value::Number(nums[0].negate()));
+    }
+    Ok(Value::Number(nums[1..].iter().fold(nums[0].clone(), |d, n| d.sub(n))))
+}
+
+fn native_multiply(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let nums = try!(evaluate_numbers(args, env));
+    Ok(Value::Number(nums.iter().fold(Number::Integer(1), |p, n| p.mul(n))))
+}
+
+fn native_divide(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument to /: {:?}", args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    if nums.len() == 1 {
+        return Ok(Value::Number(try!(Number::Integer(1).div(&nums[0]))));
+    }
+    let mut result = nums[0].clone();
+    for n in nums[1..].iter() {
+        result = try!(result.div(n));
+    }
+    Ok(Value::Number(result))
+}
+
+fn native_lessthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_numbers(args, env, "<", |o| o == Ordering::Less)
+}
+
+fn native_greaterthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_numbers(args, env, ">", |o| o == Ordering::Greater)
+}
+
+fn native_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_numbers(args, env, "=", |o| o == Ordering::Equal)
+}
+
+fn native_lessthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_numbers(args, env, "<=", |o| o != Ordering::Greater)
+}
+
+fn native_greaterthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_numbers(args, env, ">=", |o| o != Ordering::Less)
+}
+
+fn native_max(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument to max: {:?}", args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    Ok(Value::Number(nums[1..].iter().fold(nums[0].clone(), |m, n| m.max(n))))
+}
+
+fn native_min(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument to min: {:?}", args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    Ok(Value::Number(nums[1..].iter().fold(nums[0].clone(), |m, n| m.min(n))))
+}
+
+fn native_quotient(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_binary_number(args, env, "quotient", Number::quotient)
+}
+
+fn native_remainder(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_binary_number(args, env, "remainder", Number::remainder)
+}
+
+fn native_modulo(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_binary_number(args, env, "modulo", Number::modulo)
+}
+
+fn native_expt(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_binary_number(args, env, "expt", Number::expt)
+}
+
+fn native_gcd(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut res = Number::Integer(0);
+    for n in try!(evaluate_numbers(args, env)).iter() {
+        res = try!(res.gcd(n));
+    }
+    Ok(Value::Number(res))
+}
+
+fn native_lcm(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut res = Number::Integer(1);
+    for n in try!(evaluate_numbers(args, env)).iter() {
+        res = try!(res.lcm(n));
+    }
+    Ok(Value::Number(res))
+}
+
+fn native_abs(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "abs", |n| Ok(n.abs()))
+}
+
+fn native_floor(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "floor", |n| Ok(n.floor()))
+}
+
+fn native_ceiling(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "ceiling", |n| Ok(n.ceiling()))
+}
+
+fn native_round(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "round", |n| Ok(n.round()))
+}
+
+fn native_truncate(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "truncate", |n| Ok(n.truncate()))
+}
+
+fn native_numerator(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "numerator", Number::numerator)
+}
+
+fn native_denominator(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "denominator", Number::denominator)
+}
+
+fn native_exact(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "exact", Number::to_exact)
+}
+
+fn native_inexact(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "inexact", |n| Ok(n.to_inexact()))
+}
+
+fn native_sqrt(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "sqrt", |n| Ok(n.sqrt()))
+}
+
+fn native_exp(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "exp", |n| Ok(n.map_real(f64::exp)))
+}
+
+fn native_log(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() == 2 {
+        // (log z1 z2) is the logarithm of z1 in base z2
+        let nums = try!(evaluate_numbers(args, env));
+        return Ok(Value::Number(Number::Real(nums[0].to_f64().ln() / nums[1].to_f64().ln())));
+    }
+    evaluate_unary_number(args, env, "log", |n| Ok(n.map_real(f64::ln)))
+}
+
+fn native_sin(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "sin", |n| Ok(n.map_real(f64::sin)))
+}
+
+fn native_cos(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "cos", |n| Ok(n.map_real(f64::cos)))
+}
+
+fn native_tan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "tan", |n| Ok(n.map_real(f64::tan)))
+}
+
+fn native_asin(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "asin", |n| Ok(n.map_real(f64::asin)))
+}
+
+fn native_acos(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_unary_number(args, env, "acos", |n| Ok(n.map_real(f64::acos)))
+}
+
+fn native_atan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() == 2 {
+        // (atan y x) is the angle of the point (x, y)
+        let nums = try!(evaluate_numbers(args, env));
+        return Ok(Value::Number(Number::Real(nums[0].to_f64().atan2(nums[1].to_f64()))));
+    }
+    evaluate_unary_number(args, env, "atan", |n| Ok(n.map_real(f64::atan)))
+}
+
+fn native_is_number(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_type_predicate(args, env, "number?", |_| true)
+}
+
+fn native_is_rational(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_type_predicate(args, env, "rational?", Number::is_rational)
+}
+
+fn native_is_integer(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_type_predicate(args, env, "integer?", Number::is_integer)
+}
+
+fn native_is_exact_integer(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_type_predicate(args, env, "exact-integer?", Number::is_exact_integer)
+}
+
+fn native_is_exact(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "exact?", |n| Ok(n.is_exact()))
+}
+
+fn native_is_inexact(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "inexact?", |n| Ok(!n.is_exact()))
+}
+
+fn native_is_nan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "nan?", |n| Ok(n.is_nan()))
+}
+
+fn native_is_zero(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "zero?", |n| Ok(n.is_zero()))
+}
+
+fn native_is_positive(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "positive?", |n| Ok(n.is_positive()))
+}
+
+fn native_is_negative(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "negative?", |n| Ok(n.is_negative()))
+}
+
+fn native_is_odd(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "odd?", |n| n.is_even().map(|e| !e))
+}
+
+fn native_is_even(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_number_predicate(args, env, "even?", Number::is_even)
+}
+
+fn evaluate_chars(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Vec<char>, RuntimeError> {
+    args.iter().map(|c| match try!(evaluate_value(c, env.clone())) {
+        Value::Char(x) => Ok(x),
+        v => runtime_error!(WrongType(v); "Expected a char value: {:?}", v)
+    }).collect()
+}
+
+fn evaluate_char_conversion(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, op: fn(char) -> char) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    let cs = try!(evaluate_chars(args, env));
+    Ok(Value::Char(op(cs[0])))
+}
+
+fn evaluate_char_predicate(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, pred: fn(char) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    let cs = try!(evaluate_chars(args, env));
+    Ok(Value::Boolean(pred(cs[0])))
+}
+
+// The -ci variants compare the case-folded characters
+fn compare_chars(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, fold: bool, accept: fn(Ordering) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to {}: {:?}", name, args);
+    }
+    let cs: Vec<char> = try!(evaluate_chars(args, env)).into_iter().map(|c| if fold { character::foldcase(c) } else { c }).collect();
+    Ok(Value::Boolean(cs.windows(2).all(|pair| accept(pair[0].cmp(&pair[1])))))
+}
+
+fn native_is_char(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to char?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Char(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_char_to_integer(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to char->integer: {:?}", args);
+    }
+    let cs = try!(evaluate_chars(args, env));
+    Ok(Value::Number(Number::Integer(cs[0] as i64)))
+}
+
+fn native_integer_to_char(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to integer->char: {:?}", args);
+    }
+    let nums = try!(evaluate_numbers(args, env));
+    let c = match nums[0] {
+        Number::Integer(i) => u32::try_from(i).ok().and_then(::std::char::from_u32),
+        _ => None
+    };
+    match c {
+        Some(c) => Ok(Value::Char(c)),
+        None => runtime_error!("Not a Unicode scalar value: {}", nums[0])
+    }
+}
+
+fn native_char_upcase(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_conversion(args, env, "char-upcase", character::upcase)
+}
+
+fn native_char_downcase(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_conversion(args, env, "char-downcase", character::downcase)
+}
+
+fn native_char_foldcase(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_conversion(args, env, "char-foldcase", character::foldcase)
+}
+
+fn native_is_char_alphabetic(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_predicate(args, env, "char-alphabetic?", char::is_alphabetic)
+}
+
+fn native_is_char_numeric(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_predicate(args, env, "char-numeric?", char::is_numeric)
+}
+
+fn native_is_char_whitespace(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_predicate(args, env, "char-whitespace?", char::is_whitespace)
+}
+
+fn native_is_char_upper_case(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_predicate(args, env, "char-upper-case?", char::is_uppercase)
+}
+
+fn native_is_char_lower_case(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_char_predicate(args, env, "char-lower-case?", char::is_lowercase)
+}
+
+fn native_char_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char=?", false, |o| o == Ordering::Equal)
+}
+
+fn native_char_lessthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char<?", false, |o| o == Ordering::Less)
+}
+
+fn native_char_greaterthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char>?", false, |o| o == Ordering::Greater)
+}
+
+fn native_char_lessthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char<=?", false, |o| o != Ordering::Greater)
+}
+
+fn native_char_greaterthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char>=?", false, |o| o != Ordering::Less)
+}
+
+fn native_char_ci_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char-ci=?", true, |o| o == Ordering::Equal)
+}
+
+fn native_char_ci_lessthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char-ci<?", true, |o| o == Ordering::Less)
+}
+
+fn native_char_ci_greaterthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char-ci>?", true, |o| o == Ordering::Greater)
+}
+
+fn native_char_ci_lessthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char-ci<=?", true, |o| o != Ordering::Greater)
+}
+
+fn native_char_ci_greaterthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_chars(args, env, "char-ci>=?", true, |o| o != Ordering::Less)
+}
+
+fn evaluate_string(arg: &Value, env: Rc<RefCell<Environment>>) -> Result<String, RuntimeError> {
+    match try!(evaluate_value(arg, env)) {
+        Value::String(s) => Ok(Rc::unwrap_or_clone(s)),
+        v => runtime_error!(WrongType(v); "Expected a string value: {:?}", v)
+    }
+}
+
+fn evaluate_index(arg: &Value, env: Rc<RefCell<Environment>>) -> Result<usize, RuntimeError> {
+    match try!(evaluate_value(arg, env)) {
+        Value::Number(Number::Integer(i)) if i >= 0 => Ok(i as usize),
+        v => runtime_error!(WrongType(v); "Expected a non-negative integer index: {:?}", v)
+    }
+}
+
+fn compare_strings(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, accept: fn(Ordering) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to {}: {:?}", name, args);
+    }
+    let res: Result<Vec<String>, RuntimeError> = args.iter().map(|a| evaluate_string(a, env.clone())).collect();
+    let strs = try!(res);
+    Ok(Value::Boolean(strs.windows(2).all(|pair| accept(pair[0].cmp(&pair[1])))))
+}
+
+fn native_is_string(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to string?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::String(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_string(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::from_string(try!(evaluate_chars(args, env)).into_iter().collect()))
+}
+
+fn native_make_string(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 && args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one or two arguments to make-string: {:?}", args);
+    }
+    let k = try!(evaluate_index(&args[0], env.clone()));
+    let fill = if args.len() == 2 { try!(evaluate_chars(&args[1..], env))[0] } else { ' ' };
+    Ok(Value::from_string(try!(repeat_char(fill, k))))
+}
+
+// make-string and make-vector reserve their space first, so that a size there isn't room for is an
+// error instead of aborting the process
+fn repeat_char(fill: char, k: usize) -> Result<String, RuntimeError> {
+    let mut s = String::new();
+    if k.checked_mul(fill.len_utf8()).is_none_or(|len| s.try_reserve_exact(len).is_err()) {
+        runtime_error!("Can't make a string of {} characters", k);
+    }
+    s.extend(iter::repeat_n(fill, k));
+    Ok(s)
+}
+
+fn native_string_length(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to string-length: {:?}", args);
+    }
+    let s = try!(evaluate_string(&args[0], env));
+    Ok(Value::Number(Number::Integer(s.chars().count() as i64)))
+}
+
+fn native_string_ref(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to string-ref: {:?}", args);
+    }
+    let s = try!(evaluate_string(&args[0], env.clone()));
+    let k = try!(evaluate_index(&args[1], env));
+    match s.chars().nth(k) {
+        Some(c) => Ok(Value::Char(c)),
+        None => runtime_error!("Index out of range for string-ref: {} {}", string::Written(&s), k)
+    }
+}
+
+// Shared by substring, where both indices are required, and string-copy, where they are optional
+fn evaluate_substring(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, min_args: usize) -> Result<Value, RuntimeError> {
+    if args.len() < min_args || args.len() > 3 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Wrong number of arguments to {}: {:?}", name, args);
+    }
+    let s = try!(evaluate_string(&args[0], env.clone()));
+    let start = if args.len() > 1 { try!(evaluate_index(&args[1], env.clone())) } else { 0 };
+    let end = if args.len() > 2 { try!(evaluate_index(&args[2], env)) } else { s.chars().count() };
+    match string::char_range(&s, start, end) {
+        Some(sub) => Ok(Value::from_string(sub.to_string())),
+        None => runtime_error!("Index out of range for {}: {} {} {}", name, string::Written(&s), start, end)
+    }
+}
+
+fn native_substring(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_substring(args, env, "substring", 3)
+}
+
+fn native_string_copy(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_substring(args, env, "string-copy", 1)
+}
+
+fn native_string_append(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let res: Result<Vec<String>, RuntimeError> = args.iter().map(|a| evaluate_string(a, env.clone())).collect();
+    Ok(Value::from_string(try!(res).concat()))
+}
+
+fn native_string_to_list(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to string->list: {:?}", args);
+    }
+    let s = try!(evaluate_string(&args[0], env));
+    Ok(Value::from_vec(s.chars().map(Value::Char).collect()))
+}
+
+fn native_list_to_string(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to list->string: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(l) => {
+            let res: Result<String, RuntimeError> = l.iter().cloned().map(|v| match v {
+                Value::Char(c) => Ok(c),
+                v => runtime_error!(WrongType(v); "Expected a char value: {:?}", v)
+            }).collect();
+            Ok(Value::from_string(try!(res)))
+        },
+        v => runtime_error!(WrongType(v); "Expected a list value: {:?}", v)
+    }
+}
+
+fn native_string_to_symbol(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to string->symbol: {:?}", args);
+    }
+    Ok(Value::Symbol(try!(evaluate_string(&args[0], env))))
+}
+
+fn native_symbol_to_string(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to symbol->string: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Symbol(s) => Ok(Value::from_string(s)),
+        v => runtime_error!(WrongType(v); "Expected a symbol value: {:?}", v)
+    }
+}
+
+fn native_number_to_string(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 && args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one or two arguments to number->string: {:?}", args);
+    }
+    let n = try!(evaluate_numbers(&args[..1], env.clone())).remove(0);
+    let radix = if args.len() == 2 { try!(evaluate_index(&args[1], env)) } else { 10 };
+    match radix {
+        2 | 8 | 10 | 16 => Ok(Value::from_string(try!(n.to_string_radix(radix as u32)))),
+        _ => runtime_error!("Invalid radix for number->string: {}", radix)
+    }
+}
+
+fn native_string_to_number(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 && args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one or two arguments to string->number: {:?}", args);
+    }
+    let s = try!(evaluate_string(&args[0], env.clone()));
+    let radix = if args.len() == 2 { try!(evaluate_index(&args[1], env)) } else { 10 };
+    let prefix = match radix {
+        2 => "#b",
+        8 => "#o",
+        10 => "",
+        16 => "#x",
+        _ => runtime_error!("Invalid radix for string->number: {}", radix)
+    };
+    match Number::parse(&format!("{}{}", prefix, s)) {
+        Some(n) => Ok(Value::Number(n)),
+        None => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_string_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_strings(args, env, "string=?", |o| o == Ordering::Equal)
+}
+
+fn native_string_lessthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_strings(args, env, "string<?", |o| o == Ordering::Less)
+}
+
+fn native_string_greaterthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_strings(args, env, "string>?", |o| o == Ordering::Greater)
+}
+
+fn native_string_lessthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_strings(args, env, "string<=?", |o| o != Ordering::Greater)
+}
+
+fn native_string_greaterthan_or_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare_strings(args, env, "string>=?", |o| o != Ordering::Less)
+}
+
+fn native_string_upcase(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to string-upcase: {:?}", args);
+    }
+    Ok(Value::from_string(try!(evaluate_string(&args[0], env)).to_uppercase()))
+}
+
+fn native_string_downcase(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to string-downcase: {:?}", args);
+    }
+    Ok(Value::from_string(try!(evaluate_string(&args[0], env)).to_lowercase()))
+}
+
+// (string-split string [separator]), where the separator is a char or a string
+fn native_string_split(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 && args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one or two arguments to string-split: {:?}", args);
+    }
+    let s = try!(evaluate_string(&args[0], env.clone()));
+    let sep = if args.len() == 2 {
+        match try!(evaluate_value(&args[1], env)) {
+            Value::Char(c) => Some(c.to_string()),
+            Value::String(sep) => Some(Rc::unwrap_or_clone(sep)),
+            v => runtime_error!(WrongType(v); "Expected a char or string separator: {:?}", v)
+        }
+    } else {
+        None
+    };
+    let parts = string::split(&s, sep.as_ref().map(|sep| &sep[..]));
+    Ok(Value::from_vec(parts.into_iter().map(Value::from_string).collect()))
+}
+
+// (string-index string char [start]) returns the index of the first occurrence, or #f
+fn native_string_index(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 && args.len() != 3 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply two or three arguments to string-index: {:?}", args);
+    }
+    let s = try!(evaluate_string(&args[0], env.clone()));
+    let c = try!(evaluate_chars(&args[1..2], env.clone()))[0];
+    let start = if args.len() == 3 { try!(evaluate_index(&args[2], env)) } else { 0 };
+    match s.chars().skip(start).position(|x| x == c) {
+        Some(i) => Ok(Value::Number(Number::Integer((start + i) as i64))),
+        None => Ok(Value::Boolean(false))
+    }
+}
+
+fn evaluate_vector(arg: &Value, env: Rc<RefCell<Environment>>) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match try!(evaluate_value(arg, env)) {
+        Value::Vector(v) => Ok(v),
+        v => runtime_error!(WrongType(v); "Expected a vector value: {:?}", v)
+    }
+}
+
+// Evaluates the optional start and end arguments taken by vector->list, vector-copy and vector-fill!
+fn evaluate_range(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, len: usize) -> Result<(usize, usize), RuntimeError> {
+    let start = if !args.is_empty() { try!(evaluate_index(&args[0], env.clone())) } else { 0 };
+    let end = if args.len() > 1 { try!(evaluate_index(&args[1], env)) } else { len };
+    if start > end || end > len {
+        runtime_error!("Index out of range for {}: {} {}", name, start, end);
+    }
+    Ok((start, end))
+}
+
+fn native_is_vector(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to vector?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Vector(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_make_vector(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 && args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one or two arguments to make-vector: {:?}", args);
+    }
+    let k = try!(evaluate_index(&args[0], env.clone()));
+    let fill = if args.len() == 2 { try!(evaluate_value(&args[1], env)) } else { Value::Number(Number::Integer(0)) };
+    Ok(Value::from_vector(try!(repeat_value(fill, k))))
+}
+
+fn repeat_value(fill: Value, k: usize) -> Result<Vec<Value>, RuntimeError> {
+    let mut vec = Vec::new();
+    if vec.try_reserve_exact(k).is_err() {
+        runtime_error!("Can't make a vector of {} elements", k);
+    }
+    vec.resize(k, fill);
+    Ok(vec)
+}
+
+fn native_vector(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let res: Result<Vec<Value>, RuntimeError> = args.iter().map(|n| evaluate_value(n, env.clone())).collect();
+    Ok(Value::from_vector(try!(res)))
+}
+
+fn native_vector_length(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to vector-length: {:?}", args);
+    }
+    let v = try!(evaluate_vector(&args[0], env));
+    let len = v.borrow().len();
+    Ok(Value::Number(Number::Integer(len as i64)))
+}
+
+fn native_vector_ref(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to vector-ref: {:?}", args);
+    }
+    let v = try!(evaluate_vector(&args[0], env.clone()));
+    let k = try!(evaluate_index(&args[1], env));
+    let res = v.borrow().get(k).cloned();
+    match res {
+        Some(val) => Ok(val),
+        None => runtime_error!("Index out of range for vector-ref: {}", k)
     }
-    let arg_names = match args[0] {
-        Value::List(ref list) => {
-            let res: Result<Vec<String>, RuntimeError> = list.iter().map(|i| match *i {
-                Value::Symbol(ref s) => Ok(s.clone()),
-                _ => runtime_error!("Unexpected argument in lambda arguments: {:?}", i)
-            }).collect();
-            try!(res)
-        }
-        _ => runtime_error!("Unexpected value for arguments in lambda: {:?}", args)
-    };
-    let body = (&args[1..]).to_vec();
-    Ok(Value::Procedure(Function::Scheme(arg_names, body, env.clone())))
 }
 
-fn native_if(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_vector_set(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 3 {
-        runtime_error!("Must supply exactly three arguments to if: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly three arguments to vector-set!: {:?}", args);
     }
-    let condition = try!(evaluate_value(&args[0], env.clone()));
-    match condition {
-        Value::Boolean(false) => evaluate_value(&args[2], env.clone()),
-        _ => evaluate_value(&args[1], env.clone())
+    let v = try!(evaluate_vector(&args[0], env.clone()));
+    let k = try!(evaluate_index(&args[1], env.clone()));
+    let obj = try!(evaluate_value(&args[2], env));
+    let mut vec = v.borrow_mut();
+    if k >= vec.len() {
+        runtime_error!("Index out of range for vector-set!: {}", k);
     }
+    vec[k] = obj;
+    Ok(null!())
 }
 
-fn native_plus(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to +: {:?}", args);
+fn native_vector_to_list(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one to three arguments to vector->list: {:?}", args);
     }
-    let mut sum = 0;
-    for n in args.iter() {
-        let v = try!(evaluate_value(n, env.clone()));
-        match v {
-            Value::Integer(x) => sum += x,
-            _ => runtime_error!("Unexpected value during +: {:?}", n)
-        };
-    };
-    Ok(Value::Integer(sum))
+    let v = try!(evaluate_vector(&args[0], env.clone()));
+    let len = v.borrow().len();
+    let (start, end) = try!(evaluate_range(&args[1..], env, "vector->list", len));
+    let elems = v.borrow()[start..end].to_vec();
+    Ok(Value::from_vec(elems))
 }
 
-fn native_minus(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to -: {:?}", args);
+fn native_vector_copy(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one to three arguments to vector-copy: {:?}", args);
     }
-    let l = try!(evaluate_value(&args[0], env.clone()));
-    let r = try!(evaluate_value(&args[1], env.clone()));
-    let mut result = match l {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during -: {:?}", args)
-    };
-    result -= match r {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during -: {:?}", args)
-    };
-    Ok(Value::Integer(result))
+    let v = try!(evaluate_vector(&args[0], env.clone()));
+    let len = v.borrow().len();
+    let (start, end) = try!(evaluate_range(&args[1..], env, "vector-copy", len));
+    let elems = v.borrow()[start..end].to_vec();
+    Ok(Value::from_vector(elems))
 }
 
-fn native_multiply(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to *: {:?}", args);
+fn native_vector_fill(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 || args.len() > 4 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply two to four arguments to vector-fill!: {:?}", args);
     }
-    let mut product = 1;
-    for n in args.iter() {
-        let v = try!(evaluate_value(n, env.clone()));
-        match v {
-            Value::Integer(x) => product *= x,
-            _ => runtime_error!("Unexpected value during *: {:?}", n)
-        };
-    };
-    Ok(Value::Integer(product))
+    let v = try!(evaluate_vector(&args[0], env.clone()));
+    let fill = try!(evaluate_value(&args[1], env.clone()));
+    let len = v.borrow().len();
+    let (start, end) = try!(evaluate_range(&args[2..], env, "vector-fill!", len));
+    for elem in v.borrow_mut()[start..end].iter_mut() {
+        *elem = fill.clone();
+    }
+    Ok(null!())
 }
 
-fn native_divide(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to /: {:?}", args);
+fn native_list_to_vector(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to list->vector: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(l) => Ok(Value::from_vector(Rc::unwrap_or_clone(l))),
+        v => runtime_error!(WrongType(v); "Expected a list value: {:?}", v)
     }
-    let l = try!(evaluate_value(&args[0], env.clone()));
-    let r = try!(evaluate_value(&args[1], env.clone()));
-    let mut result = match l {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during /: {:?}", args)
-    };
-    result /= match r {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during /: {:?}", args)
-    };
-    Ok(Value::Integer(result))
 }
 
-fn native_lessthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to <: {:?}", args);
+fn native_vector_append(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut res = vec![];
+    for arg in args.iter() {
+        res.extend(try!(evaluate_vector(arg, env.clone())).borrow().iter().cloned());
     }
-    let l_raw = try!(evaluate_value(&args[0], env.clone()));
-    let r_raw = try!(evaluate_value(&args[1], env.clone()));
-    let l = match l_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during <: {:?}", args)
-    };
-    let r = match r_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during <: {:?}", args)
-    };
-    Ok(Value::Boolean(l < r))
+    Ok(Value::from_vector(res))
 }
 
-fn native_greaterthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to >: {:?}", args);
+// Calls the procedure on the corresponding elements of each vector, up to the length of the shortest one
+fn map_vectors(args: &[Value], env: Rc<RefCell<Environment>>, name: &str) -> Result<Vec<Value>, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to {}: {:?}", name, args);
     }
-    let l_raw = try!(evaluate_value(&args[0], env.clone()));
-    let r_raw = try!(evaluate_value(&args[1], env.clone()));
-    let l = match l_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during >: {:?}", args)
-    };
-    let r = match r_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during >: {:?}", args)
+    let func = match try!(evaluate_value(&args[0], env.clone())) {
+        Value::Procedure(func) => func,
+        v => runtime_error!(WrongType(v); "First argument to {} must be a procedure: {:?}", name, v)
     };
-    Ok(Value::Boolean(l > r))
+    let res: Result<Vec<Vec<Value>>, RuntimeError> = args[1..].iter().map(|a| evaluate_vector(a, env.clone()).map(|v| v.borrow().clone())).collect();
+    let vectors = try!(res);
+    let len = vectors.iter().map(|v| v.len()).min().unwrap_or(0);
+    (0..len).map(|i| apply_function_to_values(&func, vectors.iter().map(|v| v[i].clone()).collect(), env.clone()).and_then(one_value)).collect()
 }
 
-fn native_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to =: {:?}", args);
-    }
-    let l_raw = try!(evaluate_value(&args[0], env.clone()));
-    let r_raw = try!(evaluate_value(&args[1], env.clone()));
-    let l = match l_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during =: {:?}", args)
-    };
-    let r = match r_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during =: {:?}", args)
-    };
-    Ok(Value::Boolean(l == r))
+fn native_vector_map(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::from_vector(try!(map_vectors(args, env, "vector-map"))))
+}
+
+fn native_vector_for_each(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    try!(map_vectors(args, env, "vector-for-each"));
+    Ok(null!())
 }
 
 fn native_and(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
@@ -648,7 +2457,7 @@ fn native_or(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, Run
 
 fn native_null(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to null?: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to null?: {:?}", args);
     }
     let v = try!(evaluate_value(&args[0], env.clone()));
     match v {
@@ -660,134 +2469,653 @@ fn native_null(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, R
 fn native_list(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     let res: Result<Vec<Value>, RuntimeError> = args.iter().map(|n| evaluate_value(n, env.clone())).collect();
     let elements = try!(res);
-    Ok(Value::List(elements))
+    Ok(Value::from_vec(elements))
 }
 
 fn native_car(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to car: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to car: {:?}", args);
     }
     let v = try!(evaluate_value(&args[0], env.clone()));
     match v {
-        Value::List(mut l) => {
+        Value::List(l) => {
             if l.len() > 0 {
-                Ok(l.remove(0))
+                Ok(l[0].clone())
             } else {
-                runtime_error!("Can't run car on an empty list")
+                runtime_error!(WrongType(Value::from_vec(vec![])); "Can't run car on an empty list")
             }
         }
-        _ => runtime_error!("Must supply a list to car")
+        Value::DottedList(l, _) => Ok(l[0].clone()),
+        v => runtime_error!(WrongType(v); "Must supply a list to car")
     }
 }
 
 fn native_cdr(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to cdr: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to cdr: {:?}", args);
     }
     let v = try!(evaluate_value(&args[0], env.clone()));
     match v {
-        Value::List(mut l) => {
+        Value::List(l) => {
             if l.len() > 0 {
-                l.remove(0);
-                Ok(Value::List(l))
+                Ok(Value::from_vec(l[1..].to_vec()))
             } else {
-                runtime_error!("Can't run cdr on an empty list")
+                runtime_error!(WrongType(Value::from_vec(vec![])); "Can't run cdr on an empty list")
             }
         }
-        _ => runtime_error!("Must supply a list to cdr")
+        Value::DottedList(l, tail) => Ok(Value::from_vec_dotted(l[1..].to_vec(), *tail)),
+        v => runtime_error!(WrongType(v); "Must supply a list to cdr")
     }
 }
 
 fn native_cons(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to cons: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to cons: {:?}", args);
     }
 
     let first = try!(evaluate_value(&args[0], env.clone()));
     let second = try!(evaluate_value(&args[1], env.clone()));
-    match second {
-        Value::List(elements) => {
-            let mut new_elements = vec![first];
-            for e in elements.into_iter() {
-                new_elements.push(e);
-            }
-            return Ok(Value::List(new_elements))
-        }
-        _ => runtime_error!("Second argument to cons must be a list: {:?}", second)
-    }
+    Ok(Value::from_vec_dotted(vec![first], second))
 }
 
 fn native_append(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to append: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to append: {:?}", args);
     }
 
     let first = try!(evaluate_value(&args[0], env.clone()));
     let second = try!(evaluate_value(&args[1], env.clone()));
-    let mut first_vec = match first {
-        Value::List(elements) => elements,
-        _ => runtime_error!("First argument to append must be a list: {:?}", first)
+    let first_vec = match first {
+        Value::List(elements) => Rc::unwrap_or_clone(elements),
+        _ => runtime_error!(WrongType(first); "First argument to append must be a list: {:?}", first)
     };
-    let second_vec = match second {
-        Value::List(elements) => elements,
-        _ => runtime_error!("Second argument to append must be a list: {:?}", second)
-    };
-    for e in second_vec.into_iter() {
-        first_vec.push(e);
+    // The last argument becomes the tail as is, so it doesn't have to be a list
+    Ok(Value::from_vec_dotted(first_vec, second))
+}
+
+fn native_is_pair(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to pair?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(l) => Ok(Value::Boolean(!l.is_empty())),
+        Value::DottedList(_, _) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+// eq? and eqv?. Lists, strings and vectors are the same object only if they share their storage. As
+// lists aren't made of pairs here, the cdr of a list is a new list each time it's taken.
+fn values_eqv(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.is_eqv(y),
+        // Empty lists and strings hold nothing to tell them apart by, so they're all the same object
+        (Value::List(x), Value::List(y)) => Rc::ptr_eq(x, y) || x.is_empty() && y.is_empty(),
+        (Value::DottedList(x, _), Value::DottedList(y, _)) => Rc::ptr_eq(x, y),
+        (Value::String(x), Value::String(y)) => Rc::ptr_eq(x, y) || x.is_empty() && y.is_empty(),
+        (Value::Vector(x), Value::Vector(y)) => Rc::ptr_eq(x, y),
+        (Value::ErrorObject(x), Value::ErrorObject(y)) => Rc::ptr_eq(x, y),
+        _ => a == b
+    }
+}
+
+// equal? also compares the contents of vectors. Vectors that are already being compared are assumed
+// to be equal, so comparing vectors that contain themselves terminates.
+fn values_equal(a: &Value, b: &Value) -> bool {
+    fn equal(a: &Value, b: &Value, seen: &mut Vec<(usize, usize)>) -> bool {
+        match (a, b) {
+            (Value::List(x), Value::List(y)) => {
+                x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| equal(a, b, seen))
+            },
+            (Value::DottedList(x, x_tail), Value::DottedList(y, y_tail)) => {
+                x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| equal(a, b, seen)) && equal(x_tail, y_tail, seen)
+            },
+            (Value::String(x), Value::String(y)) => x == y,
+            (Value::Vector(x), Value::Vector(y)) => {
+                let key = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+                if Rc::ptr_eq(x, y) || seen.contains(&key) {
+                    return true;
+                }
+                seen.push(key);
+                let (x, y) = (x.borrow(), y.borrow());
+                x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| equal(a, b, seen))
+            },
+            _ => values_eqv(a, b)
+        }
+    }
+    equal(a, b, &mut vec![])
+}
+
+fn native_eqv(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to eqv?: {:?}", args);
+    }
+    let a = try!(evaluate_value(&args[0], env.clone()));
+    let b = try!(evaluate_value(&args[1], env.clone()));
+    Ok(Value::Boolean(values_eqv(&a, &b)))
+}
+
+fn native_equal_values(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to equal?: {:?}", args);
+    }
+    let a = try!(evaluate_value(&args[0], env.clone()));
+    let b = try!(evaluate_value(&args[1], env.clone()));
+    Ok(Value::Boolean(values_equal(&a, &b)))
+}
+
+fn native_memv(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_member(args, env, "memv", values_eqv)
+}
+
+fn native_member(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_member(args, env, "member", values_equal)
+}
+
+// Returns the rest of the list starting at the first element that matches, or #f
+fn evaluate_member(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, matches: fn(&Value, &Value) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to {}: {:?}", name, args);
+    }
+    let obj = try!(evaluate_value(&args[0], env.clone()));
+    match try!(evaluate_value(&args[1], env.clone())) {
+        Value::List(l) => {
+            match l.iter().position(|v| matches(&obj, v)) {
+                Some(i) => Ok(Value::from_vec(l[i..].to_vec())),
+                None => Ok(Value::Boolean(false))
+            }
+        },
+        v => runtime_error!(WrongType(v); "Must supply a list to {}: {:?}", name, v)
+    }
+}
+
+fn native_assv(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_assoc(args, env, "assv", values_eqv)
+}
+
+fn native_assoc(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_assoc(args, env, "assoc", values_equal)
+}
+
+// Returns the first pair in an association list whose car matches the key, or #f
+fn evaluate_assoc(args: &[Value], env: Rc<RefCell<Environment>>, name: &str, matches: fn(&Value, &Value) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to {}: {:?}", name, args);
+    }
+    let key = try!(evaluate_value(&args[0], env.clone()));
+    match try!(evaluate_value(&args[1], env.clone())) {
+        Value::List(alist) => {
+            for entry in alist.iter().cloned() {
+                let found = match entry {
+                    Value::List(ref pair) if pair.len() > 0 => matches(&key, &pair[0]),
+                    Value::DottedList(ref pair, _) => matches(&key, &pair[0]),
+                    _ => runtime_error!(WrongType(entry); "Expected a pair in the association list for {}: {:?}", name, entry)
+                };
+                if found {
+                    return Ok(entry);
+                }
+            }
+            Ok(Value::Boolean(false))
+        },
+        v => runtime_error!(WrongType(v); "Must supply a list to {}: {:?}", name, v)
     }
-    return Ok(Value::List(first_vec))
 }
 
-fn native_quote(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_is_list(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to quote: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to list?: {:?}", args);
     }
-    quote_value(&args[0], false, env.clone())
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_quote(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to quote: {:?}", args);
+    }
+    Ok(args[0].clone())
 }
 
 fn native_quasiquote(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to quasiquote: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to quasiquote: {:?}", args);
     }
-    quote_value(&args[0], true, env.clone())
+    quasiquote_value(&args[0], 1, env.clone())
 }
 
+// (error message irritant ...)
 fn native_error(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() == 0 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least one argument to error");
+    }
+    let res: Result<Vec<Value>, RuntimeError> = args.iter().map(|n| evaluate_value(n, env.clone())).collect();
+    let mut values = try!(res);
+    let mut e = RuntimeError::new(values.iter().map(|v| format!("{:?}", v)).collect::<Vec<_>>().join(" "));
+    e.irritants = values.split_off(1);
+    Err(e.of(ErrorKind::Raised, values.pop()))
+}
+
+// Handlers are kept in the root environment while the code they handle runs, and errors are Rust errors
+// that unwind to the with-exception-handler or guard that installed them. A handler for raise or a
+// built-in error is called once the error has unwound there, so it can't return to where it happened
+// anyway; raise-continuable calls its handler directly, as it has to return the handler's value, and
+// tries a guard's clauses where it was called for the same reason.
+fn native_raise(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to raise: {:?}", args);
+    }
+    Err(raised(try!(evaluate_value(&args[0], env))))
+}
+
+// The error for raising obj. An error object that's raised again is reported as the error it was.
+fn raised(obj: Value) -> RuntimeError {
+    let mut e = match obj {
+        Value::ErrorObject(ref e) => e.to_object(),
+        _ => RuntimeError::new(format!("Uncaught exception: {:?}", obj)).of(ErrorKind::Raised, Some(obj.clone()))
+    };
+    e.object = Some(Box::new(obj));
+    e
+}
+
+fn native_raise_continuable(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to raise-continuable: {:?}", args);
+    }
+    let obj = try!(evaluate_value(&args[0], env.clone()));
+    raise_continuable(obj, env)
+}
+
+// The innermost handler runs with the handlers outside it. A guard leaves its body to try its clauses,
+// and passes the value of the one that matches out to itself; if none of them do, it goes back in, and
+// obj goes on to the next handler.
+fn raise_continuable(obj: Value, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let root = Environment::get_root(env.clone());
+    let innermost = match root.borrow_mut().handlers.pop() {
+        Some(handler) => handler,
+        None => return Err(raised(obj))
+    };
+    let depth = root.borrow().handlers.len();
+    let res = match innermost {
+        Handler::Procedure(ref handler) => apply_function_to_values(handler, vec![obj], env).map_err(|e| e.outside(depth)),
+        Handler::Guard(ref var, ref clauses, ref guard_env, winds) => {
+            match guard_outside(winds, &env, || guard_clauses(var, clauses, obj.clone(), guard_env.clone())) {
+                Ok(Some(val)) => {
+                    // only the guard itself can catch this
+                    let mut e = raised(obj);
+                    e.guarded = Some(Box::new(val));
+                    e.handler_depth = depth + 1;
+                    Err(e)
+                },
+                Ok(None) => raise_continuable(obj, env).map_err(|e| e.outside(depth)),
+                Err(e) => Err(e.outside(depth))
+            }
+        }
+    };
+    root.borrow_mut().handlers.push(innermost);
+    res
+}
+
+// Runs body with a handler installed. An error that the handler can catch comes back in the Ok result,
+// and any other one is passed on.
+fn with_handler<F>(handler: Handler, env: &Rc<RefCell<Environment>>, body: F) -> Result<Result<Value, RuntimeError>, RuntimeError>
+    where F: FnOnce() -> Result<Value, RuntimeError> {
+    let root = Environment::get_root(env.clone());
+    let depth = root.borrow().handlers.len();
+    root.borrow_mut().handlers.push(handler);
+    let res = body();
+    root.borrow_mut().handlers.truncate(depth);
+    match res {
+        Err(e) => if depth < e.handler_depth { Ok(Err(e)) } else { Err(e) },
+        ok => Ok(ok)
+    }
+}
+
+fn native_with_exception_handler(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to with-exception-handler: {:?}", args);
+    }
+    let mut procedures = vec![];
+    for arg in args {
+        match try!(evaluate_value(arg, env.clone())) {
+            Value::Procedure(f) => procedures.push(f),
+            v => runtime_error!(WrongType(v); "Must supply procedures to with-exception-handler: {:?}", v)
+        }
+    }
+    let (thunk, handler) = (procedures.pop().unwrap(), procedures.pop().unwrap());
+    match try!(with_handler(Handler::Procedure(handler.clone()), &env, || apply_function_to_values(&thunk, vec![], env.clone()))) {
+        Err(e) => {
+            let condition = e.condition();
+            try!(apply_function_to_values(&handler, vec![condition.clone()], env));
+            // the error is where the raise was, as the handler returned to it
+            let mut err = RuntimeError::new(format!("Exception handler returned from a non-continuable raise of {:?}", condition));
+            err.location = e.location.clone();
+            Err(err)
+        },
+        ok => ok
+    }
+}
+
+// (guard (<variable> <clause> ...) <body> ...) picks a clause the way cond does for what the body
+// raised, and raises it again if none of them match. What raise-continuable raises is handled where
+// it was raised, so the body carries on if the handler outside the guard returns.
+fn native_guard(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to guard: {:?}", args);
+    }
+    let (var, clauses) = match args[0] {
+        Value::List(ref spec) if !spec.is_empty() => match spec[0] {
+            Value::Symbol(ref var) => (var.clone(), Rc::new(spec[1..].to_vec())),
+            _ => runtime_error!("Unexpected value for the variable in guard: {:?}", spec[0])
+        },
+        _ => runtime_error!("Must supply a variable in guard: {:?}", args)
+    };
+    let body_env = Environment::new_child(env.clone());
+    let winds = Environment::get_root(env.clone()).borrow().winds.len();
+    let guard = Handler::Guard(var.clone(), clauses.clone(), env.clone(), winds);
+    match try!(with_handler(guard, &env, || evaluate_values(&args[1..], body_env))) {
+        Err(ref mut e) if e.guarded.is_some() => Ok(*e.guarded.take().unwrap()),
+        Err(e) => {
+            match try!(guard_clauses(&var, &clauses, e.condition(), env)) {
+                Some(val) => Ok(val),
+                None => Err(e)
+            }
+        },
+        ok => ok
+    }
+}
+
+// Runs the after thunks of the winds inside the guard, innermost first, to run clauses outside them.
+// Unless a clause matched, their before thunks are run again to go back to where obj was raised.
+fn guard_outside<F>(winds: usize, env: &Rc<RefCell<Environment>>, clauses: F) -> Result<Option<Value>, RuntimeError>
+    where F: FnOnce() -> Result<Option<Value>, RuntimeError> {
+    let root = Environment::get_root(env.clone());
+    let mut left = vec![];
+    loop {
+        let wind = {
+            let mut root = root.borrow_mut();
+            if root.winds.len() <= winds {
+                break;
+            }
+            root.winds.pop().unwrap()
+        };
+        try!((wind.1)());
+        left.push(wind);
+    }
+    let res = try!(clauses());
+    if res.is_none() {
+        for wind in left.into_iter().rev() {
+            try!((wind.0)());
+            root.borrow_mut().winds.push(wind);
+        }
+    }
+    Ok(res)
+}
+
+fn guard_clauses(var: &str, clauses: &[Value], condition: Value, env: Rc<RefCell<Environment>>) -> Result<Option<Value>, RuntimeError> {
+    let clause_env = Environment::new_child(env);
+    try!(clause_env.borrow_mut().define(var.to_string(), condition));
+    evaluate_cond(clauses, clause_env)
+}
+
+// A single value is itself, and any other number of them is packed up until something unpacks them
+fn native_values(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut vals = vec![];
+    for arg in args {
+        vals.push(try!(evaluate_value(arg, env.clone())));
+    }
+    if vals.len() == 1 {
+        return Ok(vals.pop().unwrap());
+    }
+    Ok(Value::Values(vals))
+}
+
+fn unpack_values(val: Value) -> Vec<Value> {
+    match val {
+        Value::Values(vals) => vals,
+        val => vec![val]
+    }
+}
+
+fn native_call_with_values(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to call-with-values: {:?}", args);
+    }
+    let mut procedures = vec![];
+    for arg in args {
+        match try!(evaluate_value(arg, env.clone())) {
+            Value::Procedure(f) => procedures.push(f),
+            v => runtime_error!(WrongType(v); "Must supply procedures to call-with-values: {:?}", v)
+        }
+    }
+    let vals = unpack_values(try!(apply_function_to_values(&procedures[0], vec![], env.clone())));
+    apply_function_to_values(&procedures[1], vals, env)
+}
+
+// Evaluates init and defines its values in env, the way a procedure with the formals would get them as arguments
+fn define_values(formals: &Value, init: &Value, init_env: Rc<RefCell<Environment>>, env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    let params = try!(Params::parse(formals));
+    let vals = unpack_values(try!(evaluate_tail(init, init_env)));
+    try!(params.check_arity(&vals));
+    params.define(vals, env)
+}
+
+// let-values evaluates all the inits outside the new environment, and let*-values evaluates each one
+// inside the formals before it
+fn native_let_values(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_let_values("let-values", args, env)
+}
+
+fn native_let_star_values(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_let_values("let*-values", args, env)
+}
+
+fn evaluate_let_values(f: &str, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to {}: {:?}", f, args);
+    }
+    let bindings = match args[0] {
+        Value::List(ref bindings) => bindings,
+        _ => runtime_error!("Expected a list of bindings in {}: {:?}", f, args[0])
+    };
+    let mut let_env = Environment::new_child(env.clone());
+    for binding in bindings.iter() {
+        match *binding {
+            Value::List(ref binding) if binding.len() == 2 => {
+                let init_env = if f == "let*-values" { let_env.clone() } else { env.clone() };
+                try!(define_values(&binding[0], &binding[1], init_env, &let_env));
+            },
+            _ => runtime_error!("Unexpected value for a binding in {}: {:?}", f, binding)
+        }
+        if f == "let*-values" {
+            let_env = Environment::new_child(let_env);
+        }
+    }
+    evaluate_values(&args[1..], Environment::new_child(let_env))
+}
+
+// (receive <formals> <expression> <body> ...) is (let-values ((<formals> <expression>)) <body> ...)
+fn native_receive(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 3 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least three arguments to receive: {:?}", args);
+    }
+    let let_env = Environment::new_child(env.clone());
+    try!(define_values(&args[0], &args[1], env, &let_env));
+    evaluate_values(&args[2..], Environment::new_child(let_env))
+}
+
+fn native_define_values(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to define-values: {:?}", args);
+    }
+    try!(define_values(&args[0], &args[1], env.clone(), &env));
+    Ok(null!())
+}
+
+// There are no continuations to escape or re-enter through here, so leaving the thunk means returning
+// or failing, and the after thunk is called either way, unless a guard has already left the thunk
+fn wind(before: Winder, thunk: &dyn Fn() -> Result<Value, RuntimeError>, after: Winder, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let root = Environment::get_root(env.clone());
+    try!(before());
+    let depth = root.borrow().winds.len();
+    root.borrow_mut().winds.push((before, after.clone()));
+    let res = thunk();
+    if root.borrow().winds.len() > depth {
+        root.borrow_mut().winds.truncate(depth);
+        try!(after());
+    }
+    res
+}
+
+fn native_dynamic_wind(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 3 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly three arguments to dynamic-wind: {:?}", args);
+    }
+    let mut thunks = vec![];
+    for arg in args {
+        match try!(evaluate_value(arg, env.clone())) {
+            Value::Procedure(f) => thunks.push(f),
+            v => runtime_error!(WrongType(v); "Must supply procedures to dynamic-wind: {:?}", v)
+        }
+    }
+    let winder = |f: Function, env: Rc<RefCell<Environment>>| -> Winder { Rc::new(move || apply_function_to_values(&f, vec![], env.clone())) };
+    wind(winder(thunks[0].clone(), env.clone()), &|| apply_function_to_values(&thunks[1], vec![], env.clone()), winder(thunks[2].clone(), env.clone()), &env)
+}
+
+fn native_make_parameter(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply one or two arguments to make-parameter: {:?}", args);
+    }
+    let mut value = try!(evaluate_value(&args[0], env.clone()));
+    let converter = match args.get(1) {
+        Some(arg) => match try!(evaluate_value(arg, env.clone())) {
+            // the converter is applied to the initial value as well
+            Value::Procedure(f) => {
+                value = try!(apply_function_to_values(&f, vec![value], env).and_then(one_value));
+                Some(f)
+            },
+            v => runtime_error!(WrongType(v); "The converter of a parameter must be a procedure: {:?}", v)
+        },
+        None => None
+    };
+    Ok(Value::Procedure(Function::Parameter(Rc::new(Parameter { value: RefCell::new(value), converter }))))
+}
+
+// (parameterize ((<parameter> <value>) ...) <body> ...) swaps the converted values into the parameters
+// on the way into the body, and swaps the old ones back out on the way out
+fn native_parameterize(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at least two arguments to parameterize: {:?}", args);
+    }
+    let bindings = match args[0] {
+        Value::List(ref bindings) => bindings,
+        _ => runtime_error!("Expected a list of bindings in parameterize: {:?}", args[0])
+    };
+    let mut parameters = vec![];
+    for binding in bindings.iter() {
+        let (parameter, value) = match *binding {
+            Value::List(ref binding) if binding.len() == 2 => {
+                (try!(evaluate_value(&binding[0], env.clone())), try!(evaluate_value(&binding[1], env.clone())))
+            },
+            _ => runtime_error!("Unexpected value for a binding in parameterize: {:?}", binding)
+        };
+        let parameter = match parameter {
+            Value::Procedure(Function::Parameter(p)) => p,
+            v => runtime_error!(WrongType(v); "Expected a parameter to parameterize: {:?}", v)
+        };
+        let value = match parameter.converter {
+            Some(ref converter) => try!(apply_function_to_values(converter, vec![value], env.clone()).and_then(one_value)),
+            None => value
+        };
+        parameters.push((parameter, RefCell::new(value)));
+    }
+    let swap: Winder = Rc::new(move || {
+        for (parameter, value) in &parameters {
+            let old = parameter.value.replace(value.borrow().clone());
+            *value.borrow_mut() = old;
+        }
+        Ok(null!())
+    });
+    let body_env = Environment::new_child(env.clone());
+    wind(swap.clone(), &|| evaluate_values(&args[1..], body_env.clone()), swap, &env)
+}
+
+fn evaluate_error_object(args: &[Value], env: Rc<RefCell<Environment>>, name: &str) -> Result<Rc<RuntimeError>, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::ErrorObject(e) => Ok(e),
+        v => runtime_error!(WrongType(v); "Expected an error object for {}: {:?}", name, v)
+    }
+}
+
+fn native_is_error_object(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one arguments to error: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to error-object?: {:?}", args);
     }
-    let e = try!(evaluate_value(&args[0], env.clone()));
-    runtime_error!("{:?}", e);
+    match try!(evaluate_value(&args[0], env)) {
+        Value::ErrorObject(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_error_object_message(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(try!(evaluate_error_object(args, env, "error-object-message")).message_value())
+}
+
+fn native_error_object_irritants(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(try!(evaluate_error_object(args, env, "error-object-irritants")).irritant_values())
 }
 
 fn native_apply(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to apply: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly two arguments to apply: {:?}", args);
     }
     let func = match try!(evaluate_value(&args[0], env.clone())) {
         Value::Procedure(func) => func,
-        _ => runtime_error!("First argument to apply must be a procedure: {:?}", args)
+        v => runtime_error!(WrongType(v); "First argument to apply must be a procedure: {:?}", args)
     };
     let func_args = match try!(evaluate_value(&args[1], env.clone())) {
         Value::List(func_args) => func_args,
-        _ => runtime_error!("Second argument to apply must be a list of arguments: {:?}", args)
+        v => runtime_error!(WrongType(v); "Second argument to apply must be a list of arguments: {:?}", args)
     };
     apply_function(&func, &func_args, env.clone())
 }
 
 fn native_eval(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to eval: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to eval: {:?}", args);
     }
 
     // eval is basically just a double-evaluation -- the first evaluate returns the data using the local envirnoment, and the second evaluate evaluates the data as code using the global environment
     let res = try!(evaluate_value(&args[0], env.clone()));
-    evaluate_value(&res, Environment::get_root(env))
+    let expanded = try!(Environment::expander(env.clone()).expand(&try!(res.to_node())));
+    evaluate_tail(&Value::from_node(&expanded), Environment::get_toplevel(env))
+}
+
+fn native_macroexpand(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to macroexpand: {:?}", args);
+    }
+    let form = try!(try!(evaluate_value(&args[0], env.clone())).to_node());
+    let expanded = try!(Environment::expander(env).macroexpand(&form));
+    Ok(Value::from_node(&expanded))
+}
+
+fn native_macroexpand_1(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to macroexpand-1: {:?}", args);
+    }
+    let form = try!(try!(evaluate_value(&args[0], env.clone())).to_node());
+    let expanded = try!(Environment::expander(env).macroexpand_1(&form));
+    Ok(Value::from_node(&expanded))
 }
 
 fn native_write(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to write: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to write: {:?}", args);
     }
 
     let val = try!(evaluate_value(&args[0], env.clone()));
@@ -797,7 +3125,7 @@ fn native_write(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value,
 
 fn native_display(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to display: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to display: {:?}", args);
     }
 
     let val = try!(evaluate_value(&args[0], env.clone()));
@@ -807,7 +3135,7 @@ fn native_display(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value
 
 fn native_displayln(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to displayln: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to displayln: {:?}", args);
     }
 
     let val = try!(evaluate_value(&args[0], env.clone()));
@@ -817,7 +3145,7 @@ fn native_displayln(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Val
 
 fn native_print(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to print: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to print: {:?}", args);
     }
 
     let val = try!(evaluate_value(&args[0], env.clone()));
@@ -828,10 +3156,41 @@ fn native_print(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value,
     Ok(null!())
 }
 
+// (gensym [<prefix>]) makes a fresh symbol for a macro to bind
+fn native_gensym(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() > 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply at most one argument to gensym: {:?}", args);
+    }
+    let prefix = match args.first() {
+        Some(arg) => match try!(evaluate_value(arg, env)) {
+            Value::Symbol(s) => s,
+            Value::String(s) => Rc::unwrap_or_clone(s),
+            v => runtime_error!(WrongType(v); "Expected a symbol or string as the prefix for gensym: {:?}", v)
+        },
+        None => "g".to_string()
+    };
+    Ok(Value::Symbol(expander::gensym(&prefix)))
+}
+
+// a fresh symbol for each element of the list, named after it if it's a symbol
+fn native_generate_temporaries(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly one argument to generate-temporaries: {:?}", args);
+    }
+    let list = match try!(evaluate_value(&args[0], env)) {
+        Value::List(list) => list,
+        v => runtime_error!(WrongType(v); "Must supply a list to generate-temporaries: {:?}", v)
+    };
+    Ok(Value::from_vec(list.iter().map(|v| match *v {
+        Value::Symbol(ref s) => Value::Symbol(expander::gensym(s)),
+        _ => Value::Symbol(expander::gensym("g"))
+    }).collect()))
+}
+
 #[allow(unused_variables)]
 fn native_newline(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 0 {
-        runtime_error!("Must supply exactly zero arguments to newline: {:?}", args);
+        runtime_error!(Arity(Value::from_vec(args.to_vec())); "Must supply exactly zero arguments to newline: {:?}", args);
     }
     println!("");
     Ok(null!())
@@ -839,12 +3198,12 @@ fn native_newline(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value
 
 #[test]
 fn test_interpreter_global_variables() {
-    assert_eq!(new().run(&[Node::List(vec![Node::Identifier("define".to_string()), Node::Identifier("x".to_string()), Node::Integer(2)]), Node::List(vec![Node::Identifier("+".to_string()), Node::Identifier("x".to_string()), Node::Identifier("x".to_string()), Node::Identifier("x".to_string())])]).unwrap(),
-               Value::Integer(6));
+    assert_eq!(new().run(&[Node::List(vec![Node::Identifier("define".to_string()), Node::Identifier("x".to_string()), Node::Number(Number::Integer(2))]), Node::List(vec![Node::Identifier("+".to_string()), Node::Identifier("x".to_string()), Node::Identifier("x".to_string()), Node::Identifier("x".to_string())])]).unwrap(),
+               Value::Number(Number::Integer(6)));
 }
 
 #[test]
 fn test_interpreter_global_function_definition() {
-    assert_eq!(new().run(&[Node::List(vec![Node::Identifier("define".to_string()), Node::Identifier("double".to_string()), Node::List(vec![Node::Identifier("lambda".to_string()), Node::List(vec![Node::Identifier("x".to_string())]), Node::List(vec![Node::Identifier("+".to_string()), Node::Identifier("x".to_string()), Node::Identifier("x".to_string())])])]), Node::List(vec![Node::Identifier("double".to_string()), Node::Integer(8)])]).unwrap(),
-               Value::Integer(16));
+    assert_eq!(new().run(&[Node::List(vec![Node::Identifier("define".to_string()), Node::Identifier("double".to_string()), Node::List(vec![Node::Identifier("lambda".to_string()), Node::List(vec![Node::Identifier("x".to_string())]), Node::List(vec![Node::Identifier("+".to_string()), Node::Identifier("x".to_string()), Node::Identifier("x".to_string())])])]), Node::List(vec![Node::Identifier("double".to_string()), Node::Number(Number::Integer(8))])]).unwrap(),
+               Value::Number(Number::Integer(16)));
 }
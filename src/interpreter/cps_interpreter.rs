@@ -1,10 +1,23 @@
+use crate::reader::lexer;
 use crate::reader::parser::*;
+use crate::reader::span::Span;
+use crate::error::{Error, Details, ErrorKind, Frame};
+use crate::core::character;
+use crate::core::number::{Number, NumberError};
+use crate::core::string;
+use crate::interpreter::syntax_rules::MacroError;
+use crate::interpreter::expander::{self, Expander, Evaluator, Transformer, Renaming};
 
 use std::fmt;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use std::iter;
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec;
 
 pub fn new() -> Result<Interpreter, RuntimeError> {
@@ -13,24 +26,68 @@ pub fn new() -> Result<Interpreter, RuntimeError> {
 
 #[derive(Clone)]
 pub struct Interpreter {
-    root: Rc<RefCell<Environment>>
+    toplevel: Rc<RefCell<Environment>>
 }
 
+// The SRFI 158 generators and accumulators, which are written in Scheme
+const GENERATORS: &str = include_str!("generators.scm");
+
 impl Interpreter {
     pub fn new() -> Result<Interpreter, RuntimeError> {
-        let env = try!(Environment::new_root());
-        Ok(Interpreter { root: env })
+        let toplevel = try!(Environment::new_toplevel());
+        // The library goes in the root with the natives, where programs can shadow it too
+        let tokens = try!(lexer::tokenize(GENERATORS).map_err(|e| RuntimeError::new(format!("Can't read the generators library: {}", e))));
+        let nodes = try!(parse(&tokens).map_err(|e| RuntimeError::new(format!("Can't read the generators library: {}", e))));
+        try!(process_toplevel(nodes.into_iter().map(|n| (n, None)).collect(), Environment::get_root(toplevel.clone())));
+        Ok(Interpreter { toplevel })
     }
 
     pub fn run(&self, nodes: &[Node]) -> Result<Value, RuntimeError> {
-        let exprs = List::from_nodes(nodes);
-        process(exprs, self.root.clone())
+        process_toplevel(nodes.iter().map(|n| (n.clone(), None)).collect(), self.toplevel.clone())
+    }
+
+    // Like run, but errors say where in the source they happened
+    pub fn run_spanned(&self, forms: &[(Node, SourceMap)]) -> Result<Value, RuntimeError> {
+        process_toplevel(forms.iter().map(|(n, map)| (n.clone(), Some(map.clone()))).collect(), self.toplevel.clone())
+    }
+
+    // How many bounces of the trampoline each green thread gets before the next one that's ready has a turn
+    pub fn set_time_slice(&self, bounces: usize) {
+        Environment::scheduler(self.toplevel.clone()).borrow_mut().slice = bounces.max(1);
+    }
+
+    // Expands the macros in a program without running it, defining the macros it defines
+    pub fn expand(&self, nodes: &[Node]) -> Result<Vec<Node>, RuntimeError> {
+        let expander = Environment::expander(self.toplevel.clone());
+        let mut expanded = vec![];
+        for node in nodes {
+            expanded.push(try!(expander.expand(node)));
+        }
+        Ok(expanded)
     }
 }
 
+// Errors are Other unless they say what they were about, as in runtime_error!(WrongType(val); "..."),
+// or at least what kind of error they are, when the value is gone
 macro_rules! runtime_error {
+    ($kind:ident($value:expr); $($arg:tt)*) => (
+        return Err(RuntimeError::new(format!($($arg)*)).of(ErrorKind::$kind, Some($value)))
+    );
+    ($kind:ident; $($arg:tt)*) => (
+        return Err(RuntimeError::new(format!($($arg)*)).of(ErrorKind::$kind, None))
+    );
     ($($arg:tt)*) => (
-        return Err(RuntimeError { message: format!($($arg)*)})
+        return Err(RuntimeError::new(format!($($arg)*)))
+    )
+}
+
+// For errors that happen while k is waiting for a value, so the calls it's part of go in the backtrace
+macro_rules! runtime_error_in {
+    ($k:expr, $kind:ident($value:expr); $($arg:tt)*) => (
+        return Err(RuntimeError::new(format!($($arg)*)).of(ErrorKind::$kind, Some($value)).unwinding(&$k))
+    );
+    ($k:expr, $($arg:tt)*) => (
+        return Err(RuntimeError::new(format!($($arg)*)).unwinding(&$k))
     )
 }
 
@@ -39,7 +96,7 @@ macro_rules! shift_or_error {
         try!(
             match $list.shift() {
                 Some((car, cdr)) => Ok((car, cdr)),
-                None => Err(RuntimeError { message: format!($($arg)*)})
+                None => Err(RuntimeError::new(format!($($arg)*)))
             }
         )
     )
@@ -48,14 +105,27 @@ macro_rules! shift_or_error {
 #[derive(PartialEq, Clone)]
 pub enum Value {
     Symbol(String),
-    Integer(i64),
+    Number(Number),
     Boolean(bool),
-    String(String),
+    Char(char),
+    // Strings have identity, so eq? can tell fresh strings apart
+    String(Rc<String>),
     List(List),
+    Vector(Rc<RefCell<Vec<Value>>>),
     Procedure(Function),
     SpecialForm(SpecialForm),
-    Macro(Vec<String>, Box<Value>),
-    Continuation(Box<Continuation>),
+    Continuation(Rc<Continuation>),
+    // The frames inside a prompt, down to a hole that's filled with the continuation it's called in
+    ComposableContinuation(Rc<Continuation>),
+    // None is the default tag, which reset and shift use
+    PromptTag(Option<Rc<PromptTag>>),
+    ErrorObject(Rc<RuntimeError>),
+    // What generators give once they've run out, and accumulators take to say there's no more
+    Eof,
+    Thread(Rc<Thread>),
+    Channel(Rc<Channel>),
+    // What a program gives back when it ends by returning other than one value
+    Values(Vec<Value>),
 }
 
 impl Value {
@@ -63,27 +133,128 @@ impl Value {
         List::from_vec(vec).to_value()
     }
 
+    // Builds a list ending in the given tail instead of null, which is improper unless the tail is a list
+    fn from_vec_dotted(vec: Vec<Value>, tail: Value) -> Value {
+        let mut out = tail;
+        for v in vec.into_iter().rev() {
+            out = List::cons(v, out).to_value();
+        }
+        out
+    }
+
+    fn from_vector(vec: Vec<Value>) -> Value {
+        Value::Vector(Rc::new(RefCell::new(vec)))
+    }
+
+    fn from_string(s: String) -> Value {
+        Value::String(Rc::new(s))
+    }
+
     fn from_node(node: &Node) -> Value {
         match *node {
             Node::Identifier(ref val) => Value::Symbol(val.clone()),
-            Node::Integer(val) => Value::Integer(val),
+            Node::Number(ref val) => Value::Number(val.clone()),
             Node::Boolean(val) => Value::Boolean(val),
-            Node::String(ref val) => Value::String(val.clone()),
-            Node::List(ref nodes) => Value::List(List::from_nodes(&nodes))
+            Node::Char(val) => Value::Char(val),
+            Node::String(ref val) => Value::from_string(val.clone()),
+            Node::List(ref nodes) => Value::List(List::from_nodes(nodes)),
+            Node::DottedList(ref nodes, ref tail) => Value::from_vec_dotted(nodes.iter().map(Value::from_node).collect(), Value::from_node(tail)),
+            Node::Vector(ref nodes) => Value::from_vector(nodes.iter().map(Value::from_node).collect())
+        }
+    }
+
+    // Like from_node, but each list remembers where it was read from, and so does each identifier in
+    // one. Macros can rewrite code into a different shape, and then everything they produce gets the
+    // span of the macro use.
+    fn from_node_spanned(node: &Node, map: &SourceMap) -> Value {
+        let (nodes, count, tail) = match *node {
+            Node::List(ref nodes) if !nodes.is_empty() => (nodes, nodes.len(), List::Null.to_value()),
+            Node::DottedList(ref nodes, ref tail) => {
+                let count = nodes.len() + 1;
+                (nodes, count, Value::from_node_spanned(tail, &map.part(count - 1, count)))
+            },
+            _ => return Value::from_node(node)
+        };
+        let mut out = tail;
+        for (i, node) in nodes.iter().enumerate().rev() {
+            let part = map.part(i, count);
+            let car_span = match *node {
+                Node::Identifier(_) => Some(Rc::new(part.span.clone())),
+                _ => None
+            };
+            let car = Value::from_node_spanned(node, &part);
+            out = List::Cell(Rc::new(RefCell::new(Pair { car, cdr: out, span: None, car_span }))).to_value();
+        }
+        match out {
+            Value::List(list) => list.with_span(Rc::new(map.span.clone())).to_value(),
+            val => val
+        }
+    }
+
+    // The inverse of from_node, for handing code to the macro expander
+    fn to_node(&self) -> Result<Node, RuntimeError> {
+        match *self {
+            Value::Symbol(ref val) => Ok(Node::Identifier(val.clone())),
+            Value::Number(ref val) => Ok(Node::Number(val.clone())),
+            Value::Boolean(val) => Ok(Node::Boolean(val)),
+            Value::Char(val) => Ok(Node::Char(val)),
+            Value::String(ref val) => Ok(Node::String((**val).clone())),
+            Value::List(ref list) => {
+                let mut nodes = vec![];
+                let mut list = list.clone();
+                loop {
+                    let cdr = match list {
+                        List::Cell(ref pair) => {
+                            let pair = pair.borrow();
+                            nodes.push(try!(pair.car.to_node()));
+                            pair.cdr.clone()
+                        },
+                        List::Null => return Ok(Node::List(nodes))
+                    };
+                    list = match cdr {
+                        Value::List(next) => next,
+                        tail => return Ok(Node::DottedList(nodes, Box::new(try!(tail.to_node()))))
+                    };
+                }
+            },
+            Value::Vector(ref vec) => {
+                let res: Result<Vec<Node>, RuntimeError> = vec.borrow().iter().map(|v| v.to_node()).collect();
+                Ok(Node::Vector(try!(res)))
+            },
+            _ => runtime_error!("Can't use {:?} as syntax", self)
+        }
+    }
+
+    // For values in errors, which may not be data, or may be circular
+    fn to_datum(&self) -> Node {
+        let mut cycles = HashMap::new();
+        find_cycles(self, &mut HashSet::new(), &mut HashSet::new(), &mut cycles);
+        if cycles.is_empty() {
+            if let Ok(node) = self.to_node() {
+                return node;
+            }
         }
+        Node::Identifier(format!("{:?}", self))
     }
 
     fn as_symbol(self) -> Result<String, RuntimeError> {
         match self {
             Value::Symbol(s) => Ok(s),
-            _ => runtime_error!("Expected a symbol value: {:?}", self)
+            _ => runtime_error!(WrongType(self); "Expected a symbol value: {:?}", self)
+        }
+    }
+
+    fn as_number(self) -> Result<Number, RuntimeError> {
+        match self {
+            Value::Number(n) => Ok(n),
+            _ => runtime_error!(WrongType(self); "Expected a number value: {:?}", self)
         }
     }
 
-    fn as_integer(self) -> Result<i64, RuntimeError> {
+    fn as_char(self) -> Result<char, RuntimeError> {
         match self {
-            Value::Integer(i) => Ok(i),
-            _ => runtime_error!("Expected an integer value: {:?}", self)
+            Value::Char(c) => Ok(c),
+            _ => runtime_error!(WrongType(self); "Expected a char value: {:?}", self)
         }
     }
 
@@ -94,33 +265,97 @@ impl Value {
     //     }
     // }
 
-    // fn as_string(self) -> Result<String, RuntimeError> {
-    //     match self {
-    //         Value::String(s) => Ok(s),
-    //         _ => runtime_error!("Expected a string value: {:?}", self)
-    //     }
-    // }
+    fn as_string(self) -> Result<String, RuntimeError> {
+        match self {
+            Value::String(s) => Ok(Rc::try_unwrap(s).unwrap_or_else(|s| (*s).clone())),
+            _ => runtime_error!(WrongType(self); "Expected a string value: {:?}", self)
+        }
+    }
+
+    fn as_index(self) -> Result<usize, RuntimeError> {
+        match self {
+            Value::Number(Number::Integer(i)) if i >= 0 => Ok(i as usize),
+            _ => runtime_error!(WrongType(self); "Expected a non-negative integer index: {:?}", self)
+        }
+    }
 
     fn as_list(self) -> Result<List, RuntimeError> {
         match self {
-            Value::List(l) => Ok(l),
-            _ => runtime_error!("Expected a list value: {:?}", self)
+            Value::List(l) => {
+                if !l.is_proper() {
+                    runtime_error!(WrongType(l.to_value()); "Expected a proper list: {:?}", l)
+                }
+                Ok(l)
+            },
+            _ => runtime_error!(WrongType(self); "Expected a list value: {:?}", self)
+        }
+    }
+
+    fn as_pair(self) -> Result<(Value, Value), RuntimeError> {
+        match self {
+            Value::List(List::Cell(pair)) => {
+                let pair = pair.borrow();
+                Ok((pair.car.clone(), pair.cdr.clone()))
+            },
+            _ => runtime_error!(WrongType(self); "Expected a pair: {:?}", self)
+        }
+    }
+
+    fn as_vector(self) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+        match self {
+            Value::Vector(v) => Ok(v),
+            _ => runtime_error!(WrongType(self); "Expected a vector value: {:?}", self)
         }
     }
+
+    // Identity, as used by eq? and eqv?: pairs and vectors are only the same if they share their
+    // storage. Strings can't be mutated, so two strings with the same characters can't be told apart.
+    fn is(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.is_eqv(b),
+            (Value::List(a), Value::List(b)) => a.is(b),
+            // Empty strings hold nothing to tell them apart by, so they're all the same object
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b) || a.is_empty() && b.is_empty(),
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Procedure(a), Value::Procedure(b)) => a.is(b),
+            (Value::ErrorObject(a), Value::ErrorObject(b)) => Rc::ptr_eq(a, b),
+            (Value::Continuation(a), Value::Continuation(b)) => Rc::ptr_eq(a, b),
+            (Value::ComposableContinuation(a), Value::ComposableContinuation(b)) => Rc::ptr_eq(a, b),
+            _ => self == other
+        }
+    }
+
+    // Structural equality, as used by equal?
+    fn equal(&self, other: &Value) -> bool {
+        values_equal(self, other, &mut HashSet::new())
+    }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Value::Symbol(ref val) => write!(f, "{}", val),
-            Value::Integer(val)    => write!(f, "{}", val),
+            Value::Number(ref val) => write!(f, "{}", val),
             Value::Boolean(val)    => write!(f, "#{}", if val { "t" } else { "f" }),
+            Value::Char(val)       => write!(f, "{}", val),
             Value::String(ref val) => write!(f, "{}", val),
-            Value::List(ref list)  => write!(f, "{}", list),
+            Value::List(_) | Value::Vector(_) => Printer::new(false, self).value(f, self),
             Value::Procedure(_)    => write!(f, "#<procedure>"),
             Value::SpecialForm(_)  => write!(f, "#<special_form>"),
-            Value::Continuation(_) => write!(f, "#<continuation>"),
-            Value::Macro(_,_)      => write!(f, "#<macro>"),
+            Value::Continuation(_) | Value::ComposableContinuation(_) => write!(f, "#<continuation>"),
+            Value::PromptTag(None) => write!(f, "#<continuation-prompt-tag:default>"),
+            Value::PromptTag(Some(ref tag)) => match tag.name {
+                Some(ref name) => write!(f, "#<continuation-prompt-tag:{}>", name),
+                None => write!(f, "#<continuation-prompt-tag>")
+            },
+            Value::ErrorObject(ref e) => write!(f, "#<error-object {}>", e.message),
+            Value::Eof => write!(f, "#<eof>"),
+            Value::Thread(ref thread) => write!(f, "{:?}", thread),
+            Value::Channel(_) => write!(f, "#<channel>"),
+            Value::Values(ref vals) => {
+                let strs: Vec<String> = vals.iter().map(|v| format!("{}", v)).collect();
+                write!(f, "{}", &strs.join("\n"))
+            },
         }
     }
 }
@@ -128,8 +363,13 @@ impl fmt::Display for Value {
 impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Value::String(ref val) => write!(f, "\"{}\"", val),
-            Value::List(ref list)  => write!(f, "{:?}", list),
+            Value::Char(val)       => write!(f, "{}", character::Written(val)),
+            Value::String(ref val) => write!(f, "{}", string::Written(val)),
+            Value::List(_) | Value::Vector(_) => Printer::new(true, self).value(f, self),
+            Value::Values(ref vals) => {
+                let strs: Vec<String> = vals.iter().map(|v| format!("{:?}", v)).collect();
+                write!(f, "{}", &strs.join("\n"))
+            },
             _                      => write!(f, "{}", self)
         }
     }
@@ -137,16 +377,218 @@ impl fmt::Debug for Value {
 
 #[derive(Clone, PartialEq)]
 pub enum Function {
-    Scheme(Vec<String>, List, Rc<RefCell<Environment>>),
+    // the name is the one it was defined with, for backtraces
+    Scheme(Params, List, Rc<RefCell<Environment>>, Option<Rc<str>>),
+    CaseLambda(Vec<(Params, List)>, Rc<RefCell<Environment>>),
     Native(&'static str),
+    // the rename and compare procedures an er-macro-transformer is called with
+    Rename(Rc<Renaming>),
+    Compare(Rc<Renaming>),
+    Parameter(Rc<Parameter>),
+}
+
+// What make-parameter makes: calling it gives its value, which parameterize changes for the extent
+// of its body, after passing the new value through the converter
+pub struct Parameter {
+    value: RefCell<Value>,
+    converter: Option<Value>,
+}
+
+impl PartialEq for Parameter {
+    fn eq(&self, other: &Parameter) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// What make-continuation-prompt-tag makes, which is only ever the same tag as itself
+pub struct PromptTag {
+    name: Option<String>,
+}
+
+impl PartialEq for PromptTag {
+    fn eq(&self, other: &PromptTag) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// What spawn makes, or the main thread, which a program runs in until it spawns others. Its result is
+// the values it returned, or the error it stopped with, once it's done.
+pub struct Thread {
+    id: usize,
+    // Only the call of process that started a main thread can run it
+    main: bool,
+    result: RefCell<Option<Result<Vec<Value>, Rc<RuntimeError>>>>,
+    // The threads waiting for this one to finish in thread-join!
+    joiners: RefCell<Vec<Waiter>>,
+}
+
+// A thread that's waiting, with the continuation it carries on in
+type Waiter = (Rc<Thread>, Rc<Continuation>);
+
+impl PartialEq for Thread {
+    fn eq(&self, other: &Thread) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.main {
+            write!(f, "#<thread:main>")
+        } else {
+            write!(f, "#<thread:{}>", self.id)
+        }
+    }
+}
+
+// What make-channel makes. Items wait in it until they're taken, as many as the capacity when it has
+// one, and threads wait in it to put items while it's full, or to take them while it's empty.
+pub struct Channel {
+    capacity: Option<usize>,
+    items: RefCell<VecDeque<Value>>,
+    putters: RefCell<VecDeque<(Waiter, Value)>>,
+    getters: RefCell<VecDeque<Waiter>>,
+}
+
+impl PartialEq for Channel {
+    fn eq(&self, other: &Channel) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Function {
+    // Procedures are only identical if they come from the same lambda, evaluated in the same environment
+    fn is(&self, other: &Function) -> bool {
+        match (self, other) {
+            (Function::Scheme(_, a, a_env, _), Function::Scheme(_, b, b_env, _)) => {
+                a.is(b) && Rc::ptr_eq(a_env, b_env)
+            },
+            (Function::CaseLambda(a, a_env), Function::CaseLambda(b, b_env)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|((_, a), (_, b))| a.is(b)) && Rc::ptr_eq(a_env, b_env)
+            },
+            (Function::Native(a), Function::Native(b)) => a == b,
+            (Function::Rename(a), Function::Rename(b)) => Rc::ptr_eq(a, b),
+            (Function::Compare(a), Function::Compare(b)) => Rc::ptr_eq(a, b),
+            (Function::Parameter(a), Function::Parameter(b)) => Rc::ptr_eq(a, b),
+            _ => false
+        }
+    }
 }
 
 impl fmt::Debug for Function {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Function::Scheme(_, _, _) => write!(f, "#<procedure>"),
+            Function::Scheme(_, _, _, _) => write!(f, "#<procedure>"),
+            Function::CaseLambda(_, _) => write!(f, "#<procedure>"),
             Function::Native(ref s) => write!(f, "#<procedure:{}>", s),
+            Function::Rename(_) => write!(f, "#<procedure:rename>"),
+            Function::Compare(_) => write!(f, "#<procedure:compare>"),
+            Function::Parameter(_) => write!(f, "#<procedure:parameter>"),
+        }
+    }
+}
+
+// The parameters of a lambda: the required ones, then #!optional ones along with the expressions for
+// their defaults, then a rest parameter that collects any remaining arguments into a list
+#[derive(Clone, PartialEq, Debug)]
+pub struct Params {
+    required: Vec<String>,
+    optional: Vec<(String, Value)>,
+    rest: Option<String>,
+}
+
+impl Params {
+    // Accepts (a b), (a b . rest) and a lone symbol, which takes every argument as a list. An optional
+    // parameter is either a name, which defaults to #f, or (name default), e.g. (a #!optional (b 1) #!rest c)
+    fn parse(formals: Value) -> Result<Params, RuntimeError> {
+        let mut items = vec![];
+        let mut cur = formals;
+        let tail = loop {
+            match cur {
+                Value::List(List::Null) => break None,
+                Value::List(_) => {
+                    let (car, cdr) = try!(cur.as_pair());
+                    items.push(car);
+                    cur = cdr;
+                },
+                other => break Some(other)
+            }
+        };
+
+        #[derive(PartialEq)]
+        enum Section { Required, Optional, Rest }
+        let mut section = Section::Required;
+        let mut params = Params { required: vec![], optional: vec![], rest: None };
+        for item in items {
+            match item {
+                Value::Symbol(ref s) if s.starts_with("#!") => {
+                    section = match (s.as_ref(), section) {
+                        ("#!optional", Section::Required) => Section::Optional,
+                        ("#!rest", Section::Required) | ("#!rest", Section::Optional) => Section::Rest,
+                        _ => runtime_error!("Unexpected {} in parameter list", s)
+                    };
+                },
+                Value::Symbol(s) => {
+                    match section {
+                        Section::Required => params.required.push(s),
+                        Section::Optional => params.optional.push((s, Value::Boolean(false))),
+                        Section::Rest if params.rest.is_none() => params.rest = Some(s),
+                        Section::Rest => runtime_error!("Only one parameter can follow #!rest: {}", s)
+                    }
+                },
+                Value::List(ref l) if section == Section::Optional => {
+                    let (name, default) = try!(l.clone().unpack2());
+                    params.optional.push((try!(name.as_symbol()), default));
+                },
+                _ => runtime_error!("Unexpected value in parameter list: {:?}", item)
+            }
+        }
+        if section == Section::Rest && params.rest.is_none() {
+            runtime_error!("Missing parameter after #!rest");
+        }
+        match tail {
+            Some(Value::Symbol(s)) if params.rest.is_none() => params.rest = Some(s),
+            Some(other) => runtime_error!("Unexpected value for rest parameter: {:?}", other),
+            None => ()
+        }
+        Ok(params)
+    }
+
+    fn accepts(&self, n: usize) -> bool {
+        n >= self.required.len() && (self.rest.is_some() || n <= self.required.len() + self.optional.len())
+    }
+
+    fn check_arity(&self, args: &List) -> Result<(), RuntimeError> {
+        if self.accepts(args.len()) {
+            return Ok(());
+        }
+        let min = self.required.len();
+        if self.rest.is_some() {
+            runtime_error!(Arity(args.clone().to_value()); "Must supply at least {} arguments to function: {:?}", min, args)
+        } else if self.optional.is_empty() {
+            runtime_error!(Arity(args.clone().to_value()); "Must supply exactly {} arguments to function: {:?}", min, args)
+        } else {
+            runtime_error!(Arity(args.clone().to_value()); "Must supply between {} and {} arguments to function: {:?}", min, min + self.optional.len(), args)
+        }
+    }
+
+    // Defines the supplied arguments, and returns the optional parameters that still need their defaults
+    fn bind(&self, args: List, env: &Rc<RefCell<Environment>>) -> Result<Vec<(String, Value)>, RuntimeError> {
+        let mut args = args.into_iter();
+        for name in self.required.iter() {
+            try!(env.borrow_mut().define(name.clone(), args.next().unwrap()));
+        }
+        let mut missing = vec![];
+        for (name, default) in self.optional.iter() {
+            match args.next() {
+                Some(arg) => try!(env.borrow_mut().define(name.clone(), arg)),
+                None => missing.push((name.clone(), default.clone()))
+            }
+        }
+        if let Some(ref name) = self.rest {
+            try!(env.borrow_mut().define(name.clone(), Value::from_vec(args.collect())));
         }
+        Ok(missing)
     }
 }
 
@@ -156,7 +598,16 @@ pub enum SpecialForm {
     Define,
     Set,
     Lambda,
+    CaseLambda,
     Let,
+    LetStar,
+    Letrec,
+    LetrecStar,
+    Cond,
+    Case,
+    When,
+    Unless,
+    Do,
     Quote,
     Quasiquote,
     Eval,
@@ -165,62 +616,297 @@ pub enum SpecialForm {
     And,
     Or,
     CallCC,
-    DefineSyntaxRule,
+    Macroexpand,
+    Macroexpand1,
+    Guard,
+    Parameterize,
+    LetValues,
+    LetStarValues,
+    Receive,
+    DefineValues,
+    Reset,
+    Shift,
 }
 
 pub enum Trampoline {
-    Bounce(Value, Rc<RefCell<Environment>>, Continuation),
-    QuasiBounce(Value, Rc<RefCell<Environment>>, Continuation),
-    Run(Value, Continuation),
+    Bounce(Value, Rc<RefCell<Environment>>, Rc<Continuation>),
+    // Bounce for an identifier read from a source file, which knows where it is for error messages
+    Lookup(String, Rc<Span>, Rc<RefCell<Environment>>, Rc<Continuation>),
+    QuasiBounce(Value, usize, Rc<RefCell<Environment>>, Rc<Continuation>),
+    Run(Value, Rc<Continuation>),
+    // Like Run, for any number of values
+    RunValues(Vec<Value>, Rc<Continuation>),
+    // Raises an error that happened where k was waiting, for its handlers to catch
+    Raise(Value, Rc<Continuation>),
+    // Hands the running thread over to the scheduler, which only process can get at
+    Schedule(Request),
     Land(Value),
 }
 
+// What a thread asks of the scheduler, with the continuation it waits in for the answer
+pub enum Request {
+    Spawn(Value, Rc<Continuation>),
+    Yield(Rc<Continuation>),
+    // until the time it wakes up
+    Sleep(Instant, Rc<Continuation>),
+    Join(Rc<Thread>, Rc<Continuation>),
+    Put(Rc<Channel>, Value, Rc<Continuation>),
+    Get(Rc<Channel>, Rc<Continuation>),
+    Current(Rc<Continuation>),
+    // A thread has returned these values from its thunk
+    End(Rc<Thread>, Vec<Value>),
+}
+
+impl Trampoline {
+    // Where in the source the step is: the list it's about to evaluate, or else the call waiting for its
+    // result. The environment is the one the code is evaluated in, which tells the procedure it's part of.
+    fn position(&self) -> Option<(Rc<Span>, Rc<RefCell<Environment>>)> {
+        match *self {
+            Trampoline::Bounce(Value::List(ref list), ref env, ref k) => match list.span() {
+                Some(span) => Some((span, env.clone())),
+                None => k.position()
+            },
+            Trampoline::Bounce(_, _, ref k) | Trampoline::Lookup(_, _, _, ref k) | Trampoline::QuasiBounce(_, _, _, ref k) | Trampoline::Run(_, ref k) |
+            Trampoline::RunValues(_, ref k) | Trampoline::Raise(_, ref k) => k.position(),
+            Trampoline::Schedule(_) | Trampoline::Land(_) => None
+        }
+    }
+}
+
+// A frame of the Scheme call stack, waiting for a value. Each holds the one it returns to in an Rc,
+// so capturing a continuation with call/cc shares the frames instead of copying them.
 #[derive(PartialEq, Clone, Debug)]
 pub enum Continuation {
-    EvaluateExpressions(List, Rc<RefCell<Environment>>, Box<Continuation>),
-    BeginFunc(List, Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateIf(Value, Value, Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateDefine(String, Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateSet(String, Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateFunc(Value, List, List, Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateLet(String, List, List, Rc<RefCell<Environment>>, Box<Continuation>),
-    ContinueQuasiquoting(List, List, Rc<RefCell<Environment>>, Box<Continuation>),
-    ExecuteEval(Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateApplyArgs(Value, Rc<RefCell<Environment>>, Box<Continuation>),
-    ExecuteApply(Value, Box<Continuation>),
-    EvaluateAnd(List, Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateOr(List, Rc<RefCell<Environment>>, Box<Continuation>),
-    ExecuteCallCC(Box<Continuation>),
+    EvaluateExpressions(List, Rc<RefCell<Environment>>, Rc<Continuation>),
+    // The top-level forms of a program, with where they were read from if that's known, and the
+    // position of the next one to run. Each is expanded just before it runs, so it can use the macros
+    // defined before it, and a continuation captured at the top level still has the rest to run.
+    EvaluateToplevel(Rc<Vec<(Node, Option<SourceMap>)>>, usize, Rc<RefCell<Environment>>, Rc<Continuation>),
+    BeginFunc(List, Option<Rc<Span>>, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateIf(Value, Value, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateDefine(String, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateSet(String, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateFunc(Value, List, List, Option<Rc<Span>>, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateLet(String, List, List, Rc<RefCell<Environment>>, Rc<Continuation>),
+    ContinueQuasiquoting(Quasiquotation, Rc<RefCell<Environment>>, Rc<Continuation>),
+    SpliceQuasiquoted(Quasiquotation, Rc<RefCell<Environment>>, Rc<Continuation>),
+    FinishQuasiquoting(Vec<Value>, Rc<Continuation>),
+    WrapQuasiquoted(&'static str, Rc<Continuation>),
+    ExecuteEval(Rc<RefCell<Environment>>, Rc<Continuation>),
+    ExecuteMacroexpand(bool, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateApplyArgs(Value, Rc<RefCell<Environment>>, Rc<Continuation>),
+    ExecuteApply(Value, Rc<Continuation>),
+    EvaluateAnd(List, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateOr(List, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateCase(List, Rc<RefCell<Environment>>, Rc<Continuation>),
+    ExecuteCallCC(Rc<Continuation>),
+    ContinueVectorMap(Value, Vec<List>, Vec<Value>, bool, Rc<Continuation>),
+    // The handlers installed by with-exception-handler, innermost first, for the code that returns here.
+    // A handler that's called returns here too, with where the exception it's handling was raised.
+    Handlers(List, Option<(Rc<Span>, Rc<RefCell<Environment>>)>, Rc<Continuation>),
+    // Where a handler for a raise that isn't continuable returns to, which it mustn't do
+    HandlerReturned(Value, Rc<Continuation>),
+    // The dynamic-wind calls that the code returning here is inside, innermost first, as (before . after) pairs
+    Winders(List, Rc<Continuation>),
+    ExecuteWindThunk(Value, Value, Value, Rc<Continuation>),
+    ExecuteWindAfter(Value, Rc<Continuation>),
+    // Passes on the values it holds instead of the ones it gets, as dynamic-wind does once the after thunk returns
+    ContinueWith(Vec<Value>, Rc<Continuation>),
+    // The thunks still to call on the way into a continuation, last first, each with the winders it runs inside
+    ContinueWinding(Vec<(Value, List)>, Vec<Value>, Rc<Continuation>),
+    ExecuteMakeParameter(Value, Rc<Continuation>),
+    ExecuteCallWithValues(Value, Rc<Continuation>),
+    // The formals the values are for, the bindings after them, the body, the environment the inits are
+    // evaluated in and the one the formals are bound in
+    EvaluateLetValues(Params, List, List, Rc<RefCell<Environment>>, Rc<RefCell<Environment>>, Rc<Continuation>),
+    EvaluateDefineValues(Params, Rc<RefCell<Environment>>, Rc<Continuation>),
+    // Where an uncaught error goes once the after thunks it escaped through have been called
+    Abort(Box<RuntimeError>),
+    // A prompt, with its tag and the handler that the values aborted to it are passed to, if any
+    Prompt(Value, Option<Value>, Rc<Continuation>),
+    // The bottom of a composable continuation, where the continuation it's called in goes. It keeps
+    // the handlers and winders from outside the prompt, which the frames above it have at the end of theirs.
+    Hole(List, List),
+    // Where the thunk of a thread that spawn made returns to, which ends the thread
+    EndThread(Rc<Thread>),
     Return,
 }
 
-#[derive(Debug)]
+// A list or vector part way through being quasiquoted. The elements still to go are popped off the
+// end, so they're stored in reverse order. The tail is () for a proper list, and None for a vector.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Quasiquotation {
+    todo: Vec<Value>,
+    done: Vec<Value>,
+    tail: Option<Value>,
+    depth: usize,
+}
+
+#[derive(PartialEq, Clone, Debug)]
 pub struct RuntimeError {
+    kind: ErrorKind,
     message: String,
+    value: Option<Box<Value>>,
+    irritants: Vec<Value>,
+    location: Option<Rc<Span>>,
+    backtrace: Vec<Frame>,
+    // Where the program was when the error happened, so the handlers installed there can catch it
+    continuation: Option<Rc<Continuation>>,
+}
+
+impl RuntimeError {
+    fn new(message: String) -> RuntimeError {
+        RuntimeError { kind: ErrorKind::Other, message, value: None, irritants: vec![], location: None, backtrace: vec![], continuation: None }
+    }
+
+    fn of(mut self, kind: ErrorKind, value: Option<Value>) -> RuntimeError {
+        self.kind = kind;
+        self.value = value.map(Box::new);
+        self
+    }
+
+    // Errors are located by the innermost code that knows where it is, so an existing location is kept
+    fn at(mut self, location: Option<Rc<Span>>) -> RuntimeError {
+        if self.location.is_none() {
+            self.location = location;
+        }
+        self
+    }
+
+    // The continuation is the Scheme call stack, so the calls waiting on it make up the backtrace
+    fn unwinding(mut self, k: &Rc<Continuation>) -> RuntimeError {
+        if self.backtrace.is_empty() {
+            self.backtrace = k.frames();
+        }
+        if self.continuation.is_none() {
+            self.continuation = Some(k.clone());
+        }
+        self
+    }
+
+    // What error-object-message gives: the message error was called with, or a built-in error's own
+    fn message_value(&self) -> Value {
+        match (self.kind, &self.value) {
+            (ErrorKind::Raised, Some(msg)) => (**msg).clone(),
+            _ => Value::from_string(self.message.clone())
+        }
+    }
+
+    // What error-object-irritants gives: the irritants error was called with, or what a built-in error was about
+    fn irritant_values(&self) -> Value {
+        match (self.kind, &self.value) {
+            (ErrorKind::Raised, _) => Value::from_vec(self.irritants.clone()),
+            (_, Some(val)) => Value::from_vec(vec![(**val).clone()]),
+            _ => List::Null.to_value()
+        }
+    }
+}
+
+// A call made at span, by the procedure whose body env belongs to
+fn frame(span: &Rc<Span>, env: &Rc<RefCell<Environment>>) -> Frame {
+    Frame { procedure: Environment::procedure(env).map(|name| name.to_string()), location: Some((**span).clone()) }
 }
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "RuntimeError: {}", self.message)
+        match self.location {
+            Some(ref span) => write!(f, "{}: RuntimeError: {}", span, self.message),
+            None => write!(f, "RuntimeError: {}", self.message)
+        }
+    }
+}
+
+impl From<NumberError> for RuntimeError {
+    fn from(e: NumberError) -> RuntimeError {
+        RuntimeError::new(e.to_string())
+    }
+}
+
+impl From<MacroError> for RuntimeError {
+    fn from(e: MacroError) -> RuntimeError {
+        RuntimeError::new(e.message)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Error {
+        Error::Runtime(Box::new(Details {
+            kind: e.kind,
+            message: e.message,
+            value: e.value.map(|v| v.to_datum()),
+            irritants: e.irritants.iter().map(Value::to_datum).collect(),
+            location: e.location.map(|span| (*span).clone()),
+            backtrace: e.backtrace
+        }))
     }
 }
 
+impl From<RuntimeError> for MacroError {
+    fn from(e: RuntimeError) -> MacroError {
+        MacroError { message: e.message }
+    }
+}
+
+// Pairs live on the heap and are shared between every reference to them, so set-car! and
+// set-cdr! are visible everywhere. A pair's cdr is usually another list, but can be any value, as in (a . b).
+// The first pair of a list read from a source file has the list's span, for error messages, and a pair
+// whose car is an identifier read from the file has the identifier's.
 #[derive(PartialEq, Clone)]
 pub enum List {
-    Cell(Box<Value>, Box<List>),
+    Cell(Rc<RefCell<Pair>>),
     Null
 }
 
+pub struct Pair {
+    car: Value,
+    cdr: Value,
+    span: Option<Rc<Span>>,
+    car_span: Option<Rc<Span>>,
+}
+
+impl PartialEq for Pair {
+    fn eq(&self, other: &Pair) -> bool {
+        self.car == other.car && self.cdr == other.cdr
+    }
+}
+
 // null == empty list
 macro_rules! null { () => (List::Null.to_value()) }
 
 impl List {
+    fn cons(car: Value, cdr: Value) -> List {
+        List::Cell(Rc::new(RefCell::new(Pair { car, cdr, span: None, car_span: None })))
+    }
+
+    fn with_span(self, span: Rc<Span>) -> List {
+        if let List::Cell(ref pair) = self {
+            pair.borrow_mut().span = Some(span);
+        }
+        self
+    }
+
+    fn span(&self) -> Option<Rc<Span>> {
+        match *self {
+            List::Cell(ref pair) => pair.borrow().span.clone(),
+            List::Null => None
+        }
+    }
+
+    // Like shift, but also gives the span of the car when it's an identifier read from a source file
+    fn shift_spanned(self) -> Option<(Value, Option<Rc<Span>>, List)> {
+        let span = match self {
+            List::Cell(ref pair) => pair.borrow().car_span.clone(),
+            List::Null => None
+        };
+        self.shift().map(|(car, cdr)| (car, span, cdr))
+    }
+
     fn from_vec(mut vec: Vec<Value>) -> List {
         if vec.len() > 0 {
             let mut out = List::Null;
             while vec.len() > 0 {
                 let v = vec.pop().unwrap();
-                out = List::Cell(Box::new(v), Box::new(out));
+                out = out.unshift(v);
             }
             out
         } else {
@@ -233,26 +919,88 @@ impl List {
         List::from_vec(vec)
     }
 
+    // The rest of the list, or None if the cdr isn't a list
+    fn next(&self) -> Option<List> {
+        match *self {
+            List::Cell(ref pair) => {
+                match pair.borrow().cdr {
+                    Value::List(ref next) => Some(next.clone()),
+                    _ => None
+                }
+            },
+            List::Null => None
+        }
+    }
+
+    fn is(&self, other: &List) -> bool {
+        match (self, other) {
+            (List::Cell(a), List::Cell(b)) => Rc::ptr_eq(a, b),
+            (List::Null, List::Null) => true,
+            _ => false
+        }
+    }
+
+    // Circular lists aren't proper, so a second cursor runs at double speed to detect them
+    fn is_proper(&self) -> bool {
+        let mut slow = self.clone();
+        let mut fast = self.clone();
+        loop {
+            for _ in 0..2 {
+                if fast.is_empty() {
+                    return true;
+                }
+                fast = match fast.next() {
+                    Some(next) => next,
+                    None => return false
+                };
+            }
+            slow = slow.next().unwrap();
+            if slow.is(&fast) && !slow.is_empty() {
+                return false;
+            }
+        }
+    }
+
     fn is_empty(&self) -> bool {
         self == &List::Null
     }
 
+    // Iterating only makes sense on proper lists, so an improper tail is treated as the end of the list.
+    // Pairs that aren't shared with anything else are taken apart instead of copied.
     fn shift(self) -> Option<(Value, List)> {
         match self {
-            List::Cell(car, cdr) => Some((*car, *cdr)),
+            List::Cell(pair) => {
+                let (car, cdr) = match Rc::try_unwrap(pair) {
+                    Ok(pair) => {
+                        let pair = pair.into_inner();
+                        (pair.car, pair.cdr)
+                    },
+                    Err(pair) => {
+                        let pair = pair.borrow();
+                        (pair.car.clone(), pair.cdr.clone())
+                    }
+                };
+                match cdr {
+                    Value::List(rest) => Some((car, rest)),
+                    _ => Some((car, List::Null))
+                }
+            },
             List::Null => None
         }
     }
 
     fn unshift(self, car: Value) -> List {
-        List::Cell(Box::new(car), Box::new(self))
+        List::cons(car, self.to_value())
     }
 
     fn len(&self) -> usize {
-        match self {
-            &List::Cell(_, ref cdr) => 1 + cdr.len(),
-            &List::Null => 0
+        let mut n = 0;
+        let mut l = self.clone();
+        while !l.is_empty() {
+            n += 1;
+            l = l.next().unwrap_or(List::Null);
         }
+        n
     }
 
     fn unpack1(self) -> Result<Value, RuntimeError> {
@@ -315,69 +1063,458 @@ impl iter::IntoIterator for List {
 
 impl fmt::Display for List {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let strs: Vec<String> = self.clone().into_iter().map(|v| format!("{}", v)).collect();
-        write!(f, "({})", &strs.join(" "))
+        write!(f, "{}", self.clone().to_value())
     }
 }
 
 impl fmt::Debug for List {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let strs: Vec<String> = self.clone().into_iter().map(|v| format!("{:?}", v)).collect();
-        write!(f, "({})", &strs.join(" "))
+        write!(f, "{:?}", self.clone().to_value())
     }
 }
 
-impl Continuation {
-    fn run(self, val: Value) -> Result<Trampoline, RuntimeError> {
-        match self {
-            Continuation::EvaluateExpressions(rest, env, k) => {
-                if !rest.is_empty() {
-                    evaluate_expressions(rest, env, k)
-                } else {
-                    Ok(Trampoline::Run(val, *k))
+// Writes lists and vectors for Display (write: false) and Debug (write: true). Mutation makes circular
+// structure possible, so pairs and vectors that contain themselves get datum labels, as in #0=(1 . #0#)
+struct Printer {
+    write: bool,
+    labels: HashMap<usize, Option<usize>>,
+    next_label: usize,
+}
+
+impl Printer {
+    fn new(write: bool, val: &Value) -> Printer {
+        let mut labels = HashMap::new();
+        find_cycles(val, &mut HashSet::new(), &mut HashSet::new(), &mut labels);
+        Printer { write, labels, next_label: 0 }
+    }
+
+    fn value(&mut self, f: &mut fmt::Formatter, val: &Value) -> fmt::Result {
+        match *val {
+            Value::List(List::Cell(ref pair)) => self.pairs(f, pair.clone()),
+            Value::List(List::Null) => write!(f, "()"),
+            Value::Vector(ref vec) => {
+                if try!(self.label(f, address(vec))) {
+                    return Ok(());
+                }
+                try!(write!(f, "#("));
+                for (i, v) in vec.borrow().iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, " "));
+                    }
+                    try!(self.value(f, v));
                 }
+                write!(f, ")")
             },
-            Continuation::BeginFunc(rest, env, k) => {
-                match val {
-                    Value::SpecialForm(f) => {
-                        match f {
-                            SpecialForm::If => {
-                                let (condition, if_expr, else_expr) = try!(rest.unpack3());
-                                Ok(Trampoline::Bounce(condition, env.clone(), Continuation::EvaluateIf(if_expr, else_expr, env, k)))
-                            },
-                            SpecialForm::Define => {
-                                let (car, cdr) = shift_or_error!(rest, "Must provide at least two arguments to define");
-                                match car {
-                                    Value::Symbol(name) => {
-                                        let val = try!(cdr.unpack1());
-                                        Ok(Trampoline::Bounce(val, env.clone(), Continuation::EvaluateDefine(name, env, k)))
-                                    },
-                                    Value::List(list) => {
-                                        let (caar, cdar) = shift_or_error!(list, "Must provide at least two params in first argument of define");
-                                        let name = try!(caar.as_symbol());
+            _ if self.write => write!(f, "{:?}", val),
+            _ => write!(f, "{}", val)
+        }
+    }
 
-                                        let arg_names = try!(cdar.into_iter().map(|v| v.as_symbol()).collect());
-                                        let body = cdr;
-                                        let f = Function::Scheme(arg_names, body, env.clone());
+    fn pairs(&mut self, f: &mut fmt::Formatter, mut pair: Rc<RefCell<Pair>>) -> fmt::Result {
+        if try!(self.label(f, address(&pair))) {
+            return Ok(());
+        }
+        try!(write!(f, "("));
+        loop {
+            let (car, cdr) = {
+                let p = pair.borrow();
+                (p.car.clone(), p.cdr.clone())
+            };
+            try!(self.value(f, &car));
+            match cdr {
+                Value::List(List::Null) => break,
+                // A labelled pair has to start a dotted tail, so there is somewhere to put its label
+                Value::List(List::Cell(ref next)) if !self.labels.contains_key(&address(next)) => {
+                    try!(write!(f, " "));
+                    pair = next.clone();
+                },
+                ref tail => {
+                    try!(write!(f, " . "));
+                    try!(self.value(f, tail));
+                    break;
+                }
+            }
+        }
+        write!(f, ")")
+    }
 
-                                        try!(env.borrow_mut().define(name, Value::Procedure(f)));
-                                        Ok(Trampoline::Run(null!(), *k))
-                                    },
-                                    _ => runtime_error!("Bad argument to define: {:?}", car)
-                                }
-                            },
+    // Writes #n= the first time a labelled object is seen, or #n# after that. Returns true in the
+    // latter case, as the object itself shouldn't be written again.
+    fn label(&mut self, f: &mut fmt::Formatter, addr: usize) -> Result<bool, fmt::Error> {
+        match self.labels.get_mut(&addr) {
+            Some(&mut Some(n)) => {
+                try!(write!(f, "#{}#", n));
+                Ok(true)
+            },
+            Some(label) => {
+                *label = Some(self.next_label);
+                try!(write!(f, "#{}=", self.next_label));
+                self.next_label += 1;
+                Ok(false)
+            },
+            None => Ok(false)
+        }
+    }
+}
+
+// Pairs and vectors that are already being compared are assumed to be equal, so comparing circular
+// structures terminates. The cdrs of a list are followed with a loop, as lists can be long.
+fn values_equal(a: &Value, b: &Value, seen: &mut HashSet<(usize, usize)>) -> bool {
+    match (a, b) {
+        (&Value::List(List::Cell(ref x)), &Value::List(List::Cell(ref y))) => {
+            let (mut x, mut y) = (x.clone(), y.clone());
+            loop {
+                if Rc::ptr_eq(&x, &y) || !seen.insert((address(&x), address(&y))) {
+                    return true;
+                }
+                let (x_car, x_cdr) = {
+                    let p = x.borrow();
+                    (p.car.clone(), p.cdr.clone())
+                };
+                let (y_car, y_cdr) = {
+                    let p = y.borrow();
+                    (p.car.clone(), p.cdr.clone())
+                };
+                if !values_equal(&x_car, &y_car, seen) {
+                    return false;
+                }
+                match (x_cdr, y_cdr) {
+                    (Value::List(List::Cell(x_next)), Value::List(List::Cell(y_next))) => {
+                        x = x_next;
+                        y = y_next;
+                    },
+                    (x_tail, y_tail) => return values_equal(&x_tail, &y_tail, seen)
+                }
+            }
+        },
+        (Value::Vector(x), Value::Vector(y)) => {
+            if Rc::ptr_eq(x, y) || !seen.insert((address(x), address(y))) {
+                return true;
+            }
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| values_equal(a, b, seen))
+        },
+        (Value::String(x), Value::String(y)) => x == y,
+        _ => a.is(b)
+    }
+}
+
+fn address<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as usize
+}
+
+// Finds the pairs and vectors that can be reached from themselves. Objects on the current path are
+// in `active`, and fully explored ones in `done`. The cdrs of a list are followed with a loop, as lists can be long.
+fn find_cycles(val: &Value, active: &mut HashSet<usize>, done: &mut HashSet<usize>, labels: &mut HashMap<usize, Option<usize>>) {
+    match *val {
+        Value::List(List::Cell(_)) => {
+            let mut chain = vec![];
+            let mut cur = val.clone();
+            loop {
+                let pair = match cur {
+                    Value::List(List::Cell(ref pair)) => pair.clone(),
+                    ref tail => {
+                        find_cycles(tail, active, done, labels);
+                        break;
+                    }
+                };
+                let addr = address(&pair);
+                if active.contains(&addr) {
+                    labels.insert(addr, None);
+                    break;
+                }
+                if done.contains(&addr) {
+                    break;
+                }
+                active.insert(addr);
+                chain.push(addr);
+                let (car, cdr) = {
+                    let p = pair.borrow();
+                    (p.car.clone(), p.cdr.clone())
+                };
+                find_cycles(&car, active, done, labels);
+                cur = cdr;
+            }
+            for addr in chain {
+                active.remove(&addr);
+                done.insert(addr);
+            }
+        },
+        Value::Vector(ref vec) => {
+            let addr = address(vec);
+            if active.contains(&addr) {
+                labels.insert(addr, None);
+                return;
+            }
+            if done.contains(&addr) {
+                return;
+            }
+            active.insert(addr);
+            for v in vec.borrow().iter() {
+                find_cycles(v, active, done, labels);
+            }
+            active.remove(&addr);
+            done.insert(addr);
+        },
+        _ => {}
+    }
+}
+
+impl Continuation {
+    fn position(&self) -> Option<(Rc<Span>, Rc<RefCell<Environment>>)> {
+        match *self {
+            Continuation::BeginFunc(_, Some(ref span), ref env, _) | Continuation::EvaluateFunc(_, _, _, Some(ref span), ref env, _) |
+            Continuation::Handlers(_, Some((ref span, ref env)), _) => {
+                Some((span.clone(), env.clone()))
+            },
+            Continuation::HandlerReturned(_, ref k) => k.position(),
+            _ => None
+        }
+    }
+
+    // The frame that this one returns to
+    fn parent(&self) -> Option<&Continuation> {
+        match *self {
+            Continuation::EvaluateExpressions(_, _, ref k) | Continuation::EvaluateToplevel(_, _, _, ref k) |
+            Continuation::BeginFunc(_, _, _, ref k) |
+            Continuation::EvaluateIf(_, _, _, ref k) | Continuation::EvaluateDefine(_, _, ref k) |
+            Continuation::EvaluateSet(_, _, ref k) | Continuation::EvaluateFunc(_, _, _, _, _, ref k) |
+            Continuation::EvaluateLet(_, _, _, _, ref k) | Continuation::ContinueQuasiquoting(_, _, ref k) |
+            Continuation::SpliceQuasiquoted(_, _, ref k) | Continuation::FinishQuasiquoting(_, ref k) |
+            Continuation::WrapQuasiquoted(_, ref k) | Continuation::ExecuteEval(_, ref k) |
+            Continuation::ExecuteMacroexpand(_, _, ref k) | Continuation::EvaluateApplyArgs(_, _, ref k) |
+            Continuation::ExecuteApply(_, ref k) | Continuation::EvaluateAnd(_, _, ref k) |
+            Continuation::EvaluateOr(_, _, ref k) | Continuation::EvaluateCase(_, _, ref k) |
+            Continuation::ExecuteCallCC(ref k) | Continuation::ContinueVectorMap(_, _, _, _, ref k) |
+            Continuation::Handlers(_, _, ref k) | Continuation::HandlerReturned(_, ref k) |
+            Continuation::Winders(_, ref k) | Continuation::ExecuteWindThunk(_, _, _, ref k) |
+            Continuation::ExecuteWindAfter(_, ref k) | Continuation::ContinueWith(_, ref k) |
+            Continuation::ContinueWinding(_, _, ref k) | Continuation::ExecuteMakeParameter(_, ref k) |
+            Continuation::ExecuteCallWithValues(_, ref k) | Continuation::EvaluateLetValues(_, _, _, _, _, ref k) |
+            Continuation::EvaluateDefineValues(_, _, ref k) | Continuation::Prompt(_, _, ref k) => Some(k),
+            Continuation::Abort(_) | Continuation::Hole(_, _) | Continuation::EndThread(_) | Continuation::Return => None
+        }
+    }
+
+    // The frame that this one returns to, for changing a copy of the frames, which Rc::make_mut
+    // makes one at a time so that the continuations sharing them are left as they were
+    fn parent_mut(&mut self) -> Option<&mut Rc<Continuation>> {
+        match *self {
+            Continuation::EvaluateExpressions(_, _, ref mut k) | Continuation::EvaluateToplevel(_, _, _, ref mut k) |
+            Continuation::BeginFunc(_, _, _, ref mut k) |
+            Continuation::EvaluateIf(_, _, _, ref mut k) | Continuation::EvaluateDefine(_, _, ref mut k) |
+            Continuation::EvaluateSet(_, _, ref mut k) | Continuation::EvaluateFunc(_, _, _, _, _, ref mut k) |
+            Continuation::EvaluateLet(_, _, _, _, ref mut k) | Continuation::ContinueQuasiquoting(_, _, ref mut k) |
+            Continuation::SpliceQuasiquoted(_, _, ref mut k) | Continuation::FinishQuasiquoting(_, ref mut k) |
+            Continuation::WrapQuasiquoted(_, ref mut k) | Continuation::ExecuteEval(_, ref mut k) |
+            Continuation::ExecuteMacroexpand(_, _, ref mut k) | Continuation::EvaluateApplyArgs(_, _, ref mut k) |
+            Continuation::ExecuteApply(_, ref mut k) | Continuation::EvaluateAnd(_, _, ref mut k) |
+            Continuation::EvaluateOr(_, _, ref mut k) | Continuation::EvaluateCase(_, _, ref mut k) |
+            Continuation::ExecuteCallCC(ref mut k) | Continuation::ContinueVectorMap(_, _, _, _, ref mut k) |
+            Continuation::Handlers(_, _, ref mut k) | Continuation::HandlerReturned(_, ref mut k) |
+            Continuation::Winders(_, ref mut k) | Continuation::ExecuteWindThunk(_, _, _, ref mut k) |
+            Continuation::ExecuteWindAfter(_, ref mut k) | Continuation::ContinueWith(_, ref mut k) |
+            Continuation::ContinueWinding(_, _, ref mut k) | Continuation::ExecuteMakeParameter(_, ref mut k) |
+            Continuation::ExecuteCallWithValues(_, ref mut k) | Continuation::EvaluateLetValues(_, _, _, _, _, ref mut k) |
+            Continuation::EvaluateDefineValues(_, _, ref mut k) | Continuation::Prompt(_, _, ref mut k) => Some(k),
+            Continuation::Abort(_) | Continuation::Hole(_, _) | Continuation::EndThread(_) | Continuation::Return => None
+        }
+    }
+
+    // The innermost prompt with the tag that this continuation is inside
+    fn prompt(&self, tag: &Value) -> Option<&Continuation> {
+        let mut k = Some(self);
+        while let Some(current) = k {
+            if let Continuation::Prompt(ref t, _, _) = *current {
+                if t.is(tag) {
+                    return Some(current);
+                }
+            }
+            k = current.parent();
+        }
+        None
+    }
+
+    // A copy of the frames inside the innermost prompt with the tag, with a hole in place of the
+    // prompt, or under a copy of it when calling the continuation is to reinstate the prompt
+    fn slice_to_prompt(&self, tag: &Value, reinstate: bool) -> Option<Rc<Continuation>> {
+        let (handlers, winders) = match self.prompt(tag) {
+            Some(prompt) => (prompt.handlers(), prompt.winders()),
+            None => return None
+        };
+        let mut slice = Rc::new(self.clone());
+        {
+            let mut current = &mut slice;
+            loop {
+                if let Continuation::Prompt(ref t, ref handler, _) = **current {
+                    if t.is(tag) {
+                        let hole = Rc::new(Continuation::Hole(handlers, winders));
+                        *current = if reinstate { Rc::new(Continuation::Prompt(t.clone(), handler.clone(), hole)) } else { hole };
+                        break;
+                    }
+                }
+                current = Rc::make_mut(current).parent_mut().expect("The prompt must be in the continuation");
+            }
+        }
+        Some(slice)
+    }
+
+    // Fills in the hole at the bottom of a composable continuation with k. The handlers and winders
+    // inside it were the ones inside the prompt, and become the ones inside k.
+    fn compose(self: Rc<Self>, k: Rc<Continuation>) -> Rc<Continuation> {
+        let mut slice = self;
+        let mut bottom: &Continuation = &slice;
+        while let Some(parent) = bottom.parent() {
+            bottom = parent;
+        }
+        let (base_handlers, base_winders) = match *bottom {
+            Continuation::Hole(ref handlers, ref winders) => (handlers.clone(), winders.clone()),
+            _ => (List::Null, List::Null)
+        };
+        let (handlers, winders) = (k.handlers(), k.winders());
+        let mut rebuilt = HashMap::new();
+        {
+            let mut current = &mut slice;
+            while !matches!(**current, Continuation::Hole(_, _)) {
+                let frame = Rc::make_mut(current);
+                match *frame {
+                    Continuation::Handlers(ref mut list, _, _) => *list = rebase(list, &base_handlers, &handlers, &mut rebuilt),
+                    Continuation::Winders(ref mut list, _) => *list = rebase(list, &base_winders, &winders, &mut rebuilt),
+                    _ => ()
+                }
+                current = frame.parent_mut().expect("A composable continuation must end in a hole");
+            }
+            *current = k;
+        }
+        slice
+    }
+
+    // The calls in this continuation that came from source code with a known location
+    fn frames(&self) -> Vec<Frame> {
+        let mut frames = vec![];
+        let mut k = Some(self);
+        while let Some(current) = k {
+            match *current {
+                Continuation::BeginFunc(_, Some(ref span), ref env, _) | Continuation::EvaluateFunc(_, _, _, Some(ref span), ref env, _) => {
+                    frames.push(frame(span, env));
+                },
+                _ => ()
+            }
+            k = current.parent();
+        }
+        frames
+    }
+
+    // The handlers installed where this continuation was captured, innermost first
+    fn handlers(&self) -> List {
+        let mut k = Some(self);
+        while let Some(current) = k {
+            if let Continuation::Handlers(ref handlers, _, _) = *current {
+                return handlers.clone();
+            }
+            k = current.parent();
+        }
+        List::Null
+    }
+
+    // The before and after thunks of the dynamic-wind calls this continuation is inside
+    fn winders(&self) -> List {
+        let mut k = Some(self);
+        while let Some(current) = k {
+            if let Continuation::Winders(ref winders, _) = *current {
+                return winders.clone();
+            }
+            k = current.parent();
+        }
+        List::Null
+    }
+
+    // Frames are shared, by the continuations that call/cc captures and the ones they return to, so
+    // running one takes it out of the Rc if nothing else has it, and copies it otherwise
+    fn run(self: Rc<Self>, val: Value) -> Result<Trampoline, RuntimeError> {
+        match Rc::unwrap_or_clone(self) {
+            Continuation::EvaluateExpressions(rest, env, k) => {
+                if !rest.is_empty() {
+                    evaluate_expressions(rest, env, k)
+                } else {
+                    Ok(Trampoline::Run(val, k))
+                }
+            },
+            Continuation::EvaluateToplevel(forms, i, env, k) => {
+                let (node, map) = match forms.get(i) {
+                    Some(form) => form.clone(),
+                    None => return Ok(Trampoline::Run(val, k))
+                };
+                let span = map.as_ref().map(|map| Rc::new(map.span.clone()));
+                let expanded = try!(Environment::expander(env.clone()).expand(&node).map_err(|e| RuntimeError::from(e).at(span.clone())));
+                let expr = match map {
+                    Some(ref map) => Value::from_node_spanned(&expanded, map),
+                    None => Value::from_node(&expanded)
+                };
+                // The last form is in tail position, so it gets the continuation as is
+                if i + 1 == forms.len() {
+                    Ok(bounce(expr, span, env, k))
+                } else {
+                    Ok(bounce(expr, span, env.clone(), Rc::new(Continuation::EvaluateToplevel(forms, i + 1, env, k))))
+                }
+            },
+            Continuation::BeginFunc(rest, span, env, k) => {
+                match val {
+                    Value::SpecialForm(f) => {
+                        match f {
+                            SpecialForm::If => {
+                                let (condition, if_expr, else_expr) = try!(rest.unpack3());
+                                Ok(Trampoline::Bounce(condition, env.clone(), Rc::new(Continuation::EvaluateIf(if_expr, else_expr, env, k))))
+                            },
+                            SpecialForm::Define => {
+                                let (car, cdr) = shift_or_error!(rest, "Must provide at least two arguments to define");
+                                match car {
+                                    Value::Symbol(name) => {
+                                        let val = try!(cdr.unpack1());
+                                        Ok(Trampoline::Bounce(val, env.clone(), Rc::new(Continuation::EvaluateDefine(name, env, k))))
+                                    },
+                                    Value::List(list) => {
+                                        if list.is_empty() {
+                                            runtime_error!("Must provide at least two params in first argument of define");
+                                        }
+                                        let (caar, cdar) = try!(list.to_value().as_pair());
+                                        let name = try!(caar.as_symbol());
+
+                                        let params = try!(Params::parse(cdar));
+                                        let body = cdr;
+                                        let f = Function::Scheme(params, body, env.clone(), Some(Rc::from(name.as_str())));
+
+                                        try!(env.borrow_mut().define(name, Value::Procedure(f)));
+                                        Ok(Trampoline::Run(null!(), k))
+                                    },
+                                    _ => runtime_error!("Bad argument to define: {:?}", car)
+                                }
+                            },
                             SpecialForm::Set => {
                                 let (name_raw, val) = try!(rest.unpack2());
                                 let name = try!(name_raw.as_symbol());
-                                Ok(Trampoline::Bounce(val, env.clone(), Continuation::EvaluateSet(name, env, k)))
+                                Ok(Trampoline::Bounce(val, env.clone(), Rc::new(Continuation::EvaluateSet(name, env, k))))
                             },
                             SpecialForm::Lambda => {
                                 let (arg_defns_raw, body) = shift_or_error!(rest, "Must provide at least two arguments to lambda");
-                                let arg_defns = try!(arg_defns_raw.as_list());
-                                let arg_names = try!(arg_defns.into_iter().map(|v| v.as_symbol()).collect());
+                                let params = try!(Params::parse(arg_defns_raw));
+
+                                let f = Function::Scheme(params, body, env, None);
+                                Ok(Trampoline::Run(Value::Procedure(f), k))
+                            },
+                            SpecialForm::CaseLambda => {
+                                // (case-lambda (<params> <body>) ...) picks the first clause that accepts the arguments
+                                let mut clauses = vec![];
+                                for clause in rest {
+                                    let (arg_defns_raw, body) = shift_or_error!(try!(clause.as_list()), "Must provide at least two elements in a case-lambda clause");
+                                    clauses.push((try!(Params::parse(arg_defns_raw)), body));
+                                }
 
-                                let f = Function::Scheme(arg_names, body, env);
-                                Ok(Trampoline::Run(Value::Procedure(f), *k))
+                                let f = Function::CaseLambda(clauses, env);
+                                Ok(Trampoline::Run(Value::Procedure(f), k))
+                            },
+                            SpecialForm::Let if matches!(rest.clone().shift(), Some((Value::Symbol(_), _))) => {
+                                Ok(Trampoline::Bounce(try!(expand_named_let(rest)), env, k))
                             },
                             SpecialForm::Let => {
                                 let (arg_defns_raw, body) = shift_or_error!(rest, "Must provide at least two arguments to let");
@@ -391,120 +1528,180 @@ impl Continuation {
                                     let (first_defn, rest_defns) = shift_or_error!(arg_defns, "Error in let definiton");
                                     let (defn_key, defn_val) = try!(try!(first_defn.as_list()).unpack2());
                                     let name = try!(defn_key.as_symbol());
-                                    Ok(Trampoline::Bounce(defn_val, env, Continuation::EvaluateLet(name, rest_defns, body, proc_env, k)))
+                                    Ok(Trampoline::Bounce(defn_val, env, Rc::new(Continuation::EvaluateLet(name, rest_defns, body, proc_env, k))))
                                 } else {
                                     // Let bindings were empty, just execute the body directly
                                     evaluate_expressions(body, env, k)
                                 }
                             },
+                            SpecialForm::LetStar => {
+                                Ok(Trampoline::Bounce(try!(expand_let_star("let*", "let", rest)), env, k))
+                            },
+                            SpecialForm::Letrec => {
+                                Ok(Trampoline::Bounce(try!(expand_letrec("letrec", rest)), env, k))
+                            },
+                            SpecialForm::LetrecStar => {
+                                Ok(Trampoline::Bounce(try!(expand_letrec("letrec*", rest)), env, k))
+                            },
+                            SpecialForm::Cond => {
+                                Ok(Trampoline::Bounce(try!(expand_cond(rest)), env, k))
+                            },
+                            SpecialForm::Case => {
+                                let (key, clauses) = shift_or_error!(rest, "Must provide at least one argument to case");
+                                Ok(Trampoline::Bounce(key, env.clone(), Rc::new(Continuation::EvaluateCase(clauses, env, k))))
+                            },
+                            SpecialForm::When => {
+                                Ok(Trampoline::Bounce(try!(expand_when_unless("when", rest)), env, k))
+                            },
+                            SpecialForm::Unless => {
+                                Ok(Trampoline::Bounce(try!(expand_when_unless("unless", rest)), env, k))
+                            },
+                            SpecialForm::Do => {
+                                Ok(Trampoline::Bounce(try!(expand_do(rest)), env, k))
+                            },
                             SpecialForm::Quote => {
                                 let expr = try!(rest.unpack1());
-                                Ok(Trampoline::Run(expr, *k))
+                                Ok(Trampoline::Run(expr, k))
                             },
                             SpecialForm::Quasiquote => {
                                 let expr = try!(rest.unpack1());
-                                match expr {
-                                    Value::List(list) => {
-                                        match list.shift() {
-                                            Some((car, cdr)) => Ok(Trampoline::QuasiBounce(car, env.clone(), Continuation::ContinueQuasiquoting(cdr, List::Null, env, k))),
-                                            None => Ok(Trampoline::Run(null!(), *k))
-                                        }
-                                    },
-                                    _ => Ok(Trampoline::Run(expr, *k))
-                                }
+                                Ok(Trampoline::QuasiBounce(expr, 1, env, k))
                             },
                             SpecialForm::Eval => {
                                 let expr = try!(rest.unpack1());
-                                Ok(Trampoline::Bounce(expr, env.clone(), Continuation::ExecuteEval(env, k)))
+                                Ok(Trampoline::Bounce(expr, env.clone(), Rc::new(Continuation::ExecuteEval(env, k))))
+                            },
+                            SpecialForm::Macroexpand => {
+                                let expr = try!(rest.unpack1());
+                                Ok(Trampoline::Bounce(expr, env.clone(), Rc::new(Continuation::ExecuteMacroexpand(false, env, k))))
+                            },
+                            SpecialForm::Macroexpand1 => {
+                                let expr = try!(rest.unpack1());
+                                Ok(Trampoline::Bounce(expr, env.clone(), Rc::new(Continuation::ExecuteMacroexpand(true, env, k))))
                             },
                             SpecialForm::Apply => {
                                 let (func, args) = try!(rest.unpack2());
-                                Ok(Trampoline::Bounce(func, env.clone(), Continuation::EvaluateApplyArgs(args, env, k)))
+                                Ok(Trampoline::Bounce(func, env.clone(), Rc::new(Continuation::EvaluateApplyArgs(args, env, k))))
                             },
                             SpecialForm::Begin => {
-                                match rest.shift() {
-                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateExpressions(cdr, env, k))),
-                                    None => runtime_error!("Must provide at least one argument to a begin statement")
+                                if rest.is_empty() {
+                                    runtime_error!("Must provide at least one argument to a begin statement");
                                 }
+                                evaluate_expressions(rest, env, k)
                             },
                             SpecialForm::And => {
                                 match rest.shift() {
-                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateAnd(cdr, env, k))),
-                                    None => Ok(Trampoline::Run(Value::Boolean(true), *k))
+                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Rc::new(Continuation::EvaluateAnd(cdr, env, k)))),
+                                    None => Ok(Trampoline::Run(Value::Boolean(true), k))
                                 }
                             },
                             SpecialForm::Or => {
                                 match rest.shift() {
-                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateOr(cdr, env, k))),
-                                    None => Ok(Trampoline::Run(Value::Boolean(false), *k))
+                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Rc::new(Continuation::EvaluateOr(cdr, env, k)))),
+                                    None => Ok(Trampoline::Run(Value::Boolean(false), k))
                                 }
                             },
                             SpecialForm::CallCC => {
                                 let f = try!(rest.unpack1());
-                                Ok(Trampoline::Bounce(f, env, Continuation::ExecuteCallCC(k)))
+                                Ok(Trampoline::Bounce(f, env, Rc::new(Continuation::ExecuteCallCC(k))))
                             },
-                            SpecialForm::DefineSyntaxRule => {
-                                let (defn, body) = try!(rest.unpack2());
-
-                                let (name, arg_names_raw) = match try!(defn.as_list()).shift() {
-                                    Some((car, cdr)) => (try!(car.as_symbol()), cdr),
-                                    None => runtime_error!("Must supply at least two params to first argument in define-syntax-rule")
-                                };
-
-                                let arg_names = try!(arg_names_raw.into_iter().map(|v| v.as_symbol()).collect());
-
-                                let m = Value::Macro(arg_names, Box::new(body));
-                                try!(env.borrow_mut().define(name, m));
-                                Ok(Trampoline::Run(null!(), *k))
+                            SpecialForm::Guard => {
+                                Ok(Trampoline::Bounce(try!(expand_guard(rest)), env, k))
+                            },
+                            SpecialForm::Parameterize => {
+                                Ok(Trampoline::Bounce(try!(expand_parameterize(rest)), env, k))
+                            },
+                            SpecialForm::Reset => {
+                                // (reset <body>) => (call-with-continuation-prompt (lambda () <body>))
+                                if rest.is_empty() {
+                                    runtime_error!("Must provide at least one argument to reset");
+                                }
+                                let thunk = rest.unshift(null!()).unshift(symbol("lambda")).to_value();
+                                let call = Value::from_vec(vec![Value::Procedure(Function::Native("call-with-continuation-prompt")), thunk]);
+                                Ok(Trampoline::Bounce(call, env, k))
+                            },
+                            SpecialForm::Shift => {
+                                // (shift k <body>) calls (lambda (k) <body>) with the continuation up to the reset
+                                let (name, body) = shift_or_error!(rest, "Must provide at least two arguments to shift");
+                                if body.is_empty() {
+                                    runtime_error!("Must provide at least two arguments to shift");
+                                }
+                                let f = body.unshift(Value::from_vec(vec![name])).unshift(symbol("lambda")).to_value();
+                                let call = Value::from_vec(vec![Value::Procedure(Function::Native("shift")), f]);
+                                Ok(Trampoline::Bounce(call, env, k))
+                            },
+                            SpecialForm::LetValues => {
+                                let (bindings, body) = shift_or_error!(rest, "Must provide at least two arguments to let-values");
+                                // the inits are evaluated outside the new environment, which gets the values of each in turn
+                                match try!(bindings.as_list()).shift() {
+                                    Some((binding, rest)) => {
+                                        let (formals, init) = try!(try!(binding.as_list()).unpack2());
+                                        let params = try!(Params::parse(formals));
+                                        let let_env = Environment::new_child(env.clone());
+                                        Ok(Trampoline::Bounce(init, env.clone(), Rc::new(Continuation::EvaluateLetValues(params, rest, body, env, let_env, k))))
+                                    },
+                                    None => evaluate_expressions(body, Environment::new_child(env), k)
+                                }
+                            },
+                            SpecialForm::LetStarValues => {
+                                Ok(Trampoline::Bounce(try!(expand_let_star("let*-values", "let-values", rest)), env, k))
+                            },
+                            SpecialForm::Receive => {
+                                // (receive <formals> <expression> <body>) => (let-values ((<formals> <expression>)) <body>)
+                                let (formals, rest) = shift_or_error!(rest, "Must provide at least three arguments to receive");
+                                let (init, body) = shift_or_error!(rest, "Must provide at least three arguments to receive");
+                                let bindings = Value::from_vec(vec![Value::from_vec(vec![formals, init])]);
+                                Ok(Trampoline::Bounce(body.unshift(bindings).unshift(symbol("let-values")).to_value(), env, k))
+                            },
+                            SpecialForm::DefineValues => {
+                                let (formals, init) = try!(rest.unpack2());
+                                let params = try!(Params::parse(formals));
+                                Ok(Trampoline::Bounce(init, env.clone(), Rc::new(Continuation::EvaluateDefineValues(params, env, k))))
                             },
                         }
                     },
-                    Value::Macro(arg_names, body) => {
-                        let args = rest;
-                        if arg_names.len() != args.len() {
-                            runtime_error!("Must supply exactly {} arguments to macro: {:?}", arg_names.len(), args);
-                        }
-
-                        // Create a lookup table for symbol substitutions
-                        let mut substitutions = HashMap::new();
-                        for (name, value) in arg_names.into_iter().zip(args.into_iter()) {
-                            substitutions.insert(name, value);
-                        }
-
-                        // Expand the macro
-                        let expanded = expand_macro(*body, &substitutions);
-
-                        // Finished expanding macro, now evaluate the code manually
-                        Ok(Trampoline::Bounce(expanded, env, *k))
-                    },
                     _ => {
-                        match rest.shift() {
-                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateFunc(val, cdr, List::Null, env, k))),
+                        match rest.shift_spanned() {
+                            Some((car, car_span, cdr)) => Ok(bounce(car, car_span, env.clone(), Rc::new(Continuation::EvaluateFunc(val, cdr, List::Null, span, env, k)))),
                             None => apply(val, List::Null, k)
                         }
                     }
                 }
             },
-            Continuation::EvaluateFunc(f, rest, acc, env, k) => {
+            Continuation::EvaluateFunc(f, rest, acc, span, env, k) => {
                 let acc2 = acc.unshift(val);
-                match rest.shift() {
-                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateFunc(f, cdr, acc2, env, k))),
-                    None => apply(f, acc2.reverse(), k)
+                match rest.shift_spanned() {
+                    Some((car, car_span, cdr)) => Ok(bounce(car, car_span, env.clone(), Rc::new(Continuation::EvaluateFunc(f, cdr, acc2, span, env, k)))),
+                    // what the handler raises without handling it is reported where the call raised it
+                    None => match f {
+                        Value::Procedure(Function::Native(g)) if g == "raise" || g == "raise-continuable" => {
+                            let obj = try!(acc2.unpack1().map_err(|e| e.unwinding(&k)));
+                            raise(obj, g == "raise-continuable", span.map(|span| (span, env)), k)
+                        },
+                        f => apply(f, acc2.reverse(), k)
+                    }
                 }
             },
             Continuation::EvaluateIf(if_expr, else_expr, env, k) => {
                 match val {
-                    Value::Boolean(false) => Ok(Trampoline::Bounce(else_expr, env, *k)),
-                    _ => Ok(Trampoline::Bounce(if_expr, env, *k))
+                    Value::Boolean(false) => Ok(Trampoline::Bounce(else_expr, env, k)),
+                    _ => Ok(Trampoline::Bounce(if_expr, env, k))
                 }
             },
             Continuation::EvaluateDefine(name, env, k) => {
-                try!(env.borrow_mut().define(name, val));
-                Ok(Trampoline::Run(null!(), *k))
+                // a procedure is named after the first variable it's defined as
+                let val = match val {
+                    Value::Procedure(Function::Scheme(params, body, func_env, None)) => {
+                        Value::Procedure(Function::Scheme(params, body, func_env, Some(Rc::from(name.as_str()))))
+                    },
+                    val => val
+                };
+                try!(env.borrow_mut().define(name, val).map_err(|e| e.unwinding(&k)));
+                Ok(Trampoline::Run(null!(), k))
             },
             Continuation::EvaluateSet(name, env, k) => {
-                try!(env.borrow_mut().set(name, val));
-                Ok(Trampoline::Run(null!(), *k))
+                try!(env.borrow_mut().set(name, val).map_err(|e| e.unwinding(&k)));
+                Ok(Trampoline::Run(null!(), k))
             },
             Continuation::EvaluateLet(name, rest, body, env, k) => {
                 // Define variable in let scope
@@ -513,7 +1710,7 @@ impl Continuation {
                     Some((next_defn, rest_defns)) => {
                         let (defn_key, defn_val) = try!(try!(next_defn.as_list()).unpack2());
                         let name = try!(defn_key.as_symbol());
-                        Ok(Trampoline::Bounce(defn_val, env.clone(), Continuation::EvaluateLet(name, rest_defns, body, env, k)))
+                        Ok(Trampoline::Bounce(defn_val, env.clone(), Rc::new(Continuation::EvaluateLet(name, rest_defns, body, env, k))))
                     },
                     None => {
                         let inner_env = Environment::new_child(env);
@@ -521,29 +1718,50 @@ impl Continuation {
                     }
                 }
             },
-            Continuation::ContinueQuasiquoting(rest, acc, env, k) => {
-                let acc2 = acc.unshift(val);
-                match rest.shift() {
-                    Some((car, cdr)) => Ok(Trampoline::QuasiBounce(car, env.clone(), Continuation::ContinueQuasiquoting(cdr, acc2, env, k))),
-                    None => Ok(Trampoline::Run(acc2.reverse().to_value(), *k))
+            Continuation::ContinueQuasiquoting(mut q, env, k) => {
+                q.done.push(val);
+                continue_quasiquoting(q, env, k)
+            },
+            Continuation::SpliceQuasiquoted(mut q, env, k) => {
+                match val {
+                    Value::List(ref l) if l.is_proper() => q.done.extend(l.clone()),
+                    _ => runtime_error!("unquote-splicing must produce a list: {:?}", val)
                 }
+                continue_quasiquoting(q, env, k)
+            },
+            Continuation::FinishQuasiquoting(done, k) => {
+                Ok(Trampoline::Run(Value::from_vec_dotted(done, val), k))
+            },
+            Continuation::WrapQuasiquoted(name, k) => {
+                Ok(Trampoline::Run(Value::from_vec(vec![symbol(name), val]), k))
             },
             Continuation::ExecuteEval(env, k) => {
-                Ok(Trampoline::Bounce(val, Environment::get_root(env), *k))
+                let expanded = try!(Environment::expander(env.clone()).expand(&try!(val.to_node())));
+                Ok(Trampoline::Bounce(Value::from_node(&expanded), Environment::get_toplevel(env), k))
+            },
+            Continuation::ExecuteMacroexpand(once, env, k) => {
+                let expander = Environment::expander(env);
+                let node = try!(val.to_node());
+                let expanded = if once {
+                    try!(expander.macroexpand_1(&node))
+                } else {
+                    try!(expander.macroexpand(&node))
+                };
+                Ok(Trampoline::Run(Value::from_node(&expanded), k))
             },
             Continuation::EvaluateApplyArgs(args, env, k) => {
-                Ok(Trampoline::Bounce(args, env, Continuation::ExecuteApply(val, k)))
+                Ok(Trampoline::Bounce(args, env, Rc::new(Continuation::ExecuteApply(val, k))))
             },
             Continuation::ExecuteApply(f, k) => {
                 apply(f, try!(val.as_list()), k)
             },
             Continuation::EvaluateAnd(rest, env, k) => {
                 match val {
-                    Value::Boolean(false) => Ok(Trampoline::Run(Value::Boolean(false), *k)),
+                    Value::Boolean(false) => Ok(Trampoline::Run(Value::Boolean(false), k)),
                     _ => {
                         match rest.shift() {
-                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateAnd(cdr, env, k))),
-                            None => Ok(Trampoline::Run(val, *k))
+                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Rc::new(Continuation::EvaluateAnd(cdr, env, k)))),
+                            None => Ok(Trampoline::Run(val, k))
                         }
                     }
                 }
@@ -552,166 +1770,1135 @@ impl Continuation {
                 match val {
                     Value::Boolean(false) => {
                         match rest.shift() {
-                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateOr(cdr, env, k))),
-                            None => Ok(Trampoline::Run(Value::Boolean(false), *k))
+                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Rc::new(Continuation::EvaluateOr(cdr, env, k)))),
+                            None => Ok(Trampoline::Run(Value::Boolean(false), k))
                         }
                     },
-                    _ => Ok(Trampoline::Run(val, *k))
+                    _ => Ok(Trampoline::Run(val, k))
                 }
             },
+            Continuation::EvaluateCase(clauses, env, k) => {
+                for clause in clauses {
+                    let (data, body) = shift_or_error!(try!(clause.as_list()), "Empty clause in case");
+                    let matched = match data {
+                        Value::Symbol(ref s) if s == "else" => true,
+                        data => try!(data.as_list()).into_iter().any(|datum| datum.is(&val))
+                    };
+                    if matched {
+                        let expr = match body.clone().shift() {
+                            Some((Value::Symbol(ref arrow), receiver)) if arrow == "=>" => {
+                                Value::from_vec(vec![try!(receiver.unpack1()), Value::from_vec(vec![symbol("quote"), val])])
+                            },
+                            _ => body.unshift(symbol("begin")).to_value()
+                        };
+                        return Ok(Trampoline::Bounce(expr, env, k));
+                    }
+                }
+                Ok(Trampoline::Run(null!(), k))
+            },
             Continuation::ExecuteCallCC(k) => {
                 apply(val, List::Null.unshift(Value::Continuation(k.clone())), k)
             },
+            Continuation::ContinueVectorMap(f, calls, mut acc, collect, k) => {
+                if collect {
+                    acc.push(val);
+                }
+                continue_vector_map(f, calls, acc, collect, k)
+            },
+            Continuation::Handlers(_, _, k) => Ok(Trampoline::Run(val, k)),
+            Continuation::HandlerReturned(obj, k) => {
+                runtime_error_in!(k, "Exception handler returned from a non-continuable raise of {:?}", obj)
+            },
+            Continuation::Winders(_, k) => Ok(Trampoline::Run(val, k)),
+            Continuation::ExecuteWindThunk(before, thunk, after, k) => {
+                // the thunk returns through a frame that holds this call in front of the ones outside it
+                let winders = k.winders().unshift(List::cons(before, after.clone()).to_value());
+                apply(thunk, List::Null, Rc::new(Continuation::Winders(winders, Rc::new(Continuation::ExecuteWindAfter(after, k)))))
+            },
+            Continuation::ExecuteWindAfter(after, k) => {
+                apply(after, List::Null, Rc::new(Continuation::ContinueWith(vec![val], k)))
+            },
+            Continuation::ContinueWith(vals, k) => Ok(Trampoline::RunValues(vals, k)),
+            Continuation::ContinueWinding(thunks, vals, k) => continue_winding(thunks, vals, k),
+            k @ Continuation::ExecuteCallWithValues(_, _) | k @ Continuation::EvaluateLetValues(_, _, _, _, _, _) |
+            k @ Continuation::EvaluateDefineValues(_, _, _) => Rc::new(k).run_values(vec![val]),
+            Continuation::ExecuteMakeParameter(converter, k) => {
+                let parameter = Parameter { value: RefCell::new(val), converter: Some(converter) };
+                Ok(Trampoline::Run(Value::Procedure(Function::Parameter(Rc::new(parameter))), k))
+            },
+            Continuation::Prompt(_, _, k) => Ok(Trampoline::Run(val, k)),
+            Continuation::Hole(_, _) => runtime_error!("Can't return to a composable continuation that hasn't been called"),
+            Continuation::Abort(e) => Err(*e),
+            Continuation::EndThread(thread) => Ok(Trampoline::Schedule(Request::End(thread, vec![val]))),
             Continuation::Return => Ok(Trampoline::Land(val))
         }
     }
+
+    // Most continuations take one value. The ones that call-with-values and the forms binding values
+    // make take any number, as do the ones that ignore what they get or just pass it on.
+    fn run_values(self: Rc<Self>, mut vals: Vec<Value>) -> Result<Trampoline, RuntimeError> {
+        match Rc::unwrap_or_clone(self) {
+            Continuation::ExecuteCallWithValues(consumer, k) => apply(consumer, List::from_vec(vals), k),
+            Continuation::EvaluateLetValues(params, rest, mut body, env, let_env, k) => {
+                let args = List::from_vec(vals);
+                try!(params.check_arity(&args).map_err(|e| e.unwinding(&k)));
+                // defaults of missing optional values are evaluated at the start of the body, like a procedure's
+                for (name, default) in try!(params.bind(args, &let_env)).into_iter().rev() {
+                    body = body.unshift(Value::from_vec(vec![symbol("define"), Value::Symbol(name), default]));
+                }
+                match rest.shift() {
+                    Some((binding, rest)) => {
+                        let (formals, init) = try!(try!(binding.as_list()).unpack2());
+                        let params = try!(Params::parse(formals));
+                        Ok(Trampoline::Bounce(init, env.clone(), Rc::new(Continuation::EvaluateLetValues(params, rest, body, env, let_env, k))))
+                    },
+                    None => evaluate_expressions(body, Environment::new_child(let_env), k)
+                }
+            },
+            Continuation::EvaluateDefineValues(params, env, k) => {
+                let args = List::from_vec(vals);
+                try!(params.check_arity(&args).map_err(|e| e.unwinding(&k)));
+                let missing = try!(params.bind(args, &env));
+                if missing.is_empty() {
+                    return Ok(Trampoline::Run(null!(), k));
+                }
+                let defines = missing.into_iter().map(|(name, default)| Value::from_vec(vec![symbol("define"), Value::Symbol(name), default]));
+                Ok(Trampoline::Bounce(List::from_vec(defines.collect()).unshift(symbol("begin")).to_value(), env, k))
+            },
+            Continuation::Handlers(_, _, k) | Continuation::Winders(_, k) | Continuation::Prompt(_, _, k) => Ok(Trampoline::RunValues(vals, k)),
+            Continuation::ExecuteWindAfter(after, k) => {
+                apply(after, List::Null, Rc::new(Continuation::ContinueWith(vals, k)))
+            },
+            Continuation::EndThread(thread) => Ok(Trampoline::Schedule(Request::End(thread, vals))),
+            Continuation::Return if vals.len() != 1 => Ok(Trampoline::Land(Value::Values(vals))),
+            k => {
+                let k = Rc::new(k);
+                if vals.len() == 1 {
+                    return k.run(vals.pop().unwrap());
+                }
+                match *k {
+                    Continuation::EvaluateExpressions(ref rest, _, _) if !rest.is_empty() => (),
+                    Continuation::EvaluateToplevel(ref forms, i, _, _) if i < forms.len() => (),
+                    Continuation::ContinueWith(_, _) | Continuation::ContinueWinding(_, _, _) |
+                    Continuation::HandlerReturned(_, _) | Continuation::Abort(_) => (),
+                    _ => {
+                        let vals = List::from_vec(vals);
+                        runtime_error_in!(k, Arity(vals.clone().to_value()); "Expected one value, but got {}: {:?}", vals.len(), vals)
+                    }
+                }
+                k.run(null!())
+            }
+        }
+    }
 }
 
-fn apply(val: Value, args: List, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+fn apply(val: Value, args: List, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
     match val {
         Value::Procedure(f) => {
             match f {
-                Function::Scheme(arg_names, body, func_env) => {
-                    if arg_names.len() != args.len() {
-                        runtime_error!("Must supply exactly {} arguments to function: {:?}", arg_names.len(), args);
-                    }
+                Function::Scheme(params, body, func_env, name) => {
+                    try!(params.check_arity(&args).map_err(|e| e.unwinding(&k)));
 
                     // Create a new, child environment for the procedure and define the arguments as local variables
                     let proc_env = Environment::new_child(func_env);
-                    for (name, value) in arg_names.into_iter().zip(args.into_iter()) {
-                        try!(proc_env.borrow_mut().define(name, value));
+                    proc_env.borrow_mut().procedure = name;
+                    let missing = try!(params.bind(args, &proc_env).map_err(|e| e.unwinding(&k)));
+
+                    // Missing optional arguments are defined at the start of the body, so their defaults are
+                    // evaluated on the trampoline and can refer to the earlier parameters
+                    let mut body = body;
+                    for (name, default) in missing.into_iter().rev() {
+                        body = body.unshift(Value::from_vec(vec![Value::Symbol("define".to_string()), Value::Symbol(name), default]));
                     }
 
                     // Evaluate procedure body with new environment with procedure environment as parent
                     let inner_env = Environment::new_child(proc_env);
                     evaluate_expressions(body, inner_env, k)
                 },
+                Function::CaseLambda(clauses, func_env) => {
+                    let n = args.len();
+                    match clauses.into_iter().find(|(params, _)| params.accepts(n)) {
+                        Some((params, body)) => apply(Value::Procedure(Function::Scheme(params, body, func_env, None)), args, k),
+                        None => runtime_error_in!(k, Arity(args.clone().to_value()); "No case-lambda clause accepts {} arguments: {:?}", n, args)
+                    }
+                },
+                Function::Native(g) if g == "vector-map" || g == "vector-for-each" => {
+                    apply_vector_map(g, args, k)
+                },
+                Function::Native(g) if g == "raise" || g == "raise-continuable" => {
+                    let obj = try!(args.unpack1().map_err(|e| e.unwinding(&k)));
+                    raise(obj, g == "raise-continuable", None, k)
+                },
+                Function::Native("with-exception-handler") => {
+                    // the thunk returns through a frame that holds the new handler in front of the current ones
+                    let (handler, thunk) = try!(args.unpack2().map_err(|e| e.unwinding(&k)));
+                    let handlers = k.handlers().unshift(handler);
+                    apply(thunk, List::Null, Rc::new(Continuation::Handlers(handlers, None, k)))
+                },
+                Function::Native("values") => {
+                    Ok(Trampoline::RunValues(args.to_vec(), k))
+                },
+                Function::Native(g) if g == "exact-integer-sqrt" || g == "floor/" || g == "truncate/" => {
+                    let vals = try!(number_pair(g, args).map_err(|e| e.unwinding(&k)));
+                    Ok(Trampoline::RunValues(vals, k))
+                },
+                Function::Native("call-with-values") => {
+                    let (producer, consumer) = try!(args.unpack2().map_err(|e| e.unwinding(&k)));
+                    apply(producer, List::Null, Rc::new(Continuation::ExecuteCallWithValues(consumer, k)))
+                },
+                Function::Native("dynamic-wind") => {
+                    let (before, thunk, after) = try!(args.unpack3().map_err(|e| e.unwinding(&k)));
+                    apply(before.clone(), List::Null, Rc::new(Continuation::ExecuteWindThunk(before, thunk, after, k)))
+                },
+                Function::Native("make-parameter") => {
+                    // the converter is applied to the initial value as well
+                    if args.len() == 2 {
+                        let (value, converter) = try!(args.unpack2().map_err(|e| e.unwinding(&k)));
+                        return apply(converter.clone(), List::Null.unshift(value), Rc::new(Continuation::ExecuteMakeParameter(converter, k)));
+                    }
+                    let value = try!(args.unpack1().map_err(|e| e.unwinding(&k)));
+                    let parameter = Parameter { value: RefCell::new(value), converter: None };
+                    Ok(Trampoline::Run(Value::Procedure(Function::Parameter(Rc::new(parameter))), k))
+                },
+                Function::Native("parameter-convert") => {
+                    let (parameter, value) = try!(args.unpack2().map_err(|e| e.unwinding(&k)));
+                    match parameter {
+                        Value::Procedure(Function::Parameter(ref p)) => match p.converter {
+                            Some(ref converter) => apply(converter.clone(), List::Null.unshift(value), k),
+                            None => Ok(Trampoline::Run(value, k))
+                        },
+                        _ => runtime_error_in!(k, WrongType(parameter.clone()); "Expected a parameter to parameterize: {:?}", parameter)
+                    }
+                },
+                Function::Native("call-with-continuation-prompt") => {
+                    // (call-with-continuation-prompt proc [tag [handler]] arg ...)
+                    let (proc, rest) = shift_or_error!(args, "Must provide at least one argument to call-with-continuation-prompt");
+                    let (tag, rest) = rest.shift().unwrap_or((Value::PromptTag(None), List::Null));
+                    let (handler, rest) = rest.shift().unwrap_or((Value::Boolean(false), List::Null));
+                    let tag = try!(prompt_tag(tag).map_err(|e| e.unwinding(&k)));
+                    let handler = if handler == Value::Boolean(false) { None } else { Some(handler) };
+                    apply(proc, rest, Rc::new(Continuation::Prompt(tag, handler, k)))
+                },
+                Function::Native("abort-current-continuation") => {
+                    // the values go to the prompt's handler, which is called outside it
+                    let (tag, vals) = shift_or_error!(args, "Must provide at least one argument to abort-current-continuation");
+                    let tag = try!(prompt_tag(tag).map_err(|e| e.unwinding(&k)));
+                    let to = match k.prompt(&tag) {
+                        Some(&Continuation::Prompt(_, Some(ref handler), ref parent)) => {
+                            let to = Rc::new(Continuation::ExecuteApply(handler.clone(), parent.clone()));
+                            return wind(vec![vals.to_value()], &k, to);
+                        },
+                        Some(&Continuation::Prompt(_, None, ref parent)) => parent.clone(),
+                        _ => runtime_error_in!(k, "No continuation prompt for {:?}", tag)
+                    };
+                    wind(vals.to_vec(), &k, to)
+                },
+                Function::Native("call-with-composable-continuation") => {
+                    let (proc, rest) = shift_or_error!(args, "Must provide at least one argument to call-with-composable-continuation");
+                    let (tag, _) = rest.shift().unwrap_or((Value::PromptTag(None), List::Null));
+                    let tag = try!(prompt_tag(tag).map_err(|e| e.unwinding(&k)));
+                    match k.slice_to_prompt(&tag, false) {
+                        Some(slice) => apply(proc, List::Null.unshift(Value::ComposableContinuation(slice)), k),
+                        None => runtime_error_in!(k, "No continuation prompt for {:?}", tag)
+                    }
+                },
+                Function::Native("shift") => {
+                    // f runs inside the prompt, once the calls between here and there have been left, with a
+                    // continuation that puts the prompt back when it's called
+                    let f = try!(args.unpack1().map_err(|e| e.unwinding(&k)));
+                    let tag = Value::PromptTag(None);
+                    let (slice, prompt) = match (k.slice_to_prompt(&tag, true), k.prompt(&tag)) {
+                        (Some(slice), Some(prompt)) => (slice, Rc::new(prompt.clone())),
+                        _ => runtime_error_in!(k, "Can't shift outside of a reset")
+                    };
+                    let args = Value::from_vec(vec![Value::ComposableContinuation(slice)]);
+                    wind(vec![args], &k, Rc::new(Continuation::ExecuteApply(f, prompt)))
+                },
+                Function::Native(g) if g == "spawn" || g == "yield" || g == "sleep" || g == "thread-join!" ||
+                                        g == "channel-put!" || g == "channel-get!" || g == "current-thread" => {
+                    schedule(g, args, k)
+                },
                 Function::Native(g) => {
-                    let res = try!(primitive(g, args));
-                    Ok(Trampoline::Run(res, *k))
+                    let res = try!(primitive(g, args).map_err(|e| e.unwinding(&k)));
+                    Ok(Trampoline::Run(res, k))
+                },
+                Function::Rename(renaming) => {
+                    let name = try!(try!(args.unpack1()).as_symbol());
+                    Ok(Trampoline::Run(Value::Symbol(renaming.rename(&name)), k))
+                },
+                Function::Compare(renaming) => {
+                    let same = match try!(args.unpack2()) {
+                        (Value::Symbol(a), Value::Symbol(b)) => renaming.compare(&a, &b),
+                        _ => false
+                    };
+                    Ok(Trampoline::Run(Value::Boolean(same), k))
+                },
+                Function::Parameter(parameter) => {
+                    if !args.is_empty() {
+                        runtime_error_in!(k, Arity(args.clone().to_value()); "A parameter takes no arguments: {:?}", args);
+                    }
+                    let val = parameter.value.borrow().clone();
+                    Ok(Trampoline::Run(val, k))
                 },
             }
         },
         Value::Continuation(k_prime) => {
-            Ok(Trampoline::Run(args.to_value(), *k_prime))
+            // (k v ...) returns the values from call/cc, once the dynamic-wind calls on the way there have been left and entered
+            wind(args.to_vec(), &k, k_prime)
+        },
+        Value::ComposableContinuation(slice) => {
+            // the frames are added to k instead of replacing it, entering the dynamic-wind calls among them
+            wind(args.to_vec(), &k, slice.compose(k.clone()))
         },
         _ => {
-            runtime_error!("Don't know how to apply: {:?}", val)
+            runtime_error_in!(k, WrongType(val); "Don't know how to apply: {:?}", val)
+        }
+    }
+}
+
+// Calls the innermost handler with obj where the raise happened, except that the handlers are the
+// ones outside it, so that raising something in a handler goes to the next one out. When there are
+// no handlers, the program stops with an error.
+fn raise(obj: Value, continuable: bool, at: Option<(Rc<Span>, Rc<RefCell<Environment>>)>, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match k.handlers().shift() {
+        Some((handler, outer)) => {
+            let k = Rc::new(Continuation::Handlers(outer, at, k));
+            let k = if continuable { k } else { Rc::new(Continuation::HandlerReturned(obj.clone(), k)) };
+            apply(handler, List::Null.unshift(obj), k)
+        },
+        None => match obj {
+            // an error that was caught and raised again is reported as it was
+            Value::ErrorObject(e) => Err(RuntimeError::clone(&e).unwinding(&k)),
+            obj => runtime_error_in!(k, Raised(obj.clone()); "Uncaught exception: {:?}", obj)
         }
     }
 }
 
-fn expand_macro(value: Value, substitutions: &HashMap<String,Value>) -> Value {
-    match value {
-        Value::Symbol(s) => {
-            match substitutions.get(&s) {
-                Some(v) => v.clone(),
-                None => Value::Symbol(s)
+// The natives that the scheduler answers, which may run another thread while this one waits
+fn schedule(f: &'static str, args: List, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    let request = match f {
+        "spawn" => {
+            let thunk = try!(args.unpack1().map_err(|e| e.unwinding(&k)));
+            match thunk {
+                Value::Procedure(_) | Value::Continuation(_) | Value::ComposableContinuation(_) => Request::Spawn(thunk, k),
+                _ => runtime_error_in!(k, WrongType(thunk.clone()); "Expected a procedure to spawn: {:?}", thunk)
+            }
+        },
+        "yield" | "current-thread" => {
+            if !args.is_empty() {
+                runtime_error_in!(k, Arity(args.clone().to_value()); "{} takes no arguments", f);
+            }
+            if f == "yield" { Request::Yield(k) } else { Request::Current(k) }
+        },
+        "sleep" => {
+            // the time is in seconds
+            let secs = try!(args.unpack1().and_then(|v| v.as_number()).map_err(|e| e.unwinding(&k)));
+            // a negative time, or one too long to wake up from, is an error
+            match Duration::try_from_secs_f64(secs.to_f64()).ok().and_then(|duration| Instant::now().checked_add(duration)) {
+                Some(wake) => Request::Sleep(wake, k),
+                None => runtime_error_in!(k, WrongType(Value::Number(secs)); "Can't sleep for {} seconds", secs)
+            }
+        },
+        "thread-join!" => match try!(args.unpack1().map_err(|e| e.unwinding(&k))) {
+            Value::Thread(thread) => Request::Join(thread, k),
+            val => runtime_error_in!(k, WrongType(val.clone()); "Expected a thread to join: {:?}", val)
+        },
+        "channel-put!" => match try!(args.unpack2().map_err(|e| e.unwinding(&k))) {
+            (Value::Channel(channel), val) => Request::Put(channel, val, k),
+            (val, _) => runtime_error_in!(k, WrongType(val.clone()); "Expected a channel to put to: {:?}", val)
+        },
+        "channel-get!" => match try!(args.unpack1().map_err(|e| e.unwinding(&k))) {
+            Value::Channel(channel) => Request::Get(channel, k),
+            val => runtime_error_in!(k, WrongType(val.clone()); "Expected a channel to get from: {:?}", val)
+        },
+        _ => runtime_error_in!(k, "Unknown thread operation: {}", f)
+    };
+    Ok(Trampoline::Schedule(request))
+}
+
+fn prompt_tag(val: Value) -> Result<Value, RuntimeError> {
+    match val {
+        Value::PromptTag(_) => Ok(val),
+        _ => runtime_error!(WrongType(val); "Expected a continuation prompt tag: {:?}", val)
+    }
+}
+
+// Rebuilds a list of handlers or winders that ends in base to end in onto instead. The lists of the
+// frames in a continuation share their tails, and so do the ones they're rebuilt into.
+fn rebase(list: &List, base: &List, onto: &List, rebuilt: &mut HashMap<usize, List>) -> List {
+    match *list {
+        List::Cell(ref pair) if !list.is(base) => {
+            if let Some(out) = rebuilt.get(&address(pair)) {
+                return out.clone();
             }
+            let (car, cdr) = {
+                let pair = pair.borrow();
+                (pair.car.clone(), pair.cdr.clone())
+            };
+            let rest = match cdr {
+                Value::List(ref rest) => rebase(rest, base, onto, rebuilt),
+                _ => onto.clone()
+            };
+            let out = rest.unshift(car);
+            rebuilt.insert(address(pair), out.clone());
+            out
+        },
+        _ => onto.clone()
+    }
+}
+
+// Going from one continuation to another leaves the dynamic-wind calls that only the first is inside,
+// calling their after thunks innermost first, then enters the ones that only the second is inside,
+// calling their before thunks outermost first. Each thunk runs inside the calls around its own.
+fn wind(vals: Vec<Value>, from: &Continuation, to: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    let (mut leaving, mut entering) = (from.winders(), to.winders());
+    let (mut afters, mut befores) = (vec![], vec![]);
+    // the calls both are inside are a shared tail of both lists
+    while !leaving.is(&entering) {
+        let (from_len, to_len) = (leaving.len(), entering.len());
+        if from_len >= to_len {
+            let (winder, rest) = shift_or_error!(leaving, "Unexpected end of the dynamic-wind calls");
+            afters.push((try!(winder.as_pair()).1, rest.clone()));
+            leaving = rest;
+        }
+        if to_len >= from_len {
+            let (winder, rest) = shift_or_error!(entering, "Unexpected end of the dynamic-wind calls");
+            befores.push((try!(winder.as_pair()).0, rest.clone()));
+            entering = rest;
+        }
+    }
+    let mut thunks = befores;
+    thunks.extend(afters.into_iter().rev());
+    continue_winding(thunks, vals, to)
+}
+
+fn continue_winding(mut thunks: Vec<(Value, List)>, vals: Vec<Value>, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match thunks.pop() {
+        Some((thunk, winders)) => {
+            let k = Rc::new(Continuation::Winders(winders, Rc::new(Continuation::ContinueWinding(thunks, vals, k))));
+            apply(thunk, List::Null, k)
         },
+        None => Ok(Trampoline::RunValues(vals, k))
+    }
+}
+
+// (parameterize ((<parameter> <value>) ...) <body> ...) swaps the converted values into the parameters
+// on the way into the body, and swaps the old ones back out on the way out
+fn expand_parameterize(args: List) -> Result<Value, RuntimeError> {
+    let (bindings, body) = shift_or_error!(args, "Must provide at least two arguments to parameterize");
+    if body.is_empty() {
+        runtime_error!("Must provide at least two arguments to parameterize");
+    }
+    let native = |name| Value::Procedure(Function::Native(name));
+    let thunk = |body: List| body.unshift(null!()).unshift(symbol("lambda")).to_value();
+    let (mut parameters, mut values, mut swaps) = (vec![], vec![], vec![]);
+    for (i, binding) in try!(bindings.as_list()).into_iter().enumerate() {
+        let (parameter, value) = try!(try!(binding.as_list()).unpack2());
+        let (p, v) = (symbol(&format!(" parameter-{}", i)), symbol(&format!(" value-{}", i)));
+        parameters.push(Value::from_vec(vec![p.clone(), parameter]));
+        values.push(Value::from_vec(vec![v.clone(), Value::from_vec(vec![native("parameter-convert"), p.clone(), value])]));
+        swaps.push(Value::from_vec(vec![symbol("set!"), v.clone(), Value::from_vec(vec![native("parameter-swap!"), p, v])]));
+    }
+    if swaps.is_empty() {
+        return Ok(Value::from_vec(vec![thunk(body)]));
+    }
+    let swap = thunk(List::from_vec(swaps));
+    let wind = Value::from_vec(vec![native("dynamic-wind"), swap.clone(), thunk(body), swap]);
+    let inner = Value::from_vec(vec![symbol("let"), Value::from_vec(values), wind]);
+    Ok(Value::from_vec(vec![symbol("let"), Value::from_vec(parameters), inner]))
+}
+
+// The numeric procedures that give two results, which they return as two values
+fn number_pair(f: &'static str, args: List) -> Result<Vec<Value>, RuntimeError> {
+    let arity = if f == "exact-integer-sqrt" { 1 } else { 2 };
+    if args.len() != arity {
+        runtime_error!(Arity(args.clone().to_value()); "Must supply exactly {} arguments to {}: {:?}", arity, f, args);
+    }
+    let nums = try!(numbers(args));
+    let (a, b) = match f {
+        "exact-integer-sqrt" => try!(nums[0].exact_integer_sqrt()),
+        "floor/" => try!(nums[0].floor_division(&nums[1])),
+        _ => try!(nums[0].truncate_division(&nums[1]))
+    };
+    Ok(vec![Value::Number(a), Value::Number(b)])
+}
+
+// vector-map and vector-for-each call back into Scheme, so they run on the trampoline instead of as primitives
+fn apply_vector_map(f: &'static str, args: List, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    let (func, vectors_raw) = shift_or_error!(args, "Must supply at least two arguments to {}", f);
+    if vectors_raw.is_empty() {
+        runtime_error!(Arity(Value::from_vec(vec![func])); "Must supply at least two arguments to {}", f);
+    }
+    let vectors: Vec<Vec<Value>> = try!(vectors_raw.into_iter().map(|v| v.as_vector().map(|v| v.borrow().clone())).collect());
+    let len = vectors.iter().map(|v| v.len()).min().unwrap_or(0);
+
+    // The calls are popped off the end, so store them in reverse order
+    let calls = (0..len).rev().map(|i| List::from_vec(vectors.iter().map(|v| v[i].clone()).collect())).collect();
+    continue_vector_map(func, calls, Vec::with_capacity(len), f == "vector-map", k)
+}
+
+fn continue_vector_map(func: Value, mut calls: Vec<List>, acc: Vec<Value>, collect: bool, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match calls.pop() {
+        Some(args) => apply(func.clone(), args, Rc::new(Continuation::ContinueVectorMap(func, calls, acc, collect, k))),
+        None => Ok(Trampoline::Run(if collect { Value::from_vector(acc) } else { null!() }, k))
+    }
+}
+
+// Each nested quasiquote goes a level deeper and each unquote a level back out, and only what is
+// unquoted at depth 1 is evaluated. Lists and vectors are taken apart into a Quasiquotation.
+fn quasiquote(val: Value, depth: usize, env: Rc<RefCell<Environment>>, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match val {
+        Value::List(List::Null) => Ok(Trampoline::Run(null!(), k)),
         Value::List(list) => {
-            let expanded = list.into_iter().map(|v| expand_macro(v, substitutions)).collect();
-            Value::from_vec(expanded)
+            if let Some((name, operand)) = quasiquote_form(&list) {
+                return match name {
+                    "unquote" if depth == 1 => Ok(Trampoline::Bounce(operand, env, k)),
+                    "unquote-splicing" if depth == 1 => runtime_error!("unquote-splicing must be inside a list or vector: {:?}", list),
+                    _ => {
+                        let depth = if name == "quasiquote" { depth + 1 } else { depth - 1 };
+                        Ok(Trampoline::QuasiBounce(operand, depth, env, Rc::new(Continuation::WrapQuasiquoted(name, k))))
+                    }
+                }
+            }
+
+            // the parser reads (a . ,b) as (a unquote b), so a quasiquote form after the first element is the tail
+            let mut todo = vec![];
+            let mut rest = list.to_value();
+            while let Value::List(List::Cell(pair)) = rest.clone() {
+                if !todo.is_empty() && quasiquote_form(&List::Cell(pair.clone())).is_some() {
+                    break;
+                }
+                let pair = pair.borrow();
+                todo.push(pair.car.clone());
+                rest = pair.cdr.clone();
+            }
+            todo.reverse();
+            continue_quasiquoting(Quasiquotation { todo, done: vec![], tail: Some(rest), depth }, env, k)
         },
-        other => other
+        Value::Vector(vec) => {
+            let todo = vec.borrow().iter().rev().cloned().collect();
+            continue_quasiquoting(Quasiquotation { todo, done: vec![], tail: None, depth }, env, k)
+        },
+        _ => Ok(Trampoline::Run(val, k))
+    }
+}
+
+fn continue_quasiquoting(mut q: Quasiquotation, env: Rc<RefCell<Environment>>, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match q.todo.pop() {
+        Some(Value::List(ref l)) if q.depth == 1 && quasiquote_form(l).map(|(name, _)| name) == Some("unquote-splicing") => {
+            let (_, operand) = quasiquote_form(l).unwrap();
+            Ok(Trampoline::Bounce(operand, env.clone(), Rc::new(Continuation::SpliceQuasiquoted(q, env, k))))
+        },
+        Some(val) => {
+            let depth = q.depth;
+            Ok(Trampoline::QuasiBounce(val, depth, env.clone(), Rc::new(Continuation::ContinueQuasiquoting(q, env, k))))
+        },
+        None => {
+            match q.tail {
+                Some(tail) => Ok(Trampoline::QuasiBounce(tail, q.depth, env, Rc::new(Continuation::FinishQuasiquoting(q.done, k)))),
+                None => Ok(Trampoline::Run(Value::from_vector(q.done), k))
+            }
+        }
+    }
+}
+
+// Matches (quasiquote x), (unquote x) and (unquote-splicing x)
+fn quasiquote_form(list: &List) -> Option<(&'static str, Value)> {
+    let (car, cdr) = match list.clone().shift() {
+        Some((car, cdr)) if cdr.next() == Some(List::Null) => (car, cdr),
+        _ => return None
+    };
+    let name = match car {
+        Value::Symbol(ref s) if s == "quasiquote" => "quasiquote",
+        Value::Symbol(ref s) if s == "unquote" => "unquote",
+        Value::Symbol(ref s) if s == "unquote-splicing" => "unquote-splicing",
+        _ => return None
+    };
+    cdr.shift().map(|(operand, _)| (name, operand))
+}
+
+fn symbol(s: &str) -> Value {
+    Value::Symbol(s.to_string())
+}
+
+// The derived forms below are rewritten into core special forms, following section 7.3 of R7RS. The
+// loops they create are ordinary tail calls, so they run on the trampoline without growing the
+// continuation. Temporary variables have names containing a space, which the reader can never
+// produce, so they can't capture the user's variables.
+
+fn expand_when_unless(f: &str, args: List) -> Result<Value, RuntimeError> {
+    let (test, body) = shift_or_error!(args, "Must provide at least two arguments to {}", f);
+    if body.is_empty() {
+        runtime_error!("Must provide at least two arguments to {}", f);
+    }
+    let body = body.unshift(symbol("begin")).to_value();
+    let unspecified = Value::from_vec(vec![symbol("quote"), null!()]);
+    Ok(Value::from_vec(if f == "when" {
+        vec![symbol("if"), test, body, unspecified]
+    } else {
+        vec![symbol("if"), test, unspecified, body]
+    }))
+}
+
+// (let* ((a 1) (b a)) body) => (let ((a 1)) (let* ((b a)) body)), and likewise for let*-values
+fn expand_let_star(f: &str, inner_f: &str, args: List) -> Result<Value, RuntimeError> {
+    let (bindings, body) = shift_or_error!(args, "Must provide at least two arguments to {}", f);
+    match try!(bindings.as_list()).shift() {
+        Some((first, rest)) => {
+            let inner = body.unshift(rest.to_value()).unshift(symbol(f)).to_value();
+            Ok(Value::from_vec(vec![symbol(inner_f), List::Null.unshift(first).to_value(), inner]))
+        },
+        None => Ok(body.unshift(null!()).unshift(symbol(inner_f)).to_value())
+    }
+}
+
+// (letrec ((f init) ...) body) => ((lambda () (define f init) ... body)), which also gives letrec*
+// its left to right order
+fn expand_letrec(f: &str, args: List) -> Result<Value, RuntimeError> {
+    let (bindings, body) = shift_or_error!(args, "Must provide at least two arguments to {}", f);
+    let mut exprs = vec![];
+    for binding in try!(bindings.as_list()) {
+        let (name, init) = try!(try!(binding.as_list()).unpack2());
+        exprs.push(Value::from_vec(vec![symbol("define"), name, init]));
     }
+    exprs.extend(body);
+    let lambda = List::from_vec(exprs).unshift(null!()).unshift(symbol("lambda"));
+    Ok(Value::from_vec(vec![lambda.to_value()]))
 }
 
-fn evaluate_expressions(exprs: List, env: Rc<RefCell<Environment>>, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
-    match exprs.shift() {
-        Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateExpressions(cdr, env, k))),
+// (let loop ((i 0)) body) => ((letrec ((loop (lambda (i) body))) loop) 0)
+fn expand_named_let(args: List) -> Result<Value, RuntimeError> {
+    let (name, rest) = shift_or_error!(args, "Must provide at least three arguments to a named let");
+    let (bindings, body) = shift_or_error!(rest, "Must provide at least three arguments to a named let");
+    let (mut vars, mut inits) = (vec![], vec![]);
+    for binding in try!(bindings.as_list()) {
+        let (var, init) = try!(try!(binding.as_list()).unpack2());
+        vars.push(var);
+        inits.push(init);
+    }
+    let lambda = body.unshift(Value::from_vec(vars)).unshift(symbol("lambda")).to_value();
+    let bindings = Value::from_vec(vec![Value::from_vec(vec![name.clone(), lambda])]);
+    inits.insert(0, Value::from_vec(vec![symbol("letrec"), bindings, name]));
+    Ok(Value::from_vec(inits))
+}
+
+// (do ((var init step) ...) (test expr ...) command ...) becomes a named let that runs the commands
+// and loops with the steps until the test is true
+fn expand_do(args: List) -> Result<Value, RuntimeError> {
+    let (specs, rest) = shift_or_error!(args, "Must provide at least two arguments to do");
+    let (exit, commands) = shift_or_error!(rest, "Must provide at least two arguments to do");
+    let (test, exprs) = shift_or_error!(try!(exit.as_list()), "Must provide a test in do");
+    let (mut bindings, mut steps) = (vec![], vec![symbol(" do-loop")]);
+    for spec in try!(specs.as_list()) {
+        let (var, rest) = shift_or_error!(try!(spec.as_list()), "Empty variable spec in do");
+        let (init, step) = shift_or_error!(rest, "Must provide an initial value for {} in do", var);
+        let step = match step.shift() {
+            Some((step, more)) => {
+                if !more.is_empty() {
+                    runtime_error!("Too many values in do for {}", var);
+                }
+                step
+            },
+            None => var.clone()
+        };
+        bindings.push(Value::from_vec(vec![var, init]));
+        steps.push(step);
+    }
+    let result = if exprs.is_empty() {
+        Value::from_vec(vec![symbol("quote"), null!()])
+    } else {
+        exprs.unshift(symbol("begin")).to_value()
+    };
+    let mut body = commands.to_vec();
+    body.push(Value::from_vec(steps));
+    let next = List::from_vec(body).unshift(symbol("begin")).to_value();
+    let loop_body = Value::from_vec(vec![symbol("if"), test, result, next]);
+    Ok(Value::from_vec(vec![symbol("let"), symbol(" do-loop"), Value::from_vec(bindings), loop_body]))
+}
+
+// Each clause becomes an if, with the rest of the clauses in a new cond as the alternative
+fn expand_cond(args: List) -> Result<Value, RuntimeError> {
+    let (clause, rest) = match args.shift() {
+        Some(pair) => pair,
+        None => return Ok(Value::from_vec(vec![symbol("quote"), null!()]))
+    };
+    let (test, body) = shift_or_error!(try!(clause.as_list()), "Empty clause in cond");
+    if test == symbol("else") {
+        if !rest.is_empty() {
+            runtime_error!("else must be the last clause in cond");
+        }
+        return Ok(body.unshift(symbol("begin")).to_value());
+    }
+    let alternative = rest.unshift(symbol("cond")).to_value();
+    match body.shift() {
+        // (test) => (or test (cond ...))
+        None => Ok(Value::from_vec(vec![symbol("or"), test, alternative])),
+        // (test => f) => (let ((t test)) (if t (f t) (cond ...)))
+        Some((Value::Symbol(ref arrow), receiver)) if arrow == "=>" => {
+            let temp = symbol(" cond-test");
+            let call = Value::from_vec(vec![try!(receiver.unpack1()), temp.clone()]);
+            let bindings = Value::from_vec(vec![Value::from_vec(vec![temp.clone(), test])]);
+            Ok(Value::from_vec(vec![symbol("let"), bindings, Value::from_vec(vec![symbol("if"), temp, call, alternative])]))
+        },
+        // (test expr ...) => (if test (begin expr ...) (cond ...))
+        Some((first, more)) => {
+            let body = more.unshift(first).unshift(symbol("begin")).to_value();
+            Ok(Value::from_vec(vec![symbol("if"), test, body, alternative]))
+        }
+    }
+}
+
+// (guard (var clause ...) body ...) follows the reference implementation in R7RS. The handler goes
+// back to the guard's continuation to pick a clause, and if none of them match, returns to the
+// handler's continuation to pass the condition on with raise-continuable:
+// ((call/cc (lambda (guard-k)
+//    (with-exception-handler
+//      (lambda (condition)
+//        ((call/cc (lambda (handler-k)
+//           (guard-k (lambda () (let ((var condition)) (cond clause ... (else (handler-k (lambda () (raise-continuable condition))))))))))))
+//      (lambda () (let ((value ((lambda () body ...)))) (guard-k (lambda () value))))))))
+fn expand_guard(args: List) -> Result<Value, RuntimeError> {
+    let (spec, body) = shift_or_error!(args, "Must provide at least two arguments to guard");
+    if body.is_empty() {
+        runtime_error!("Must provide at least two arguments to guard");
+    }
+    let (var, clauses) = shift_or_error!(try!(spec.as_list()), "Must provide a variable in guard");
+    let (guard_k, handler_k, condition, value) = (symbol(" guard-k"), symbol(" handler-k"), symbol(" guard-condition"), symbol(" guard-value"));
+    let thunk = |body: Value| Value::from_vec(vec![symbol("lambda"), null!(), body]);
+
+    let mut clauses = clauses.to_vec();
+    let has_else = match clauses.last() {
+        Some(Value::List(clause)) => matches!(clause.clone().shift(), Some((ref test, _)) if *test == symbol("else")),
+        _ => false
+    };
+    if !has_else {
+        let reraise = Value::from_vec(vec![symbol("raise-continuable"), condition.clone()]);
+        clauses.push(Value::from_vec(vec![symbol("else"), Value::from_vec(vec![handler_k.clone(), thunk(reraise)])]));
+    }
+    let select = Value::from_vec(vec![
+        symbol("let"),
+        Value::from_vec(vec![Value::from_vec(vec![var, condition.clone()])]),
+        List::from_vec(clauses).unshift(symbol("cond")).to_value()
+    ]);
+    let reenter = Value::from_vec(vec![guard_k.clone(), thunk(select)]);
+    let handler = Value::from_vec(vec![
+        symbol("lambda"),
+        Value::from_vec(vec![condition]),
+        Value::from_vec(vec![Value::from_vec(vec![symbol("call/cc"), Value::from_vec(vec![symbol("lambda"), Value::from_vec(vec![handler_k]), reenter])])])
+    ]);
+
+    let result = Value::from_vec(vec![body.unshift(null!()).unshift(symbol("lambda")).to_value()]);
+    let finish = Value::from_vec(vec![guard_k.clone(), thunk(value.clone())]);
+    let protected = thunk(Value::from_vec(vec![symbol("let"), Value::from_vec(vec![Value::from_vec(vec![value, result])]), finish]));
+    let install = Value::from_vec(vec![symbol("with-exception-handler"), handler, protected]);
+    let escape = Value::from_vec(vec![symbol("lambda"), Value::from_vec(vec![guard_k]), install]);
+    Ok(Value::from_vec(vec![Value::from_vec(vec![symbol("call/cc"), escape])]))
+}
+
+fn evaluate_expressions(exprs: List, env: Rc<RefCell<Environment>>, k: Rc<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match exprs.shift_spanned() {
+        // The last expression is in tail position, so it gets the continuation as is
+        Some((car, span, cdr)) if cdr.is_empty() => Ok(bounce(car, span, env, k)),
+        Some((car, span, cdr)) => Ok(bounce(car, span, env.clone(), Rc::new(Continuation::EvaluateExpressions(cdr, env, k)))),
         None => runtime_error!("Trying to evaluate an empty expression list")
     }
 }
 
+// Evaluates expr, which says where it is when it's an identifier with a span
+fn bounce(expr: Value, span: Option<Rc<Span>>, env: Rc<RefCell<Environment>>, k: Rc<Continuation>) -> Trampoline {
+    match (expr, span) {
+        (Value::Symbol(name), Some(span)) => Trampoline::Lookup(name, span, env, k),
+        (expr, _) => Trampoline::Bounce(expr, env, k)
+    }
+}
+
 fn process(exprs: List, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if exprs.len() == 0 {
         return Ok(null!());
     }
+    let start = try!(evaluate_expressions(exprs, env.clone(), Rc::new(Continuation::Return)));
+    run_program(start, env)
+}
 
-    let mut b = try!(evaluate_expressions(exprs, env, Box::new(Continuation::Return)));
+// Runs a program read from source, whose top-level forms haven't been expanded yet
+fn process_toplevel(forms: Vec<(Node, Option<SourceMap>)>, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let k = Continuation::EvaluateToplevel(Rc::new(forms), 0, env.clone(), Rc::new(Continuation::Return));
+    run_program(Trampoline::Run(null!(), Rc::new(k)), env)
+}
+
+fn run_program(start: Trampoline, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    // The program is the main thread, and the threads that join it get what it returns
+    let scheduler = Environment::scheduler(env.clone());
+    let main = scheduler.borrow_mut().new_thread(true);
+    let res = run_threads(start, &scheduler, &main);
+    let result = match res {
+        Ok(Value::Values(ref vals)) => Ok(vals.clone()),
+        Ok(ref val) => Ok(vec![val.clone()]),
+        Err(ref e) => Err(Rc::new(e.clone()))
+    };
+    scheduler.borrow_mut().finish(&main, result);
+    // the error of a thread that nothing joined would be lost, so the program ends with it instead
+    let failed = mem::take(&mut scheduler.borrow_mut().failed);
+    match failed.first().map(|thread| thread.result.borrow().clone()) {
+        Some(Some(Err(ref e))) if res.is_ok() => Err(RuntimeError::clone(e)),
+        _ => res
+    }
+}
+
+// Runs the trampoline until the main thread lands, switching to another thread whenever the one
+// running waits or has had its slice. Spawned threads that haven't finished by then carry on the next
+// time a program runs.
+fn run_threads(mut b: Trampoline, scheduler: &RefCell<Scheduler>, main: &Rc<Thread>) -> Result<Value, RuntimeError> {
+    // Errors are reported at the last place in the source that the trampoline passed through, which
+    // heads the backtrace unless the continuation that was waiting already starts there
+    let mut current = main.clone();
+    let mut position = None;
+    let slice = scheduler.borrow().slice;
+    let mut bounces = 0;
     loop {
-        match b {
-            // Bounce is the usual execution path. It's used for pretty much everything.
-            // Special forms are caught here instead of in env so that they can't be redefined in env.
-            Trampoline::Bounce(a, env, k) => {
-                b = match a {
-                    Value::List(list) => {
-                        match list.shift() {
-                            Some((car, cdr)) => Trampoline::Bounce(car, env.clone(), Continuation::BeginFunc(cdr, env, Box::new(k))),
-                            None => runtime_error!("Can't apply an empty list as a function")
-                        }
+        let res = match b {
+            // Land just returns the value. It's only ever created by the continuation that process starts the main thread with.
+            Trampoline::Land(a) => return Ok(a),
+            Trampoline::Schedule(request) => scheduler.borrow_mut().request(&current, request),
+            mut running => {
+                // a thread that doesn't wait still gives up its turn once it's taken a slice of bounces
+                bounces += 1;
+                if bounces >= slice {
+                    bounces = 0;
+                    let mut scheduler = scheduler.borrow_mut();
+                    if let Some((thread, next)) = scheduler.next(main) {
+                        scheduler.ready.push_back((mem::replace(&mut current, thread), running));
+                        position = None;
+                        running = next;
+                    }
+                }
+                if let Some(here) = running.position() {
+                    position = Some(here);
+                }
+                match step(running) {
+                    Ok(next) => {
+                        b = next;
+                        continue;
                     },
-                    Value::Symbol(ref s) => {
-                        let val = match s.as_ref() {
-                            "if"     => Value::SpecialForm(SpecialForm::If),
-                            "define" => Value::SpecialForm(SpecialForm::Define),
-                            "set!"   => Value::SpecialForm(SpecialForm::Set),
-                            "lambda" => Value::SpecialForm(SpecialForm::Lambda),
-                            "λ"      => Value::SpecialForm(SpecialForm::Lambda),
-                            "let"    => Value::SpecialForm(SpecialForm::Let),
-                            "quote"  => Value::SpecialForm(SpecialForm::Quote),
-                            "quasiquote" => Value::SpecialForm(SpecialForm::Quasiquote),
-                            "eval"   => Value::SpecialForm(SpecialForm::Eval),
-                            "apply"  => Value::SpecialForm(SpecialForm::Apply),
-                            "begin"  => Value::SpecialForm(SpecialForm::Begin),
-                            "and"    => Value::SpecialForm(SpecialForm::And),
-                            "or"     => Value::SpecialForm(SpecialForm::Or),
-                            "call/cc" => Value::SpecialForm(SpecialForm::CallCC),
-                            "define-syntax-rule" => Value::SpecialForm(SpecialForm::DefineSyntaxRule),
-                            _ => {
-                                match env.borrow().get(s) {
-                                    Some(v) => v,
-                                    None => runtime_error!("Identifier not found: {}", s)
-                                }
-                            }
-                        };
-                        try!(k.run(val))
+                    Err(e) => Err(e)
+                }
+            }
+        };
+        let next = match res {
+            Ok(next) => next,
+            Err(mut e) => {
+                if let Some((ref span, ref env)) = position {
+                    let frame = frame(span, env);
+                    if e.location.is_none() && e.backtrace.first() != Some(&frame) {
+                        e.backtrace.insert(0, frame);
+                    }
+                    e = e.at(Some(span.clone()));
+                }
+                // errors in code with handlers installed are raised as error objects, for them to catch
+                // and others leave the dynamic-wind calls they escape from on their way out. An uncaught
+                // error stops the program, or just the thread it happened in when that isn't the main one.
+                match e.continuation.take() {
+                    Some(k) if !k.handlers().is_empty() => Some(Trampoline::Raise(Value::ErrorObject(Rc::new(e)), k)),
+                    Some(k) if !k.winders().is_empty() => Some(try!(wind(vec![], &k, Rc::new(Continuation::Abort(Box::new(e)))))),
+                    _ if Rc::ptr_eq(&current, main) => return Err(e),
+                    _ => {
+                        scheduler.borrow_mut().finish(&current, Err(Rc::new(e)));
+                        None
+                    }
+                }
+            }
+        };
+        b = match next {
+            Some(next) => next,
+            // the thread is waiting or done, so it's the next one's turn
+            None => {
+                let (thread, next) = try!(scheduler.borrow_mut().wait(main));
+                current = thread;
+                position = None;
+                bounces = 0;
+                next
+            }
+        };
+    }
+}
+
+// The value of an identifier. Special forms are caught here instead of in env so that they can't be redefined in env.
+fn evaluate_symbol(s: &String, env: &Rc<RefCell<Environment>>, k: &Rc<Continuation>) -> Result<Value, RuntimeError> {
+    Ok(match s.as_ref() {
+        "if"     => Value::SpecialForm(SpecialForm::If),
+        "define" => Value::SpecialForm(SpecialForm::Define),
+        "set!"   => Value::SpecialForm(SpecialForm::Set),
+        "lambda" => Value::SpecialForm(SpecialForm::Lambda),
+        "λ"      => Value::SpecialForm(SpecialForm::Lambda),
+        "case-lambda" => Value::SpecialForm(SpecialForm::CaseLambda),
+        "let"    => Value::SpecialForm(SpecialForm::Let),
+        "let*"   => Value::SpecialForm(SpecialForm::LetStar),
+        "letrec" => Value::SpecialForm(SpecialForm::Letrec),
+        "letrec*" => Value::SpecialForm(SpecialForm::LetrecStar),
+        "cond"   => Value::SpecialForm(SpecialForm::Cond),
+        "case"   => Value::SpecialForm(SpecialForm::Case),
+        "when"   => Value::SpecialForm(SpecialForm::When),
+        "unless" => Value::SpecialForm(SpecialForm::Unless),
+        "do"     => Value::SpecialForm(SpecialForm::Do),
+        "quote"  => Value::SpecialForm(SpecialForm::Quote),
+        "quasiquote" => Value::SpecialForm(SpecialForm::Quasiquote),
+        "eval"   => Value::SpecialForm(SpecialForm::Eval),
+        "apply"  => Value::SpecialForm(SpecialForm::Apply),
+        "begin"  => Value::SpecialForm(SpecialForm::Begin),
+        "and"    => Value::SpecialForm(SpecialForm::And),
+        "or"     => Value::SpecialForm(SpecialForm::Or),
+        "call/cc" => Value::SpecialForm(SpecialForm::CallCC),
+        "macroexpand" => Value::SpecialForm(SpecialForm::Macroexpand),
+        "macroexpand-1" => Value::SpecialForm(SpecialForm::Macroexpand1),
+        "guard"  => Value::SpecialForm(SpecialForm::Guard),
+        "parameterize" => Value::SpecialForm(SpecialForm::Parameterize),
+        "let-values" => Value::SpecialForm(SpecialForm::LetValues),
+        "let*-values" => Value::SpecialForm(SpecialForm::LetStarValues),
+        "receive" => Value::SpecialForm(SpecialForm::Receive),
+        "define-values" => Value::SpecialForm(SpecialForm::DefineValues),
+        "reset"  => Value::SpecialForm(SpecialForm::Reset),
+        "shift"  => Value::SpecialForm(SpecialForm::Shift),
+        _ => {
+            match env.borrow().get(s) {
+                Some(v) => v,
+                None => runtime_error_in!(k, UnboundVariable(Value::Symbol(s.clone())); "Identifier not found: {}", s)
+            }
+        }
+    })
+}
+
+// Takes one step of the trampoline
+fn step(b: Trampoline) -> Result<Trampoline, RuntimeError> {
+    match b {
+        // Bounce is the usual execution path. It's used for pretty much everything.
+        Trampoline::Bounce(a, env, k) => {
+            Ok(match a {
+                Value::List(ref list) if !list.is_proper() => {
+                    runtime_error_in!(k, "Can't evaluate an improper list: {:?}", list)
+                },
+                Value::List(list) => {
+                    let span = list.span();
+                    match list.shift_spanned() {
+                        Some((car, car_span, cdr)) => bounce(car, car_span, env.clone(), Rc::new(Continuation::BeginFunc(cdr, span, env, k))),
+                        None => runtime_error_in!(k, "Can't apply an empty list as a function")
+                    }
+                },
+                Value::Symbol(ref s) => {
+                    let val = try!(evaluate_symbol(s, &env, &k));
+                    try!(k.run(val))
+                },
+                _ => try!(k.run(a))
+            })
+        },
+
+        Trampoline::Lookup(name, span, env, k) => {
+            let val = try!(evaluate_symbol(&name, &env, &k).map_err(|e| e.at(Some(span))));
+            k.run(val)
+        },
+
+        // QuasiBounce is for quasiquoting mode -- it just passes the value right through, UNLESS it's of the form (unquote X) at depth 1, in which case it switches back to regular evaluating mode using X as the value. Lists and vectors are quasiquoted element by element.
+        Trampoline::QuasiBounce(a, depth, env, k) => {
+            quasiquote(a, depth, env, k)
+        },
+
+        // Run doesn't evaluate the value, it just runs k with it. It's similar to running inline, but bounces to avoid growing the stack.
+        Trampoline::Run(a, k) => {
+            k.run(a)
+        },
+
+        Trampoline::RunValues(vals, k) => {
+            k.run_values(vals)
+        },
+
+        Trampoline::Raise(obj, k) => {
+            raise(obj, false, None, k)
+        },
+
+        Trampoline::Schedule(request) => Ok(Trampoline::Schedule(request)),
+        Trampoline::Land(a) => Ok(Trampoline::Land(a)),
+    }
+}
+
+// The green threads, which take turns on the trampoline in process. The ones that are ready to run
+// wait in a queue with the step they take next, while the ones waiting for a channel or another thread
+// are kept in what they're waiting for, so the scheduler doesn't hold on to them.
+pub struct Scheduler {
+    ready: VecDeque<(Rc<Thread>, Trampoline)>,
+    sleeping: Vec<(Instant, Waiter)>,
+    // How many bounces a thread gets before the next one that's ready has a turn
+    slice: usize,
+    spawned: usize,
+    // The threads that stopped with an error that no thread has joined them to get
+    failed: Vec<Rc<Thread>>,
+}
+
+impl Scheduler {
+    fn new() -> Scheduler {
+        Scheduler { ready: VecDeque::new(), sleeping: vec![], slice: 1000, spawned: 0, failed: vec![] }
+    }
+
+    fn new_thread(&mut self, main: bool) -> Rc<Thread> {
+        if !main {
+            self.spawned += 1;
+        }
+        Rc::new(Thread { id: self.spawned, main, result: RefCell::new(None), joiners: RefCell::new(vec![]) })
+    }
+
+    // Answers right away with the step the thread takes next, or puts it somewhere to wait
+    fn request(&mut self, current: &Rc<Thread>, request: Request) -> Result<Option<Trampoline>, RuntimeError> {
+        match request {
+            Request::Spawn(thunk, k) => {
+                let thread = self.new_thread(false);
+                let start = Rc::new(Continuation::ExecuteApply(thunk, Rc::new(Continuation::EndThread(thread.clone()))));
+                self.ready.push_back((thread.clone(), Trampoline::Run(null!(), start)));
+                Ok(Some(Trampoline::Run(Value::Thread(thread), k)))
+            },
+            Request::Yield(k) => {
+                self.ready.push_back((current.clone(), Trampoline::Run(null!(), k)));
+                Ok(None)
+            },
+            Request::Sleep(wake, k) => {
+                self.sleeping.push((wake, (current.clone(), k)));
+                Ok(None)
+            },
+            Request::Join(thread, k) => {
+                match *thread.result.borrow() {
+                    Some(Ok(ref vals)) => return Ok(Some(Trampoline::RunValues(vals.clone(), k))),
+                    Some(Err(ref e)) => {
+                        self.failed.retain(|failed| !Rc::ptr_eq(failed, &thread));
+                        return Ok(Some(Trampoline::Raise(Value::ErrorObject(e.clone()), k)));
                     },
-                    _ => try!(k.run(a))
+                    None if Rc::ptr_eq(&thread, current) => runtime_error_in!(k, "A thread can't join itself"),
+                    None => ()
                 }
+                thread.joiners.borrow_mut().push((current.clone(), k));
+                Ok(None)
             },
-
-            // QuasiBounce is for quasiquoting mode -- it just passes the value right through, UNLESS it's of the form (unquote X), in which case it switches back to regular evaluating mode using X as the value.
-            Trampoline::QuasiBounce(a, env, k) => {
-                b = match a {
-                    Value::List(list) => {
-                        match list.shift() {
-                            Some((car, cdr)) => {
-                                match car {
-                                    Value::Symbol(ref s) if s == "unquote" => {
-                                        let expr = try!(cdr.unpack1());
-                                        Trampoline::Bounce(expr, env, k)
-                                    },
-                                    _ => {
-                                        Trampoline::QuasiBounce(car, env.clone(), Continuation::ContinueQuasiquoting(cdr, List::Null, env, Box::new(k)))
-                                    }
-                                }
+            Request::Put(channel, val, k) => {
+                // a thread waiting to get an item takes this one, without it going in the channel
+                let getter = waiting(&channel.getters, |(thread, _)| thread);
+                if let Some((thread, getter)) = getter {
+                    self.ready.push_back((thread, Trampoline::Run(val, getter)));
+                } else if channel.capacity.is_none_or(|capacity| channel.items.borrow().len() < capacity) {
+                    channel.items.borrow_mut().push_back(val);
+                } else {
+                    channel.putters.borrow_mut().push_back(((current.clone(), k), val));
+                    return Ok(None);
+                }
+                Ok(Some(Trampoline::Run(null!(), k)))
+            },
+            Request::Get(channel, k) => {
+                // the first thread waiting to put an item puts it in the space this one leaves
+                let item = channel.items.borrow_mut().pop_front();
+                let putter = waiting(&channel.putters, |&((ref thread, _), _)| thread);
+                let item = match (item, putter) {
+                    (item, Some(((thread, putter), val))) => {
+                        self.ready.push_back((thread, Trampoline::Run(null!(), putter)));
+                        match item {
+                            Some(item) => {
+                                channel.items.borrow_mut().push_back(val);
+                                item
                             },
-                            None => try!(k.run(null!()))
+                            None => val
                         }
                     },
-                    _ => try!(k.run(a))
-                }
+                    (Some(item), None) => item,
+                    (None, None) => {
+                        channel.getters.borrow_mut().push_back((current.clone(), k));
+                        return Ok(None);
+                    }
+                };
+                Ok(Some(Trampoline::Run(item, k)))
             },
+            Request::Current(k) => Ok(Some(Trampoline::Run(Value::Thread(current.clone()), k))),
+            Request::End(thread, vals) => {
+                self.finish(&thread, Ok(vals));
+                Ok(None)
+            }
+        }
+    }
 
-            // Run doesn't evaluate the value, it just runs k with it. It's similar to running inline, but bounces to avoid growing the stack.
-            Trampoline::Run(a, k) => {
-                b = try!(k.run(a))
-            },
+    // Records what a thread ended with, and gives it to the threads that joined it
+    fn finish(&mut self, thread: &Rc<Thread>, result: Result<Vec<Value>, Rc<RuntimeError>>) {
+        if result.is_err() && !thread.main && thread.joiners.borrow().is_empty() {
+            self.failed.push(thread.clone());
+        }
+        for (joiner, k) in thread.joiners.borrow_mut().drain(..) {
+            let next = match result {
+                Ok(ref vals) => Trampoline::RunValues(vals.clone(), k),
+                Err(ref e) => Trampoline::Raise(Value::ErrorObject(e.clone()), k)
+            };
+            self.ready.push_back((joiner, next));
+        }
+        *thread.result.borrow_mut() = Some(result);
+    }
 
-            // Land just returns the value. It should only ever be created at the very beginning of process, and will be the last Trampoline value called.
-            Trampoline::Land(a) => {
-                return Ok(a);
-            },
+    // The next thread that's ready, once the ones whose sleep is over have joined the queue. The main
+    // thread of another program, which can only be a program running inside this one, as macros do,
+    // is left in the queue, and ones whose programs have stopped are dropped.
+    fn next(&mut self, main: &Rc<Thread>) -> Option<(Rc<Thread>, Trampoline)> {
+        if !self.sleeping.is_empty() {
+            let now = Instant::now();
+            self.sleeping.sort_by_key(|&(until, _)| until);
+            while !self.sleeping.is_empty() && self.sleeping[0].0 <= now {
+                let (_, (thread, k)) = self.sleeping.remove(0);
+                self.ready.push_back((thread, Trampoline::Run(null!(), k)));
+            }
         }
-    };
+        for _ in 0..self.ready.len() {
+            let (thread, next) = self.ready.pop_front().expect("The queue must have a thread");
+            if !thread.main || Rc::ptr_eq(&thread, main) {
+                return Some((thread, next));
+            }
+            if thread.result.borrow().is_none() {
+                self.ready.push_back((thread, next));
+            }
+        }
+        None
+    }
+
+    // Like next, but sleeps until a thread wakes up when none are ready. When none are asleep
+    // either, every thread is waiting for another, and none of them ever will be ready.
+    fn wait(&mut self, main: &Rc<Thread>) -> Result<(Rc<Thread>, Trampoline), RuntimeError> {
+        loop {
+            if let Some(next) = self.next(main) {
+                return Ok(next);
+            }
+            match self.sleeping.iter().map(|&(until, _)| until).min() {
+                Some(until) => thread::sleep(until.saturating_duration_since(Instant::now())),
+                None => runtime_error!("Deadlock: every thread is waiting on a channel or another thread")
+            }
+        }
+    }
+}
+
+// Takes the first thread waiting in a channel that can still run, leaving out the main threads of
+// programs that stopped while they waited
+fn waiting<T>(queue: &RefCell<VecDeque<T>>, thread: fn(&T) -> &Rc<Thread>) -> Option<T> {
+    let mut queue = queue.borrow_mut();
+    while let Some(waiter) = queue.pop_front() {
+        if thread(&waiter).result.borrow().is_none() {
+            return Some(waiter);
+        }
+    }
+    None
 }
 
-#[derive(PartialEq)]
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
     values: HashMap<String, Value>,
+    // The name of the procedure whose call made this environment, for backtraces
+    procedure: Option<Rc<str>>,
+    // Only the root has the expander, which holds the macros defined at the top level
+    expander: Option<Rc<Expander>>,
+    // and the scheduler, which holds the threads that are ready to run
+    scheduler: Option<Rc<RefCell<Scheduler>>>,
+}
+
+impl PartialEq for Environment {
+    fn eq(&self, other: &Environment) -> bool {
+        self.parent == other.parent && self.values == other.values
+    }
 }
 
 impl fmt::Debug for Environment {
@@ -724,8 +2911,10 @@ impl fmt::Debug for Environment {
 }
 
 impl Environment {
-    fn new_root() -> Result<Rc<RefCell<Environment>>, RuntimeError> {
-        let mut env = Environment { parent: None, values: HashMap::new() };
+    // The built-ins are in the root, and programs run in a child of it, so their top-level
+    // definitions can shadow them
+    fn new_toplevel() -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+        let mut env = Environment { parent: None, values: HashMap::new(), procedure: None, expander: None, scheduler: None };
         try!(env.define("+".to_string(), Value::Procedure(Function::Native("+"))));
         try!(env.define("-".to_string(), Value::Procedure(Function::Native("-"))));
         try!(env.define("*".to_string(), Value::Procedure(Function::Native("*"))));
@@ -733,23 +2922,190 @@ impl Environment {
         try!(env.define("<".to_string(), Value::Procedure(Function::Native("<"))));
         try!(env.define(">".to_string(), Value::Procedure(Function::Native(">"))));
         try!(env.define("=".to_string(), Value::Procedure(Function::Native("="))));
+        try!(env.define("<=".to_string(), Value::Procedure(Function::Native("<="))));
+        try!(env.define(">=".to_string(), Value::Procedure(Function::Native(">="))));
+        try!(env.define("max".to_string(), Value::Procedure(Function::Native("max"))));
+        try!(env.define("min".to_string(), Value::Procedure(Function::Native("min"))));
+        try!(env.define("quotient".to_string(), Value::Procedure(Function::Native("quotient"))));
+        try!(env.define("remainder".to_string(), Value::Procedure(Function::Native("remainder"))));
+        try!(env.define("modulo".to_string(), Value::Procedure(Function::Native("modulo"))));
+        try!(env.define("gcd".to_string(), Value::Procedure(Function::Native("gcd"))));
+        try!(env.define("lcm".to_string(), Value::Procedure(Function::Native("lcm"))));
+        try!(env.define("abs".to_string(), Value::Procedure(Function::Native("abs"))));
+        try!(env.define("floor".to_string(), Value::Procedure(Function::Native("floor"))));
+        try!(env.define("ceiling".to_string(), Value::Procedure(Function::Native("ceiling"))));
+        try!(env.define("round".to_string(), Value::Procedure(Function::Native("round"))));
+        try!(env.define("truncate".to_string(), Value::Procedure(Function::Native("truncate"))));
+        try!(env.define("numerator".to_string(), Value::Procedure(Function::Native("numerator"))));
+        try!(env.define("denominator".to_string(), Value::Procedure(Function::Native("denominator"))));
+        try!(env.define("exact".to_string(), Value::Procedure(Function::Native("exact"))));
+        try!(env.define("inexact".to_string(), Value::Procedure(Function::Native("inexact"))));
+        try!(env.define("exact->inexact".to_string(), Value::Procedure(Function::Native("exact->inexact"))));
+        try!(env.define("inexact->exact".to_string(), Value::Procedure(Function::Native("inexact->exact"))));
+        try!(env.define("sqrt".to_string(), Value::Procedure(Function::Native("sqrt"))));
+        try!(env.define("expt".to_string(), Value::Procedure(Function::Native("expt"))));
+        try!(env.define("exp".to_string(), Value::Procedure(Function::Native("exp"))));
+        try!(env.define("log".to_string(), Value::Procedure(Function::Native("log"))));
+        try!(env.define("sin".to_string(), Value::Procedure(Function::Native("sin"))));
+        try!(env.define("cos".to_string(), Value::Procedure(Function::Native("cos"))));
+        try!(env.define("tan".to_string(), Value::Procedure(Function::Native("tan"))));
+        try!(env.define("asin".to_string(), Value::Procedure(Function::Native("asin"))));
+        try!(env.define("acos".to_string(), Value::Procedure(Function::Native("acos"))));
+        try!(env.define("atan".to_string(), Value::Procedure(Function::Native("atan"))));
+        try!(env.define("number?".to_string(), Value::Procedure(Function::Native("number?"))));
+        try!(env.define("complex?".to_string(), Value::Procedure(Function::Native("complex?"))));
+        try!(env.define("real?".to_string(), Value::Procedure(Function::Native("real?"))));
+        try!(env.define("rational?".to_string(), Value::Procedure(Function::Native("rational?"))));
+        try!(env.define("integer?".to_string(), Value::Procedure(Function::Native("integer?"))));
+        try!(env.define("exact?".to_string(), Value::Procedure(Function::Native("exact?"))));
+        try!(env.define("inexact?".to_string(), Value::Procedure(Function::Native("inexact?"))));
+        try!(env.define("exact-integer?".to_string(), Value::Procedure(Function::Native("exact-integer?"))));
+        try!(env.define("nan?".to_string(), Value::Procedure(Function::Native("nan?"))));
+        try!(env.define("zero?".to_string(), Value::Procedure(Function::Native("zero?"))));
+        try!(env.define("positive?".to_string(), Value::Procedure(Function::Native("positive?"))));
+        try!(env.define("negative?".to_string(), Value::Procedure(Function::Native("negative?"))));
+        try!(env.define("odd?".to_string(), Value::Procedure(Function::Native("odd?"))));
+        try!(env.define("even?".to_string(), Value::Procedure(Function::Native("even?"))));
+        try!(env.define("char?".to_string(), Value::Procedure(Function::Native("char?"))));
+        try!(env.define("char->integer".to_string(), Value::Procedure(Function::Native("char->integer"))));
+        try!(env.define("integer->char".to_string(), Value::Procedure(Function::Native("integer->char"))));
+        try!(env.define("char-upcase".to_string(), Value::Procedure(Function::Native("char-upcase"))));
+        try!(env.define("char-downcase".to_string(), Value::Procedure(Function::Native("char-downcase"))));
+        try!(env.define("char-foldcase".to_string(), Value::Procedure(Function::Native("char-foldcase"))));
+        try!(env.define("char-alphabetic?".to_string(), Value::Procedure(Function::Native("char-alphabetic?"))));
+        try!(env.define("char-numeric?".to_string(), Value::Procedure(Function::Native("char-numeric?"))));
+        try!(env.define("char-whitespace?".to_string(), Value::Procedure(Function::Native("char-whitespace?"))));
+        try!(env.define("char-upper-case?".to_string(), Value::Procedure(Function::Native("char-upper-case?"))));
+        try!(env.define("char-lower-case?".to_string(), Value::Procedure(Function::Native("char-lower-case?"))));
+        try!(env.define("char=?".to_string(), Value::Procedure(Function::Native("char=?"))));
+        try!(env.define("char<?".to_string(), Value::Procedure(Function::Native("char<?"))));
+        try!(env.define("char>?".to_string(), Value::Procedure(Function::Native("char>?"))));
+        try!(env.define("char<=?".to_string(), Value::Procedure(Function::Native("char<=?"))));
+        try!(env.define("char>=?".to_string(), Value::Procedure(Function::Native("char>=?"))));
+        try!(env.define("char-ci=?".to_string(), Value::Procedure(Function::Native("char-ci=?"))));
+        try!(env.define("char-ci<?".to_string(), Value::Procedure(Function::Native("char-ci<?"))));
+        try!(env.define("char-ci>?".to_string(), Value::Procedure(Function::Native("char-ci>?"))));
+        try!(env.define("char-ci<=?".to_string(), Value::Procedure(Function::Native("char-ci<=?"))));
+        try!(env.define("char-ci>=?".to_string(), Value::Procedure(Function::Native("char-ci>=?"))));
+        try!(env.define("string?".to_string(), Value::Procedure(Function::Native("string?"))));
+        try!(env.define("string".to_string(), Value::Procedure(Function::Native("string"))));
+        try!(env.define("make-string".to_string(), Value::Procedure(Function::Native("make-string"))));
+        try!(env.define("string-length".to_string(), Value::Procedure(Function::Native("string-length"))));
+        try!(env.define("string-ref".to_string(), Value::Procedure(Function::Native("string-ref"))));
+        try!(env.define("substring".to_string(), Value::Procedure(Function::Native("substring"))));
+        try!(env.define("string-copy".to_string(), Value::Procedure(Function::Native("string-copy"))));
+        try!(env.define("string-append".to_string(), Value::Procedure(Function::Native("string-append"))));
+        try!(env.define("string->list".to_string(), Value::Procedure(Function::Native("string->list"))));
+        try!(env.define("list->string".to_string(), Value::Procedure(Function::Native("list->string"))));
+        try!(env.define("string->symbol".to_string(), Value::Procedure(Function::Native("string->symbol"))));
+        try!(env.define("symbol->string".to_string(), Value::Procedure(Function::Native("symbol->string"))));
+        try!(env.define("number->string".to_string(), Value::Procedure(Function::Native("number->string"))));
+        try!(env.define("string->number".to_string(), Value::Procedure(Function::Native("string->number"))));
+        try!(env.define("string=?".to_string(), Value::Procedure(Function::Native("string=?"))));
+        try!(env.define("string<?".to_string(), Value::Procedure(Function::Native("string<?"))));
+        try!(env.define("string>?".to_string(), Value::Procedure(Function::Native("string>?"))));
+        try!(env.define("string<=?".to_string(), Value::Procedure(Function::Native("string<=?"))));
+        try!(env.define("string>=?".to_string(), Value::Procedure(Function::Native("string>=?"))));
+        try!(env.define("string-upcase".to_string(), Value::Procedure(Function::Native("string-upcase"))));
+        try!(env.define("string-downcase".to_string(), Value::Procedure(Function::Native("string-downcase"))));
+        try!(env.define("string-split".to_string(), Value::Procedure(Function::Native("string-split"))));
+        try!(env.define("string-index".to_string(), Value::Procedure(Function::Native("string-index"))));
+        try!(env.define("vector?".to_string(), Value::Procedure(Function::Native("vector?"))));
+        try!(env.define("make-vector".to_string(), Value::Procedure(Function::Native("make-vector"))));
+        try!(env.define("vector".to_string(), Value::Procedure(Function::Native("vector"))));
+        try!(env.define("vector-length".to_string(), Value::Procedure(Function::Native("vector-length"))));
+        try!(env.define("vector-ref".to_string(), Value::Procedure(Function::Native("vector-ref"))));
+        try!(env.define("vector-set!".to_string(), Value::Procedure(Function::Native("vector-set!"))));
+        try!(env.define("vector->list".to_string(), Value::Procedure(Function::Native("vector->list"))));
+        try!(env.define("vector-copy".to_string(), Value::Procedure(Function::Native("vector-copy"))));
+        try!(env.define("vector-fill!".to_string(), Value::Procedure(Function::Native("vector-fill!"))));
+        try!(env.define("list->vector".to_string(), Value::Procedure(Function::Native("list->vector"))));
+        try!(env.define("vector-append".to_string(), Value::Procedure(Function::Native("vector-append"))));
+        try!(env.define("vector-map".to_string(), Value::Procedure(Function::Native("vector-map"))));
+        try!(env.define("vector-for-each".to_string(), Value::Procedure(Function::Native("vector-for-each"))));
         try!(env.define("null?".to_string(), Value::Procedure(Function::Native("null?"))));
         try!(env.define("list".to_string(), Value::Procedure(Function::Native("list"))));
+        try!(env.define("pair?".to_string(), Value::Procedure(Function::Native("pair?"))));
+        try!(env.define("list?".to_string(), Value::Procedure(Function::Native("list?"))));
+        try!(env.define("set-car!".to_string(), Value::Procedure(Function::Native("set-car!"))));
+        try!(env.define("set-cdr!".to_string(), Value::Procedure(Function::Native("set-cdr!"))));
+        try!(env.define("eq?".to_string(), Value::Procedure(Function::Native("eq?"))));
+        try!(env.define("eqv?".to_string(), Value::Procedure(Function::Native("eqv?"))));
+        try!(env.define("equal?".to_string(), Value::Procedure(Function::Native("equal?"))));
+        try!(env.define("memq".to_string(), Value::Procedure(Function::Native("memq"))));
+        try!(env.define("memv".to_string(), Value::Procedure(Function::Native("memv"))));
+        try!(env.define("member".to_string(), Value::Procedure(Function::Native("member"))));
+        try!(env.define("assq".to_string(), Value::Procedure(Function::Native("assq"))));
+        try!(env.define("assv".to_string(), Value::Procedure(Function::Native("assv"))));
+        try!(env.define("assoc".to_string(), Value::Procedure(Function::Native("assoc"))));
         try!(env.define("car".to_string(), Value::Procedure(Function::Native("car"))));
         try!(env.define("cdr".to_string(), Value::Procedure(Function::Native("cdr"))));
         try!(env.define("cons".to_string(), Value::Procedure(Function::Native("cons"))));
         try!(env.define("append".to_string(), Value::Procedure(Function::Native("append"))));
         try!(env.define("error".to_string(), Value::Procedure(Function::Native("error"))));
+        try!(env.define("raise".to_string(), Value::Procedure(Function::Native("raise"))));
+        try!(env.define("raise-continuable".to_string(), Value::Procedure(Function::Native("raise-continuable"))));
+        try!(env.define("with-exception-handler".to_string(), Value::Procedure(Function::Native("with-exception-handler"))));
+        try!(env.define("values".to_string(), Value::Procedure(Function::Native("values"))));
+        try!(env.define("call-with-values".to_string(), Value::Procedure(Function::Native("call-with-values"))));
+        try!(env.define("exact-integer-sqrt".to_string(), Value::Procedure(Function::Native("exact-integer-sqrt"))));
+        try!(env.define("floor/".to_string(), Value::Procedure(Function::Native("floor/"))));
+        try!(env.define("truncate/".to_string(), Value::Procedure(Function::Native("truncate/"))));
+        try!(env.define("dynamic-wind".to_string(), Value::Procedure(Function::Native("dynamic-wind"))));
+        try!(env.define("make-parameter".to_string(), Value::Procedure(Function::Native("make-parameter"))));
+        try!(env.define("call-with-continuation-prompt".to_string(), Value::Procedure(Function::Native("call-with-continuation-prompt"))));
+        try!(env.define("abort-current-continuation".to_string(), Value::Procedure(Function::Native("abort-current-continuation"))));
+        try!(env.define("call-with-composable-continuation".to_string(), Value::Procedure(Function::Native("call-with-composable-continuation"))));
+        try!(env.define("make-continuation-prompt-tag".to_string(), Value::Procedure(Function::Native("make-continuation-prompt-tag"))));
+        try!(env.define("default-continuation-prompt-tag".to_string(), Value::Procedure(Function::Native("default-continuation-prompt-tag"))));
+        try!(env.define("continuation-prompt-tag?".to_string(), Value::Procedure(Function::Native("continuation-prompt-tag?"))));
+        try!(env.define("eof-object".to_string(), Value::Procedure(Function::Native("eof-object"))));
+        try!(env.define("eof-object?".to_string(), Value::Procedure(Function::Native("eof-object?"))));
+        try!(env.define("spawn".to_string(), Value::Procedure(Function::Native("spawn"))));
+        try!(env.define("yield".to_string(), Value::Procedure(Function::Native("yield"))));
+        try!(env.define("sleep".to_string(), Value::Procedure(Function::Native("sleep"))));
+        try!(env.define("thread-join!".to_string(), Value::Procedure(Function::Native("thread-join!"))));
+        try!(env.define("current-thread".to_string(), Value::Procedure(Function::Native("current-thread"))));
+        try!(env.define("thread?".to_string(), Value::Procedure(Function::Native("thread?"))));
+        try!(env.define("make-channel".to_string(), Value::Procedure(Function::Native("make-channel"))));
+        try!(env.define("channel-put!".to_string(), Value::Procedure(Function::Native("channel-put!"))));
+        try!(env.define("channel-get!".to_string(), Value::Procedure(Function::Native("channel-get!"))));
+        try!(env.define("channel?".to_string(), Value::Procedure(Function::Native("channel?"))));
+        try!(env.define("error-object?".to_string(), Value::Procedure(Function::Native("error-object?"))));
+        try!(env.define("error-object-message".to_string(), Value::Procedure(Function::Native("error-object-message"))));
+        try!(env.define("error-object-irritants".to_string(), Value::Procedure(Function::Native("error-object-irritants"))));
         try!(env.define("write".to_string(), Value::Procedure(Function::Native("write"))));
         try!(env.define("display".to_string(), Value::Procedure(Function::Native("display"))));
         try!(env.define("displayln".to_string(), Value::Procedure(Function::Native("displayln"))));
         try!(env.define("print".to_string(), Value::Procedure(Function::Native("print"))));
         try!(env.define("newline".to_string(), Value::Procedure(Function::Native("newline"))));
-        Ok(Rc::new(RefCell::new(env)))
+        try!(env.define("gensym".to_string(), Value::Procedure(Function::Native("gensym"))));
+        try!(env.define("generate-temporaries".to_string(), Value::Procedure(Function::Native("generate-temporaries"))));
+
+        // eval, apply, call/cc and macroexpand are special forms here, but are procedures as far as macros are concerned
+        let mut globals: Vec<String> = env.values.keys().cloned().collect();
+        globals.extend(["eval", "apply", "call/cc", "macroexpand", "macroexpand-1"].iter().map(|s| s.to_string()));
+        let env = Rc::new(RefCell::new(env));
+        let toplevel = Environment::new_child(env.clone());
+        let evaluator = MacroEvaluator { toplevel: Rc::downgrade(&toplevel) };
+        env.borrow_mut().expander = Some(Rc::new(Expander::new(&globals, Box::new(evaluator))));
+        env.borrow_mut().scheduler = Some(Rc::new(RefCell::new(Scheduler::new())));
+        Ok(toplevel)
+    }
+
+    // The named procedure that the code running in env is written in, which for a lambda that was
+    // never named is the procedure around it. It's None at the top level.
+    fn procedure(env: &Rc<RefCell<Environment>>) -> Option<Rc<str>> {
+        let env = env.borrow();
+        match (&env.procedure, &env.parent) {
+            (Some(name), _) => Some(name.clone()),
+            (None, Some(parent)) => Environment::procedure(parent),
+            (None, None) => None
+        }
     }
 
     fn new_child(parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
-        let env = Environment { parent: Some(parent), values: HashMap::new() };
+        let env = Environment { parent: Some(parent), values: HashMap::new(), procedure: None, expander: None, scheduler: None };
         Rc::new(RefCell::new(env))
     }
 
@@ -775,7 +3131,7 @@ impl Environment {
             // Recurse up the environment tree until a value is found or the end is reached
             match self.parent {
                 Some(ref parent) => parent.borrow_mut().set(key, value),
-                None => runtime_error!("Can't set! an undefined variable: {:?}", key)
+                None => runtime_error!(UnboundVariable(Value::Symbol(key)); "Can't set! an undefined variable: {:?}", key)
             }
         }
     }
@@ -800,62 +3156,572 @@ impl Environment {
             None => env_ref.clone()
         }
     }
+
+    // Where a program's top-level definitions go, which is the child of the root that env is in
+    fn get_toplevel(env_ref: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let env = env_ref.borrow();
+        match env.parent {
+            Some(ref parent) if parent.borrow().parent.is_some() => Environment::get_toplevel(parent.clone()),
+            _ => env_ref.clone()
+        }
+    }
+
+    // The expander is kept in the root, with the macros defined at the top level
+    fn expander(env_ref: Rc<RefCell<Environment>>) -> Rc<Expander> {
+        let root = Environment::get_root(env_ref);
+        let expander = root.borrow().expander.clone();
+        expander.expect("The root environment must have an expander")
+    }
+
+    fn scheduler(env_ref: Rc<RefCell<Environment>>) -> Rc<RefCell<Scheduler>> {
+        let root = Environment::get_root(env_ref);
+        let scheduler = root.borrow().scheduler.clone();
+        scheduler.expect("The root environment must have a scheduler")
+    }
+}
+
+// Runs the transformers of procedural macros at the top level, while the expander is expanding.
+// The root environment keeps the expander, so this only has a weak reference back to the top level.
+struct MacroEvaluator {
+    toplevel: Weak<RefCell<Environment>>,
+}
+
+struct MacroProcedure {
+    procedure: Value,
+    toplevel: Weak<RefCell<Environment>>,
+}
+
+impl Evaluator for MacroEvaluator {
+    fn evaluate(&self, expr: &Node) -> Result<Rc<dyn Transformer>, MacroError> {
+        let toplevel = self.toplevel.upgrade().expect("The expander must not outlive its environment");
+        match try!(process(List::from_vec(vec![Value::from_node(expr)]), toplevel)) {
+            procedure @ Value::Procedure(_) | procedure @ Value::Continuation(_) => {
+                Ok(Rc::new(MacroProcedure { procedure, toplevel: self.toplevel.clone() }))
+            },
+            v => Err(MacroError { message: format!("A macro transformer must be a procedure: {:?}", v) })
+        }
+    }
+}
+
+impl Transformer for MacroProcedure {
+    fn call(&self, args: &[Node], renaming: Option<Rc<Renaming>>) -> Result<Node, MacroError> {
+        let toplevel = self.toplevel.upgrade().expect("The expander must not outlive its environment");
+        let mut call = vec![self.procedure.clone()];
+        call.extend(args.iter().map(|n| Value::from_vec(vec![symbol("quote"), Value::from_node(n)])));
+        if let Some(renaming) = renaming {
+            call.push(Value::Procedure(Function::Rename(renaming.clone())));
+            call.push(Value::Procedure(Function::Compare(renaming)));
+        }
+        let res = try!(process(List::from_vec(vec![Value::from_vec(call)]), toplevel));
+        Ok(try!(res.to_node()))
+    }
 }
 
 fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
     match f {
         "+" => {
-            let sum = try!(args.into_iter().fold(Ok(0), |s, a| match s {
-                Ok(z) => Ok(z + try!(a.as_integer())),
-                _ => s
-            }));
-            Ok(Value::Integer(sum))
+            let sum = try!(numbers(args)).iter().fold(Number::Integer(0), |s, n| s.add(n));
+            Ok(Value::Number(sum))
         },
         "-" => {
-            if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to -: {:?}", args);
+            if args.len() < 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply at least one argument to -: {:?}", args);
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Integer(try!(l.as_integer()) - try!(r.as_integer())))
+            let nums = try!(numbers(args));
+            if nums.len() == 1 {
+                return Ok(Value::Number(nums[0].negate()));
+            }
+            let difference = nums[1..].iter().fold(nums[0].clone(), |d, n| d.sub(n));
+            Ok(Value::Number(difference))
         },
         "*" => {
-            let product = try!(args.into_iter().fold(Ok(1), |s, a| match s {
-                Ok(z) => Ok(z * try!(a.as_integer())),
-                _ => s
-            }));
-            Ok(Value::Integer(product))
+            let product = try!(numbers(args)).iter().fold(Number::Integer(1), |p, n| p.mul(n));
+            Ok(Value::Number(product))
         },
         "/" => {
-            if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to /: {:?}", args);
+            if args.len() < 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply at least one argument to /: {:?}", args);
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Integer(try!(l.as_integer()) / try!(r.as_integer())))
+            let nums = try!(numbers(args));
+            if nums.len() == 1 {
+                return Ok(Value::Number(try!(Number::Integer(1).div(&nums[0]))));
+            }
+            let mut quotient = nums[0].clone();
+            for n in nums[1..].iter() {
+                quotient = try!(quotient.div(n));
+            }
+            Ok(Value::Number(quotient))
+        },
+        "=" | "<" | ">" | "<=" | ">=" => {
+            if args.len() < 2 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply at least two arguments to {}: {:?}", f, args);
+            }
+            let nums = try!(numbers(args));
+            let res = nums.windows(2).all(|pair| {
+                match pair[0].compare(&pair[1]) {
+                    Some(Ordering::Less) => f == "<" || f == "<=",
+                    Some(Ordering::Equal) => f == "=" || f == "<=" || f == ">=",
+                    Some(Ordering::Greater) => f == ">" || f == ">=",
+                    None => false
+                }
+            });
+            Ok(Value::Boolean(res))
         },
-        "<" => {
+        "max" | "min" => {
+            if args.len() < 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply at least one argument to {}: {:?}", f, args);
+            }
+            let nums = try!(numbers(args));
+            let res = nums[1..].iter().fold(nums[0].clone(), |m, n| if f == "max" { m.max(n) } else { m.min(n) });
+            Ok(Value::Number(res))
+        },
+        "quotient" | "remainder" | "modulo" | "expt" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to <: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to {}: {:?}", f, args);
             }
             let (l, r) = try!(args.unpack2());
-            Ok(Value::Boolean(try!(l.as_integer()) < try!(r.as_integer())))
+            let (l, r) = (try!(l.as_number()), try!(r.as_number()));
+            let res = match f {
+                "quotient" => try!(l.quotient(&r)),
+                "remainder" => try!(l.remainder(&r)),
+                "modulo" => try!(l.modulo(&r)),
+                _ => try!(l.expt(&r))
+            };
+            Ok(Value::Number(res))
+        },
+        "gcd" | "lcm" => {
+            let nums = try!(numbers(args));
+            let mut res = Number::Integer(if f == "gcd" { 0 } else { 1 });
+            for n in nums.iter() {
+                res = if f == "gcd" { try!(res.gcd(n)) } else { try!(res.lcm(n)) };
+            }
+            Ok(Value::Number(res))
         },
-        ">" => {
+        "abs" | "floor" | "ceiling" | "round" | "truncate" | "numerator" | "denominator" |
+        "exact" | "inexact" | "exact->inexact" | "inexact->exact" | "sqrt" |
+        "exp" | "sin" | "cos" | "tan" | "asin" | "acos" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let n = try!(try!(args.unpack1()).as_number());
+            let res = match f {
+                "abs" => n.abs(),
+                "floor" => n.floor(),
+                "ceiling" => n.ceiling(),
+                "round" => n.round(),
+                "truncate" => n.truncate(),
+                "numerator" => try!(n.numerator()),
+                "denominator" => try!(n.denominator()),
+                "exact" | "inexact->exact" => try!(n.to_exact()),
+                "inexact" | "exact->inexact" => n.to_inexact(),
+                "sqrt" => n.sqrt(),
+                "exp" => n.map_real(f64::exp),
+                "sin" => n.map_real(f64::sin),
+                "cos" => n.map_real(f64::cos),
+                "tan" => n.map_real(f64::tan),
+                "asin" => n.map_real(f64::asin),
+                _ => n.map_real(f64::acos)
+            };
+            Ok(Value::Number(res))
+        },
+        "log" | "atan" => {
+            // (log z1 z2) is the logarithm of z1 in base z2, (atan y x) is the angle of the point (x, y)
+            let nums = try!(numbers(args));
+            let res = match (f, nums.len()) {
+                ("log", 1) => nums[0].map_real(f64::ln),
+                ("log", 2) => Number::Real(nums[0].to_f64().ln() / nums[1].to_f64().ln()),
+                ("atan", 1) => nums[0].map_real(f64::atan),
+                ("atan", 2) => Number::Real(nums[0].to_f64().atan2(nums[1].to_f64())),
+                _ => runtime_error!(Arity; "Must supply one or two arguments to {}: {:?}", f, nums)
+            };
+            Ok(Value::Number(res))
+        },
+        "number?" | "complex?" | "real?" | "rational?" | "integer?" | "exact?" | "inexact?" | "exact-integer?" | "nan?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let res = match try!(args.unpack1()) {
+                Value::Number(n) => {
+                    match f {
+                        "rational?" => n.is_rational(),
+                        "integer?" => n.is_integer(),
+                        "exact?" => n.is_exact(),
+                        "inexact?" => !n.is_exact(),
+                        "exact-integer?" => n.is_exact_integer(),
+                        "nan?" => n.is_nan(),
+                        _ => true
+                    }
+                },
+                v => {
+                    if f == "exact?" || f == "inexact?" || f == "nan?" {
+                        runtime_error!(WrongType(v); "Expected a number value: {:?}", v);
+                    }
+                    false
+                }
+            };
+            Ok(Value::Boolean(res))
+        },
+        "zero?" | "positive?" | "negative?" | "odd?" | "even?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let n = try!(try!(args.unpack1()).as_number());
+            let res = match f {
+                "zero?" => n.is_zero(),
+                "positive?" => n.is_positive(),
+                "negative?" => n.is_negative(),
+                "odd?" => !try!(n.is_even()),
+                _ => try!(n.is_even())
+            };
+            Ok(Value::Boolean(res))
+        },
+        "char?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to char?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Char(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "char->integer" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to char->integer: {:?}", args);
+            }
+            let c = try!(try!(args.unpack1()).as_char());
+            Ok(Value::Number(Number::Integer(c as i64)))
+        },
+        "integer->char" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to integer->char: {:?}", args);
+            }
+            let n = try!(try!(args.unpack1()).as_number());
+            let c = match n {
+                Number::Integer(i) => u32::try_from(i).ok().and_then(::std::char::from_u32),
+                _ => None
+            };
+            match c {
+                Some(c) => Ok(Value::Char(c)),
+                None => runtime_error!("Not a Unicode scalar value: {}", n)
+            }
+        },
+        "char-upcase" | "char-downcase" | "char-foldcase" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let c = try!(try!(args.unpack1()).as_char());
+            let res = match f {
+                "char-upcase" => character::upcase(c),
+                "char-downcase" => character::downcase(c),
+                _ => character::foldcase(c)
+            };
+            Ok(Value::Char(res))
+        },
+        "char-alphabetic?" | "char-numeric?" | "char-whitespace?" | "char-upper-case?" | "char-lower-case?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let c = try!(try!(args.unpack1()).as_char());
+            let res = match f {
+                "char-alphabetic?" => c.is_alphabetic(),
+                "char-numeric?" => c.is_numeric(),
+                "char-whitespace?" => c.is_whitespace(),
+                "char-upper-case?" => c.is_uppercase(),
+                _ => c.is_lowercase()
+            };
+            Ok(Value::Boolean(res))
+        },
+        "char=?" | "char<?" | "char>?" | "char<=?" | "char>=?" |
+        "char-ci=?" | "char-ci<?" | "char-ci>?" | "char-ci<=?" | "char-ci>=?" => {
+            if args.len() < 2 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply at least two arguments to {}: {:?}", f, args);
+            }
+            let ci = f.starts_with("char-ci");
+            let cs: Vec<char> = try!(chars(args)).into_iter().map(|c| if ci { character::foldcase(c) } else { c }).collect();
+            let op = &f[if ci { 7 } else { 4 }..];
+            let res = cs.windows(2).all(|pair| {
+                match pair[0].cmp(&pair[1]) {
+                    Ordering::Less => op == "<?" || op == "<=?",
+                    Ordering::Equal => op == "=?" || op == "<=?" || op == ">=?",
+                    Ordering::Greater => op == ">?" || op == ">=?"
+                }
+            });
+            Ok(Value::Boolean(res))
+        },
+        "string?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to string?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::String(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "string" => {
+            Ok(Value::from_string(try!(chars(args)).into_iter().collect()))
+        },
+        "make-string" => {
+            let mut args = args.into_iter();
+            let (k, fill) = match (args.next(), args.next(), args.next()) {
+                (Some(k), None, None) => (try!(k.as_index()), ' '),
+                (Some(k), Some(c), None) => (try!(k.as_index()), try!(c.as_char())),
+                _ => runtime_error!(Arity; "Must supply one or two arguments to make-string")
+            };
+            Ok(Value::from_string(try!(repeat_char(fill, k))))
+        },
+        "string-length" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to string-length: {:?}", args);
+            }
+            let s = try!(try!(args.unpack1()).as_string());
+            Ok(Value::Number(Number::Integer(s.chars().count() as i64)))
+        },
+        "string-ref" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to >: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to string-ref: {:?}", args);
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Boolean(try!(l.as_integer()) > try!(r.as_integer())))
+            let (s, k) = try!(args.unpack2());
+            let (s, k) = (try!(s.as_string()), try!(k.as_index()));
+            match s.chars().nth(k) {
+                Some(c) => Ok(Value::Char(c)),
+                None => runtime_error!("Index out of range for string-ref: {} {}", string::Written(&s), k)
+            }
+        },
+        "substring" | "string-copy" => {
+            // (substring string start end), (string-copy string [start [end]])
+            let mut args = args.into_iter();
+            let s = match args.next() {
+                Some(s) => try!(s.as_string()),
+                None => runtime_error!(Arity; "Must supply a string to {}", f)
+            };
+            let len = s.chars().count();
+            let (start, end) = match (args.next(), args.next(), args.next()) {
+                (None, None, None) if f == "string-copy" => (0, len),
+                (Some(start), None, None) if f == "string-copy" => (try!(start.as_index()), len),
+                (Some(start), Some(end), None) => (try!(start.as_index()), try!(end.as_index())),
+                _ => runtime_error!(Arity; "Wrong number of arguments to {}", f)
+            };
+            match string::char_range(&s, start, end) {
+                Some(sub) => Ok(Value::from_string(sub.to_string())),
+                None => runtime_error!("Index out of range for {}: {} {} {}", f, string::Written(&s), start, end)
+            }
+        },
+        "string-append" => {
+            let strs: Result<Vec<String>, RuntimeError> = args.into_iter().map(|v| v.as_string()).collect();
+            Ok(Value::from_string(try!(strs).concat()))
+        },
+        "string->list" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to string->list: {:?}", args);
+            }
+            let s = try!(try!(args.unpack1()).as_string());
+            Ok(Value::from_vec(s.chars().map(Value::Char).collect()))
+        },
+        "list->string" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to list->string: {:?}", args);
+            }
+            let l = try!(try!(args.unpack1()).as_list());
+            Ok(Value::from_string(try!(chars(l)).into_iter().collect()))
+        },
+        "string->symbol" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to string->symbol: {:?}", args);
+            }
+            Ok(Value::Symbol(try!(try!(args.unpack1()).as_string())))
+        },
+        "symbol->string" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to symbol->string: {:?}", args);
+            }
+            Ok(Value::from_string(try!(try!(args.unpack1()).as_symbol())))
+        },
+        "number->string" => {
+            let mut args = args.into_iter();
+            let (n, radix) = match (args.next(), args.next(), args.next()) {
+                (Some(n), None, None) => (try!(n.as_number()), 10),
+                (Some(n), Some(radix), None) => (try!(n.as_number()), try!(radix.as_index())),
+                _ => runtime_error!(Arity; "Must supply one or two arguments to number->string")
+            };
+            match radix {
+                2 | 8 | 10 | 16 => Ok(Value::from_string(try!(n.to_string_radix(radix as u32)))),
+                _ => runtime_error!("Invalid radix for number->string: {}", radix)
+            }
+        },
+        "string->number" => {
+            let mut args = args.into_iter();
+            let (s, radix) = match (args.next(), args.next(), args.next()) {
+                (Some(s), None, None) => (try!(s.as_string()), 10),
+                (Some(s), Some(radix), None) => (try!(s.as_string()), try!(radix.as_index())),
+                _ => runtime_error!(Arity; "Must supply one or two arguments to string->number")
+            };
+            let prefix = match radix {
+                2 => "#b",
+                8 => "#o",
+                10 => "",
+                16 => "#x",
+                _ => runtime_error!("Invalid radix for string->number: {}", radix)
+            };
+            match Number::parse(&format!("{}{}", prefix, s)) {
+                Some(n) => Ok(Value::Number(n)),
+                None => Ok(Value::Boolean(false))
+            }
+        },
+        "string=?" | "string<?" | "string>?" | "string<=?" | "string>=?" => {
+            if args.len() < 2 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply at least two arguments to {}: {:?}", f, args);
+            }
+            let strs: Vec<String> = try!(args.into_iter().map(|v| v.as_string()).collect());
+            let res = strs.windows(2).all(|pair| {
+                match pair[0].cmp(&pair[1]) {
+                    Ordering::Less => f == "string<?" || f == "string<=?",
+                    Ordering::Equal => f == "string=?" || f == "string<=?" || f == "string>=?",
+                    Ordering::Greater => f == "string>?" || f == "string>=?"
+                }
+            });
+            Ok(Value::Boolean(res))
+        },
+        "string-upcase" | "string-downcase" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let s = try!(try!(args.unpack1()).as_string());
+            Ok(Value::from_string(if f == "string-upcase" { s.to_uppercase() } else { s.to_lowercase() }))
+        },
+        "string-split" => {
+            // (string-split string [separator]), where the separator is a char or a string
+            let mut args = args.into_iter();
+            let (s, sep) = match (args.next(), args.next(), args.next()) {
+                (Some(s), None, None) => (try!(s.as_string()), None),
+                (Some(s), Some(Value::Char(c)), None) => (try!(s.as_string()), Some(c.to_string())),
+                (Some(s), Some(sep), None) => (try!(s.as_string()), Some(try!(sep.as_string()))),
+                _ => runtime_error!(Arity; "Must supply one or two arguments to string-split")
+            };
+            let parts = string::split(&s, sep.as_ref().map(|sep| &sep[..]));
+            Ok(Value::from_vec(parts.into_iter().map(Value::from_string).collect()))
+        },
+        "string-index" => {
+            // (string-index string char [start]) returns the index of the first occurrence, or #f
+            let mut args = args.into_iter();
+            let (s, c, start) = match (args.next(), args.next(), args.next(), args.next()) {
+                (Some(s), Some(c), None, None) => (try!(s.as_string()), try!(c.as_char()), 0),
+                (Some(s), Some(c), Some(start), None) => (try!(s.as_string()), try!(c.as_char()), try!(start.as_index())),
+                _ => runtime_error!(Arity; "Must supply two or three arguments to string-index")
+            };
+            match s.chars().skip(start).position(|x| x == c) {
+                Some(i) => Ok(Value::Number(Number::Integer((start + i) as i64))),
+                None => Ok(Value::Boolean(false))
+            }
+        },
+        "vector?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to vector?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Vector(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "make-vector" => {
+            let mut args = args.into_iter();
+            let (k, fill) = match (args.next(), args.next(), args.next()) {
+                (Some(k), None, None) => (try!(k.as_index()), Value::Number(Number::Integer(0))),
+                (Some(k), Some(fill), None) => (try!(k.as_index()), fill),
+                _ => runtime_error!(Arity; "Must supply one or two arguments to make-vector")
+            };
+            Ok(Value::from_vector(try!(repeat_value(fill, k))))
+        },
+        "vector" => {
+            Ok(Value::from_vector(args.to_vec()))
+        },
+        "vector-length" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to vector-length: {:?}", args);
+            }
+            let v = try!(try!(args.unpack1()).as_vector());
+            let len = v.borrow().len();
+            Ok(Value::Number(Number::Integer(len as i64)))
         },
-        "=" => {
+        "vector-ref" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to =: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to vector-ref: {:?}", args);
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Boolean(try!(l.as_integer()) == try!(r.as_integer())))
+            let (v, k) = try!(args.unpack2());
+            let (v, k) = (try!(v.as_vector()), try!(k.as_index()));
+            let res = v.borrow().get(k).cloned();
+            match res {
+                Some(val) => Ok(val),
+                None => runtime_error!("Index out of range for vector-ref: {}", k)
+            }
+        },
+        "vector-set!" => {
+            if args.len() != 3 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly three arguments to vector-set!: {:?}", args);
+            }
+            let (v, k, obj) = try!(args.unpack3());
+            let (v, k) = (try!(v.as_vector()), try!(k.as_index()));
+            let mut vec = v.borrow_mut();
+            if k >= vec.len() {
+                runtime_error!("Index out of range for vector-set!: {}", k);
+            }
+            vec[k] = obj;
+            Ok(null!())
+        },
+        "vector->list" | "vector-copy" | "vector-fill!" => {
+            // (vector->list vector [start [end]]), and likewise for vector-copy and (vector-fill! vector fill [start [end]])
+            let mut args = args.into_iter();
+            let v = match args.next() {
+                Some(v) => try!(v.as_vector()),
+                None => runtime_error!(Arity; "Must supply a vector to {}", f)
+            };
+            let fill = if f == "vector-fill!" {
+                match args.next() {
+                    Some(fill) => Some(fill),
+                    None => runtime_error!(Arity; "Must supply a fill value to vector-fill!")
+                }
+            } else {
+                None
+            };
+            let len = v.borrow().len();
+            let (start, end) = match (args.next(), args.next(), args.next()) {
+                (None, None, None) => (0, len),
+                (Some(start), None, None) => (try!(start.as_index()), len),
+                (Some(start), Some(end), None) => (try!(start.as_index()), try!(end.as_index())),
+                _ => runtime_error!(Arity; "Wrong number of arguments to {}", f)
+            };
+            if start > end || end > len {
+                runtime_error!("Index out of range for {}: {} {}", f, start, end);
+            }
+            match fill {
+                Some(fill) => {
+                    for elem in v.borrow_mut()[start..end].iter_mut() {
+                        *elem = fill.clone();
+                    }
+                    Ok(null!())
+                },
+                None => {
+                    let elems = v.borrow()[start..end].to_vec();
+                    Ok(if f == "vector->list" { Value::from_vec(elems) } else { Value::from_vector(elems) })
+                }
+            }
+        },
+        "list->vector" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to list->vector: {:?}", args);
+            }
+            let l = try!(try!(args.unpack1()).as_list());
+            Ok(Value::from_vector(l.to_vec()))
+        },
+        "vector-append" => {
+            let mut res = vec![];
+            for v in args {
+                res.extend(try!(v.as_vector()).borrow().iter().cloned());
+            }
+            Ok(Value::from_vector(res))
         },
         "null?" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to null?: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to null?: {:?}", args);
             }
             let v = try!(args.unpack1());
             match v {
@@ -868,54 +3734,211 @@ fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
         },
         "car" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly two arguments to car: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to car: {:?}", args);
             }
-            let l = try!(try!(args.unpack1()).as_list());
-            match l.shift() {
-                Some((car, _)) => Ok(car),
-                None => runtime_error!("Can't run car on an empty list")
+            match try!(args.unpack1()) {
+                Value::List(List::Null) => runtime_error!(WrongType(null!()); "Can't run car on an empty list"),
+                v => Ok(try!(v.as_pair()).0)
             }
         },
         "cdr" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly two arguments to cdr: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to cdr: {:?}", args);
             }
-            let l = try!(try!(args.unpack1()).as_list());
-            match l.shift() {
-                Some((_, cdr)) => Ok(cdr.to_value()),
-                None => runtime_error!("Can't run cdr on an empty list")
+            match try!(args.unpack1()) {
+                Value::List(List::Null) => runtime_error!(WrongType(null!()); "Can't run cdr on an empty list"),
+                v => Ok(try!(v.as_pair()).1)
             }
         },
         "cons" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to cons: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to cons: {:?}", args);
+            }
+            let (car, cdr) = try!(args.unpack2());
+            Ok(List::cons(car, cdr).to_value())
+        },
+        "set-car!" | "set-cdr!" => {
+            if args.len() != 2 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to {}: {:?}", f, args);
+            }
+            let (pair, obj) = try!(args.unpack2());
+            match pair {
+                Value::List(List::Cell(ref pair)) => {
+                    if f == "set-car!" {
+                        pair.borrow_mut().car = obj;
+                    } else {
+                        pair.borrow_mut().cdr = obj;
+                    }
+                    Ok(null!())
+                },
+                _ => runtime_error!(WrongType(pair); "Expected a pair: {:?}", pair)
+            }
+        },
+        "eq?" | "eqv?" | "equal?" => {
+            if args.len() != 2 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to {}: {:?}", f, args);
+            }
+            let (a, b) = try!(args.unpack2());
+            Ok(Value::Boolean(if f == "equal?" { a.equal(&b) } else { a.is(&b) }))
+        },
+        "memq" | "memv" | "member" => {
+            if args.len() != 2 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to {}: {:?}", f, args);
+            }
+            // returns the rest of the list starting at the first match, sharing its pairs
+            let (obj, list) = try!(args.unpack2());
+            let matches = |v: &Value| if f == "member" { obj.equal(v) } else { obj.is(v) };
+            let mut l = try!(list.as_list());
+            while let Some((car, cdr)) = l.clone().shift() {
+                if matches(&car) {
+                    return Ok(l.to_value());
+                }
+                l = cdr;
+            }
+            Ok(Value::Boolean(false))
+        },
+        "assq" | "assv" | "assoc" => {
+            if args.len() != 2 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to {}: {:?}", f, args);
+            }
+            let (key, alist) = try!(args.unpack2());
+            let matches = |v: &Value| if f == "assoc" { key.equal(v) } else { key.is(v) };
+            for entry in try!(alist.as_list()) {
+                let (entry_key, _) = try!(entry.clone().as_pair());
+                if matches(&entry_key) {
+                    return Ok(entry);
+                }
             }
-            let (elem, list) = try!(args.unpack2());
-            Ok(try!(list.as_list()).unshift(elem).to_value())
+            Ok(Value::Boolean(false))
         },
         "append" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to append: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly two arguments to append: {:?}", args);
             }
-            let (list1raw, list2raw) = try!(args.unpack2());
+            // The last argument becomes the tail as is, so it doesn't have to be a list
+            let (list1raw, list2) = try!(args.unpack2());
             let list1 = try!(list1raw.as_list());
-            let mut list2 = try!(list2raw.as_list());
-
-            for elem in list1.reverse() {
-                list2 = list2.unshift(elem)
+            Ok(Value::from_vec_dotted(list1.to_vec(), list2))
+        },
+        "pair?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to pair?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::List(List::Cell(_)) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
             }
-            Ok(list2.to_value())
         },
-        "error" => {
+        "list?" => {
+            if args.len() != 1 {
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to list?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::List(l) => Ok(Value::Boolean(l.is_proper())),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "gensym" => {
+            let prefix = match args.len() {
+                0 => "g".to_string(),
+                1 => match try!(args.unpack1()) {
+                    Value::Symbol(s) => s,
+                    Value::String(s) => (*s).clone(),
+                    v => runtime_error!(WrongType(v); "Expected a symbol or string as the prefix for gensym: {:?}", v)
+                },
+                _ => runtime_error!(Arity(args.clone().to_value()); "Must supply at most one argument to gensym: {:?}", args)
+            };
+            Ok(Value::Symbol(expander::gensym(&prefix)))
+        },
+        "generate-temporaries" => {
+            // a fresh symbol for each element of the list, named after it if it's a symbol
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to error: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to generate-temporaries: {:?}", args);
+            }
+            let temps = try!(try!(args.unpack1()).as_list()).into_iter().map(|v| match v {
+                Value::Symbol(s) => Value::Symbol(expander::gensym(&s)),
+                _ => Value::Symbol(expander::gensym("g"))
+            }).collect();
+            Ok(Value::from_vec(temps))
+        },
+        "error" => {
+            // (error message irritant ...)
+            let (msg, irritants) = match args.shift() {
+                Some(split) => split,
+                None => runtime_error!(Arity(null!()); "Must supply at least one argument to error")
+            };
+            let irritants: Vec<Value> = irritants.into_iter().collect();
+            let mut e = RuntimeError::new(iter::once(&msg).chain(&irritants).map(|v| format!("{:?}", v)).collect::<Vec<_>>().join(" "));
+            e.irritants = irritants;
+            Err(e.of(ErrorKind::Raised, Some(msg)))
+        },
+        "parameter-swap!" => {
+            // gives back the value the parameter had, for swapping back in later
+            match try!(args.unpack2()) {
+                (Value::Procedure(Function::Parameter(ref p)), value) => Ok(p.value.replace(value)),
+                (val, _) => runtime_error!(WrongType(val); "Expected a parameter to parameterize: {:?}", val)
+            }
+        },
+        "make-continuation-prompt-tag" => {
+            // the optional name is only for printing
+            let name = args.shift().map(|(name, _)| format!("{}", name));
+            Ok(Value::PromptTag(Some(Rc::new(PromptTag { name }))))
+        },
+        "default-continuation-prompt-tag" => {
+            if !args.is_empty() {
+                runtime_error!(Arity(args.to_value()); "default-continuation-prompt-tag takes no arguments");
+            }
+            Ok(Value::PromptTag(None))
+        },
+        "continuation-prompt-tag?" => {
+            let val = try!(args.unpack1());
+            Ok(Value::Boolean(matches!(val, Value::PromptTag(_))))
+        },
+        "make-channel" => {
+            // without a capacity, a channel holds as many items as are put in it
+            let capacity = match args.shift() {
+                Some((capacity, _)) => Some(try!(capacity.as_index())),
+                None => None
+            };
+            Ok(Value::Channel(Rc::new(Channel {
+                capacity,
+                items: RefCell::new(VecDeque::new()),
+                putters: RefCell::new(VecDeque::new()),
+                getters: RefCell::new(VecDeque::new()),
+            })))
+        },
+        "thread?" => {
+            let val = try!(args.unpack1());
+            Ok(Value::Boolean(matches!(val, Value::Thread(_))))
+        },
+        "channel?" => {
+            let val = try!(args.unpack1());
+            Ok(Value::Boolean(matches!(val, Value::Channel(_))))
+        },
+        "eof-object" => {
+            if !args.is_empty() {
+                runtime_error!(Arity(args.to_value()); "eof-object takes no arguments");
+            }
+            Ok(Value::Eof)
+        },
+        "eof-object?" => {
+            let val = try!(args.unpack1());
+            Ok(Value::Boolean(val == Value::Eof))
+        },
+        "error-object?" => {
+            let val = try!(args.unpack1());
+            Ok(Value::Boolean(matches!(val, Value::ErrorObject(_))))
+        },
+        "error-object-message" | "error-object-irritants" => {
+            match try!(args.unpack1()) {
+                Value::ErrorObject(ref e) if f == "error-object-message" => Ok(e.message_value()),
+                Value::ErrorObject(ref e) => Ok(e.irritant_values()),
+                val => runtime_error!(WrongType(val); "Expected an error object for {}: {:?}", f, val)
             }
-            let msg = try!(args.unpack1());
-            runtime_error!("{:?}", msg)
         },
         "write" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to write: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to write: {:?}", args);
             }
             let val = try!(args.unpack1());
             print!("{:?}", val);
@@ -923,7 +3946,7 @@ fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
         },
         "display" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to display: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to display: {:?}", args);
             }
             let val = try!(args.unpack1());
             print!("{}", val);
@@ -931,7 +3954,7 @@ fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
         },
         "displayln" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to displayln: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to displayln: {:?}", args);
             }
             let val = try!(args.unpack1());
             println!("{}", val);
@@ -939,7 +3962,7 @@ fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
         },
         "print" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to print: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly one argument to print: {:?}", args);
             }
             let val = try!(args.unpack1());
             match val {
@@ -950,7 +3973,7 @@ fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
         },
         "newline" => {
             if args.len() != 0 {
-                runtime_error!("Must supply exactly zero arguments to newline: {:?}", args);
+                runtime_error!(Arity(args.clone().to_value()); "Must supply exactly zero arguments to newline: {:?}", args);
             }
             println!("");
             Ok(null!())
@@ -961,19 +3984,48 @@ fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
     }
 }
 
+fn numbers(args: List) -> Result<Vec<Number>, RuntimeError> {
+    args.into_iter().map(|v| v.as_number()).collect()
+}
+
+fn chars(args: List) -> Result<Vec<char>, RuntimeError> {
+    args.into_iter().map(|v| v.as_char()).collect()
+}
+
+// make-string and make-vector reserve their space first, so that a size there isn't room for is an
+// error instead of aborting the process
+fn repeat_char(fill: char, k: usize) -> Result<String, RuntimeError> {
+    let mut s = String::new();
+    if k.checked_mul(fill.len_utf8()).is_none_or(|len| s.try_reserve_exact(len).is_err()) {
+        runtime_error!("Can't make a string of {} characters", k);
+    }
+    s.extend(iter::repeat_n(fill, k));
+    Ok(s)
+}
+
+fn repeat_value(fill: Value, k: usize) -> Result<Vec<Value>, RuntimeError> {
+    let mut vec = Vec::new();
+    if vec.try_reserve_exact(k).is_err() {
+        runtime_error!("Can't make a vector of {} elements", k);
+    }
+    vec.resize(k, fill);
+    Ok(vec)
+}
+
 #[cfg(test)]
 fn exec(list: List) -> Result<Value, RuntimeError> {
-    process(list, try!(Environment::new_root()))
+    let nodes: Result<Vec<Node>, RuntimeError> = list.into_iter().map(|v| v.to_node()).collect();
+    try!(Interpreter::new()).run(&try!(nodes))
 }
 
 #[test]
 fn test_add1() {
     // runTest (+ 1 2) => 3
     let i = vec![Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                      Value::Integer(1),
-                                      Value::Integer(2)])];
+                                      Value::Number(Number::Integer(1)),
+                                      Value::Number(Number::Integer(2))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(3));
+               Value::Number(Number::Integer(3)));
 }
 
 #[test]
@@ -981,13 +4033,13 @@ fn test_add2() {
     // runTest (+ (+ 1 2) (+ 3 4)) => 10
     let i = vec![Value::from_vec(vec![Value::Symbol("+".to_string()),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                           Value::Integer(1),
-                                                           Value::Integer(2)]),
+                                                           Value::Number(Number::Integer(1)),
+                                                           Value::Number(Number::Integer(2))]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                           Value::Integer(3),
-                                                           Value::Integer(4)])])];
+                                                           Value::Number(Number::Integer(3)),
+                                                           Value::Number(Number::Integer(4))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(10));
+               Value::Number(Number::Integer(10)));
 }
 
 #[test]
@@ -995,26 +4047,26 @@ fn test_add3() {
     // runTest (+ (+ 1 2) (+ (+ 3 5 6) 4)) => 21
     let i = vec![Value::from_vec(vec![Value::Symbol("+".to_string()),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                           Value::Integer(1),
-                                                           Value::Integer(2)]),
+                                                           Value::Number(Number::Integer(1)),
+                                                           Value::Number(Number::Integer(2))]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                            Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                                                Value::Integer(3),
-                                                                                Value::Integer(5),
-                                                                                Value::Integer(6)]),
-                                                           Value::Integer(4)])])];
+                                                                                Value::Number(Number::Integer(3)),
+                                                                                Value::Number(Number::Integer(5)),
+                                                                                Value::Number(Number::Integer(6))]),
+                                                           Value::Number(Number::Integer(4))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(21));
+               Value::Number(Number::Integer(21)));
 }
 
 #[test]
 fn test_subtract1() {
     // runTest (- 3 2) => 1
     let i = vec![Value::from_vec(vec![Value::Symbol("-".to_string()),
-                                      Value::Integer(3),
-                                      Value::Integer(2)])];
+                                      Value::Number(Number::Integer(3)),
+                                      Value::Number(Number::Integer(2))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(1));
+               Value::Number(Number::Integer(1)));
 }
 
 #[test]
@@ -1022,12 +4074,12 @@ fn test_if1() {
     // runTest (if (> 1 2) 3 4) => 4
     let i = vec![Value::from_vec(vec![Value::Symbol("if".to_string()),
                                       Value::from_vec(vec![Value::Symbol(">".to_string()),
-                                                           Value::Integer(1),
-                                                           Value::Integer(2)]),
-                                      Value::Integer(3),
-                                      Value::Integer(4)])];
+                                                           Value::Number(Number::Integer(1)),
+                                                           Value::Number(Number::Integer(2))]),
+                                      Value::Number(Number::Integer(3)),
+                                      Value::Number(Number::Integer(4))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(4));
+               Value::Number(Number::Integer(4)));
 }
 
 #[test]
@@ -1035,12 +4087,12 @@ fn test_if2() {
     // runTest (if (> 2 3) (error 4) (error 5)) => null
     let i = vec![Value::from_vec(vec![Value::Symbol("if".to_string()),
                                       Value::from_vec(vec![Value::Symbol(">".to_string()),
-                                                           Value::Integer(2),
-                                                           Value::Integer(3)]),
+                                                           Value::Number(Number::Integer(2)),
+                                                           Value::Number(Number::Integer(3))]),
                                       Value::from_vec(vec![Value::Symbol("error".to_string()),
-                                                           Value::Integer(4)]),
+                                                           Value::Number(Number::Integer(4))]),
                                       Value::from_vec(vec![Value::Symbol("error".to_string()),
-                                                           Value::Integer(5)])])];
+                                                           Value::Number(Number::Integer(5))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap_err().to_string(),
                "RuntimeError: 5");
 }
@@ -1051,35 +4103,35 @@ fn test_if3() {
     let i = vec![Value::from_vec(vec![Value::Symbol("if".to_string()),
                                       Value::from_vec(vec![Value::from_vec(vec![Value::Symbol("if".to_string()),
                                                                                 Value::from_vec(vec![Value::Symbol(">".to_string()),
-                                                                                                     Value::Integer(5),
-                                                                                                     Value::Integer(4)]),
+                                                                                                     Value::Number(Number::Integer(5)),
+                                                                                                     Value::Number(Number::Integer(4))]),
                                                                                 Value::Symbol(">".to_string()),
                                                                                 Value::Symbol("<".to_string())]),
                                                            Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                                                Value::Integer(1),
-                                                                                Value::Integer(2)]),
-                                                           Value::Integer(2)]),
+                                                                                Value::Number(Number::Integer(1)),
+                                                                                Value::Number(Number::Integer(2))]),
+                                                           Value::Number(Number::Integer(2))]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                           Value::Integer(5),
-                                                           Value::Integer(7),
-                                                           Value::Integer(8)]),
+                                                           Value::Number(Number::Integer(5)),
+                                                           Value::Number(Number::Integer(7)),
+                                                           Value::Number(Number::Integer(8))]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                           Value::Integer(9),
-                                                           Value::Integer(10),
-                                                           Value::Integer(11)])])];
+                                                           Value::Number(Number::Integer(9)),
+                                                           Value::Number(Number::Integer(10)),
+                                                           Value::Number(Number::Integer(11))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(20));
+               Value::Number(Number::Integer(20)));
 }
 
 #[test]
 fn test_if4() {
     // runTest (if 0 3 4) => 3
     let i = vec![Value::from_vec(vec![Value::Symbol("if".to_string()),
-                                      Value::Integer(0),
-                                      Value::Integer(3),
-                                      Value::Integer(4)])];
+                                      Value::Number(Number::Integer(0)),
+                                      Value::Number(Number::Integer(3)),
+                                      Value::Number(Number::Integer(4))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(3));
+               Value::Number(Number::Integer(3)));
 }
 
 #[test]
@@ -1114,10 +4166,10 @@ fn test_and3() {
 fn test_and4() {
     // runTest (and 0 1) => 1
     let i = vec![Value::from_vec(vec![Value::Symbol("and".to_string()),
-                                      Value::Integer(0),
-                                      Value::Integer(1)])];
+                                      Value::Number(Number::Integer(0)),
+                                      Value::Number(Number::Integer(1))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(1));
+               Value::Number(Number::Integer(1)));
 }
 
 #[test]
@@ -1126,7 +4178,7 @@ fn test_and5() {
     let i = vec![Value::from_vec(vec![Value::Symbol("and".to_string()),
                                       Value::Boolean(false),
                                       Value::from_vec(vec![Value::Symbol("error".to_string()),
-                                                           Value::Integer(2)])])];
+                                                           Value::Number(Number::Integer(2))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
                Value::Boolean(false));
 }
@@ -1163,10 +4215,10 @@ fn test_or3() {
 fn test_or4() {
     // runTest (or 0 1) => 0
     let i = vec![Value::from_vec(vec![Value::Symbol("or".to_string()),
-                                      Value::Integer(0),
-                                      Value::Integer(1)])];
+                                      Value::Number(Number::Integer(0)),
+                                      Value::Number(Number::Integer(1))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(0));
+               Value::Number(Number::Integer(0)));
 }
 
 #[test]
@@ -1175,7 +4227,7 @@ fn test_or5() {
     let i = vec![Value::from_vec(vec![Value::Symbol("or".to_string()),
                                       Value::Boolean(true),
                                       Value::from_vec(vec![Value::Symbol("error".to_string()),
-                                                           Value::Integer(2)])])];
+                                                           Value::Number(Number::Integer(2))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
                Value::Boolean(true));
 }
@@ -1184,40 +4236,40 @@ fn test_or5() {
 fn test_multiple_statements() {
     // runTest (+ 1 2) (+ 3 4) => 7
     let i = vec![Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                      Value::Integer(1),
-                                      Value::Integer(2)]),
+                                      Value::Number(Number::Integer(1)),
+                                      Value::Number(Number::Integer(2))]),
                  Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                      Value::Integer(3),
-                                      Value::Integer(4)])];
+                                      Value::Number(Number::Integer(3)),
+                                      Value::Number(Number::Integer(4))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(7));
+               Value::Number(Number::Integer(7)));
 }
 
 #[test]
 fn test_list() {
     // runTest (list 1 2 3) => '(1 2 3)
     let i = vec![Value::from_vec(vec![Value::Symbol("list".to_string()),
-                                      Value::Integer(1),
-                                      Value::Integer(2),
-                                      Value::Integer(3)])];
+                                      Value::Number(Number::Integer(1)),
+                                      Value::Number(Number::Integer(2)),
+                                      Value::Number(Number::Integer(3))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::from_vec(vec![Value::Integer(1),
-                                    Value::Integer(2),
-                                    Value::Integer(3)]));
+               Value::from_vec(vec![Value::Number(Number::Integer(1)),
+                                    Value::Number(Number::Integer(2)),
+                                    Value::Number(Number::Integer(3))]));
 }
 
 #[test]
 fn test_cons() {
     // runTest (cons 1 (list 2 3)) => '(1 2 3)
     let i = vec![Value::from_vec(vec![Value::Symbol("cons".to_string()),
-                                      Value::Integer(1),
+                                      Value::Number(Number::Integer(1)),
                                       Value::from_vec(vec![Value::Symbol("list".to_string()),
-                                                           Value::Integer(2),
-                                                           Value::Integer(3)])])];
+                                                           Value::Number(Number::Integer(2)),
+                                                           Value::Number(Number::Integer(3))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::from_vec(vec![Value::Integer(1),
-                                    Value::Integer(2),
-                                    Value::Integer(3)]));
+               Value::from_vec(vec![Value::Number(Number::Integer(1)),
+                                    Value::Number(Number::Integer(2)),
+                                    Value::Number(Number::Integer(3))]));
 }
 
 #[test]
@@ -1225,12 +4277,12 @@ fn test_define() {
     // runTest (define x 2) (+ x x) => 4
     let i = vec![Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::Symbol("x".to_string()),
-                                      Value::Integer(2)]),
+                                      Value::Number(Number::Integer(2))]),
                  Value::from_vec(vec![Value::Symbol("+".to_string()),
                                       Value::Symbol("x".to_string()),
                                       Value::Symbol("x".to_string())])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(4));
+               Value::Number(Number::Integer(4)));
 }
 
 #[test]
@@ -1238,15 +4290,15 @@ fn test_set() {
     // runTest (define x 2) (set! x 3) (+ x x) => 6
     let i = vec![Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::Symbol("x".to_string()),
-                                      Value::Integer(2)]),
+                                      Value::Number(Number::Integer(2))]),
                  Value::from_vec(vec![Value::Symbol("set!".to_string()),
                                       Value::Symbol("x".to_string()),
-                                      Value::Integer(3)]),
+                                      Value::Number(Number::Integer(3))]),
                  Value::from_vec(vec![Value::Symbol("+".to_string()),
                                       Value::Symbol("x".to_string()),
                                       Value::Symbol("x".to_string())])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(6));
+               Value::Number(Number::Integer(6)));
 }
 
 #[test]
@@ -1256,10 +4308,10 @@ fn test_lambda() {
                                                            Value::from_vec(vec![Value::Symbol("x".to_string())]),
                                                            Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                                                 Value::Symbol("x".to_string()),
-                                                                                Value::Integer(2)])]),
-                                      Value::Integer(3)])];
+                                                                                Value::Number(Number::Integer(2))])]),
+                                      Value::Number(Number::Integer(3))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(5));
+               Value::Number(Number::Integer(5)));
 }
 
 #[test]
@@ -1269,10 +4321,10 @@ fn test_lambda_symbol() {
                                                            Value::from_vec(vec![Value::Symbol("x".to_string())]),
                                                            Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                                                 Value::Symbol("x".to_string()),
-                                                                                Value::Integer(2)])]),
-                                      Value::Integer(3)])];
+                                                                                Value::Number(Number::Integer(2))])]),
+                                      Value::Number(Number::Integer(3))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(5));
+               Value::Number(Number::Integer(5)));
 }
 
 #[test]
@@ -1283,11 +4335,11 @@ fn test_define_func() {
                                                            Value::Symbol("x".to_string())]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                            Value::Symbol("x".to_string()),
-                                                           Value::Integer(2)])]),
+                                                           Value::Number(Number::Integer(2))])]),
                  Value::from_vec(vec![Value::Symbol("f".to_string()),
-                                      Value::Integer(3)])];
+                                      Value::Number(Number::Integer(3))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(5));
+               Value::Number(Number::Integer(5)));
 }
 
 #[test]
@@ -1296,21 +4348,21 @@ fn test_define_func2() {
     let i = vec![Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::from_vec(vec![Value::Symbol("noop".to_string())]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                           Value::Integer(0),
-                                                           Value::Integer(0)])]),
+                                                           Value::Number(Number::Integer(0)),
+                                                           Value::Number(Number::Integer(0))])]),
                  Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::from_vec(vec![Value::Symbol("f".to_string()),
                                                            Value::Symbol("x".to_string())]),
                                       Value::from_vec(vec![Value::Symbol("noop".to_string())]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                            Value::Symbol("x".to_string()),
-                                                           Value::Integer(2)])]),
+                                                           Value::Number(Number::Integer(2))])]),
                  Value::from_vec(vec![Value::from_vec(vec![Value::Symbol("lambda".to_string()),
                                                            null!(),
                                                            Value::from_vec(vec![Value::Symbol("f".to_string()),
-                                                                                Value::Integer(3)])])])];
+                                                                                Value::Number(Number::Integer(3))])])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(5));
+               Value::Number(Number::Integer(5)));
 }
 
 #[test]
@@ -1326,14 +4378,14 @@ fn test_dynamic_native_fn() {
     // runTest ((if (> 3 2) + -) 4 3) => 7
     let i = vec![Value::from_vec(vec![Value::from_vec(vec![Value::Symbol("if".to_string()),
                                                            Value::from_vec(vec![Value::Symbol(">".to_string()),
-                                                                                Value::Integer(3),
-                                                                                Value::Integer(2)]),
+                                                                                Value::Number(Number::Integer(3)),
+                                                                                Value::Number(Number::Integer(2))]),
                                                            Value::Symbol("+".to_string()),
                                                            Value::Symbol("-".to_string())]),
-                                      Value::Integer(4),
-                                      Value::Integer(3)])];
+                                      Value::Number(Number::Integer(4)),
+                                      Value::Number(Number::Integer(3))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(7));
+               Value::Number(Number::Integer(7)));
 }
 
 #[test]
@@ -1341,39 +4393,39 @@ fn test_let_bindings() {
     // runTest (let ((x 3)) (+ x 1)) => 4
     let i = vec![Value::from_vec(vec![Value::Symbol("let".to_string()),
                                       Value::from_vec(vec![Value::from_vec(vec![Value::Symbol("x".to_string()),
-                                                                                Value::Integer(3)])]),
+                                                                                Value::Number(Number::Integer(3))])]),
                                       Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                            Value::Symbol("x".to_string()),
-                                                           Value::Integer(1)])])];
+                                                           Value::Number(Number::Integer(1))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(4));
+               Value::Number(Number::Integer(4)));
 }
 
 #[test]
 fn test_quoting() {
     // runTest (quote (1 2)) => (1 2)
     let i = vec![Value::from_vec(vec![Value::Symbol("quote".to_string()),
-                                      Value::from_vec(vec![Value::Integer(1),
-                                                           Value::Integer(2)])])];
+                                      Value::from_vec(vec![Value::Number(Number::Integer(1)),
+                                                           Value::Number(Number::Integer(2))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::from_vec(vec![Value::Integer(1),
-                                    Value::Integer(2)]));
+               Value::from_vec(vec![Value::Number(Number::Integer(1)),
+                                    Value::Number(Number::Integer(2))]));
 }
 
 #[test]
 fn test_quasiquoting() {
     // runTest (quasiquote (2 (unquote (+ 1 2)) 4)) => (2 3 4)
     let i = vec![Value::from_vec(vec![Value::Symbol("quasiquote".to_string()),
-                                      Value::from_vec(vec![Value::Integer(2),
+                                      Value::from_vec(vec![Value::Number(Number::Integer(2)),
                                                            Value::from_vec(vec![Value::Symbol("unquote".to_string()),
                                                                                 Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                                                                     Value::Integer(1),
-                                                                                                     Value::Integer(2)])]),
-                                                           Value::Integer(4)])])];
+                                                                                                     Value::Number(Number::Integer(1)),
+                                                                                                     Value::Number(Number::Integer(2))])]),
+                                                           Value::Number(Number::Integer(4))])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::from_vec(vec![Value::Integer(2),
-                                    Value::Integer(3),
-                                    Value::Integer(4)]));
+               Value::from_vec(vec![Value::Number(Number::Integer(2)),
+                                    Value::Number(Number::Integer(3)),
+                                    Value::Number(Number::Integer(4))]));
 }
 
 #[test]
@@ -1382,10 +4434,10 @@ fn test_eval() {
     let i = vec![Value::from_vec(vec![Value::Symbol("eval".to_string()),
                                       Value::from_vec(vec![Value::Symbol("quote".to_string()),
                                                            Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                                                Value::Integer(1),
-                                                                                Value::Integer(2)])])])];
+                                                                                Value::Number(Number::Integer(1)),
+                                                                                Value::Number(Number::Integer(2))])])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(3));
+               Value::Number(Number::Integer(3)));
 }
 
 #[test]
@@ -1397,13 +4449,13 @@ fn test_eval2() {
                                       Value::from_vec(vec![Value::Symbol("eval".to_string()),
                                                            Value::from_vec(vec![Value::Symbol("quote".to_string()),
                                                                                 Value::from_vec(vec![Value::Symbol("+".to_string()),
-                                                                                                     Value::Integer(1),
-                                                                                                     Value::Integer(2)])])]),
+                                                                                                     Value::Number(Number::Integer(1)),
+                                                                                                     Value::Number(Number::Integer(2))])])]),
                                       Value::Symbol("x".to_string())]),
                  Value::from_vec(vec![Value::Symbol("foo".to_string()),
-                                      Value::Integer(5)])];
+                                      Value::Number(Number::Integer(5))])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(5));
+               Value::Number(Number::Integer(5)));
 }
 
 #[test]
@@ -1412,11 +4464,11 @@ fn test_apply() {
     let i = vec![Value::from_vec(vec![Value::Symbol("apply".to_string()),
                                       Value::Symbol("+".to_string()),
                                       Value::from_vec(vec![Value::Symbol("quote".to_string()),
-                                                           Value::from_vec(vec![Value::Integer(1),
-                                                                                Value::Integer(2),
-                                                                                Value::Integer(3)])])])];
+                                                           Value::from_vec(vec![Value::Number(Number::Integer(1)),
+                                                                                Value::Number(Number::Integer(2)),
+                                                                                Value::Number(Number::Integer(3))])])])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(6));
+               Value::Number(Number::Integer(6)));
 }
 
 #[test]
@@ -1424,19 +4476,19 @@ fn test_begin() {
     // runTest (define x 1) (begin (set! x 5) (set! x (+ x 2)) x) => 7
     let i = vec![Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::Symbol("x".to_string()),
-                                      Value::Integer(1)]),
+                                      Value::Number(Number::Integer(1))]),
                  Value::from_vec(vec![Value::Symbol("begin".to_string()),
                                       Value::from_vec(vec![Value::Symbol("set!".to_string()),
                                                            Value::Symbol("x".to_string()),
-                                                           Value::Integer(5)]),
+                                                           Value::Number(Number::Integer(5))]),
                                       Value::from_vec(vec![Value::Symbol("set!".to_string()),
                                                            Value::Symbol("x".to_string()),
                                                            Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                                                 Value::Symbol("x".to_string()),
-                                                                                Value::Integer(2)])]),
+                                                                                Value::Number(Number::Integer(2))])]),
                                       Value::Symbol("x".to_string())])];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(7));
+               Value::Number(Number::Integer(7)));
 }
 
 #[test]
@@ -1453,7 +4505,7 @@ fn test_callcc() {
     // => 11
     let i = vec![Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::Symbol("x".to_string()),
-                                      Value::Integer(0)]),
+                                      Value::Number(Number::Integer(0))]),
                  Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::from_vec(vec![Value::Symbol("+x".to_string()),
                                                            Value::Symbol("n".to_string())]),
@@ -1466,21 +4518,21 @@ fn test_callcc() {
                                       Value::from_vec(vec![Value::Symbol("foo".to_string()),
                                                            Value::Symbol("k".to_string())]),
                                       Value::from_vec(vec![Value::Symbol("+x".to_string()),
-                                                           Value::Integer(2)]),
+                                                           Value::Number(Number::Integer(2))]),
                                       Value::from_vec(vec![Value::Symbol("k".to_string())]),
                                       Value::from_vec(vec![Value::Symbol("+x".to_string()),
-                                                           Value::Integer(4)])]),
+                                                           Value::Number(Number::Integer(4))])]),
                  Value::from_vec(vec![Value::from_vec(vec![Value::Symbol("lambda".to_string()),
                                                            null!(),
                                                            Value::from_vec(vec![Value::Symbol("+x".to_string()),
-                                                                                Value::Integer(1)]),
+                                                                                Value::Number(Number::Integer(1))]),
                                                            Value::from_vec(vec![Value::Symbol("call/cc".to_string()),
                                                                                 Value::Symbol("foo".to_string())]),
                                                            Value::from_vec(vec![Value::Symbol("+x".to_string()),
-                                                                                Value::Integer(8)])])]),
+                                                                                Value::Number(Number::Integer(8))])])]),
                  Value::Symbol("x".to_string())];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(11));
+               Value::Number(Number::Integer(11)));
 }
 
 #[test]
@@ -1493,42 +4545,112 @@ fn test_macros() {
                                                            Value::Symbol("x".to_string()),
                                                            Value::from_vec(vec![Value::Symbol("+".to_string()),
                                                                                 Value::Symbol("x".to_string()),
-                                                                                Value::Integer(1)])])]),
+                                                                                Value::Number(Number::Integer(1))])])]),
                  Value::from_vec(vec![Value::Symbol("define".to_string()),
                                       Value::Symbol("a".to_string()),
-                                      Value::Integer(1)]),
+                                      Value::Number(Number::Integer(1))]),
                  Value::from_vec(vec![Value::Symbol("incr".to_string()),
                                       Value::Symbol("a".to_string())]),
                  Value::Symbol("a".to_string())];
     assert_eq!(exec(List::from_vec(i)).unwrap(),
-               Value::Integer(2));
+               Value::Number(Number::Integer(2)));
 }
 
 #[test]
 fn test_list_iter() {
-    let l = List::Cell(
-        Box::new(Value::Integer(1)),
-        Box::new(List::Cell(
-            Box::new(Value::Integer(2)),
-            Box::new(List::Cell(
-                Box::new(Value::Integer(3)),
-                Box::new(List::Null))))));
+    let l = List::cons(
+        Value::Number(Number::Integer(1)),
+        List::cons(
+            Value::Number(Number::Integer(2)),
+            List::cons(
+                Value::Number(Number::Integer(3)),
+                null!()).to_value()).to_value());
     let mut x = 0;
     for i in l {
         x += 1;
-        assert_eq!(i, Value::Integer(x));
+        assert_eq!(i, Value::Number(Number::Integer(x)));
     }
     assert_eq!(x, 3);
 }
 
 #[test]
 fn test_list_to_string() {
-    let l = List::Cell(
-        Box::new(Value::Integer(1)),
-        Box::new(List::Cell(
-            Box::new(Value::Integer(2)),
-            Box::new(List::Cell(
-                Box::new(Value::Integer(3)),
-                Box::new(List::Null))))));
+    let l = List::cons(
+        Value::Number(Number::Integer(1)),
+        List::cons(
+            Value::Number(Number::Integer(2)),
+            List::cons(
+                Value::Number(Number::Integer(3)),
+                null!()).to_value()).to_value());
     assert_eq!(l.to_string(), "(1 2 3)");
 }
+
+#[test]
+fn test_improper_list_to_string() {
+    let l = List::cons(
+        Value::Number(Number::Integer(1)),
+        List::cons(
+            Value::Number(Number::Integer(2)),
+            Value::from_string("x".to_string())).to_value());
+    assert_eq!(l.to_string(), "(1 2 . x)");
+    assert_eq!(format!("{:?}", l), "(1 2 . \"x\")");
+    assert_eq!(l.len(), 2);
+    assert!(!l.is_proper());
+    assert!(List::from_vec(vec![null!()]).is_proper());
+}
+
+#[test]
+fn test_circular_list_to_string() {
+    let l = List::from_vec(vec![Value::Number(Number::Integer(1)), Value::Number(Number::Integer(2))]);
+    let last = l.next().unwrap();
+    if let List::Cell(ref pair) = last {
+        pair.borrow_mut().cdr = l.clone().to_value();
+    }
+    assert_eq!(l.to_string(), "#0=(1 2 . #0#)");
+    assert_eq!(last.to_string(), "#0=(2 1 . #0#)");
+    assert!(!l.is_proper());
+    let shared = List::cons(l.clone().to_value(), l.clone().to_value());
+    assert_eq!(shared.to_string(), "(#0=(1 2 . #0#) . #0#)");
+    if let List::Cell(ref pair) = l {
+        pair.borrow_mut().cdr = null!();
+    }
+    assert_eq!(shared.to_string(), "((1) 1)");
+    assert!(shared.is_proper());
+}
+
+#[cfg(test)]
+fn exec_source(interpreter: &Interpreter, source: &str) -> Result<Value, RuntimeError> {
+    let tokens = lexer::tokenize(source).unwrap();
+    interpreter.run(&parse(&tokens).unwrap())
+}
+
+#[test]
+fn test_time_slice() {
+    // the spinner counts until the other thread stops it, which it gets a turn to do after one slice
+    let source = "(define stop #f)
+                  (define spinner (spawn (lambda () (let loop ((n 0)) (if stop n (loop (+ n 1)))))))
+                  (define stopper (spawn (lambda () (set! stop #t))))
+                  (thread-join! spinner)";
+    let count = |slice| {
+        let interpreter = Interpreter::new().unwrap();
+        interpreter.set_time_slice(slice);
+        match exec_source(&interpreter, source).unwrap() {
+            Value::Number(n) => n.to_f64(),
+            v => panic!("Expected a count: {:?}", v)
+        }
+    };
+    assert!(count(5) <= 1.0);
+    assert!(count(1000) > 10.0);
+}
+
+#[test]
+fn test_threads_across_programs() {
+    // a spawned thread waits for the next program to run, and a program that stopped in a deadlock
+    // doesn't take the item put in the channel it was waiting on
+    let interpreter = Interpreter::new().unwrap();
+    exec_source(&interpreter, "(define c (make-channel)) (define t (spawn (lambda () 'later)))").unwrap();
+    let e = exec_source(&interpreter, "(channel-get! c)").err().unwrap();
+    assert_eq!(e.to_string(), "RuntimeError: Deadlock: every thread is waiting on a channel or another thread");
+    exec_source(&interpreter, "(define putter (spawn (lambda () (channel-put! c 'item))))").unwrap();
+    assert_eq!(exec_source(&interpreter, "(list (thread-join! putter) (channel-get! c) (thread-join! t))").unwrap().to_string(), "(() item later)");
+}
@@ -0,0 +1,1095 @@
+use crate::reader::parser::Node;
+use crate::interpreter::syntax_rules::{self, SyntaxRules, MacroError, Hygiene};
+
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::slice;
+
+macro_rules! macro_error {
+    ($($arg:tt)*) => (
+        return Err(MacroError { message: format!($($arg)*)})
+    )
+}
+
+// A macro that uses itself without end is an error, instead of running out of stack or never
+// finishing. MAX_MACRO_DEPTH bounds how many macro uses can be expanded inside each other's output,
+// and MAX_EXPANSIONS how many macro uses a top-level form can expand. MAX_DEPTH bounds how deeply
+// forms can be nested at all, as the expander recurses into them.
+const MAX_MACRO_DEPTH: usize = 400;
+const MAX_EXPANSIONS: usize = 100_000;
+const MAX_DEPTH: usize = 10_000;
+
+// The special forms the interpreters implement, whose shape the expander has to know to find the
+// variables they bind, along with the auxiliary syntax that only means something inside them
+const CORE_FORMS: &[&str] = &[
+    "quote", "quasiquote", "unquote", "unquote-splicing", "lambda", "λ", "case-lambda", "define", "set!",
+    "if", "begin", "let", "let*", "letrec", "letrec*", "cond", "case", "when", "unless", "do", "and", "or",
+    "guard", "parameterize", "let-values", "let*-values", "receive", "define-values", "reset", "shift", "define-syntax", "define-syntax-rule", "define-macro", "let-syntax", "letrec-syntax",
+    "syntax-rules", "er-macro-transformer", "else", "=>", "...", "_",
+];
+
+// Expands macros hygienically, by renaming (as in Clinger and Rees, "Macros That Work"). Every
+// identifier that a template introduces is replaced with a fresh alias, which remembers the
+// identifier it stands for and the scope of the macro definition. An alias in a binding position
+// gets a new name in the output, so it can't capture a variable from the macro use, and any other
+// alias is looked up where the macro was defined, so the macro use can't capture it either.
+//
+// User variables keep their names unless they shadow something that a macro might refer to, in
+// which case they are renamed as well. Renamed variables look like tmp{1}, which the reader can't
+// produce, so they can't clash with anything.
+//
+// Procedural macros are run by the interpreter, through its Evaluator, in the middle of an
+// expansion. They can call eval, which expands code as well, so the expander works through
+// shared references.
+pub struct Expander {
+    root: Rc<Scope>,
+    names: Rc<Names>,
+    evaluator: Box<dyn Evaluator>,
+    depth: Cell<usize>,
+    macro_depth: Cell<usize>,
+    expansions: Cell<usize>,
+}
+
+// Evaluates the transformer of a procedural macro at the top level of the interpreter, once the
+// expander has expanded it
+pub trait Evaluator {
+    fn evaluate(&self, expr: &Node) -> Result<Rc<dyn Transformer>, MacroError>;
+}
+
+// The procedure of a procedural macro, which is called with the nodes as data. An explicit-renaming
+// transformer also gets rename and compare procedures, which use the renaming.
+pub trait Transformer {
+    fn call(&self, args: &[Node], renaming: Option<Rc<Renaming>>) -> Result<Node, MacroError>;
+}
+
+// What an identifier refers to
+#[derive(Clone)]
+enum Binding {
+    // a variable, under the name it has in the output
+    Variable(String),
+    Macro(Rc<Macro>),
+    Core(&'static str),
+}
+
+enum Macro {
+    Rules(SyntaxRules, Rc<Scope>),
+    // er-macro-transformer, which renames the identifiers it introduces itself
+    ExplicitRenaming(Rc<dyn Transformer>, Rc<Scope>),
+    // define-macro, whose code is used as it is
+    Unhygienic(Rc<dyn Transformer>),
+}
+
+struct Scope {
+    parent: Option<Rc<Scope>>,
+    bindings: RefCell<HashMap<String, Binding>>,
+}
+
+// The aliases made so far, which are shared with the renamings handed to procedural macros
+struct Names {
+    aliases: RefCell<HashMap<String, Alias>>,
+    count: Cell<usize>,
+}
+
+struct Alias {
+    name: String,
+    scope: Rc<Scope>,
+}
+
+// The hygiene of a single macro expansion, which renames each introduced identifier once
+pub struct Renaming {
+    names: Rc<Names>,
+    use_scope: Rc<Scope>,
+    def_scope: Rc<Scope>,
+    renames: RefCell<HashMap<String, String>>,
+}
+
+// A body form, after its definitions are bound but before it is expanded
+enum BodyForm {
+    Define(String, Node),
+    DefineValues(Node, Node),
+    Expression(Node),
+}
+
+impl Binding {
+    fn is(&self, other: &Binding) -> bool {
+        match (self, other) {
+            (Binding::Variable(a), Binding::Variable(b)) => a == b,
+            (Binding::Macro(a), Binding::Macro(b)) => Rc::ptr_eq(a, b),
+            (Binding::Core(a), Binding::Core(b)) => a == b,
+            _ => false
+        }
+    }
+}
+
+impl Scope {
+    fn new(parent: Option<Rc<Scope>>) -> Rc<Scope> {
+        Rc::new(Scope { parent, bindings: RefCell::new(HashMap::new()) })
+    }
+
+    fn child(parent: &Rc<Scope>) -> Rc<Scope> {
+        Scope::new(Some(parent.clone()))
+    }
+
+    fn bind(&self, name: String, binding: Binding) {
+        self.bindings.borrow_mut().insert(name, binding);
+    }
+
+    fn get(&self, name: &str) -> Option<Binding> {
+        match self.bindings.borrow().get(name) {
+            Some(binding) => Some(binding.clone()),
+            None => match self.parent {
+                Some(ref parent) => parent.get(name),
+                None => None
+            }
+        }
+    }
+}
+
+impl Names {
+    fn fresh(&self, name: &str) -> String {
+        self.count.set(self.count.get() + 1);
+        format!("{}{{{}}}", self.original(name), self.count.get())
+    }
+
+    fn alias(&self, name: &str, scope: &Rc<Scope>) -> String {
+        let alias = self.fresh(name);
+        self.aliases.borrow_mut().insert(alias.clone(), Alias { name: name.to_string(), scope: scope.clone() });
+        alias
+    }
+
+    fn is_alias(&self, name: &str) -> bool {
+        self.aliases.borrow().contains_key(name)
+    }
+
+    // The identifier the user wrote, that an alias was made from
+    fn original(&self, name: &str) -> String {
+        let aliases = self.aliases.borrow();
+        let mut name = name;
+        while let Some(alias) = aliases.get(name) {
+            name = &alias.name;
+        }
+        name.to_string()
+    }
+
+    // Looks up an identifier where it appears, then an alias where its macro was defined. None means
+    // a global variable, which has no binding until the code runs.
+    fn resolve(&self, name: &str, scope: &Rc<Scope>) -> Option<Binding> {
+        match scope.get(name) {
+            Some(binding) => Some(binding),
+            None => {
+                let alias = self.aliases.borrow().get(name).map(|a| (a.name.clone(), a.scope.clone()));
+                match alias {
+                    Some((name, scope)) => self.resolve(&name, &scope),
+                    None => None
+                }
+            }
+        }
+    }
+}
+
+impl Renaming {
+    fn new(names: &Rc<Names>, use_scope: &Rc<Scope>, def_scope: &Rc<Scope>) -> Renaming {
+        Renaming { names: names.clone(), use_scope: use_scope.clone(), def_scope: def_scope.clone(), renames: RefCell::new(HashMap::new()) }
+    }
+
+    // An alias for an identifier as the macro definition sees it
+    pub fn rename(&self, name: &str) -> String {
+        if let Some(alias) = self.renames.borrow().get(name) {
+            return alias.clone();
+        }
+        let alias = self.names.alias(name, &self.def_scope);
+        self.renames.borrow_mut().insert(name.to_string(), alias.clone());
+        alias
+    }
+
+    // Whether two identifiers, which may be aliases, mean the same thing at the macro use
+    pub fn compare(&self, a: &str, b: &str) -> bool {
+        match (self.names.resolve(a, &self.use_scope), self.names.resolve(b, &self.use_scope)) {
+            (Some(a), Some(b)) => a.is(&b),
+            (None, None) => self.names.original(a) == self.names.original(b),
+            _ => false
+        }
+    }
+}
+
+// Each expansion has its own renaming
+impl PartialEq for Renaming {
+    fn eq(&self, other: &Renaming) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Hygiene for Renaming {
+    fn rename(&mut self, name: &str) -> String {
+        Renaming::rename(self, name)
+    }
+
+    fn is_literal(&self, literal: &str, name: &str) -> bool {
+        match (self.names.resolve(name, &self.use_scope), self.names.resolve(literal, &self.def_scope)) {
+            (Some(a), Some(b)) => a.is(&b),
+            (None, None) => self.names.original(name) == self.names.original(literal),
+            _ => false
+        }
+    }
+}
+
+impl Expander {
+    // The globals are the names the interpreter predefines, which local variables have to be
+    // renamed to shadow
+    pub fn new(globals: &[String], evaluator: Box<dyn Evaluator>) -> Expander {
+        let root = Scope::new(None);
+        for name in globals {
+            root.bind(name.clone(), Binding::Variable(name.clone()));
+        }
+        for &form in CORE_FORMS {
+            root.bind(form.to_string(), Binding::Core(form));
+        }
+        let names = Rc::new(Names { aliases: RefCell::new(HashMap::new()), count: Cell::new(0) });
+        Expander { root, names, evaluator, depth: Cell::new(0), macro_depth: Cell::new(0), expansions: Cell::new(0) }
+    }
+
+    // Expands a top-level form into one that only uses the special forms the interpreters
+    // implement, with no macros left in it. Macros it defines stay defined for the forms after it.
+    pub fn expand(&self, node: &Node) -> Result<Node, MacroError> {
+        // procedural macros can expand code while a form is being expanded, which counts towards it
+        if self.depth.get() == 0 {
+            self.expansions.set(0);
+        }
+        self.expand_expression(node, &self.root)
+    }
+
+    // Expands a form the way expand does, but without defining anything, to show what it turns into
+    pub fn macroexpand(&self, node: &Node) -> Result<Node, MacroError> {
+        let bindings = self.root.bindings.borrow().clone();
+        let res = self.expand(node);
+        *self.root.bindings.borrow_mut() = bindings;
+        res
+    }
+
+    // Expands a macro use once, leaving any other form as it is. The identifiers the macro
+    // introduced are shown as they were written in its template.
+    pub fn macroexpand_1(&self, node: &Node) -> Result<Node, MacroError> {
+        match self.resolve_head(node, &self.root) {
+            Some(Binding::Macro(m)) => {
+                let expanded = try!(self.expand_macro(&m, node, &self.root));
+                Ok(self.strip(&expanded))
+            },
+            _ => Ok(node.clone())
+        }
+    }
+
+    fn original(&self, name: &str) -> String {
+        self.names.original(name)
+    }
+
+    fn resolve(&self, name: &str, scope: &Rc<Scope>) -> Option<Binding> {
+        self.names.resolve(name, scope)
+    }
+
+    fn resolve_head(&self, node: &Node, scope: &Rc<Scope>) -> Option<Binding> {
+        match *node {
+            Node::List(ref items) => match items.first() {
+                Some(Node::Identifier(name)) => self.resolve(name, scope),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    fn is_core(&self, node: &Node, form: &str, scope: &Rc<Scope>) -> bool {
+        match *node {
+            Node::Identifier(ref name) => match self.resolve(name, scope) {
+                Some(Binding::Core(f)) => f == form,
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    // Binds a variable in the scope, and returns its name in the output
+    fn bind_variable(&self, name: &str, scope: &Rc<Scope>) -> String {
+        if let Some(Binding::Variable(out)) = scope.bindings.borrow().get(name).cloned() {
+            // defined twice in the same scope, which the interpreter reports
+            return out;
+        }
+        let out = if self.names.is_alias(name) || self.resolve(name, scope).is_some() {
+            self.names.fresh(name)
+        } else {
+            name.to_string()
+        };
+        scope.bind(name.to_string(), Binding::Variable(out.clone()));
+        out
+    }
+
+    // Top-level definitions are globals, and keep the name the user wrote even when a macro introduces them,
+    // unless it's the name of a special form, which the interpreters would still take it for
+    fn bind_definition(&self, name: &str, scope: &Rc<Scope>) -> String {
+        if Rc::ptr_eq(scope, &self.root) {
+            let name = self.original(name);
+            let out = match self.root.get(&name) {
+                Some(Binding::Core(_)) => self.names.fresh(&name),
+                Some(Binding::Variable(out)) => out,
+                _ => name.clone()
+            };
+            self.root.bind(name, Binding::Variable(out.clone()));
+            out
+        } else {
+            self.bind_variable(name, scope)
+        }
+    }
+
+    fn bind_macro(&self, name: &str, m: Macro, scope: &Rc<Scope>) {
+        let name = if Rc::ptr_eq(scope, &self.root) { self.original(name) } else { name.to_string() };
+        scope.bind(name, Binding::Macro(Rc::new(m)));
+    }
+
+    // Replaces aliases with what the user wrote, for code that is only data, like a quotation
+    fn strip(&self, node: &Node) -> Node {
+        match *node {
+            Node::Identifier(ref name) => Node::Identifier(self.original(name)),
+            Node::List(ref items) => Node::List(items.iter().map(|n| self.strip(n)).collect()),
+            Node::DottedList(ref items, ref tail) => Node::DottedList(items.iter().map(|n| self.strip(n)).collect(), Box::new(self.strip(tail))),
+            Node::Vector(ref items) => Node::Vector(items.iter().map(|n| self.strip(n)).collect()),
+            _ => node.clone()
+        }
+    }
+
+    // syntax-rules recognizes itself, the ellipsis and _ by name, which they may not have if a
+    // macro introduced them
+    fn canonical(&self, node: &Node, scope: &Rc<Scope>) -> Node {
+        match *node {
+            Node::Identifier(ref name) => match self.resolve(name, scope) {
+                Some(Binding::Core(form)) if form == "syntax-rules" || form == "..." || form == "_" => Node::Identifier(form.to_string()),
+                _ => node.clone()
+            },
+            Node::List(ref items) => Node::List(items.iter().map(|n| self.canonical(n, scope)).collect()),
+            Node::DottedList(ref items, ref tail) => Node::DottedList(items.iter().map(|n| self.canonical(n, scope)).collect(), Box::new(self.canonical(tail, scope))),
+            Node::Vector(ref items) => Node::Vector(items.iter().map(|n| self.canonical(n, scope)).collect()),
+            _ => node.clone()
+        }
+    }
+
+    fn expand_macro(&self, m: &Rc<Macro>, node: &Node, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        self.expansions.set(self.expansions.get() + 1);
+        if self.expansions.get() > MAX_EXPANSIONS {
+            macro_error!("Macro expansion doesn't end, after {} macro uses", MAX_EXPANSIONS);
+        }
+        match **m {
+            Macro::Rules(ref rules, ref def_scope) => {
+                rules.expand(node, &mut Renaming::new(&self.names, scope, def_scope))
+            },
+            Macro::ExplicitRenaming(ref transformer, ref def_scope) => {
+                transformer.call(slice::from_ref(node), Some(Rc::new(Renaming::new(&self.names, scope, def_scope))))
+            },
+            Macro::Unhygienic(ref transformer) => {
+                // (define-macro (<keyword> <formals>) <body>) gets the rest of the form as its arguments
+                match *node {
+                    Node::List(ref items) => transformer.call(&items[1..], None),
+                    _ => macro_error!("Can't use an improper list as arguments to macro {}: {}", self.strip(&node_items(node)[0]), self.strip(node))
+                }
+            }
+        }
+    }
+
+    fn expand_expression(&self, node: &Node, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        if self.depth.get() >= MAX_DEPTH {
+            macro_error!("Expressions are nested too deeply");
+        }
+        self.depth.set(self.depth.get() + 1);
+        let res = self.expand_nested(node, scope);
+        self.depth.set(self.depth.get() - 1);
+        res
+    }
+
+    // Each level of nesting goes through here, so anything but a plain call is handled elsewhere
+    fn expand_nested(&self, node: &Node, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        match *node {
+            Node::Identifier(ref name) => self.expand_identifier(name, scope),
+            Node::List(ref items) if !items.is_empty() => {
+                match self.resolve_head(node, scope) {
+                    Some(Binding::Macro(m)) => self.expand_macro_use(&m, node, scope),
+                    Some(Binding::Core(form)) => self.expand_core(form, node, &items[1..], scope),
+                    _ => self.expand_all(items, scope).map(Node::List)
+                }
+            },
+            _ => Ok(self.strip(node))
+        }
+    }
+
+    fn expand_identifier(&self, name: &str, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        match self.resolve(name, scope) {
+            Some(Binding::Variable(out)) => Ok(Node::Identifier(out)),
+            Some(Binding::Core(form)) => Ok(Node::Identifier(form.to_string())),
+            Some(Binding::Macro(_)) => macro_error!("Can't use macro {} as a variable", self.original(name)),
+            None => Ok(Node::Identifier(self.original(name)))
+        }
+    }
+
+    // Expands a macro use and then what it expanded into, which counts as a level of macro nesting
+    fn expand_macro_use(&self, m: &Rc<Macro>, node: &Node, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        if self.macro_depth.get() >= MAX_MACRO_DEPTH {
+            macro_error!("Macro expansion is nested too deeply");
+        }
+        let expanded = try!(self.expand_macro(m, node, scope));
+        self.macro_depth.set(self.macro_depth.get() + 1);
+        let res = self.expand_expression(&expanded, scope);
+        self.macro_depth.set(self.macro_depth.get() - 1);
+        res
+    }
+
+    fn expand_all(&self, nodes: &[Node], scope: &Rc<Scope>) -> Result<Vec<Node>, MacroError> {
+        let mut out = vec![];
+        for node in nodes {
+            out.push(try!(self.expand_expression(node, scope)));
+        }
+        Ok(out)
+    }
+
+    // Expands the macro use at the head of a form, if there is one, until it becomes something else
+    fn expand_head(&self, node: &Node, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let mut node = node.clone();
+        let mut count = 0;
+        while let Some(Binding::Macro(m)) = self.resolve_head(&node, scope) {
+            count += 1;
+            if self.macro_depth.get() + count > MAX_MACRO_DEPTH {
+                macro_error!("Macro expansion is nested too deeply");
+            }
+            node = try!(self.expand_macro(&m, &node, scope));
+        }
+        Ok(node)
+    }
+
+    // Forms that don't have the expected shape are passed through, and left for the interpreter to report
+    fn expand_core(&self, form: &'static str, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        // every nested form passes through here, so the forms are expanded in their own functions to
+        // keep this one's stack frame small
+        match form {
+            "quote" => Ok(self.strip(node)),
+            "quasiquote" if args.len() == 1 => self.expand_quasiquote(&args[0], 1, scope).map(|template| with_head(form, vec![template])),
+            "lambda" | "λ" if !args.is_empty() => self.expand_lambda_form(form, args, scope),
+            "case-lambda" => self.expand_case_lambda(args, scope),
+            "define" => self.expand_define(node, scope),
+            "set!" if args.len() == 2 => self.expand_set(node, args, scope),
+            "let" => self.expand_let(node, args, scope),
+            "let*" => self.expand_let_star(node, args, scope),
+            "letrec" | "letrec*" => self.expand_letrec(form, node, args, scope),
+            "do" => self.expand_do(node, args, scope),
+            "guard" => self.expand_guard(node, args, scope),
+            "parameterize" => self.expand_parameterize(node, args, scope),
+            "let-values" | "let*-values" => self.expand_let_values(form, node, args, scope),
+            "receive" if args.len() > 2 => self.expand_receive(args, scope),
+            "shift" if args.len() > 1 => self.expand_shift(args, scope),
+            "define-values" if args.len() == 2 => self.expand_define_values(args, scope),
+            "cond" => self.expand_cond(args, scope),
+            "case" if !args.is_empty() => self.expand_case(args, scope),
+            "define-syntax" | "define-syntax-rule" | "define-macro" => self.expand_syntax_definition(form, node, args, scope),
+            "let-syntax" | "letrec-syntax" if !args.is_empty() => self.expand_let_syntax(form, node, args, scope),
+            "if" | "begin" | "and" | "or" | "when" | "unless" | "reset" => self.expand_all(args, scope).map(|rest| with_head(form, rest)),
+            "quasiquote" | "lambda" | "λ" | "set!" | "case" | "let-syntax" | "letrec-syntax" => Ok(self.strip(node)),
+            // auxiliary syntax on its own is left to fail as a procedure call
+            _ => Ok(Node::List(try!(self.expand_all(&node_items(node), scope))))
+        }
+    }
+
+    fn expand_lambda_form(&self, form: &'static str, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let (formals, body) = try!(self.expand_lambda(&args[0], &args[1..], scope));
+        let mut out = vec![formals];
+        out.extend(body);
+        Ok(with_head(form, out))
+    }
+
+    fn expand_define(&self, node: &Node, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        match definition_name(node) {
+            Some(name) => {
+                let out = self.bind_definition(&name, scope);
+                self.expand_definition(out, node, scope)
+            },
+            None => Ok(self.strip(node))
+        }
+    }
+
+    fn expand_receive(&self, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let init = try!(self.expand_expression(&args[1], scope));
+        let (formals, body) = try!(self.expand_lambda(&args[0], &args[2..], scope));
+        let mut out = vec![formals, init];
+        out.extend(body);
+        Ok(with_head("receive", out))
+    }
+
+    // (shift k <body>) binds k like (lambda (k) <body>)
+    fn expand_shift(&self, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let (formals, body) = try!(self.expand_lambda(&Node::List(vec![args[0].clone()]), &args[1..], scope));
+        let mut out = node_items(&formals);
+        out.extend(body);
+        Ok(with_head("shift", out))
+    }
+
+    fn expand_define_values(&self, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let formals = try!(self.bind_formals(&args[0], scope));
+        let init = try!(self.expand_expression(&args[1], scope));
+        Ok(with_head("define-values", vec![formals, init]))
+    }
+
+    fn expand_cond(&self, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let mut out = vec![];
+        for clause in args {
+            out.push(try!(self.expand_clause(clause, None, scope)));
+        }
+        Ok(with_head("cond", out))
+    }
+
+    fn expand_case_lambda(&self, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let mut out = vec![Node::Identifier("case-lambda".to_string())];
+        for clause in args {
+            out.push(match *clause {
+                Node::List(ref items) if !items.is_empty() => {
+                    let (formals, body) = try!(self.expand_lambda(&items[0], &items[1..], scope));
+                    let mut clause = vec![formals];
+                    clause.extend(body);
+                    Node::List(clause)
+                },
+                _ => self.strip(clause)
+            });
+        }
+        Ok(Node::List(out))
+    }
+
+    fn expand_set(&self, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let name = match args[0] {
+            Node::Identifier(ref name) => match self.resolve(name, scope) {
+                Some(Binding::Variable(out)) => out,
+                Some(_) => macro_error!("Can't set! syntax: {}", self.original(name)),
+                None => self.original(name)
+            },
+            _ => return Ok(self.strip(node))
+        };
+        Ok(Node::List(vec![Node::Identifier("set!".to_string()), Node::Identifier(name), try!(self.expand_expression(&args[1], scope))]))
+    }
+
+    fn expand_case(&self, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let mut out = vec![Node::Identifier("case".to_string()), try!(self.expand_expression(&args[0], scope))];
+        for clause in &args[1..] {
+            // the data of a case clause are quoted
+            out.push(match *clause {
+                Node::List(ref items) if !items.is_empty() && !self.is_core(&items[0], "else", scope) => {
+                    try!(self.expand_clause(clause, Some(self.strip(&items[0])), scope))
+                },
+                _ => try!(self.expand_clause(clause, None, scope))
+            });
+        }
+        Ok(Node::List(out))
+    }
+
+    // The forms that define macros, which the expander handles itself and leave nothing to run. They're
+    // kept out of expand_core, whose stack frame every nested form pays for.
+    fn expand_syntax_definition(&self, form: &'static str, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        match form {
+            "define-syntax" => {
+                match (args.len(), args.first()) {
+                    (2, Some(Node::Identifier(name))) => {
+                        let m = try!(self.parse_transformer(name, &args[1], scope));
+                        self.bind_macro(name, m, scope);
+                        Ok(unspecified())
+                    },
+                    _ => macro_error!("Expected (define-syntax <keyword> <transformer>): {}", self.strip(node))
+                }
+            },
+            "define-syntax-rule" => {
+                // (define-syntax-rule (<keyword> <pattern> ...) <template>) is a syntax-rules macro with one rule
+                let name = match (args.len(), args.first()) {
+                    (2, Some(&Node::List(ref pattern))) | (2, Some(&Node::DottedList(ref pattern, _))) => match pattern.first() {
+                        Some(Node::Identifier(name)) => name.clone(),
+                        _ => macro_error!("Must supply a keyword in the pattern of define-syntax-rule: {}", self.strip(node))
+                    },
+                    _ => macro_error!("Expected (define-syntax-rule (<keyword> <pattern> ...) <template>): {}", self.strip(node))
+                };
+                let rules = try!(SyntaxRules::from_rule(&self.original(&name), self.canonical(&args[0], scope), self.canonical(&args[1], scope)));
+                self.bind_macro(&name, Macro::Rules(rules, scope.clone()), scope);
+                Ok(unspecified())
+            },
+            "define-macro" => {
+                // (define-macro (<keyword> <formals>) <body>) is short for (define-macro <keyword> (lambda <formals> <body>))
+                let (name, transformer) = match args.first() {
+                    Some(Node::Identifier(name)) if args.len() == 2 => (name.clone(), try!(self.expand_expression(&args[1], scope))),
+                    Some(&Node::List(ref signature)) | Some(&Node::DottedList(ref signature, _)) if args.len() > 1 => match signature.first() {
+                        Some(Node::Identifier(name)) => {
+                            let (formals, body) = try!(self.expand_lambda(&formals(&args[0]), &args[1..], scope));
+                            let mut lambda = vec![Node::Identifier("lambda".to_string()), formals];
+                            lambda.extend(body);
+                            (name.clone(), Node::List(lambda))
+                        },
+                        _ => macro_error!("Must supply a keyword in define-macro: {}", self.strip(node))
+                    },
+                    _ => macro_error!("Expected (define-macro (<keyword> <formals>) <body>): {}", self.strip(node))
+                };
+                let transformer = try!(self.evaluator.evaluate(&transformer));
+                self.bind_macro(&name, Macro::Unhygienic(transformer), scope);
+                Ok(unspecified())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn expand_let_syntax(&self, form: &'static str, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        // the macros of letrec-syntax can refer to each other, while those of let-syntax
+        // refer to the scope around them
+        let syntax_scope = Scope::child(scope);
+        let def_scope = if form == "letrec-syntax" { syntax_scope.clone() } else { scope.clone() };
+        match args[0] {
+            Node::List(ref bindings) => {
+                for binding in bindings {
+                    match *binding {
+                        Node::List(ref pair) if pair.len() == 2 => match pair[0] {
+                            Node::Identifier(ref name) => {
+                                let m = try!(self.parse_transformer(name, &pair[1], &def_scope));
+                                self.bind_macro(name, m, &syntax_scope);
+                            },
+                            _ => macro_error!("Unexpected value for name in {}: {}", form, self.strip(binding))
+                        },
+                        _ => macro_error!("{} bindings must have exactly 2 params: {}", form, self.strip(binding))
+                    }
+                }
+            },
+            _ => macro_error!("Unexpected value for bindings in {}: {}", form, self.strip(node))
+        }
+        // the body is the body of a let with no variables
+        let mut out = vec![Node::Identifier("let".to_string()), Node::List(vec![])];
+        out.extend(try!(self.expand_body(&args[1..], &Scope::child(&syntax_scope))));
+        Ok(Node::List(out))
+    }
+
+    // A transformer is either (syntax-rules ...) or (er-macro-transformer <procedure>), where the
+    // procedure is evaluated now, and called with the form, rename and compare
+    fn parse_transformer(&self, name: &str, spec: &Node, scope: &Rc<Scope>) -> Result<Macro, MacroError> {
+        match *spec {
+            Node::List(ref items) if items.len() == 2 && self.is_core(&items[0], "er-macro-transformer", scope) => {
+                let transformer = try!(self.expand_expression(&items[1], scope));
+                Ok(Macro::ExplicitRenaming(try!(self.evaluator.evaluate(&transformer)), scope.clone()))
+            },
+            _ => Ok(Macro::Rules(try!(SyntaxRules::parse(&self.original(name), &self.canonical(spec, scope))), scope.clone()))
+        }
+    }
+
+    // (<formals> <body> ...) of a lambda, where the formals may be a list, a dotted list or a single
+    // rest parameter, and the list may have #!optional parameters with defaults
+    fn expand_lambda(&self, formals: &Node, body: &[Node], scope: &Rc<Scope>) -> Result<(Node, Vec<Node>), MacroError> {
+        let params = Scope::child(scope);
+        let formals = try!(self.bind_formals(formals, &params));
+        let body = try!(self.expand_body(body, &Scope::child(&params)));
+        Ok((formals, body))
+    }
+
+    // Binds the variables in the formals of a lambda, or of the forms that bind multiple values, in
+    // the scope. At the top level, define-values defines globals.
+    fn bind_formals(&self, formals: &Node, params: &Rc<Scope>) -> Result<Node, MacroError> {
+        Ok(match *formals {
+            Node::Identifier(ref name) => Node::Identifier(self.bind_definition(name, params)),
+            Node::List(ref items) | Node::DottedList(ref items, _) => {
+                let mut out = vec![];
+                for item in items {
+                    out.push(match *item {
+                        Node::Identifier(ref name) if self.original(name).starts_with("#!") => Node::Identifier(self.original(name)),
+                        Node::Identifier(ref name) => Node::Identifier(self.bind_definition(name, params)),
+                        Node::List(ref pair) if pair.len() == 2 => match pair[0] {
+                            Node::Identifier(ref name) => {
+                                // a default can refer to the parameters before it
+                                let default = try!(self.expand_expression(&pair[1], params));
+                                Node::List(vec![Node::Identifier(self.bind_definition(name, params)), default])
+                            },
+                            _ => self.strip(item)
+                        },
+                        _ => self.strip(item)
+                    });
+                }
+                match *formals {
+                    Node::DottedList(_, ref tail) => match **tail {
+                        Node::Identifier(ref name) => Node::DottedList(out, Box::new(Node::Identifier(self.bind_definition(name, params)))),
+                        _ => Node::DottedList(out, Box::new(self.strip(tail)))
+                    },
+                    _ => Node::List(out)
+                }
+            },
+            _ => self.strip(formals)
+        })
+    }
+
+    // The definitions in a body are all bound before anything is expanded, so that every form in
+    // the body can refer to all of them, and to the macros it defines
+    fn expand_body(&self, nodes: &[Node], scope: &Rc<Scope>) -> Result<Vec<Node>, MacroError> {
+        let mut forms = vec![];
+        let mut pending: Vec<Node> = nodes.iter().rev().cloned().collect();
+        while let Some(node) = pending.pop() {
+            let node = try!(self.expand_head(&node, scope));
+            match self.resolve_head(&node, scope) {
+                Some(Binding::Core("begin")) => {
+                    // definitions inside a begin belong to the body
+                    pending.extend(node_items(&node)[1..].iter().rev().cloned());
+                },
+                Some(Binding::Core("define")) => {
+                    match definition_name(&node) {
+                        Some(name) => {
+                            let out = self.bind_variable(&name, scope);
+                            forms.push(BodyForm::Define(out, node));
+                        },
+                        None => forms.push(BodyForm::Expression(node))
+                    }
+                },
+                Some(Binding::Core("define-values")) if node_items(&node).len() == 3 => {
+                    let formals = try!(self.bind_formals(&node_items(&node)[1], scope));
+                    forms.push(BodyForm::DefineValues(formals, node));
+                },
+                Some(Binding::Core("define-syntax")) | Some(Binding::Core("define-syntax-rule")) | Some(Binding::Core("define-macro")) => {
+                    try!(self.expand_expression(&node, scope));
+                },
+                _ => forms.push(BodyForm::Expression(node))
+            }
+        }
+
+        let mut out = vec![];
+        for form in forms {
+            out.push(match form {
+                BodyForm::Define(name, node) => try!(self.expand_definition(name, &node, scope)),
+                BodyForm::DefineValues(formals, node) => {
+                    let init = try!(self.expand_expression(&node_items(&node)[2], scope));
+                    Node::List(vec![Node::Identifier("define-values".to_string()), formals, init])
+                },
+                BodyForm::Expression(node) => try!(self.expand_expression(&node, scope))
+            });
+        }
+        Ok(out)
+    }
+
+    // (define <name> <value>) or (define (<name> <formals>) <body>), where the name is already bound
+    fn expand_definition(&self, name: String, node: &Node, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let items = node_items(node);
+        let head = Node::Identifier("define".to_string());
+        match items[1] {
+            Node::List(_) | Node::DottedList(_, _) => {
+                let (formals, body) = try!(self.expand_lambda(&formals(&items[1]), &items[2..], scope));
+                let signature = match formals {
+                    Node::List(mut params) => {
+                        params.insert(0, Node::Identifier(name));
+                        Node::List(params)
+                    },
+                    Node::DottedList(mut params, tail) => {
+                        params.insert(0, Node::Identifier(name));
+                        Node::DottedList(params, tail)
+                    },
+                    rest => Node::DottedList(vec![Node::Identifier(name)], Box::new(rest))
+                };
+                let mut out = vec![head, signature];
+                out.extend(body);
+                Ok(Node::List(out))
+            },
+            _ => {
+                let mut out = vec![head, Node::Identifier(name)];
+                out.extend(try!(self.expand_all(&items[2..], scope)));
+                Ok(Node::List(out))
+            }
+        }
+    }
+
+    // Checks for ((<name> <value> ...) ...), with between min and max values in each
+    fn bindings<'b>(&self, node: &'b Node, min: usize, max: usize) -> Option<Vec<(&'b String, &'b [Node])>> {
+        let mut out = vec![];
+        match *node {
+            Node::List(ref items) => {
+                for item in items {
+                    match *item {
+                        Node::List(ref binding) if binding.len() > min && binding.len() <= max + 1 => match binding[0] {
+                            Node::Identifier(ref name) => out.push((name, &binding[1..])),
+                            _ => return None
+                        },
+                        _ => return None
+                    }
+                }
+                Some(out)
+            },
+            _ => None
+        }
+    }
+
+    fn expand_let(&self, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        // (let <name> ((<var> <init>) ...) <body>) binds the name in the body only
+        let (name, args) = match args.first() {
+            Some(Node::Identifier(name)) => (Some(name), &args[1..]),
+            _ => (None, args)
+        };
+        let bindings = match args.first().and_then(|b| self.bindings(b, 1, 1)) {
+            Some(bindings) => bindings,
+            None => return Ok(self.strip(node))
+        };
+        let mut inits = vec![];
+        for &(_, init) in &bindings {
+            inits.push(try!(self.expand_expression(&init[0], scope)));
+        }
+
+        let mut out = vec![Node::Identifier("let".to_string())];
+        let mut let_scope = scope.clone();
+        if let Some(name) = name {
+            let_scope = Scope::child(scope);
+            out.push(Node::Identifier(self.bind_variable(name, &let_scope)));
+        }
+        let let_scope = Scope::child(&let_scope);
+        let mut vars = vec![];
+        for (&(name, _), init) in bindings.iter().zip(inits) {
+            vars.push(Node::List(vec![Node::Identifier(self.bind_variable(name, &let_scope)), init]));
+        }
+        out.push(Node::List(vars));
+        out.extend(try!(self.expand_body(&args[1..], &Scope::child(&let_scope))));
+        Ok(Node::List(out))
+    }
+
+    // Each init of let* sees the variables before it
+    fn expand_let_star(&self, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let bindings = match args.first().and_then(|b| self.bindings(b, 1, 1)) {
+            Some(bindings) => bindings,
+            None => return Ok(self.strip(node))
+        };
+        let mut let_scope = scope.clone();
+        let mut vars = vec![];
+        for (name, init) in bindings {
+            let init = try!(self.expand_expression(&init[0], &let_scope));
+            let_scope = Scope::child(&let_scope);
+            vars.push(Node::List(vec![Node::Identifier(self.bind_variable(name, &let_scope)), init]));
+        }
+        let mut out = vec![Node::Identifier("let*".to_string()), Node::List(vars)];
+        out.extend(try!(self.expand_body(&args[1..], &Scope::child(&let_scope))));
+        Ok(Node::List(out))
+    }
+
+    // The inits of letrec and letrec* see all the variables
+    fn expand_letrec(&self, form: &'static str, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let bindings = match args.first().and_then(|b| self.bindings(b, 1, 1)) {
+            Some(bindings) => bindings,
+            None => return Ok(self.strip(node))
+        };
+        let let_scope = Scope::child(scope);
+        let names: Vec<String> = bindings.iter().map(|&(name, _)| self.bind_variable(name, &let_scope)).collect();
+        let mut vars = vec![];
+        for (name, &(_, init)) in names.into_iter().zip(bindings.iter()) {
+            vars.push(Node::List(vec![Node::Identifier(name), try!(self.expand_expression(&init[0], &let_scope))]));
+        }
+        let mut out = vec![Node::Identifier(form.to_string()), Node::List(vars)];
+        out.extend(try!(self.expand_body(&args[1..], &Scope::child(&let_scope))));
+        Ok(Node::List(out))
+    }
+
+    // (do ((<var> <init> <step>) ...) (<test> <expression> ...) <command> ...), where only the inits
+    // are outside the scope of the variables
+    fn expand_do(&self, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let bindings = match args.first().and_then(|b| self.bindings(b, 1, 2)) {
+            Some(bindings) => bindings,
+            None => return Ok(self.strip(node))
+        };
+        let exit = match args.get(1) {
+            Some(Node::List(exit)) if !exit.is_empty() => exit,
+            _ => return Ok(self.strip(node))
+        };
+        let mut inits = vec![];
+        for &(_, values) in &bindings {
+            inits.push(try!(self.expand_expression(&values[0], scope)));
+        }
+        let do_scope = Scope::child(scope);
+        let names: Vec<String> = bindings.iter().map(|&(name, _)| self.bind_variable(name, &do_scope)).collect();
+        let mut vars = vec![];
+        for ((name, init), &(_, values)) in names.into_iter().zip(inits).zip(bindings.iter()) {
+            let mut var = vec![Node::Identifier(name), init];
+            var.extend(try!(self.expand_all(&values[1..], &do_scope)));
+            vars.push(Node::List(var));
+        }
+        let mut out = vec![Node::Identifier("do".to_string()), Node::List(vars), Node::List(try!(self.expand_all(exit, &do_scope)))];
+        out.extend(try!(self.expand_all(&args[2..], &do_scope)));
+        Ok(Node::List(out))
+    }
+
+    // (guard (<variable> <clause> ...) <body> ...), where only the clauses are in the scope of the variable
+    fn expand_guard(&self, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let (var, clauses) = match args.first() {
+            Some(Node::List(spec)) if !spec.is_empty() => match spec[0] {
+                Node::Identifier(ref var) => (var, &spec[1..]),
+                _ => return Ok(self.strip(node))
+            },
+            _ => return Ok(self.strip(node))
+        };
+        let guard_scope = Scope::child(scope);
+        let mut spec = vec![Node::Identifier(self.bind_variable(var, &guard_scope))];
+        for clause in clauses {
+            spec.push(try!(self.expand_clause(clause, None, &guard_scope)));
+        }
+        let mut out = vec![Node::Identifier("guard".to_string()), Node::List(spec)];
+        out.extend(try!(self.expand_body(&args[1..], &Scope::child(scope))));
+        Ok(Node::List(out))
+    }
+
+    // The inits of let-values are outside all of the formals, and each init of let*-values is inside
+    // the formals before it
+    fn expand_let_values(&self, form: &'static str, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let bindings = match args.first() {
+            Some(Node::List(bindings)) => bindings,
+            _ => return Ok(self.strip(node))
+        };
+        let vars = Scope::child(scope);
+        let mut inner = scope.clone();
+        let mut out = vec![];
+        for binding in bindings {
+            let (formals, init) = match *binding {
+                Node::List(ref binding) if binding.len() == 2 => (&binding[0], &binding[1]),
+                _ => return Ok(self.strip(node))
+            };
+            if form == "let*-values" {
+                let init = try!(self.expand_expression(init, &inner));
+                inner = Scope::child(&inner);
+                out.push(Node::List(vec![try!(self.bind_formals(formals, &inner)), init]));
+            } else {
+                let init = try!(self.expand_expression(init, scope));
+                out.push(Node::List(vec![try!(self.bind_formals(formals, &vars)), init]));
+            }
+        }
+        let body_scope = if form == "let*-values" { inner } else { vars };
+        let mut out = vec![Node::Identifier(form.to_string()), Node::List(out)];
+        out.extend(try!(self.expand_body(&args[1..], &Scope::child(&body_scope))));
+        Ok(Node::List(out))
+    }
+
+    // (parameterize ((<parameter> <value>) ...) <body> ...), where the parameters are expressions too
+    fn expand_parameterize(&self, node: &Node, args: &[Node], scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let bindings = match args.first() {
+            Some(Node::List(bindings)) => bindings,
+            _ => return Ok(self.strip(node))
+        };
+        let mut out = vec![];
+        for binding in bindings {
+            match *binding {
+                Node::List(ref binding) if binding.len() == 2 => out.push(Node::List(try!(self.expand_all(binding, scope)))),
+                _ => return Ok(self.strip(node))
+            }
+        }
+        let mut out = vec![Node::Identifier("parameterize".to_string()), Node::List(out)];
+        out.extend(try!(self.expand_body(&args[1..], &Scope::child(scope))));
+        Ok(Node::List(out))
+    }
+
+    // A clause of cond or case: (else <expression> ...), (<test> => <receiver>) or (<test> <expression> ...).
+    // The test of a case clause is its data, which are already quoted.
+    fn expand_clause(&self, clause: &Node, data: Option<Node>, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        let items = match *clause {
+            Node::List(ref items) if !items.is_empty() => items,
+            _ => return Ok(self.strip(clause))
+        };
+        let test = match data {
+            Some(data) => data,
+            None if self.is_core(&items[0], "else", scope) => Node::Identifier("else".to_string()),
+            None => try!(self.expand_expression(&items[0], scope))
+        };
+        let mut out = vec![test];
+        if items.len() == 3 && self.is_core(&items[1], "=>", scope) {
+            out.push(Node::Identifier("=>".to_string()));
+            out.push(try!(self.expand_expression(&items[2], scope)));
+        } else {
+            out.extend(try!(self.expand_all(&items[1..], scope)));
+        }
+        Ok(Node::List(out))
+    }
+
+    // Only the unquoted parts of a quasiquotation at the outermost level are code
+    fn expand_quasiquote(&self, node: &Node, depth: usize, scope: &Rc<Scope>) -> Result<Node, MacroError> {
+        match *node {
+            Node::List(ref items) if items.len() == 2 && (self.is_core(&items[0], "unquote", scope) || self.is_core(&items[0], "unquote-splicing", scope)) => {
+                let head = self.strip(&items[0]);
+                let inner = if depth == 1 {
+                    try!(self.expand_expression(&items[1], scope))
+                } else {
+                    try!(self.expand_quasiquote(&items[1], depth - 1, scope))
+                };
+                Ok(Node::List(vec![head, inner]))
+            },
+            Node::List(ref items) if items.len() == 2 && self.is_core(&items[0], "quasiquote", scope) => {
+                Ok(Node::List(vec![self.strip(&items[0]), try!(self.expand_quasiquote(&items[1], depth + 1, scope))]))
+            },
+            // the parser reads (a . ,b) as (a unquote b), so the last two items are really the tail
+            Node::List(ref items) if items.len() > 2 && ["unquote", "unquote-splicing", "quasiquote"].iter().any(|name| self.is_core(&items[items.len() - 2], name, scope)) => {
+                let split = items.len() - 2;
+                let mut out = vec![];
+                for item in &items[..split] {
+                    out.push(try!(self.expand_quasiquote(item, depth, scope)));
+                }
+                out.extend(node_items(&try!(self.expand_quasiquote(&Node::List(items[split..].to_vec()), depth, scope))));
+                Ok(Node::List(out))
+            },
+            Node::List(ref items) | Node::Vector(ref items) => {
+                let mut out = vec![];
+                for item in items {
+                    out.push(try!(self.expand_quasiquote(item, depth, scope)));
+                }
+                Ok(match *node {
+                    Node::Vector(_) => Node::Vector(out),
+                    _ => Node::List(out)
+                })
+            },
+            Node::DottedList(ref items, ref tail) => {
+                let mut out = vec![];
+                for item in items {
+                    out.push(try!(self.expand_quasiquote(item, depth, scope)));
+                }
+                Ok(Node::DottedList(out, Box::new(try!(self.expand_quasiquote(tail, depth, scope)))))
+            },
+            _ => Ok(self.strip(node))
+        }
+    }
+}
+
+// The form (<form> <rest> ...)
+fn with_head(form: &str, rest: Vec<Node>) -> Node {
+    let mut out = vec![Node::Identifier(form.to_string())];
+    out.extend(rest);
+    Node::List(out)
+}
+
+fn node_items(node: &Node) -> Vec<Node> {
+    match *node {
+        Node::List(ref items) | Node::DottedList(ref items, _) => items.clone(),
+        _ => vec![]
+    }
+}
+
+// The formals of a procedure from its signature, (<name> <formals>)
+fn formals(signature: &Node) -> Node {
+    match *signature {
+        Node::List(ref items) => Node::List(items[1..].to_vec()),
+        Node::DottedList(ref items, ref tail) => syntax_rules::make_list(items[1..].to_vec(), Some((**tail).clone())),
+        _ => Node::List(vec![])
+    }
+}
+
+// The name a (define ...) form defines, if it has the shape of a definition
+fn definition_name(node: &Node) -> Option<String> {
+    let items = node_items(node);
+    match items.get(1) {
+        Some(Node::Identifier(name)) if items.len() > 2 => Some(name.clone()),
+        Some(&Node::List(ref signature)) | Some(&Node::DottedList(ref signature, _)) if items.len() > 2 => match signature.first() {
+            Some(Node::Identifier(name)) => Some(name.clone()),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// What a definition of syntax evaluates to, like any other definition
+fn unspecified() -> Node {
+    Node::List(vec![Node::Identifier("quote".to_string()), Node::List(vec![])])
+}
+
+thread_local! {
+    static GENSYMS: Cell<usize> = const { Cell::new(0) };
+}
+
+// A fresh symbol for a macro to bind. It prints like an uninterned symbol in Common Lisp, which
+// the reader can't produce, so it can't clash with a symbol in the program.
+pub fn gensym(prefix: &str) -> String {
+    GENSYMS.with(|count| {
+        count.set(count.get() + 1);
+        format!("#:{}{}", prefix, count.get())
+    })
+}
@@ -0,0 +1,418 @@
+;; Generators and accumulators, as in SRFI 158, which the CPS interpreter defines when it starts.
+;; A generator is a procedure of no arguments that gives the next value each time it's called, and
+;; the eof object once it has run out. An accumulator is a procedure of one argument that takes a
+;; value each time it's called, and gives back what it made of them when it's called with the eof object.
+
+;; Constructors
+
+(define (generator . items)
+  (list->generator items))
+
+(define (circular-generator item . items)
+  (let* ((all (cons item items)) (rest all))
+    (lambda ()
+      (when (null? rest)
+        (set! rest all))
+      (let ((next (car rest)))
+        (set! rest (cdr rest))
+        next))))
+
+(define (make-iota-generator count #!optional (start 0) (step 1))
+  (let ((i 0))
+    (lambda ()
+      (if (>= i count)
+          (eof-object)
+          (let ((value (+ start (* i step))))
+            (set! i (+ i 1))
+            value)))))
+
+;; Without an end, the range goes on forever
+(define (make-range-generator start #!optional end (step 1))
+  (lambda ()
+    (if (and end (>= start end))
+        (eof-object)
+        (let ((value start))
+          (set! start (+ start step))
+          value))))
+
+;; Calls proc with a yield procedure, and each call of the generator runs proc until it yields a
+;; value, which the generator gives back. The rest of proc is kept as a composable continuation, up
+;; to the prompt the generator runs it in, for the next call to carry on from.
+(define (make-coroutine-generator proc)
+  (let ((tag (make-continuation-prompt-tag 'generator))
+        (resume #f))
+    (define (yield-value value)
+      (call-with-composable-continuation
+       (lambda (k) (abort-current-continuation tag k value))
+       tag))
+    (define (finish)
+      (set! resume (lambda (ignored) (eof-object)))
+      (eof-object))
+    (set! resume (lambda (ignored) (proc yield-value) (finish)))
+    (lambda ()
+      (call-with-continuation-prompt
+       (lambda () (resume #f))
+       tag
+       (lambda (k value)
+         (set! resume k)
+         value)))))
+
+(define (list->generator items)
+  (lambda ()
+    (if (null? items)
+        (eof-object)
+        (let ((item (car items)))
+          (set! items (cdr items))
+          item))))
+
+(define (vector->generator vec #!optional (start 0) end)
+  (let ((stop (or end (vector-length vec))))
+    (lambda ()
+      (if (>= start stop)
+          (eof-object)
+          (let ((item (vector-ref vec start)))
+            (set! start (+ start 1))
+            item)))))
+
+(define (reverse-vector->generator vec #!optional (start 0) end)
+  (let ((stop (or end (vector-length vec))))
+    (lambda ()
+      (if (<= stop start)
+          (eof-object)
+          (begin
+            (set! stop (- stop 1))
+            (vector-ref vec stop))))))
+
+(define (string->generator str #!optional (start 0) end)
+  (let ((stop (or end (string-length str))))
+    (lambda ()
+      (if (>= start stop)
+          (eof-object)
+          (let ((char (string-ref str start)))
+            (set! start (+ start 1))
+            char)))))
+
+;; Turns any for-each style procedure into a generator of what it goes through
+(define (make-for-each-generator for-each obj)
+  (make-coroutine-generator (lambda (yield-value) (for-each yield-value obj))))
+
+(define (make-unfold-generator stop? mapper successor seed)
+  (lambda ()
+    (if (stop? seed)
+        (eof-object)
+        (let ((value (mapper seed)))
+          (set! seed (successor seed))
+          value))))
+
+;; Operators
+
+;; The items first, then what the generator at the end gives
+(define (gcons* . args)
+  (lambda ()
+    (if (null? (cdr args))
+        ((car args))
+        (let ((item (car args)))
+          (set! args (cdr args))
+          item))))
+
+(define (gappend . gens)
+  (lambda ()
+    (let loop ()
+      (if (null? gens)
+          (eof-object)
+          (let ((item ((car gens))))
+            (if (eof-object? item)
+                (begin
+                  (set! gens (cdr gens))
+                  (loop))
+                item))))))
+
+;; Each list the generator gives is taken apart into its items
+(define (gflatten gen)
+  (let ((items '()))
+    (lambda ()
+      (let loop ()
+        (if (pair? items)
+            (let ((item (car items)))
+              (set! items (cdr items))
+              item)
+            (let ((next (gen)))
+              (if (eof-object? next)
+                  next
+                  (begin
+                    (set! items next)
+                    (loop)))))))))
+
+;; With more than one generator, f gets an item from each, and the result ends with the shortest
+(define (gmap f gen . gens)
+  (let ((all (cons gen gens)))
+    (lambda ()
+      (let loop ((rest all) (items '()))
+        (if (null? rest)
+            (apply f items)
+            (let ((item ((car rest))))
+              (if (eof-object? item)
+                  item
+                  (loop (cdr rest) (append items (list item))))))))))
+
+;; Like gmap, except that proc gets a seed after the items, and returns the value along with the next seed
+(define (gcombine proc seed gen . gens)
+  (gmap (lambda (items)
+          (call-with-values
+              (lambda () (apply proc (append items (list seed))))
+            (lambda (value next)
+              (set! seed next)
+              value)))
+        (apply gmap (cons list (cons gen gens)))))
+
+(define (gfilter pred gen)
+  (lambda ()
+    (let loop ()
+      (let ((item (gen)))
+        (if (or (eof-object? item) (pred item))
+            item
+            (loop))))))
+
+(define (gremove pred gen)
+  (gfilter (lambda (item) (if (pred item) #f #t)) gen))
+
+;; proc gets each item and a state, and returns whether to keep the item along with the next state
+(define (gstate-filter proc seed gen)
+  (gfilter (lambda (item)
+             (call-with-values
+                 (lambda () (proc item seed))
+               (lambda (keep next)
+                 (set! seed next)
+                 keep)))
+           gen))
+
+;; If the generator runs out first, the rest of the k items are the padding, when there is one
+(define (gtake gen k #!optional (padding (eof-object)))
+  (lambda ()
+    (if (<= k 0)
+        (eof-object)
+        (let ((item (gen)))
+          (set! k (- k 1))
+          (if (eof-object? item) padding item)))))
+
+(define (gdrop gen k)
+  (lambda ()
+    (let loop ()
+      (if (> k 0)
+          (begin
+            (set! k (- k 1))
+            (gen)
+            (loop))
+          (gen)))))
+
+(define (gtake-while pred gen)
+  (let ((done #f))
+    (lambda ()
+      (if done
+          (eof-object)
+          (let ((item (gen)))
+            (if (or (eof-object? item) (pred item))
+                item
+                (begin
+                  (set! done #t)
+                  (eof-object))))))))
+
+(define (gdrop-while pred gen)
+  (let ((dropping #t))
+    (lambda ()
+      (let loop ()
+        (let ((item (gen)))
+          (cond ((eof-object? item) item)
+                ((and dropping (pred item)) (loop))
+                (else (set! dropping #f) item)))))))
+
+(define (gdelete item gen #!optional (same? equal?))
+  (gremove (lambda (x) (same? item x)) gen))
+
+;; Leaves out any item that's the same as the one before it
+(define (gdelete-neighbor-dups gen #!optional (same? equal?))
+  (let ((first #t) (previous #f))
+    (gfilter (lambda (item)
+               (let ((keep (or first (if (same? previous item) #f #t))))
+                 (set! first #f)
+                 (set! previous item)
+                 keep))
+             gen)))
+
+;; The items of value-gen at the indexes index-gen gives, which must go up
+(define (gindex value-gen index-gen)
+  (let ((position 0))
+    (lambda ()
+      (let ((index (index-gen)))
+        (if (eof-object? index)
+            index
+            (let loop ()
+              (let ((item (value-gen)))
+                (set! position (+ position 1))
+                (if (or (eof-object? item) (= position (+ index 1)))
+                    item
+                    (loop)))))))))
+
+;; The items of value-gen for which truth-gen gives true
+(define (gselect value-gen truth-gen)
+  (lambda ()
+    (let loop ()
+      (let* ((item (value-gen)) (keep (truth-gen)))
+        (cond ((eof-object? item) item)
+              ((eof-object? keep) keep)
+              (keep item)
+              (else (loop)))))))
+
+;; Consumers, which take at most n items when they're given an n
+
+(define (generator->list gen #!optional n)
+  (let ((head (list #f)))
+    (let loop ((tail head) (count 0))
+      (let ((item (if (and n (>= count n)) (eof-object) (gen))))
+        (if (eof-object? item)
+            (cdr head)
+            (let ((next (list item)))
+              (set-cdr! tail next)
+              (loop next (+ count 1))))))))
+
+(define (generator->reverse-list gen #!optional n)
+  (generator-fold cons '() (if n (gtake gen n) gen)))
+
+(define (generator->vector gen #!optional n)
+  (list->vector (generator->list gen n)))
+
+;; Fills vec from at with items, until one of them runs out, and returns how many there were
+(define (generator->vector! vec at gen)
+  (let loop ((i at))
+    (if (>= i (vector-length vec))
+        (- i at)
+        (let ((item (gen)))
+          (if (eof-object? item)
+              (- i at)
+              (begin
+                (vector-set! vec i item)
+                (loop (+ i 1))))))))
+
+(define (generator->string gen #!optional n)
+  (list->string (generator->list gen n)))
+
+;; proc gets an item from each generator, then the seed, and returns the next seed
+(define (generator-fold proc seed gen . gens)
+  (let ((next (apply gmap (cons list (cons gen gens)))))
+    (let loop ((acc seed))
+      (let ((items (next)))
+        (if (eof-object? items)
+            acc
+            (loop (apply proc (append items (list acc)))))))))
+
+(define (generator-for-each proc gen . gens)
+  (let ((next (apply gmap (cons list (cons gen gens)))))
+    (let loop ()
+      (let ((items (next)))
+        (if (eof-object? items)
+            '()
+            (begin
+              (apply proc items)
+              (loop)))))))
+
+(define (generator-map->list proc gen . gens)
+  (generator->list (apply gmap (cons proc (cons gen gens)))))
+
+(define (generator-find pred gen)
+  (let ((item ((gfilter pred gen))))
+    (if (eof-object? item) #f item)))
+
+(define (generator-count pred gen)
+  (generator-fold (lambda (item count) (if (pred item) (+ count 1) count)) 0 gen))
+
+;; The first true value pred gives, or #f
+(define (generator-any pred gen)
+  (let loop ()
+    (let ((item (gen)))
+      (if (eof-object? item)
+          #f
+          (or (pred item) (loop))))))
+
+;; The value pred gives for the last item if it gives true for all of them, or #t if there are none
+(define (generator-every pred gen)
+  (let loop ((last #t))
+    (let ((item (gen)))
+      (if (eof-object? item)
+          last
+          (let ((value (pred item)))
+            (if value (loop value) #f))))))
+
+;; Passes the generator to an SRFI 1 style unfold, with any extra arguments after it
+(define (generator-unfold gen unfold . args)
+  (apply unfold (append (list eof-object? (lambda (x) x) (lambda (x) (gen)) (gen)) args)))
+
+;; (do-generator (<variable> <generator>) <body> ...) runs the body with each item in turn
+(define-syntax do-generator
+  (syntax-rules ()
+    ((_ (var gen) body ...)
+     (let ((next gen))
+       (let loop ((var (next)))
+         (if (eof-object? var)
+             '()
+             (begin
+               body ...
+               (loop (next)))))))))
+
+;; Accumulators
+
+(define (make-accumulator kons knil finalizer)
+  (lambda (item)
+    (if (eof-object? item)
+        (finalizer knil)
+        (begin
+          (set! knil (kons item knil))
+          knil))))
+
+(define (count-accumulator)
+  (make-accumulator (lambda (item count) (+ count 1)) 0 (lambda (count) count)))
+
+(define (list-accumulator)
+  (let* ((head (list #f)) (tail head))
+    (lambda (item)
+      (if (eof-object? item)
+          (cdr head)
+          (let ((next (list item)))
+            (set-cdr! tail next)
+            (set! tail next)
+            item)))))
+
+(define (reverse-list-accumulator)
+  (make-accumulator cons '() (lambda (items) items)))
+
+(define (vector-accumulator)
+  (let ((items (list-accumulator)))
+    (lambda (item)
+      (if (eof-object? item)
+          (list->vector (items item))
+          (items item)))))
+
+(define (reverse-vector-accumulator)
+  (make-accumulator cons '() list->vector))
+
+;; Stores the items in vec from at, and gives back vec
+(define (vector-accumulator! vec at)
+  (lambda (item)
+    (if (eof-object? item)
+        vec
+        (begin
+          (vector-set! vec at item)
+          (set! at (+ at 1))
+          item))))
+
+(define (string-accumulator)
+  (let ((chars (list-accumulator)))
+    (lambda (item)
+      (if (eof-object? item)
+          (list->string (chars item))
+          (chars item)))))
+
+(define (sum-accumulator)
+  (make-accumulator + 0 (lambda (sum) sum)))
+
+(define (product-accumulator)
+  (make-accumulator * 1 (lambda (product) product)))
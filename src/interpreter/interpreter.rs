@@ -2,6 +2,7 @@ use crate::reader::lexer;
 use crate::reader::parser;
 use crate::interpreter::ast_walk_interpreter;
 use crate::interpreter::cps_interpreter;
+use crate::error::Error;
 
 #[cfg(not(test))]
 use crate::core::repl;
@@ -15,15 +16,6 @@ use std::path::Path;
 #[cfg(not(test))]
 use std::io::Read;
 
-macro_rules! try_or_err_to_string {
-    ($inp:expr) => (
-        match $inp {
-            Ok(v) => v,
-            Err(e) => return Err(e.to_string())
-        }
-    )
-}
-
 pub fn new(t: &str) -> Interpreter {
     Interpreter::new(t)
 }
@@ -42,35 +34,83 @@ impl Interpreter {
         }
     }
 
-    fn parse(&self, input: &str) -> Result<Vec<parser::Node>, String> {
-        let tokens = try_or_err_to_string!(lexer::tokenize(input));
-        let ast = try_or_err_to_string!(parser::parse(&tokens));
+    fn parse(&self, input: &str, file: Option<&str>) -> Result<Vec<(parser::Node, parser::SourceMap)>, Error> {
+        let (tokens, spans) = try!(lexer::tokenize_spanned(input, file));
+        let ast = try!(parser::parse_spanned(&tokens, &spans));
         Ok(ast)
     }
 
-    pub fn execute(&self, input: &str) -> Result<String, String> {
-        let parsed = try!(self.parse(input));
+    fn parse_nodes(&self, input: &str) -> Result<Vec<parser::Node>, Error> {
+        let parsed = try!(self.parse(input, None));
+        Ok(parsed.into_iter().map(|(node, _)| node).collect())
+    }
+
+    // Runtime errors say where in the input they happened, as in 1:5
+    pub fn execute(&self, input: &str) -> Result<String, Error> {
+        self.execute_spanned(input, None)
+    }
+
+    // Like execute, but runtime errors say where in the named file they happened, as in foo.scm:12:5
+    pub fn execute_file(&self, input: &str, filename: &str) -> Result<String, Error> {
+        self.execute_spanned(input, Some(filename))
+    }
+
+    // Runs input the way run_file and the REPL do: errors are printed with the calls that led to them,
+    // up to backtrace_depth of them. Only the CPS interpreter keeps track of its calls, in its continuations.
+    pub fn execute_traced(&self, input: &str, filename: Option<&str>, backtrace_depth: usize) -> Result<String, String> {
+        self.execute_spanned(input, filename).map_err(|e| format_backtrace(&e, backtrace_depth))
+    }
+
+    fn execute_spanned(&self, input: &str, filename: Option<&str>) -> Result<String, Error> {
+        let parsed = try!(self.parse(input, filename));
         match *self {
-            Interpreter::AstWalk(ref i) => Ok(format!("{:?}", try_or_err_to_string!(i.run(&parsed)))),
-            Interpreter::Cps(ref i)     => Ok(format!("{:?}", try_or_err_to_string!(i.run(&parsed)))),
+            Interpreter::AstWalk(ref i) => Ok(format!("{:?}", try!(i.run_spanned(&parsed)))),
+            Interpreter::Cps(ref i)     => Ok(format!("{:?}", try!(i.run_spanned(&parsed)))),
+        }
+    }
+
+    // How many bounces of the trampoline a green thread gets before the next one has a turn. Only the
+    // CPS interpreter has threads.
+    pub fn set_time_slice(&self, bounces: usize) {
+        if let Interpreter::Cps(ref i) = *self {
+            i.set_time_slice(bounces);
+        }
+    }
+
+    // Expands the macros in the input without running it, returning the code the interpreter would run
+    pub fn expand(&self, input: &str) -> Result<Vec<parser::Node>, Error> {
+        let parsed = try!(self.parse_nodes(input));
+        match *self {
+            Interpreter::AstWalk(ref i) => Ok(try!(i.expand(&parsed))),
+            Interpreter::Cps(ref i)     => Ok(try!(i.expand(&parsed))),
         }
     }
 
     #[cfg(not(test))]
-    pub fn start_repl(&self) {
+    pub fn start_repl(&self, backtrace_depth: usize) {
         println!("\nWelcome to the RustyScheme REPL!");
-        repl::start("> ", |s| self.execute(&s))
+        repl::start("> ", |s| self.execute_traced(&s, None, backtrace_depth))
     }
 
     #[cfg(not(test))]
-    pub fn run_file(&self, filename: &String) {
+    pub fn run_file(&self, filename: &String, backtrace_depth: usize) {
         let path = Path::new(&filename);
         let mut file = File::open(&path).unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
-        match self.execute(&contents) {
+        match self.execute_traced(&contents, Some(filename), backtrace_depth) {
             Ok(_) => {},
             Err(e) => println!("{}", e),
         }
     }
 }
+
+fn format_backtrace(e: &Error, depth: usize) -> String {
+    let frames = e.backtrace();
+    let mut lines = vec![e.to_string()];
+    lines.extend(frames.iter().take(depth).map(|frame| format!("  {}", frame)));
+    if depth > 0 && frames.len() > depth {
+        lines.push(format!("  ... {} more", frames.len() - depth));
+    }
+    lines.join("\n")
+}
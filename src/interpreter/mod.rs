@@ -1,3 +1,4 @@
 pub mod interpreter;
 pub mod cps_interpreter;
-pub mod ast_walk_interpreter;
\ No newline at end of file
+pub mod ast_walk_interpreter;
+pub mod syntax_rules;pub mod expander;
@@ -0,0 +1,425 @@
+use crate::reader::parser::Node;
+
+use std::fmt;
+use std::collections::HashMap;
+
+// A syntax-rules transformer (R7RS section 4.3.2). It works on the parsed syntax tree, and is run by
+// the expander, which keeps it hygienic.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SyntaxRules {
+    name: String,
+    ellipsis: String,
+    literals: Vec<String>,
+    rules: Vec<(Node, Node)>,
+}
+
+pub struct MacroError {
+    pub message: String,
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MacroError: {}", self.message)
+    }
+}
+impl fmt::Debug for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MacroError: {}", self.message)
+    }
+}
+
+macro_rules! macro_error {
+    ($($arg:tt)*) => (
+        return Err(MacroError { message: format!($($arg)*)})
+    )
+}
+
+// What a pattern variable matched: a single form, or one binding per repetition of the ellipsis it
+// was under
+#[derive(Clone)]
+enum Binding {
+    One(Node),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+// How the expander keeps a macro hygienic
+pub trait Hygiene {
+    // The identifier to put in the expansion in place of one the template introduces
+    fn rename(&mut self, name: &str) -> String;
+    // Whether an identifier from the macro use means the same thing as a literal of the macro
+    fn is_literal(&self, literal: &str, name: &str) -> bool;
+}
+
+impl SyntaxRules {
+    // (syntax-rules (<literal> ...) (<pattern> <template>) ...), optionally with a custom ellipsis
+    // identifier before the literals
+    pub fn parse(name: &str, spec: &Node) -> Result<SyntaxRules, MacroError> {
+        let items = match *spec {
+            Node::List(ref items) if items.first() == Some(&Node::Identifier("syntax-rules".to_string())) => &items[1..],
+            _ => macro_error!("Expected a syntax-rules transformer for {}: {}", name, spec)
+        };
+        let (ellipsis, items) = match items.first() {
+            Some(Node::Identifier(ellipsis)) => (ellipsis.clone(), &items[1..]),
+            _ => ("...".to_string(), items)
+        };
+        let literals = match items.first() {
+            Some(Node::List(literals)) => try!(literals.iter().map(|l| match *l {
+                Node::Identifier(ref s) => Ok(s.clone()),
+                _ => macro_error!("Literals in syntax-rules must be identifiers: {}", l)
+            }).collect()),
+            _ => macro_error!("Missing list of literals in syntax-rules for {}: {}", name, spec)
+        };
+        let mut rules = SyntaxRules { name: name.to_string(), ellipsis, literals, rules: vec![] };
+        for rule in &items[1..] {
+            match *rule {
+                Node::List(ref pair) if pair.len() == 2 => try!(rules.add_rule(pair[0].clone(), pair[1].clone())),
+                _ => macro_error!("Expected (<pattern> <template>) in syntax-rules for {}: {}", name, rule)
+            }
+        }
+        Ok(rules)
+    }
+
+    // The single rule of (define-syntax-rule <pattern> <template>)
+    pub fn from_rule(name: &str, pattern: Node, template: Node) -> Result<SyntaxRules, MacroError> {
+        let mut rules = SyntaxRules { name: name.to_string(), ellipsis: "...".to_string(), literals: vec![], rules: vec![] };
+        try!(rules.add_rule(pattern, template));
+        Ok(rules)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn add_rule(&mut self, pattern: Node, template: Node) -> Result<(), MacroError> {
+        match pattern {
+            Node::List(_) | Node::DottedList(_, _) => {
+                // the keyword position is ignored, so it can't be the subpattern of an ellipsis
+                let (items, tail) = split(&pattern);
+                if items.is_empty() {
+                    macro_error!("Empty pattern in syntax-rules for {}", self.name);
+                }
+                try!(self.check_sequence(&items[1..], &pattern));
+                if let Some(tail) = tail {
+                    try!(self.check_pattern(tail));
+                }
+            },
+            _ => macro_error!("A syntax-rules pattern must be a list: {}", pattern)
+        }
+        self.rules.push((pattern, template));
+        Ok(())
+    }
+
+    // Each list or vector in a pattern may use one ellipsis, after the subpattern it repeats
+    fn check_pattern(&self, pattern: &Node) -> Result<(), MacroError> {
+        match *pattern {
+            Node::List(_) | Node::DottedList(_, _) => {
+                let (items, tail) = split(pattern);
+                try!(self.check_sequence(&items, pattern));
+                match tail {
+                    Some(tail) => self.check_pattern(tail),
+                    None => Ok(())
+                }
+            },
+            Node::Vector(ref items) => {
+                let items: Vec<&Node> = items.iter().collect();
+                self.check_sequence(&items, pattern)
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn check_sequence(&self, items: &[&Node], pattern: &Node) -> Result<(), MacroError> {
+        let ellipses: Vec<usize> = (0..items.len()).filter(|&i| self.is_ellipsis(items[i])).collect();
+        if ellipses.len() > 1 || ellipses.first() == Some(&0) {
+            macro_error!("Misplaced {} in syntax-rules pattern: {}", self.ellipsis, pattern);
+        }
+        for item in items {
+            try!(self.check_pattern(item));
+        }
+        Ok(())
+    }
+
+    // Rewrites a use of the macro with the template of the first rule whose pattern matches it
+    pub fn expand<H: Hygiene>(&self, form: &Node, hygiene: &mut H) -> Result<Node, MacroError> {
+        let (items, tail) = split(form);
+        for (pattern, template) in &self.rules {
+            let (pattern_items, pattern_tail) = split(pattern);
+            let mut bindings = HashMap::new();
+            if self.match_sequence(&pattern_items[1..], pattern_tail, &items[1..], tail, &mut bindings, hygiene) {
+                return self.transcribe(template, &bindings, false, hygiene);
+            }
+        }
+        macro_error!("No syntax-rules pattern of {} matches: {}", self.name, form)
+    }
+
+    fn is_ellipsis(&self, node: &Node) -> bool {
+        match *node {
+            Node::Identifier(ref s) => *s == self.ellipsis && !self.literals.contains(s),
+            _ => false
+        }
+    }
+
+    fn match_pattern<H: Hygiene>(&self, pattern: &Node, form: &Node, bindings: &mut Bindings, hygiene: &H) -> bool {
+        match *pattern {
+            Node::Identifier(ref s) => {
+                if self.literals.contains(s) {
+                    match *form {
+                        Node::Identifier(ref name) => hygiene.is_literal(s, name),
+                        _ => false
+                    }
+                } else {
+                    if s != "_" {
+                        bindings.insert(s.clone(), Binding::One(form.clone()));
+                    }
+                    true
+                }
+            },
+            Node::List(_) | Node::DottedList(_, _) => {
+                match *form {
+                    Node::List(_) | Node::DottedList(_, _) => {
+                        let (pattern_items, pattern_tail) = split(pattern);
+                        let (items, tail) = split(form);
+                        self.match_sequence(&pattern_items, pattern_tail, &items, tail, bindings, hygiene)
+                    },
+                    _ => false
+                }
+            },
+            Node::Vector(ref pattern_items) => {
+                match *form {
+                    Node::Vector(ref items) => {
+                        let pattern_items: Vec<&Node> = pattern_items.iter().collect();
+                        let items: Vec<&Node> = items.iter().collect();
+                        self.match_sequence(&pattern_items, None, &items, None, bindings, hygiene)
+                    },
+                    _ => false
+                }
+            },
+            _ => pattern == form
+        }
+    }
+
+    // Matches the elements of a list or vector, where the pattern may end in a dotted tail and
+    // contain a subpattern followed by an ellipsis, which takes up whatever the others leave over
+    fn match_sequence<H: Hygiene>(&self, patterns: &[&Node], pattern_tail: Option<&Node>, items: &[&Node], tail: Option<&Node>, bindings: &mut Bindings, hygiene: &H) -> bool {
+        let (before, repeated, after) = match patterns.iter().position(|p| self.is_ellipsis(p)) {
+            Some(i) => (&patterns[..i - 1], Some(patterns[i - 1]), &patterns[i + 1..]),
+            None => (patterns, None, &patterns[patterns.len()..])
+        };
+        if items.len() < before.len() + after.len() {
+            return false;
+        }
+        for (pattern, item) in before.iter().zip(items.iter()) {
+            if !self.match_pattern(pattern, item, bindings, hygiene) {
+                return false;
+            }
+        }
+        let rest = &items[before.len()..];
+
+        let rest = match repeated {
+            Some(repeated) => {
+                let count = rest.len() - after.len();
+                let mut matches = vec![];
+                for item in &rest[..count] {
+                    let mut inner = HashMap::new();
+                    if !self.match_pattern(repeated, item, &mut inner, hygiene) {
+                        return false;
+                    }
+                    matches.push(inner);
+                }
+                for var in self.pattern_vars(repeated) {
+                    let each = matches.iter().map(|m| m[&var].clone()).collect();
+                    bindings.insert(var, Binding::Many(each));
+                }
+                for (pattern, item) in after.iter().zip(rest[count..].iter()) {
+                    if !self.match_pattern(pattern, item, bindings, hygiene) {
+                        return false;
+                    }
+                }
+                &rest[rest.len()..]
+            },
+            None => rest
+        };
+
+        match pattern_tail {
+            Some(pattern_tail) => {
+                let rest: Vec<Node> = rest.iter().map(|&n| n.clone()).collect();
+                self.match_pattern(pattern_tail, &make_list(rest, tail.cloned()), bindings, hygiene)
+            },
+            None => rest.is_empty() && tail.is_none()
+        }
+    }
+
+    fn pattern_vars(&self, pattern: &Node) -> Vec<String> {
+        let mut vars = vec![];
+        identifiers(pattern, &mut vars);
+        vars.retain(|v| v != "_" && *v != self.ellipsis && !self.literals.contains(v));
+        vars
+    }
+
+    // `escaped` is set inside (... <template>), where the ellipsis stands for itself
+    fn transcribe<H: Hygiene>(&self, template: &Node, bindings: &Bindings, escaped: bool, hygiene: &mut H) -> Result<Node, MacroError> {
+        match *template {
+            Node::Identifier(ref s) => {
+                match bindings.get(s) {
+                    Some(Binding::One(node)) => Ok(node.clone()),
+                    Some(&Binding::Many(_)) => macro_error!("Pattern variable {} must be followed by {} in template of {}", s, self.ellipsis, self.name),
+                    None => Ok(Node::Identifier(hygiene.rename(s)))
+                }
+            },
+            Node::List(_) | Node::DottedList(_, _) => {
+                let (items, tail) = split(template);
+                if !escaped && items.len() == 2 && tail.is_none() && self.is_ellipsis(items[0]) {
+                    return self.transcribe(items[1], bindings, true, hygiene);
+                }
+                let items = try!(self.transcribe_sequence(&items, bindings, escaped, hygiene));
+                let tail = match tail {
+                    Some(tail) => Some(try!(self.transcribe(tail, bindings, escaped, hygiene))),
+                    None => None
+                };
+                Ok(make_list(items, tail))
+            },
+            Node::Vector(ref items) => {
+                let items: Vec<&Node> = items.iter().collect();
+                Ok(Node::Vector(try!(self.transcribe_sequence(&items, bindings, escaped, hygiene))))
+            },
+            _ => Ok(template.clone())
+        }
+    }
+
+    fn transcribe_sequence<H: Hygiene>(&self, templates: &[&Node], bindings: &Bindings, escaped: bool, hygiene: &mut H) -> Result<Vec<Node>, MacroError> {
+        let mut out = vec![];
+        let mut i = 0;
+        while i < templates.len() {
+            // <template> ... ... splices the repetitions of a doubly repeated variable together
+            let mut depth = 0;
+            while !escaped && i + depth + 1 < templates.len() && self.is_ellipsis(templates[i + depth + 1]) {
+                depth += 1;
+            }
+            if depth == 0 {
+                out.push(try!(self.transcribe(templates[i], bindings, escaped, hygiene)));
+            } else {
+                out.extend(try!(self.transcribe_repeated(templates[i], bindings, depth, escaped, hygiene)));
+            }
+            i += depth + 1;
+        }
+        Ok(out)
+    }
+
+    fn transcribe_repeated<H: Hygiene>(&self, template: &Node, bindings: &Bindings, depth: usize, escaped: bool, hygiene: &mut H) -> Result<Vec<Node>, MacroError> {
+        // the variables under the ellipsis that matched a sequence are stepped through together,
+        // while the others are repeated as they are
+        let mut vars = vec![];
+        identifiers(template, &mut vars);
+        let sequences: Vec<(String, &Vec<Binding>)> = vars.into_iter().filter_map(|v| match bindings.get(&v) {
+            Some(Binding::Many(each)) => Some((v, each)),
+            _ => None
+        }).collect();
+        let count = match sequences.first() {
+            Some(&(_, each)) => each.len(),
+            None => macro_error!("No pattern variables to repeat before {} in template of {}: {}", self.ellipsis, self.name, template)
+        };
+        if sequences.iter().any(|&(_, each)| each.len() != count) {
+            macro_error!("Pattern variables repeated by the same {} matched different numbers of forms in {}: {}", self.ellipsis, self.name, template);
+        }
+
+        let mut out = vec![];
+        for i in 0..count {
+            let mut inner = bindings.clone();
+            for &(ref var, each) in &sequences {
+                inner.insert(var.clone(), each[i].clone());
+            }
+            if depth > 1 {
+                out.extend(try!(self.transcribe_repeated(template, &inner, depth - 1, escaped, hygiene)));
+            } else {
+                out.push(try!(self.transcribe(template, &inner, escaped, hygiene)));
+            }
+        }
+        Ok(out)
+    }
+}
+
+// The elements of a list and its dotted tail, if any
+pub fn split(node: &Node) -> (Vec<&Node>, Option<&Node>) {
+    match *node {
+        Node::List(ref items) => (items.iter().collect(), None),
+        Node::DottedList(ref items, ref tail) => (items.iter().collect(), Some(&**tail)),
+        _ => (vec![], Some(node))
+    }
+}
+
+// The inverse of split, which keeps a list that ends in another list proper
+pub fn make_list(mut items: Vec<Node>, tail: Option<Node>) -> Node {
+    match tail {
+        None => Node::List(items),
+        Some(Node::List(rest)) => {
+            items.extend(rest);
+            Node::List(items)
+        },
+        Some(Node::DottedList(rest, tail)) => {
+            items.extend(rest);
+            Node::DottedList(items, tail)
+        },
+        Some(tail) => {
+            if items.is_empty() {
+                tail
+            } else {
+                Node::DottedList(items, Box::new(tail))
+            }
+        }
+    }
+}
+
+fn identifiers(node: &Node, out: &mut Vec<String>) {
+    match *node {
+        Node::Identifier(ref s) if !out.contains(s) => out.push(s.clone()),
+        Node::List(ref items) | Node::Vector(ref items) => {
+            for item in items {
+                identifiers(item, out);
+            }
+        },
+        Node::DottedList(ref items, ref tail) => {
+            for item in items {
+                identifiers(item, out);
+            }
+            identifiers(tail, out);
+        },
+        _ => ()
+    }
+}
+
+// Expands without renaming anything, and matches literals by name
+#[cfg(test)]
+struct Unhygienic;
+
+#[cfg(test)]
+impl Hygiene for Unhygienic {
+    fn rename(&mut self, name: &str) -> String {
+        name.to_string()
+    }
+
+    fn is_literal(&self, literal: &str, name: &str) -> bool {
+        literal == name
+    }
+}
+
+#[test]
+fn test_syntax_rules_ellipsis() {
+    let spec = Node::List(vec![Node::Identifier("syntax-rules".to_string()), Node::List(vec![]),
+                               Node::List(vec![Node::List(vec![Node::Identifier("_".to_string()), Node::Identifier("x".to_string()), Node::Identifier("...".to_string())]),
+                                               Node::List(vec![Node::Identifier("list".to_string()), Node::List(vec![Node::Identifier("quote".to_string()), Node::Identifier("x".to_string())]), Node::Identifier("...".to_string())])])]);
+    let rules = SyntaxRules::parse("quote-all", &spec).unwrap();
+    let form = Node::List(vec![Node::Identifier("quote-all".to_string()), Node::Identifier("a".to_string()), Node::Number(crate::core::number::Number::Integer(1))]);
+    assert_eq!(rules.expand(&form, &mut Unhygienic).unwrap().to_string(), "(list (quote a) (quote 1))");
+}
+
+#[test]
+fn test_syntax_rules_errors() {
+    let spec = Node::List(vec![Node::Identifier("syntax-rules".to_string()), Node::List(vec![]),
+                               Node::List(vec![Node::List(vec![Node::Identifier("_".to_string()), Node::Identifier("...".to_string())]), Node::Boolean(true)])]);
+    assert_eq!(SyntaxRules::parse("bad", &spec).err().unwrap().to_string(), "MacroError: Misplaced ... in syntax-rules pattern: (_ ...)");
+    let rules = SyntaxRules::from_rule("one", Node::List(vec![Node::Identifier("_".to_string()), Node::Identifier("x".to_string())]), Node::Identifier("x".to_string())).unwrap();
+    assert_eq!(rules.expand(&Node::List(vec![Node::Identifier("one".to_string())]), &mut Unhygienic).err().unwrap().to_string(),
+               "MacroError: No syntax-rules pattern of one matches: (one)");
+}
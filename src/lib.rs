@@ -1,6 +1,21 @@
+// The code base predates the `?` operator and most of clippy. It uses try!, which is deprecated,
+// and the lints below flag idioms the original code uses, such as `len() == 0` and `as_` methods
+// that take self by value. Lints that find real mistakes, like unconditional_recursion, stay on.
+#![allow(deprecated)]
+#![allow(clippy::len_zero, clippy::wrong_self_convention, clippy::while_let_loop, clippy::map_entry,
+         clippy::println_empty_string, clippy::new_without_default, clippy::needless_borrows_for_generic_args,
+         clippy::useless_asref, clippy::module_inception, clippy::manual_map, clippy::redundant_guards,
+         clippy::empty_docs, clippy::ptr_arg, clippy::single_match, clippy::explicit_auto_deref)]
+
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
+
 pub mod reader;
 pub mod core;
 pub mod interpreter;
+pub mod error;
 
 //pub mod lexer;
 //mod parser;
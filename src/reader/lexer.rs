@@ -1,60 +1,107 @@
+use crate::core::character;
+use crate::core::number::Number;
+use crate::core::string;
+use crate::reader::span::Span;
+use crate::error::{Error, Details, ErrorKind};
+
 use std::str;
 use std::fmt;
 use std::iter;
+use std::rc::Rc;
 
 pub fn tokenize(s: &str) -> Result<Vec<Token>, SyntaxError> {
-    Lexer::tokenize(s)
+    Lexer::tokenize(s, None).map(|(tokens, _)| tokens)
+}
+
+// Like tokenize, but also returns where each token was read from, for error messages
+pub fn tokenize_spanned(s: &str, file: Option<&str>) -> Result<(Vec<Token>, Vec<Span>), SyntaxError> {
+    Lexer::tokenize(s, file)
 }
 
 #[derive(PartialEq, Debug)]
 pub enum Token {
     OpenParen,
     CloseParen,
+    OpenVector,
+    Dot,
     Quote,
     Quasiquote,
     Unquote,
+    UnquoteSplicing,
     Identifier(String),
-    Integer(i64),
+    Number(Number),
     Boolean(bool),
+    Char(char),
     String(String),
 }
 
 pub struct SyntaxError {
+    kind: ErrorKind,
     message: String,
-    line: u32,
-    column: u32,
+    location: Span,
 }
 
 impl fmt::Display for SyntaxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "SyntaxError: {} (line: {}, column: {})", self.message, self.line, self.column)
+        write!(f, "SyntaxError: {} (line: {}, column: {})", self.message, self.location.line, self.location.column)
     }
 }
 impl fmt::Debug for SyntaxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "SyntaxError: {} (line: {}, column: {})", self.message, self.line, self.column)
+        write!(f, "{}", self)
+    }
+}
+
+impl From<SyntaxError> for Error {
+    fn from(e: SyntaxError) -> Error {
+        Error::Syntax(Box::new(Details::new(e.kind, e.message, Some(e.location))))
     }
 }
 
+// Errors are Malformed input unless they say otherwise, as in syntax_error!(self, Incomplete; "...")
 macro_rules! syntax_error {
-    ($lexer:ident, $($arg:tt)*) => (
-        return Err(SyntaxError { message: format!($($arg)*), line: $lexer.line, column: $lexer.column })
-    )
+    ($lexer:ident, $kind:ident; $($arg:tt)*) => (
+        return Err(SyntaxError { kind: ErrorKind::$kind, message: format!($($arg)*), location: $lexer.location() })
+    );
+    ($lexer:ident, $($arg:tt)*) => (syntax_error!($lexer, Malformed; $($arg)*))
 }
 
 struct Lexer<'a> {
     chars: iter::Peekable<str::Chars<'a>>,
     current: Option<char>,
     tokens: Vec<Token>,
+    spans: Vec<Span>,
+    file: Option<Rc<str>>,
     line: u32,
     column: u32,
+    offset: usize,
+    token_start: Span,
 }
 
 impl<'a> Lexer<'a> {
-    fn tokenize(s: &str) -> Result<Vec<Token>, SyntaxError> {
-        let mut lexer = Lexer { chars: s.chars().peekable(), current: None, tokens: Vec::new(), line: 1, column: 0 };
+    fn tokenize(s: &str, file: Option<&str>) -> Result<(Vec<Token>, Vec<Span>), SyntaxError> {
+        let mut lexer = Lexer { chars: s.chars().peekable(), current: None, tokens: Vec::new(), spans: Vec::new(),
+                                file: file.map(Rc::from), line: 1, column: 0, offset: 0, token_start: Span::default() };
         try!(lexer.run());
-        Ok(lexer.tokens)
+        Ok((lexer.tokens, lexer.spans))
+    }
+
+    // Marks the current character as the start of the next token
+    fn start_token(&mut self) {
+        self.token_start = self.location();
+    }
+
+    fn location(&self) -> Span {
+        Span { file: self.file.clone(), line: self.line, column: self.column, start: self.offset, end: self.offset }
+    }
+
+    // Tokens are pushed once they've been read, so they end at the current character. A few single
+    // character tokens are pushed before advancing past them.
+    fn push(&mut self, token: Token) {
+        let mut span = self.token_start.clone();
+        span.end = if self.offset > span.start { self.offset } else { span.start + 1 };
+        self.tokens.push(token);
+        self.spans.push(span);
     }
 
     fn current(&self) -> Option<char> {
@@ -62,6 +109,9 @@ impl<'a> Lexer<'a> {
     }
 
     fn advance(&mut self) {
+        if let Some(c) = self.current() {
+            self.offset += c.len_utf8();
+        }
         if self.current() == Some('\x0a') {
             self.line += 1;
             self.column = 1;
@@ -83,6 +133,7 @@ impl<'a> Lexer<'a> {
         loop {
             match self.current() {
                 Some(c) => {
+                    self.start_token();
                     match c {
                         _ if c.is_whitespace() => {
                             self.advance();
@@ -104,56 +155,98 @@ impl<'a> Lexer<'a> {
                             }
                         },
                         '(' => {
-                            self.tokens.push(Token::OpenParen);
+                            self.push(Token::OpenParen);
                             self.advance();
                         },
                         ')' => {
-                            self.tokens.push(Token::CloseParen);
+                            self.push(Token::CloseParen);
                             self.advance();
                         },
                         '\'' => {
-                            self.tokens.push(Token::Quote);
+                            self.push(Token::Quote);
                             self.advance();
                         },
                         '`' => {
-                            self.tokens.push(Token::Quasiquote);
+                            self.push(Token::Quasiquote);
                             self.advance();
                         },
                         ',' => {
-                            self.tokens.push(Token::Unquote);
                             self.advance();
+                            if self.current() == Some('@') {
+                                self.advance();
+                                self.push(Token::UnquoteSplicing);
+                            } else {
+                                self.push(Token::Unquote);
+                            }
                         },
                         '+' | '-' => {
                             match self.peek() {
-                                Some('0'...'9') => {
-                                    // skip past the +/- symbol and parse the number
-                                    self.advance();
+                                Some('0'..='9') | Some('.') => {
+                                    // don't advance -- let parse_number handle the sign
+                                    let val = try!(self.parse_number());
+                                    self.push(Token::Number(val));
+                                    try!(self.parse_delimiter());
+                                },
+                                _ if self.lookahead("inf.0") || self.lookahead("nan.0") => {
                                     let val = try!(self.parse_number());
-                                    self.tokens.push(Token::Integer(if c == '-' { -1 * val } else { val }));
+                                    self.push(Token::Number(val));
                                     try!(self.parse_delimiter());
                                 },
                                 _ => {
                                     // not followed by a digit, must be an identifier
-                                    self.tokens.push(Token::Identifier(c.to_string()));
+                                    self.push(Token::Identifier(c.to_string()));
                                     self.advance();
                                     try!(self.parse_delimiter());
                                 }
                             }
                         },
+                        '#' if self.peek() == Some('(') => {
+                            self.advance();
+                            self.advance();
+                            self.push(Token::OpenVector);
+                        },
                         '#' => {
-                            let val = try!(self.parse_boolean());
-                            self.tokens.push(Token::Boolean(val));
+                            match self.peek() {
+                                Some('x') | Some('X') | Some('b') | Some('B') | Some('o') | Some('O') |
+                                Some('d') | Some('D') | Some('e') | Some('E') | Some('i') | Some('I') => {
+                                    let val = try!(self.parse_number());
+                                    self.push(Token::Number(val));
+                                },
+                                Some('\\') => {
+                                    let val = try!(self.parse_char());
+                                    self.push(Token::Char(val));
+                                },
+                                Some('!') => {
+                                    // markers in lambda lists, like #!optional and #!rest, read as identifiers
+                                    let val = try!(self.parse_identifier());
+                                    self.push(Token::Identifier(val));
+                                },
+                                _ => {
+                                    let val = try!(self.parse_boolean());
+                                    self.push(Token::Boolean(val));
+                                }
+                            }
                             try!(self.parse_delimiter());
                         },
-                        '0'...'9' => {
+                        '.' if self.peek().is_none_or(|n| n.is_whitespace() || n == '(' || n == ')') => {
+                            // a lone dot, as in (a . b)
+                            self.push(Token::Dot);
+                            self.advance();
+                        },
+                        '.' if self.peek().is_some_and(|n| n.is_ascii_digit()) => {
+                            let val = try!(self.parse_number());
+                            self.push(Token::Number(val));
+                            try!(self.parse_delimiter());
+                        },
+                        '0'..='9' => {
                             // don't advance -- let parse_number advance as needed
                             let val = try!(self.parse_number());
-                            self.tokens.push(Token::Integer(val));
+                            self.push(Token::Number(val));
                             try!(self.parse_delimiter());
                         },
                         '\"' => {
                             let val = try!(self.parse_string());
-                            self.tokens.push(Token::String(val));
+                            self.push(Token::String(val));
                             try!(self.parse_delimiter());
                         },
                         '[' | ']' | '{' | '}' | '|' | '\\' => {
@@ -161,7 +254,7 @@ impl<'a> Lexer<'a> {
                         },
                         _ => {
                             let val = try!(self.parse_identifier());
-                            self.tokens.push(Token::Identifier(val));
+                            self.push(Token::Identifier(val));
                             try!(self.parse_delimiter());
                         }
                     }
@@ -172,25 +265,75 @@ impl<'a> Lexer<'a> {
         Ok(())
     }
 
-    fn parse_number(&mut self) -> Result<i64, SyntaxError> {
+    // Check whether the characters following the current one spell out the given string
+    fn lookahead(&self, s: &str) -> bool {
+        let mut chars = self.chars.clone();
+        s.chars().all(|c| chars.next() == Some(c))
+    }
+
+    fn parse_number(&mut self) -> Result<Number, SyntaxError> {
         let mut s = String::new();
-        loop {
+        let mut radix = 10;
+
+        // radix and exactness prefixes, e.g. #x1F or #e1.5
+        while self.current() == Some('#') {
+            s.push('#');
+            self.advance();
             match self.current() {
                 Some(c) => {
-                    match c {
-                        '0'...'9' => {
-                            s.push(c);
-                            self.advance();
-                        },
-                        _ => break
+                    match c.to_ascii_lowercase() {
+                        'x' => radix = 16,
+                        'b' => radix = 2,
+                        'o' => radix = 8,
+                        'd' => radix = 10,
+                        'e' | 'i' => (),
+                        _ => syntax_error!(self, "Unexpected character in number prefix: {}", c)
                     }
+                    s.push(c);
+                    self.advance();
                 },
-                None => break
+                None => syntax_error!(self, Incomplete; "Expected a number prefix, but found EOF instead")
             }
         }
-        match s.parse() {
-            Ok(value) => Ok(value),
-            Err(_) => { syntax_error!(self, "Not a number: {}", self.current().unwrap()); },
+
+        if let Some(c) = self.current() {
+            if c == '+' || c == '-' {
+                s.push(c);
+                self.advance();
+                if self.current() == Some('i') || self.current() == Some('n') {
+                    // +inf.0, -inf.0 or +nan.0
+                    while let Some(c) = self.current() {
+                        if !c.is_alphanumeric() && c != '.' { break }
+                        s.push(c);
+                        self.advance();
+                    }
+                }
+            }
+        }
+
+        while let Some(c) = self.current() {
+            if c.is_digit(radix) || c == '.' || c == '/' {
+                s.push(c);
+                self.advance();
+            } else if radix == 10 && (c == 'e' || c == 'E') {
+                // exponent, which may be signed
+                s.push(c);
+                self.advance();
+                match self.current() {
+                    Some(sign) if sign == '+' || sign == '-' => {
+                        s.push(sign);
+                        self.advance();
+                    },
+                    _ => ()
+                }
+            } else {
+                break
+            }
+        }
+
+        match Number::parse(&s) {
+            Some(value) => Ok(value),
+            None => syntax_error!(self, "Not a number: {}", s)
         }
     }
 
@@ -213,6 +356,33 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    fn parse_char(&mut self) -> Result<char, SyntaxError> {
+        // skip the #\ prefix
+        self.advance();
+        self.advance();
+
+        // the first character is always part of the literal, even if it is a delimiter, e.g. #\(
+        let mut s = match self.current() {
+            Some(c) => c.to_string(),
+            None => syntax_error!(self, Incomplete; "Expected a character, but found EOF instead")
+        };
+        self.advance();
+        loop {
+            match self.current() {
+                Some(c) if !c.is_whitespace() && c != '(' && c != ')' && c != '\"' && c != ';' => {
+                    s.push(c);
+                    self.advance();
+                },
+                _ => break
+            }
+        }
+
+        match character::parse(&s) {
+            Some(c) => Ok(c),
+            None => syntax_error!(self, "Unknown character name: {}", s)
+        }
+    }
+
     fn parse_identifier(&mut self) -> Result<String, SyntaxError> {
         let mut s = String::new();
         loop {
@@ -250,25 +420,86 @@ impl<'a> Lexer<'a> {
                             self.advance();
                             break;
                         },
+                        '\\' => {
+                            self.advance();
+                            if let Some(c) = try!(self.parse_string_escape()) {
+                                s.push(c);
+                            }
+                        },
                         _ => {
                             s.push(c);
                             self.advance();
                         }
                     }
                 },
-                None => syntax_error!(self, "Expected end quote, but found EOF instead")
+                None => syntax_error!(self, Incomplete; "Expected end quote, but found EOF instead")
             }
         }
         Ok(s)
     }
 
+    // Parses the escape sequence following a backslash in a string. Returns None for a line
+    // continuation, which stands for no characters at all.
+    fn parse_string_escape(&mut self) -> Result<Option<char>, SyntaxError> {
+        match self.current() {
+            Some('x') | Some('X') => {
+                self.advance();
+                let mut hex = String::new();
+                loop {
+                    match self.current() {
+                        Some(';') => {
+                            self.advance();
+                            break;
+                        },
+                        Some(c) if c.is_ascii_hexdigit() => {
+                            hex.push(c);
+                            self.advance();
+                        },
+                        _ => syntax_error!(self, "Expected a hex escape ending in ;: \\x{}", hex)
+                    }
+                }
+                match u32::from_str_radix(&hex, 16).ok().and_then(::std::char::from_u32) {
+                    Some(c) => Ok(Some(c)),
+                    None => syntax_error!(self, "Not a Unicode scalar value: \\x{};", hex)
+                }
+            },
+            Some(c) if c.is_whitespace() => {
+                // line continuation: skip trailing whitespace, the newline, and leading whitespace on the next line
+                let mut newline = false;
+                loop {
+                    match self.current() {
+                        Some('\n') if !newline => newline = true,
+                        Some(c) if c.is_whitespace() && c != '\n' => (),
+                        _ => break
+                    }
+                    self.advance();
+                }
+                if !newline {
+                    syntax_error!(self, "Expected a newline after \\ in a string");
+                }
+                Ok(None)
+            },
+            Some(c) => {
+                match string::unescape(c) {
+                    Some(escaped) => {
+                        self.advance();
+                        Ok(Some(escaped))
+                    },
+                    None => syntax_error!(self, "Unknown escape sequence in string: \\{}", c)
+                }
+            },
+            None => syntax_error!(self, Incomplete; "Expected end quote, but found EOF instead")
+        }
+    }
+
     fn parse_delimiter(&mut self) -> Result<(), SyntaxError> {
         match self.current() {
             Some(c) => {
                 match c {
                     _ if c.is_whitespace() => (),
                     ')' => {
-                        self.tokens.push(Token::CloseParen);
+                        self.start_token();
+                        self.push(Token::CloseParen);
                         self.advance();
                     },
                     _ => syntax_error!(self, "Unexpected character when looking for a delimiter: {}", c),
@@ -283,25 +514,43 @@ impl<'a> Lexer<'a> {
 #[test]
 fn test_lexer_simple_lexing() {
     assert_eq!(tokenize("(+ 2 3)").unwrap(),
-               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(2), Token::Integer(3), Token::CloseParen]);
+               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::Integer(2)), Token::Number(Number::Integer(3)), Token::CloseParen]);
 }
 
 #[test]
 fn test_lexer_multi_digit_integers() {
     assert_eq!(tokenize("(+ 21 325)").unwrap(),
-               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(21), Token::Integer(325), Token::CloseParen]);
+               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::Integer(21)), Token::Number(Number::Integer(325)), Token::CloseParen]);
 }
 
 #[test]
 fn test_lexer_subtraction() {
     assert_eq!(tokenize("(- 7 42)").unwrap(),
-               vec![Token::OpenParen, Token::Identifier("-".to_string()), Token::Integer(7), Token::Integer(42), Token::CloseParen]);
+               vec![Token::OpenParen, Token::Identifier("-".to_string()), Token::Number(Number::Integer(7)), Token::Number(Number::Integer(42)), Token::CloseParen]);
 }
 
 #[test]
 fn test_lexer_negative_integers() {
     assert_eq!(tokenize("(+ -8 +2 -33)").unwrap(),
-               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(-8), Token::Integer(2), Token::Integer(-33), Token::CloseParen]);
+               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::Integer(-8)), Token::Number(Number::Integer(2)), Token::Number(Number::Integer(-33)), Token::CloseParen]);
+}
+
+#[test]
+fn test_lexer_numeric_tower() {
+    assert_eq!(tokenize("1.5 -0.25 .5 1e10 6.02E+23").unwrap(),
+               vec![Token::Number(Number::Real(1.5)), Token::Number(Number::Real(-0.25)), Token::Number(Number::Real(0.5)), Token::Number(Number::Real(1e10)), Token::Number(Number::Real(6.02e23))]);
+    assert_eq!(tokenize("3/4").unwrap(),
+               vec![Token::Number(Number::parse("3/4").unwrap())]);
+    assert_eq!(tokenize("#x1F #b101 #o-17 #d10 #i2").unwrap(),
+               vec![Token::Number(Number::Integer(31)), Token::Number(Number::Integer(5)), Token::Number(Number::Integer(-15)), Token::Number(Number::Integer(10)), Token::Number(Number::Real(2.0))]);
+    assert_eq!(tokenize("(+ #e1.5 +inf.0)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::parse("3/2").unwrap()), Token::Number(Number::Real(f64::INFINITY)), Token::CloseParen]);
+    assert_eq!(tokenize("123456789012345678901234567890").unwrap(),
+               vec![Token::Number(Number::parse("123456789012345678901234567890").unwrap())]);
+    assert_eq!(tokenize("1/0").err().unwrap().to_string(),
+               "SyntaxError: Not a number: 1/0 (line: 1, column: 4)");
+    assert_eq!(tokenize("#q1").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for t/f: q (line: 1, column: 2)");
 }
 
 #[test]
@@ -312,6 +561,38 @@ fn test_lexer_booleans() {
                vec![Token::Boolean(false)]);
 }
 
+#[test]
+fn test_lexer_chars() {
+    assert_eq!(tokenize("#\\a #\\A #\\λ #\\space #\\newline #\\x3bb #\\x").unwrap(),
+               vec![Token::Char('a'), Token::Char('A'), Token::Char('λ'), Token::Char(' '), Token::Char('\n'), Token::Char('λ'), Token::Char('x')]);
+    assert_eq!(tokenize("(#\\( #\\))").unwrap(),
+               vec![Token::OpenParen, Token::Char('('), Token::Char(')'), Token::CloseParen]);
+    assert_eq!(tokenize("#\\ ").unwrap(),
+               vec![Token::Char(' ')]);
+    assert_eq!(tokenize("#\\spaces").err().unwrap().to_string(),
+               "SyntaxError: Unknown character name: spaces (line: 1, column: 9)");
+    assert_eq!(tokenize("#\\").err().unwrap().to_string(),
+               "SyntaxError: Expected a character, but found EOF instead (line: 1, column: 3)");
+}
+
+#[test]
+fn test_lexer_vectors() {
+    assert_eq!(tokenize("#(1 #t)").unwrap(),
+               vec![Token::OpenVector, Token::Number(Number::Integer(1)), Token::Boolean(true), Token::CloseParen]);
+    assert_eq!(tokenize("'#(#())").unwrap(),
+               vec![Token::Quote, Token::OpenVector, Token::OpenVector, Token::CloseParen, Token::CloseParen]);
+}
+
+#[test]
+fn test_lexer_dots() {
+    assert_eq!(tokenize("(a . b)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Dot, Token::Identifier("b".to_string()), Token::CloseParen]);
+    assert_eq!(tokenize("(1 .(2)) .").unwrap(),
+               vec![Token::OpenParen, Token::Number(Number::Integer(1)), Token::Dot, Token::OpenParen, Token::Number(Number::Integer(2)), Token::CloseParen, Token::CloseParen, Token::Dot]);
+    assert_eq!(tokenize("... .5 .a").unwrap(),
+               vec![Token::Identifier("...".to_string()), Token::Number(Number::Real(0.5)), Token::Identifier(".a".to_string())]);
+}
+
 #[test]
 fn test_lexer_identifiers() {
     for identifier in ["*", "<", "<=", "if", "while", "$t$%*=:t059s"].iter() {
@@ -320,6 +601,12 @@ fn test_lexer_identifiers() {
     }
 }
 
+#[test]
+fn test_lexer_markers() {
+    assert_eq!(tokenize("(a #!optional b)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Identifier("#!optional".to_string()), Token::Identifier("b".to_string()), Token::CloseParen]);
+}
+
 #[test]
 fn test_lexer_strings() {
     assert_eq!(tokenize("\"hello\"").unwrap(),
@@ -330,11 +617,25 @@ fn test_lexer_strings() {
                "SyntaxError: Expected end quote, but found EOF instead (line: 1, column: 11)");
 }
 
+#[test]
+fn test_lexer_string_escapes() {
+    assert_eq!(tokenize(r#""a\"b" "\\" "\n\t\r\a\b\|" "\x41;\x3bb;""#).unwrap(),
+               vec![Token::String("a\"b".to_string()), Token::String("\\".to_string()), Token::String("\n\t\r\x07\x08|".to_string()), Token::String("Aλ".to_string())]);
+    assert_eq!(tokenize("\"one \\  \n    two\"").unwrap(),
+               vec![Token::String("one two".to_string())]);
+    assert_eq!(tokenize(r#""\q""#).err().unwrap().to_string(),
+               "SyntaxError: Unknown escape sequence in string: \\q (line: 1, column: 3)");
+    assert_eq!(tokenize(r#""\x41""#).err().unwrap().to_string(),
+               "SyntaxError: Expected a hex escape ending in ;: \\x41 (line: 1, column: 6)");
+    assert_eq!(tokenize(r#""\xd800;""#).err().unwrap().to_string(),
+               "SyntaxError: Not a Unicode scalar value: \\xd800; (line: 1, column: 9)");
+}
+
 #[test]
 fn test_lexer_whitespace() {
     assert_eq!(tokenize("(+ 1 1)\n(+\n    2\t2 \n )\r\n  \n").unwrap(),
-               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(1), Token::Integer(1), Token::CloseParen,
-                    Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(2), Token::Integer(2), Token::CloseParen]);
+               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::Integer(1)), Token::Number(Number::Integer(1)), Token::CloseParen,
+                    Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::Integer(2)), Token::Number(Number::Integer(2)), Token::CloseParen]);
 }
 
 #[test]
@@ -374,12 +675,16 @@ fn test_lexer_quasiquoting() {
                vec![Token::Quasiquote, Token::OpenParen, Token::Unquote, Token::Identifier("a".to_string()), Token::CloseParen]);
     assert_eq!(tokenize("`(,a b ,c)").unwrap(),
                vec![Token::Quasiquote, Token::OpenParen, Token::Unquote, Token::Identifier("a".to_string()), Token::Identifier("b".to_string()), Token::Unquote, Token::Identifier("c".to_string()), Token::CloseParen]);
+    assert_eq!(tokenize("`(a ,@b)").unwrap(),
+               vec![Token::Quasiquote, Token::OpenParen, Token::Identifier("a".to_string()), Token::UnquoteSplicing, Token::Identifier("b".to_string()), Token::CloseParen]);
+    assert_eq!(tokenize("`(a , @b)").unwrap(),
+               vec![Token::Quasiquote, Token::OpenParen, Token::Identifier("a".to_string()), Token::Unquote, Token::Identifier("@b".to_string()), Token::CloseParen]);
 }
 
 #[test]
 fn test_lexer_complex_code_block() {
     assert_eq!(tokenize("(define (list-of-squares n)\n  (let loop ((i n) (res (list)))\n    (if (< i 0)\n        res\n        (loop (- i 1) (cons (* i i) res)))))").unwrap(),
-               vec![Token::OpenParen, Token::Identifier("define".to_string()), Token::OpenParen, Token::Identifier("list-of-squares".to_string()), Token::Identifier("n".to_string()), Token::CloseParen, Token::OpenParen, Token::Identifier("let".to_string()), Token::Identifier("loop".to_string()), Token::OpenParen, Token::OpenParen, Token::Identifier("i".to_string()), Token::Identifier("n".to_string()), Token::CloseParen, Token::OpenParen, Token::Identifier("res".to_string()), Token::OpenParen, Token::Identifier("list".to_string()), Token::CloseParen, Token::CloseParen, Token::CloseParen, Token::OpenParen, Token::Identifier("if".to_string()), Token::OpenParen, Token::Identifier("<".to_string()), Token::Identifier("i".to_string()), Token::Integer(0), Token::CloseParen, Token::Identifier("res".to_string()), Token::OpenParen, Token::Identifier("loop".to_string()), Token::OpenParen, Token::Identifier("-".to_string()), Token::Identifier("i".to_string()), Token::Integer(1), Token::CloseParen, Token::OpenParen, Token::Identifier("cons".to_string()), Token::OpenParen, Token::Identifier("*".to_string()), Token::Identifier("i".to_string()), Token::Identifier("i".to_string()), Token::CloseParen, Token::Identifier("res".to_string()), Token::CloseParen, Token::CloseParen, Token::CloseParen, Token::CloseParen, Token::CloseParen]);
+               vec![Token::OpenParen, Token::Identifier("define".to_string()), Token::OpenParen, Token::Identifier("list-of-squares".to_string()), Token::Identifier("n".to_string()), Token::CloseParen, Token::OpenParen, Token::Identifier("let".to_string()), Token::Identifier("loop".to_string()), Token::OpenParen, Token::OpenParen, Token::Identifier("i".to_string()), Token::Identifier("n".to_string()), Token::CloseParen, Token::OpenParen, Token::Identifier("res".to_string()), Token::OpenParen, Token::Identifier("list".to_string()), Token::CloseParen, Token::CloseParen, Token::CloseParen, Token::OpenParen, Token::Identifier("if".to_string()), Token::OpenParen, Token::Identifier("<".to_string()), Token::Identifier("i".to_string()), Token::Number(Number::Integer(0)), Token::CloseParen, Token::Identifier("res".to_string()), Token::OpenParen, Token::Identifier("loop".to_string()), Token::OpenParen, Token::Identifier("-".to_string()), Token::Identifier("i".to_string()), Token::Number(Number::Integer(1)), Token::CloseParen, Token::OpenParen, Token::Identifier("cons".to_string()), Token::OpenParen, Token::Identifier("*".to_string()), Token::Identifier("i".to_string()), Token::Identifier("i".to_string()), Token::CloseParen, Token::Identifier("res".to_string()), Token::CloseParen, Token::CloseParen, Token::CloseParen, Token::CloseParen, Token::CloseParen]);
 }
 
 #[test]
@@ -391,3 +696,12 @@ fn test_lexer_unicode_identifiers() {
     assert_eq!(tokenize("日本国").unwrap(),
                vec![Token::Identifier("日本国".to_string())]);
 }
+
+#[test]
+fn test_lexer_spans() {
+    let (tokens, spans) = tokenize_spanned("(car\n  #(λ ,@x))", Some("foo.scm")).unwrap();
+    assert_eq!(tokens.len(), spans.len());
+    let positions: Vec<(u32, u32, usize, usize)> = spans.iter().map(|s| (s.line, s.column, s.start, s.end)).collect();
+    assert_eq!(positions, vec![(1, 1, 0, 1), (1, 2, 1, 4), (2, 3, 7, 9), (2, 5, 9, 11), (2, 7, 12, 14), (2, 9, 14, 15), (2, 10, 15, 16), (2, 11, 16, 17)]);
+    assert_eq!(spans[2].to_string(), "foo.scm:2:3");
+}
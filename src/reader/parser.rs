@@ -1,133 +1,292 @@
 use crate::reader::lexer::*;
+use crate::reader::span::Span;
+use crate::error::{Error, Details, ErrorKind};
+use crate::core::number::Number;
+use crate::core::character;
+use crate::core::string;
 
+use std::borrow::Cow;
 use std::fmt;
 use std::slice;
 
 pub fn parse(tokens: &Vec<Token>) -> Result<Vec<Node>, ParseError> {
-    Parser::parse(tokens)
+    let parsed = try!(Parser::parse(tokens, &[]));
+    Ok(parsed.into_iter().map(|(node, _)| node).collect())
+}
+
+// Like parse, but takes the spans from lexer::tokenize_spanned and returns where each node was read from
+pub fn parse_spanned(tokens: &[Token], spans: &[Span]) -> Result<Vec<(Node, SourceMap)>, ParseError> {
+    Parser::parse(tokens, spans)
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum Node {
     Identifier(String),
-    Integer(i64),
+    Number(Number),
     Boolean(bool),
+    Char(char),
     String(String),
     List(Vec<Node>),
+    DottedList(Vec<Node>, Box<Node>),
+    Vector(Vec<Node>),
+}
+
+// Where a node was read from. Nodes don't have room for their position, so the parser returns this
+// alongside them, with a part for each element of a list or vector, followed by one for a dotted tail.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SourceMap {
+    pub span: Span,
+    pub parts: Vec<SourceMap>,
+}
+
+impl SourceMap {
+    fn leaf(span: Span) -> SourceMap {
+        SourceMap { span, parts: vec![] }
+    }
+
+    // The map for a node's nth part, or one covering the whole node if the shapes don't line up,
+    // as happens when a macro rewrites the code
+    pub fn part(&self, n: usize, count: usize) -> Cow<'_, SourceMap> {
+        if self.parts.len() == count {
+            Cow::Borrowed(&self.parts[n])
+        } else {
+            Cow::Owned(SourceMap::leaf(self.span.clone()))
+        }
+    }
+}
+
+// Nodes print as the source they were read from, for error messages about code
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Node::Identifier(ref val) => write!(f, "{}", val),
+            Node::Number(ref val) => write!(f, "{}", val),
+            Node::Boolean(val) => write!(f, "#{}", if val { "t" } else { "f" }),
+            Node::Char(val) => write!(f, "{}", character::Written(val)),
+            Node::String(ref val) => write!(f, "{}", string::Written(val)),
+            Node::List(ref nodes) => {
+                let strs: Vec<String> = nodes.iter().map(|n| n.to_string()).collect();
+                write!(f, "({})", strs.join(" "))
+            },
+            Node::DottedList(ref nodes, ref tail) => {
+                let strs: Vec<String> = nodes.iter().map(|n| n.to_string()).collect();
+                write!(f, "({} . {})", strs.join(" "), tail)
+            },
+            Node::Vector(ref nodes) => {
+                let strs: Vec<String> = nodes.iter().map(|n| n.to_string()).collect();
+                write!(f, "#({})", strs.join(" "))
+            }
+        }
+    }
 }
 
 pub struct ParseError {
+    kind: ErrorKind,
     message: String,
+    location: Option<Span>,
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ParseError: {}", self.message)
+        match self.location {
+            Some(ref span) => write!(f, "ParseError: {} (line: {}, column: {})", self.message, span.line, span.column),
+            None => write!(f, "ParseError: {}", self.message)
+        }
     }
 }
 impl fmt::Debug for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ParseError: {}", self.message)
+        write!(f, "{}", self)
     }
 }
 
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse(Box::new(Details::new(e.kind, e.message, e.location)))
+    }
+}
+
+// Errors are Malformed input unless they say otherwise, as in parse_error!(Incomplete; location, "...")
 macro_rules! parse_error {
-    ($($arg:tt)*) => (
-        return Err(ParseError { message: format!($($arg)*)})
-    )
+    ($kind:ident; $location:expr, $($arg:tt)*) => (
+        return Err(ParseError { kind: ErrorKind::$kind, message: format!($($arg)*), location: $location })
+    );
+    ($location:expr, $($arg:tt)*) => (parse_error!(Malformed; $location, $($arg)*))
 }
 
+// Spans are optional, so tokens made by hand can still be parsed. The spans of the lists and vectors
+// that are still open are kept, to point at the one that's missing its close paren.
 struct Parser<'a> {
     tokens: slice::Iter<'a, Token>,
+    spans: &'a [Span],
+    count: usize,
+    unclosed: Vec<Option<Span>>,
 }
 
 impl<'a> Parser<'a> {
-    fn parse(tokens: &Vec<Token>) -> Result<Vec<Node>, ParseError> {
-        let mut parser = Parser { tokens: tokens.iter() };
-        parser.parse_nodes(0)
+    fn parse(tokens: &[Token], spans: &[Span]) -> Result<Vec<(Node, SourceMap)>, ParseError> {
+        let mut parser = Parser { tokens: tokens.iter(), spans, count: tokens.len(), unclosed: vec![] };
+        parser.parse_nodes()
     }
 
-    fn parse_nodes(&mut self, depth: u32) -> Result<Vec<Node>, ParseError> {
+    // The span of the token that was just read
+    fn location(&self) -> Option<Span> {
+        let index = self.count - self.tokens.as_slice().len();
+        if index == 0 { None } else { self.spans.get(index - 1).cloned() }
+    }
+
+    fn span(&self) -> Span {
+        self.location().unwrap_or_default()
+    }
+
+    // Reads the nodes at the top level, up to the end of the input
+    fn parse_nodes(&mut self) -> Result<Vec<(Node, SourceMap)>, ParseError> {
         let mut vec = Vec::new();
-        loop {
-            match try!(self.parse_node(depth)) {
-                Some(node) => {
-                    vec.push(node);
-                },
-                None => {
-                    return Ok(vec);
-                }
-            }
+        while let Some(node) = try!(self.parse_node()) {
+            vec.push(node);
         }
+        Ok(vec)
     }
 
-    fn parse_node(&mut self, depth: u32) -> Result<Option<Node>, ParseError> {
-        match self.tokens.next() {
-            Some(token) => {
-                match *token {
-                    Token::OpenParen => {
-                        let inner = try!(self.parse_nodes(depth + 1));
-                        Ok(Some(Node::List(inner)))
-                    },
-                    Token::CloseParen => {
-                        if depth > 0 {
-                            Ok(None)
-                        } else {
-                            parse_error!("Unexpected close paren, depth: {}", depth)
-                        }
-                    },
-                    Token::Quote => {
-                        match try!(self.parse_node(depth)) {
-                            Some(inner) => {
-                                let quoted = Node::List(vec![Node::Identifier("quote".to_string()), inner]);
-                                Ok(Some(quoted))
-                            },
-                            None => parse_error!("Missing quoted value, depth: {}", depth)
-                        }
-                    },
-                    Token::Quasiquote => {
-                        match try!(self.parse_node(depth)) {
-                            Some(inner) => {
-                                let quoted = Node::List(vec![Node::Identifier("quasiquote".to_string()), inner]);
-                                Ok(Some(quoted))
-                            },
-                            None => parse_error!("Missing quasiquoted value, depth: {}", depth)
-                        }
+    // Pops the innermost open list or vector, whose span runs up to the close paren just read
+    fn close(&mut self, parts: Vec<SourceMap>) -> SourceMap {
+        let mut span = self.unclosed.pop().and_then(|open| open).unwrap_or_default();
+        span.end = self.span().end;
+        SourceMap { span, parts }
+    }
+
+    // Reads the next node, or None at the end of the input. The lists, vectors and quotations it's
+    // inside are kept on a stack rather than read by recursion, so deep nesting can't use up the stack.
+    fn parse_node(&mut self) -> Result<Option<(Node, SourceMap)>, ParseError> {
+        let mut stack: Vec<Open> = vec![];
+        loop {
+            let token = self.tokens.next();
+            let span = self.span();
+            let mut node = match token {
+                Some(&Token::OpenParen) => {
+                    self.unclosed.push(self.location());
+                    stack.push(Open::List(vec![], vec![], false));
+                    continue;
+                },
+                Some(&Token::OpenVector) => {
+                    self.unclosed.push(self.location());
+                    stack.push(Open::Vector(vec![], vec![]));
+                    continue;
+                },
+                // the shorthand 'x, `x, ,x and ,@x
+                Some(&Token::Quote) => {
+                    stack.push(Open::Quote("quote", "quoted", self.location()));
+                    continue;
+                },
+                Some(&Token::Quasiquote) => {
+                    stack.push(Open::Quote("quasiquote", "quasiquoted", self.location()));
+                    continue;
+                },
+                Some(&Token::Unquote) => {
+                    stack.push(Open::Quote("unquote", "unquoted", self.location()));
+                    continue;
+                },
+                Some(&Token::UnquoteSplicing) => {
+                    stack.push(Open::Quote("unquote-splicing", "unquote-spliced", self.location()));
+                    continue;
+                },
+                Some(&Token::Dot) => {
+                    match stack.last_mut() {
+                        Some(&mut Open::List(ref items, _, ref mut dotted)) if !*dotted => {
+                            if items.is_empty() {
+                                parse_error!(self.location(), "Unexpected dot at the start of a list")
+                            }
+                            *dotted = true;
+                            continue;
+                        },
+                        _ => parse_error!(self.location(), "Unexpected dot")
                     }
-                    Token::Unquote => {
-                        match try!(self.parse_node(depth)) {
-                            Some(inner) => {
-                                let quoted = Node::List(vec![Node::Identifier("unquote".to_string()), inner]);
-                                Ok(Some(quoted))
-                            },
-                            None => parse_error!("Missing unquoted value, depth: {}", depth)
-                        }
+                },
+                Some(&Token::CloseParen) => {
+                    match stack.pop() {
+                        Some(Open::List(items, parts, false)) => (Node::List(items), self.close(parts)),
+                        Some(Open::List(_, _, true)) => parse_error!(self.location(), "Missing value after dot"),
+                        Some(Open::Vector(items, parts)) => (Node::Vector(items), self.close(parts)),
+                        Some(Open::Quote(_, missing, location)) if !self.unclosed.is_empty() => parse_error!(location, "Missing {} value", missing),
+                        _ => parse_error!(self.location(), "Unexpected close paren")
                     }
-                    Token::Identifier(ref val) => {
-                        Ok(Some(Node::Identifier(val.clone())))
-                    },
-                    Token::Integer(ref val) => {
-                        Ok(Some(Node::Integer(val.clone())))
-                    },
-                    Token::Boolean(ref val) => {
-                        Ok(Some(Node::Boolean(val.clone())))
-                    },
-                    Token::String(ref val) => {
-                        Ok(Some(Node::String(val.clone())))
+                },
+                Some(Token::Identifier(val)) => (Node::Identifier(val.clone()), SourceMap::leaf(span)),
+                Some(Token::Number(val)) => (Node::Number(val.clone()), SourceMap::leaf(span)),
+                Some(&Token::Boolean(val)) => (Node::Boolean(val), SourceMap::leaf(span)),
+                Some(&Token::Char(val)) => (Node::Char(val), SourceMap::leaf(span)),
+                Some(Token::String(val)) => (Node::String(val.clone()), SourceMap::leaf(span)),
+                None => {
+                    match (self.unclosed.last(), stack.pop()) {
+                        (Some(open), _) => parse_error!(Incomplete; open.clone(), "Unclosed paren"),
+                        // nothing follows the quote because the input ended
+                        (None, Some(Open::Quote(_, missing, location))) => parse_error!(Incomplete; location, "Missing {} value", missing),
+                        _ => return Ok(None)
                     }
                 }
-            },
-            None => {
-                if depth == 0 {
-                    Ok(None)
-                } else {
-                    parse_error!("Unexpected end of input, depth: {}", depth)
+            };
+            // the node goes into the list or vector it's in, and completes the quotations around it
+            loop {
+                match stack.last_mut() {
+                    None => return Ok(Some(node)),
+                    Some(&mut Open::List(ref mut items, ref mut parts, false)) | Some(&mut Open::Vector(ref mut items, ref mut parts)) => {
+                        items.push(node.0);
+                        parts.push(node.1);
+                        break;
+                    },
+                    _ => ()
                 }
+                node = match stack.pop() {
+                    Some(Open::List(items, parts, true)) => {
+                        if self.tokens.next() != Some(&Token::CloseParen) {
+                            parse_error!(self.location(), "Expected close paren after dotted tail")
+                        }
+                        let map = self.close(parts);
+                        dotted(items, map, node)
+                    },
+                    Some(Open::Quote(name, _, location)) => {
+                        let (inner, inner_map) = node;
+                        let span = location.unwrap_or_default();
+                        let whole = Span { end: inner_map.span.end, ..span.clone() };
+                        (Node::List(vec![Node::Identifier(name.to_string()), inner]), SourceMap { span: whole, parts: vec![SourceMap::leaf(span), inner_map] })
+                    },
+                    _ => unreachable!()
+                };
             }
         }
     }
 }
 
+// A list, vector or quotation that the parser is reading. A list has the nodes read so far and
+// whether it has had a dot, and a quotation the form it makes, what to call its missing value, and
+// where the quote was.
+enum Open {
+    List(Vec<Node>, Vec<SourceMap>, bool),
+    Vector(Vec<Node>, Vec<SourceMap>),
+    Quote(&'static str, &'static str, Option<Span>),
+}
+
+// The list (a b . tail), where (a . (b c)) is the same list as (a b c)
+fn dotted(mut items: Vec<Node>, mut map: SourceMap, (tail, tail_map): (Node, SourceMap)) -> (Node, SourceMap) {
+    let node = match tail {
+        Node::List(rest) => {
+            items.extend(rest);
+            map.parts.extend(tail_map.parts);
+            Node::List(items)
+        },
+        Node::DottedList(rest, tail) => {
+            items.extend(rest);
+            map.parts.extend(tail_map.parts);
+            Node::DottedList(items, tail)
+        },
+        tail => {
+            map.parts.push(tail_map);
+            Node::DottedList(items, Box::new(tail))
+        }
+    };
+    (node, map)
+}
+
 #[test]
 fn test_parser_simple() {
     assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::CloseParen]).unwrap(),
@@ -136,8 +295,32 @@ fn test_parser_simple() {
 
 #[test]
 fn test_parser_nested() {
-    assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(1), Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(3), Token::Integer(4), Token::CloseParen, Token::CloseParen, Token::Integer(5), Token::CloseParen]).unwrap(),
-               vec![Node::List(vec![Node::Identifier("+".to_string()), Node::List(vec![Node::Identifier("+".to_string()), Node::Integer(1), Node::List(vec![Node::Identifier("+".to_string()), Node::Integer(3), Node::Integer(4)])]), Node::Integer(5)])]);
+    assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::Integer(1)), Token::OpenParen, Token::Identifier("+".to_string()), Token::Number(Number::Integer(3)), Token::Number(Number::Integer(4)), Token::CloseParen, Token::CloseParen, Token::Number(Number::Integer(5)), Token::CloseParen]).unwrap(),
+               vec![Node::List(vec![Node::Identifier("+".to_string()), Node::List(vec![Node::Identifier("+".to_string()), Node::Number(Number::Integer(1)), Node::List(vec![Node::Identifier("+".to_string()), Node::Number(Number::Integer(3)), Node::Number(Number::Integer(4))])]), Node::Number(Number::Integer(5))])]);
+}
+
+#[test]
+fn test_parser_vectors() {
+    assert_eq!(parse(&vec![Token::OpenVector, Token::Number(Number::Integer(1)), Token::OpenParen, Token::CloseParen, Token::OpenVector, Token::CloseParen, Token::CloseParen]).unwrap(),
+               vec![Node::Vector(vec![Node::Number(Number::Integer(1)), Node::List(vec![]), Node::Vector(vec![])])]);
+    assert_eq!(parse(&vec![Token::OpenVector, Token::Number(Number::Integer(1))]).err().unwrap().to_string(),
+               "ParseError: Unclosed paren");
+}
+
+#[test]
+fn test_parser_dotted_lists() {
+    assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Dot, Token::Identifier("b".to_string()), Token::CloseParen]).unwrap(),
+               vec![Node::DottedList(vec![Node::Identifier("a".to_string())], Box::new(Node::Identifier("b".to_string())))]);
+    assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Dot, Token::OpenParen, Token::Identifier("b".to_string()), Token::CloseParen, Token::CloseParen]).unwrap(),
+               vec![Node::List(vec![Node::Identifier("a".to_string()), Node::Identifier("b".to_string())])]);
+    assert_eq!(parse(&vec![Token::OpenParen, Token::Dot, Token::Identifier("b".to_string()), Token::CloseParen]).err().unwrap().to_string(),
+               "ParseError: Unexpected dot at the start of a list");
+    assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Dot, Token::CloseParen]).err().unwrap().to_string(),
+               "ParseError: Missing value after dot");
+    assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Dot, Token::Identifier("b".to_string()), Token::Identifier("c".to_string()), Token::CloseParen]).err().unwrap().to_string(),
+               "ParseError: Expected close paren after dotted tail");
+    assert_eq!(parse(&vec![Token::Dot]).err().unwrap().to_string(),
+               "ParseError: Unexpected dot");
 }
 
 #[test]
@@ -154,16 +337,38 @@ fn test_parser_quasiquoting() {
                vec![Node::List(vec![Node::Identifier("quasiquote".to_string()), Node::List(vec![Node::List(vec![Node::Identifier("unquote".to_string()), Node::Identifier("a".to_string())])])])]);
     assert_eq!(parse(&vec![Token::Quasiquote, Token::OpenParen, Token::Unquote, Token::Identifier("a".to_string()), Token::Identifier("b".to_string()), Token::Unquote, Token::Identifier("c".to_string()), Token::CloseParen]).unwrap(),
                vec![Node::List(vec![Node::Identifier("quasiquote".to_string()), Node::List(vec![Node::List(vec![Node::Identifier("unquote".to_string()), Node::Identifier("a".to_string())]), Node::Identifier("b".to_string()), Node::List(vec![Node::Identifier("unquote".to_string()), Node::Identifier("c".to_string())])])])]);
+    assert_eq!(parse(&vec![Token::Quasiquote, Token::OpenParen, Token::Identifier("a".to_string()), Token::UnquoteSplicing, Token::Identifier("b".to_string()), Token::CloseParen]).unwrap(),
+               vec![Node::List(vec![Node::Identifier("quasiquote".to_string()), Node::List(vec![Node::Identifier("a".to_string()), Node::List(vec![Node::Identifier("unquote-splicing".to_string()), Node::Identifier("b".to_string())])])])]);
 }
 
 #[test]
 fn test_parser_bad_syntax() {
     assert_eq!(parse(&vec![Token::CloseParen]).err().unwrap().to_string(),
-               "ParseError: Unexpected close paren, depth: 0");
+               "ParseError: Unexpected close paren");
     assert_eq!(parse(&vec![Token::OpenParen, Token::OpenParen, Token::CloseParen]).err().unwrap().to_string(),
-               "ParseError: Unexpected end of input, depth: 1");
+               "ParseError: Unclosed paren");
     assert_eq!(parse(&vec![Token::OpenParen, Token::CloseParen, Token::CloseParen]).err().unwrap().to_string(),
-               "ParseError: Unexpected close paren, depth: 0");
+               "ParseError: Unexpected close paren");
     assert_eq!(parse(&vec![Token::OpenParen, Token::OpenParen, Token::CloseParen, Token::OpenParen, Token::OpenParen, Token::CloseParen]).err().unwrap().to_string(),
-               "ParseError: Unexpected end of input, depth: 2");
+               "ParseError: Unclosed paren");
+}
+
+#[test]
+fn test_parser_spans() {
+    let (tokens, spans) = tokenize_spanned("(define x\n  '(1 . 2))", Some("foo.scm")).unwrap();
+    let parsed = parse_spanned(&tokens, &spans).unwrap();
+    let map = &parsed[0].1;
+    assert_eq!((map.span.line, map.span.column, map.span.start, map.span.end), (1, 1, 0, 21));
+    assert_eq!(map.parts.len(), 3);
+    let quoted = &map.parts[2];
+    assert_eq!((quoted.span.line, quoted.span.column, quoted.span.start, quoted.span.end), (2, 3, 12, 20));
+    assert_eq!(quoted.parts[1].parts.iter().map(|p| p.span.column).collect::<Vec<u32>>(), vec![5, 9]);
+    assert_eq!(map.part(0, 2).span, map.span);
+
+    let (tokens, spans) = tokenize_spanned("(+ 1 2)\n(car (list 1)", None).unwrap();
+    assert_eq!(parse_spanned(&tokens, &spans).err().unwrap().to_string(),
+               "ParseError: Unclosed paren (line: 2, column: 1)");
+    let (tokens, spans) = tokenize_spanned("(a\n  . )", None).unwrap();
+    assert_eq!(parse_spanned(&tokens, &spans).err().unwrap().to_string(),
+               "ParseError: Missing value after dot (line: 2, column: 5)");
 }
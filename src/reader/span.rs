@@ -0,0 +1,23 @@
+use std::fmt;
+use std::rc::Rc;
+
+// Where a piece of source code was read from. Lines and columns count from 1, and start and end are
+// byte offsets into the source. Code that didn't come from a file, like REPL input, has no file name.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Span {
+    pub file: Option<Rc<str>>,
+    pub line: u32,
+    pub column: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Spans print the way compilers report positions, as file:line:column
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}:{}", file, self.line, self.column),
+            None => write!(f, "{}:{}", self.line, self.column)
+        }
+    }
+}